@@ -0,0 +1,114 @@
+//! Compares a 10-item order added with one `add_items` call against ten `add_item` calls.
+//!
+//! Each call is a round trip to OpenAI in production, which this benchmark leaves out, so it
+//! only measures the server side of the calls.
+
+use async_openai::types::FunctionCall;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::{json, Value};
+
+use customer_agent::chat::handle_function_call;
+use customer_agent::functions::FunctionName;
+use customer_agent::menu::{Menu, MenuItem};
+use customer_agent::order::{EtaMode, Order, OrderLimits};
+
+/// Items of the order, each with a single choice per option
+const ITEMS: &[(&str, &[(&str, &str)])] = &[
+    ("Coke", &[("size", "small")]),
+    ("Sprite", &[("size", "large")]),
+    ("Regular Fries", &[("size", "small")]),
+    ("Sweet Potato Fries", &[("size", "large")]),
+    ("Apple Pie", &[]),
+    ("Chocolate Cake", &[]),
+    ("Red Bull", &[]),
+    ("Milk", &[]),
+    ("Garlic Fries", &[]),
+    ("Bottled Water", &[]),
+];
+
+/// Arguments of a single item, as `add_item` nests it and `add_items` lists it.
+fn item_args(item_name: &str, options: &[(&str, &str)]) -> Value {
+    json!({
+        "itemName": item_name,
+        "optionKeys": options.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+        "optionValues": options.iter().map(|(_, value)| vec![value]).collect::<Vec<_>>(),
+        "price": 0,
+        "components": [],
+        "notes": null,
+        "cartLabel": null,
+        "quantity": null,
+    })
+}
+
+/// Runs each call against a new order.
+async fn run(calls: &[FunctionCall], menu: &Menu, limits: &OrderLimits) -> Order {
+    let mut order = Order::new("bench".to_string(), "store-a".to_string());
+    for call in calls {
+        handle_function_call(call, menu, limits, &mut order)
+            .await
+            .expect("function call must succeed");
+    }
+    order
+}
+
+fn add_items(c: &mut Criterion) {
+    let items: Vec<MenuItem> = serde_json::from_str(include_str!("../static/menu.json"))
+        .expect("static/menu.json must be a valid menu");
+    let menu = Menu {
+        items,
+        max_validation_problems: 0,
+    };
+    let limits = OrderLimits {
+        max_items: 50,
+        max_thread_messages: 100,
+        ttl_seconds: 0,
+        max_invalid_turns: 3,
+        max_tokens: 0,
+        default_prep_seconds: 300,
+        eta_mode: EtaMode::Max,
+    };
+    let one_by_one: Vec<FunctionCall> = ITEMS
+        .iter()
+        .map(|(item_name, options)| FunctionCall {
+            name: FunctionName::AddItem.to_string(),
+            arguments: json!({ "item": item_args(item_name, options) }).to_string(),
+        })
+        .collect();
+    let batched = vec![FunctionCall {
+        name: FunctionName::AddItems.to_string(),
+        arguments: json!({
+            "items": ITEMS
+                .iter()
+                .map(|(item_name, options)| item_args(item_name, options))
+                .collect::<Vec<_>>(),
+        })
+        .to_string(),
+    }];
+    let runtime = tokio::runtime::Runtime::new().expect("runtime must start");
+
+    let mut group = c.benchmark_group("10-item order");
+    group.bench_function("add_item x10", |b| {
+        b.to_async(&runtime).iter_batched(
+            || one_by_one.clone(),
+            |calls| {
+                let (menu, limits) = (&menu, &limits);
+                async move { run(&calls, menu, limits).await }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("add_items", |b| {
+        b.to_async(&runtime).iter_batched(
+            || batched.clone(),
+            |calls| {
+                let (menu, limits) = (&menu, &limits);
+                async move { run(&calls, menu, limits).await }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, add_items);
+criterion_main!(benches);
@@ -0,0 +1,291 @@
+//! Compares the latency of a multi-tool run driven by polling against one driven by streamed
+//! run events (`OPENAI_STREAM_RUNS`).
+//!
+//! OpenAI is replaced by a local server that takes `STEP` to produce every step of the run: two
+//! rounds of tool calls and then the reply. Polling only notices each step at its next poll,
+//! while the stream delivers it as soon as it is ready, so the difference is the time the poll
+//! loop spends waiting on top of the three steps.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+use async_openai::{config::OpenAIConfig, Client};
+use axum::{
+    extract::{Path, State},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::{stream, StreamExt};
+use serde_json::{json, Value};
+
+use customer_agent::config::Config;
+use customer_agent::functions::{FunctionName, OrderAssistant};
+use customer_agent::menu::Menu;
+use customer_agent::order::Order;
+
+/// Time the fake model takes to produce each step of a run
+const STEP: Duration = Duration::from_millis(300);
+
+/// State of the fake OpenAI server: when the current step of each run is ready and how many
+/// steps it has been through
+#[derive(Clone, Default)]
+struct FakeOpenAI {
+    runs: Arc<Mutex<HashMap<String, (usize, Instant)>>>,
+    next_run: Arc<AtomicUsize>,
+}
+
+/// Arguments of `add_item` for an item with a single option.
+fn add_item(item_name: &str, option_key: &str, option_value: &str) -> Value {
+    json!({
+        "name": FunctionName::AddItem.to_string(),
+        "arguments": json!({
+            "item": {
+                "itemName": item_name,
+                "optionKeys": [option_key],
+                "optionValues": [[option_value]],
+                "price": 0,
+                "components": [],
+                "notes": null,
+                "cartLabel": null,
+                "quantity": null,
+            }
+        })
+        .to_string(),
+    })
+}
+
+/// The run as OpenAI reports it after `step` steps: two rounds of tool calls, then completed.
+fn run_object(run_id: &str, step: usize, ready: bool) -> Value {
+    let calls = match step {
+        0 => vec![
+            add_item("Coke", "size", "small"),
+            add_item("Regular Fries", "size", "small"),
+        ],
+        1 => vec![json!({
+            "name": FunctionName::ListItems.to_string(),
+            "arguments": "{\"limit\":null}",
+        })],
+        _ => vec![],
+    };
+    let status = match (ready, calls.is_empty()) {
+        (false, _) => "in_progress",
+        (true, false) => "requires_action",
+        (true, true) => "completed",
+    };
+    let required_action = (status == "requires_action").then(|| {
+        json!({
+            "type": "submit_tool_outputs",
+            "submit_tool_outputs": {
+                "tool_calls": calls
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, function)| json!({
+                        "id": format!("call_{}_{}", step, i),
+                        "type": "function",
+                        "function": function,
+                    }))
+                    .collect::<Vec<_>>(),
+            },
+        })
+    });
+    json!({
+        "id": run_id,
+        "object": "thread.run",
+        "created_at": 0,
+        "thread_id": "thread_bench",
+        "assistant_id": "asst_bench",
+        "status": status,
+        "required_action": required_action,
+        "model": "bench",
+        "instructions": "",
+        "tools": [],
+        "parallel_tool_calls": true,
+    })
+}
+
+/// Streams the run's events until its next step is ready.
+fn run_events(run_id: String, step: usize, created: bool) -> Response {
+    let created = created.then(|| {
+        Event::default()
+            .event("thread.run.created")
+            .json_data(run_object(&run_id, step, false))
+            .unwrap()
+    });
+    let next = async move {
+        tokio::time::sleep(STEP).await;
+        let run = run_object(&run_id, step, true);
+        let event = match run["status"].as_str() {
+            Some("completed") => "thread.run.completed",
+            _ => "thread.run.requires_action",
+        };
+        Event::default().event(event).json_data(run).unwrap()
+    };
+    let events = stream::iter(created)
+        .chain(stream::once(next))
+        .map(Ok::<_, Infallible>);
+    Sse::new(events).into_response()
+}
+
+async fn create_assistant() -> Json<Value> {
+    Json(json!({
+        "id": "asst_bench",
+        "object": "assistant",
+        "created_at": 0,
+        "model": "bench",
+        "tools": [],
+    }))
+}
+
+async fn create_message() -> Json<Value> {
+    Json(json!({
+        "id": "msg_user",
+        "object": "thread.message",
+        "created_at": 0,
+        "thread_id": "thread_bench",
+        "role": "user",
+        "content": [],
+    }))
+}
+
+async fn list_messages() -> Json<Value> {
+    Json(json!({
+        "object": "list",
+        "data": [{
+            "id": "msg_reply",
+            "object": "thread.message",
+            "created_at": 0,
+            "thread_id": "thread_bench",
+            "role": "assistant",
+            "content": [{
+                "type": "text",
+                "text": { "value": "A small Coke and small fries, anything else?", "annotations": [] },
+            }],
+        }],
+        "has_more": false,
+    }))
+}
+
+async fn create_run(State(fake): State<FakeOpenAI>, Json(request): Json<Value>) -> Response {
+    let run_id = format!("run_{}", fake.next_run.fetch_add(1, Ordering::Relaxed));
+    fake.runs
+        .lock()
+        .unwrap()
+        .insert(run_id.clone(), (0, Instant::now() + STEP));
+    if request["stream"] == json!(true) {
+        return run_events(run_id, 0, true);
+    }
+    Json(run_object(&run_id, 0, false)).into_response()
+}
+
+async fn retrieve_run(
+    State(fake): State<FakeOpenAI>,
+    Path((_thread_id, run_id)): Path<(String, String)>,
+) -> Json<Value> {
+    let (step, ready_at) = fake.runs.lock().unwrap()[&run_id];
+    Json(run_object(&run_id, step, Instant::now() >= ready_at))
+}
+
+async fn submit_tool_outputs(
+    State(fake): State<FakeOpenAI>,
+    Path((_thread_id, run_id)): Path<(String, String)>,
+    Json(request): Json<Value>,
+) -> Response {
+    let step = {
+        let mut runs = fake.runs.lock().unwrap();
+        let run = runs.get_mut(&run_id).unwrap();
+        *run = (run.0 + 1, Instant::now() + STEP);
+        run.0
+    };
+    if request["stream"] == json!(true) {
+        return run_events(run_id, step, false);
+    }
+    Json(run_object(&run_id, step, false)).into_response()
+}
+
+/// Starts the fake OpenAI server on a free local port.
+///
+/// # Returns
+/// * `String` - The API base to point the client at
+fn start_server(runtime: &tokio::runtime::Runtime) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_base = format!("http://{}", listener.local_addr().unwrap());
+    let app = Router::new()
+        .route("/assistants", post(create_assistant))
+        .route(
+            "/threads/:thread_id/messages",
+            post(create_message).get(list_messages),
+        )
+        .route("/threads/:thread_id/runs", post(create_run))
+        .route("/threads/:thread_id/runs/:run_id", get(retrieve_run))
+        .route(
+            "/threads/:thread_id/runs/:run_id/submit_tool_outputs",
+            post(submit_tool_outputs),
+        )
+        .with_state(FakeOpenAI::default());
+    runtime.spawn(async move {
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service())
+            .await
+            .unwrap()
+    });
+    api_base
+}
+
+fn run_modes(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().expect("runtime must start");
+    let api_base = start_server(&runtime);
+    std::env::set_var("API_KEYS", "bench");
+    std::env::set_var("OPENAI_API_KEY", "bench");
+    let mut config = Config::from_env().expect("bench config must be valid");
+    let menu = Menu::new(&config.menu_settings).expect("menu must load");
+
+    let mut group = c.benchmark_group("multi-tool run");
+    group
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(30));
+    for (name, stream_runs) in [("poll", false), ("stream", true)] {
+        config.openai_stream_runs = stream_runs;
+        let client = Client::with_config(
+            OpenAIConfig::new()
+                .with_api_base(&api_base)
+                .with_api_key("bench"),
+        );
+        let mut assistant = OrderAssistant::new(client, &config);
+        runtime
+            .block_on(assistant.initialize_assistant(&menu))
+            .expect("assistant must be created");
+        group.bench_function(name, |b| {
+            b.to_async(&runtime).iter(|| async {
+                let mut order = Order::new("bench".to_string(), "store-a".to_string());
+                order.thread_id = Some("thread_bench".to_string());
+                assistant
+                    .handle_message(
+                        "a small coke and small fries",
+                        "store-a",
+                        None,
+                        &mut order,
+                        &menu,
+                    )
+                    .await
+                    .expect("run must complete");
+                assert_eq!(order.order.len(), 2);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, run_modes);
+criterion_main!(benches);
@@ -1,26 +1,59 @@
-use async_openai::{config::OpenAIConfig, Client as OpenAIClient};
+use arc_swap::ArcSwap;
+use async_openai::{
+    config::{Config as _, OpenAIConfig},
+    Client as OpenAIClient,
+};
 #[allow(unused_imports)]
 use axum::{
-    extract::{Path, State},
-    http::{header::AUTHORIZATION, Request, StatusCode},
+    body::StreamBody,
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        DefaultBodyLimit, Extension, Path, Query, State,
+    },
+    http::{
+        header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode,
+    },
     middleware::{self, Next},
-    response::Response,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
+use chrono::{DateTime, Utc};
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::PrometheusHandle;
 use redis::Client as RedisClient;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::Mutex as TokioMutex;
-use tracing::{debug, info};
+use std::time::{Duration, Instant};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use uuid::Uuid;
 
-use crate::chat::{handle_chat_message, ChatMessage};
-use crate::error::AppResult;
-use crate::functions::OrderAssistant;
-use crate::menu::Menu;
-use crate::order::{Order, OrderItemResponse, OrderStore};
+use crate::chat::{
+    handle_chat_message, handle_item_selection, validate_items, ChatMessage, OrderSummary,
+    UsageCounters,
+};
+use crate::cleanup;
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::functions::{AddItemArgs, AssistantBackend, AssistantInfo, OrderAssistant};
+use crate::location::Locations;
+use crate::menu::{Menu, MenuItem, MenuSettings};
+use crate::mock::MockAssistant;
+use crate::money::Money;
+use crate::order::{
+    AuditEntry, CartSection, ItemStatusResponse, OptionValue, Order, OrderItem, OrderItemResponse,
+    OrderLimits, OrderLocks, OrderScan, OrderStatus, OrderStore, IDEMPOTENCY_KEY_TTL_SECONDS,
+};
+use crate::telemetry;
+use crate::webhook::CompletionWebhook;
+
+/// Header carrying the ID used to correlate the logs of a request
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
 /// Request payload for starting a new order
 #[derive(Debug, Serialize, Deserialize)]
@@ -47,6 +80,89 @@ pub struct ChatRequest {
     pub input: String,
     /// The location of the restaurant
     pub location: String,
+    /// Language to reply in, e.g. "es", defaults to the assistant's usual behavior
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+impl ChatRequest {
+    /// Checks that the input is worth sending to the assistant.
+    ///
+    /// # Arguments
+    /// * `max_input_chars` - Maximum number of characters allowed in the input
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the input is non-empty and within the limit, and the
+    ///   language, if any, looks like a language tag
+    pub fn validate(&self, max_input_chars: usize) -> AppResult<()> {
+        if self.input.trim().is_empty() {
+            info!("Rejecting empty chat input for order {}", self.order_id);
+            return Err(AppError::InvalidInput(
+                "Chat input must not be empty".to_string(),
+            ));
+        }
+        let input_chars = self.input.chars().count();
+        if input_chars > max_input_chars {
+            info!(
+                "Rejecting chat input of {} characters for order {}",
+                input_chars, self.order_id
+            );
+            return Err(AppError::InputTooLarge(format!(
+                "Chat input is {} characters, the maximum is {}",
+                input_chars, max_input_chars
+            )));
+        }
+        if let Some(language) = &self.language {
+            // NOTE(dev): The language ends up in the run instructions, so only allow tag-like values
+            let is_tag = !language.is_empty()
+                && language.len() <= 35
+                && language
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-');
+            if !is_tag {
+                info!(
+                    "Rejecting language {:?} for order {}",
+                    language, self.order_id
+                );
+                return Err(AppError::InvalidInput(format!(
+                    "Invalid language: {}",
+                    language
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Query parameters for opening a chat WebSocket
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatSocketQuery {
+    /// The ID of the order the socket chats on
+    #[serde(rename = "orderId")]
+    pub order_id: String,
+    /// The location of the restaurant
+    pub location: String,
+    /// The API key, otherwise expected in the first message
+    #[serde(rename = "apiKey", default)]
+    pub api_key: Option<String>,
+}
+
+/// First message of a chat WebSocket opened without `apiKey`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatSocketAuth {
+    /// The API key
+    #[serde(rename = "apiKey")]
+    pub api_key: String,
+}
+
+/// Chat message received over a chat WebSocket
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatSocketMessage {
+    /// The user's input message
+    pub input: String,
+    /// Language to reply in, e.g. "es", defaults to the assistant's usual behavior
+    #[serde(default)]
+    pub language: Option<String>,
 }
 
 /// Response payload for a chat message
@@ -59,6 +175,30 @@ pub struct ChatResponse {
     pub order: Vec<OrderItemResponse>,
     /// The chat message history
     pub messages: Vec<ChatMessage>,
+    /// Sum of the item prices
+    pub total: Money,
+    /// Currency of all prices
+    pub currency: String,
+    /// The lifecycle status of the order, `completed` once the customer confirmed it
+    pub status: OrderStatus,
+    /// Whether the order was read back and the customer's next yes completes it
+    #[serde(rename = "awaitingConfirmation")]
+    pub awaiting_confirmation: bool,
+    /// Whether the assistant's reply was cut off by a token limit
+    #[serde(default)]
+    pub incomplete: bool,
+    /// Whether the order kept ending with invalid items and a human should take over
+    #[serde(rename = "needsHuman", default)]
+    pub needs_human: bool,
+    /// Menu items to suggest with the items just added, omitted if there are none
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub suggestions: Vec<String>,
+    /// Items grouped by cart label, omitted if no item has a label
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub carts: Vec<CartSection>,
+    /// Itemized order recorded when the assistant finalized it, omitted until then
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub summary: Option<OrderSummary>,
 }
 
 /// Response payload for retrieving an order
@@ -68,192 +208,2590 @@ pub struct GetOrderResponse {
     pub order: Vec<OrderItemResponse>,
     /// The chat message history
     pub messages: Vec<ChatMessage>,
+    /// Sum of the item prices
+    pub total: Money,
+    /// Currency of all prices
+    pub currency: String,
+    /// Lifecycle status of the order
+    #[serde(default)]
+    pub status: OrderStatus,
+    /// Number of older messages left out of `messages`
+    #[serde(rename = "truncatedMessages")]
+    pub truncated_messages: usize,
+    /// Items grouped by cart label, omitted if no item has a label
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub carts: Vec<CartSection>,
+    /// Time at which the order was started, omitted for orders stored before it was recorded
+    #[serde(rename = "createdAt", skip_serializing_if = "Option::is_none", default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Time at which the order was last saved, omitted for orders stored before it was recorded
+    #[serde(rename = "updatedAt", skip_serializing_if = "Option::is_none", default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Itemized order recorded when the assistant finalized it, omitted until then
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub summary: Option<OrderSummary>,
+}
+
+impl GetOrderResponse {
+    /// Builds the response for an order.
+    ///
+    /// # Arguments
+    /// * `order` - The order to return
+    /// * `currency` - Currency of all prices
+    ///
+    /// # Returns
+    /// * `Self` - The response payload
+    pub fn new(order: Order, currency: &str) -> Self {
+        GetOrderResponse {
+            total: order.total(),
+            carts: order.carts(),
+            order: order.order.into_iter().map(Into::into).collect(),
+            messages: order.messages,
+            currency: currency.to_string(),
+            status: order.status,
+            truncated_messages: order.truncated_messages,
+            created_at: order.created_at,
+            updated_at: order.updated_at,
+            summary: order.summary,
+        }
+    }
+}
+
+/// Response payload for a completed order
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompleteOrderResponse {
+    /// The ID of the completed order
+    #[serde(rename = "orderId")]
+    pub order_id: String,
+    /// The lifecycle status of the order
+    pub status: OrderStatus,
+    /// The time at which the order was completed
+    #[serde(rename = "completedAt")]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// The final order items
+    pub order: Vec<OrderItemResponse>,
+    /// Sum of the item prices
+    pub total: Money,
+    /// Currency of all prices
+    pub currency: String,
+    /// Items grouped by cart label, omitted if no item has a label
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub carts: Vec<CartSection>,
+    /// Seconds the kitchen needs to prepare the order
+    #[serde(
+        rename = "prepSeconds",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub prep_seconds: Option<u32>,
+    /// Time at which the order is expected to be ready
+    #[serde(rename = "readyAt", skip_serializing_if = "Option::is_none", default)]
+    pub ready_at: Option<DateTime<Utc>>,
+}
+
+impl From<(Order, &str)> for CompleteOrderResponse {
+    fn from((order, currency): (Order, &str)) -> Self {
+        let ready_at = order
+            .completed_at
+            .zip(order.prep_seconds)
+            .map(|(completed_at, prep)| completed_at + chrono::Duration::seconds(prep.into()));
+        CompleteOrderResponse {
+            prep_seconds: order.prep_seconds,
+            ready_at,
+            total: order.total(),
+            carts: order.carts(),
+            order_id: order.order_id,
+            status: order.status,
+            completed_at: order.completed_at,
+            order: order.order.into_iter().map(Into::into).collect(),
+            currency: currency.to_string(),
+        }
+    }
+}
+
+/// Where the chat messages of an order are read from
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageSource {
+    /// The copy stored with the order
+    #[default]
+    Local,
+    /// The OpenAI thread of the order
+    Thread,
+}
+
+/// Query parameters for retrieving an order
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetOrderQuery {
+    /// Where to read the chat messages from, defaults to the local copy
+    #[serde(default)]
+    pub source: MessageSource,
+}
+
+/// Query parameters for listing archived orders
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedOrdersQuery {
+    /// Number of orders to skip
+    #[serde(default)]
+    pub offset: usize,
+    /// Maximum number of orders to return, defaults to 50
+    pub limit: Option<usize>,
+}
+
+/// Response payload for listing archived orders
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedOrdersResponse {
+    /// Archived orders, most recently completed first
+    pub orders: Vec<CompleteOrderResponse>,
+}
+
+/// Response payload for the audit trail of an order
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditResponse {
+    /// The ID of the order
+    #[serde(rename = "orderId")]
+    pub order_id: String,
+    /// Every function call made on the order, oldest first
+    pub entries: Vec<AuditEntry>,
+}
+
+/// Query parameters for exporting orders
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportOrdersQuery {
+    /// Only export orders changed at or after this time
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// Query parameters for order statistics
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsQuery {
+    /// Number of top items to return, defaults to 10
+    pub top: Option<usize>,
+}
+
+/// An item and how often it was ordered
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ItemCount {
+    /// Name of the item
+    #[serde(rename = "itemName")]
+    pub item_name: String,
+    /// Number of times the item was ordered
+    pub count: usize,
+}
+
+/// Response payload for order statistics
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsResponse {
+    /// Orders still being built
+    #[serde(rename = "activeOrders")]
+    pub active_orders: usize,
+    /// Orders completed and archived
+    #[serde(rename = "completedOrders")]
+    pub completed_orders: usize,
+    /// Orders abandoned before completion
+    #[serde(rename = "cancelledOrders")]
+    pub cancelled_orders: usize,
+    /// Average number of items per order
+    #[serde(rename = "averageItemsPerOrder")]
+    pub average_items_per_order: f64,
+    /// Most ordered items, most popular first
+    #[serde(rename = "topItems")]
+    pub top_items: Vec<ItemCount>,
+}
+
+/// Query parameters for listing the menu
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MenuQuery {
+    /// Only return items with this tag
+    pub tag: Option<String>,
+}
+
+/// Request payload for validating an item without adding it to an order
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateItemRequest {
+    /// Name of the menu item
+    #[serde(rename = "itemName")]
+    pub item_name: String,
+    /// Keys for the selected options
+    #[serde(rename = "optionKeys", default)]
+    pub option_keys: Vec<String>,
+    /// Values for the selected options
+    #[serde(rename = "optionValues", default)]
+    pub option_values: Vec<Vec<OptionValue>>,
+    /// Items filling the slots of a combo
+    #[serde(default)]
+    pub components: Vec<ValidateItemRequest>,
+}
+
+impl From<ValidateItemRequest> for OrderItem {
+    fn from(val: ValidateItemRequest) -> Self {
+        OrderItem {
+            id: Uuid::new_v4().to_string(),
+            item_name: val.item_name,
+            option_keys: val.option_keys,
+            option_values: val.option_values,
+            price: Money::ZERO,
+            item_status: None,
+            components: val.components.into_iter().map(Into::into).collect(),
+            notes: None,
+            cart_label: None,
+            quantity: 1,
+        }
+    }
+}
+
+/// Response payload for validating an item
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateItemResponse {
+    /// Validation status of the item
+    #[serde(rename = "itemStatus")]
+    pub item_status: ItemStatusResponse,
+    /// Price of the item computed from the menu, if it could be priced
+    pub price: Option<Money>,
+    /// Currency of the price
+    pub currency: String,
+}
+
+/// Response payload for reloading the menu
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReloadMenuResponse {
+    /// Number of items on the reloaded menu
+    #[serde(rename = "itemCount")]
+    pub item_count: usize,
+}
+
+/// A single line of an item's price breakdown
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PriceLine {
+    /// What the line is for, e.g. "meal option: meal"
+    pub label: String,
+    /// Price contributed by the line
+    pub price: Money,
+}
+
+/// Response payload for an item's price breakdown
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PriceBreakdownResponse {
+    /// ID of the order item
+    #[serde(rename = "itemId")]
+    pub item_id: String,
+    /// Name of the menu item
+    #[serde(rename = "itemName")]
+    pub item_name: String,
+    /// Contribution of each chosen option value
+    pub lines: Vec<PriceLine>,
+    /// Sum of the lines
+    pub total: Money,
+    /// Currency of all prices
+    pub currency: String,
+}
+
+/// Status of a single dependency in the health check
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyStatus {
+    /// Dependency is reachable
+    Ok,
+    /// Dependency is unavailable
+    Down,
+}
+
+/// Response payload for the health check
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthResponse {
+    /// Status of the Redis connection
+    pub redis: DependencyStatus,
+    /// Status of the OpenAI assistant
+    pub openai: DependencyStatus,
+}
+
+/// What an API key is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiKeyScope {
+    /// Only `GET` requests
+    ReadOnly,
+    /// Every request
+    ReadWrite,
+}
+
+/// What an API key is allowed to do and where
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    /// Which requests the key may make
+    pub scope: ApiKeyScope,
+    /// Locations the key may start and chat on orders for, None for every location
+    pub locations: Option<HashSet<String>>,
+}
+
+impl ApiKey {
+    /// Checks that the key may operate on a location.
+    ///
+    /// # Arguments
+    /// * `location` - The restaurant location
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if allowed, `Forbidden` otherwise
+    pub fn check_location(&self, location: &str) -> AppResult<()> {
+        match &self.locations {
+            Some(locations) if !locations.contains(location) => {
+                info!("API key is not allowed to use location {}", location);
+                Err(AppError::Forbidden(format!(
+                    "API key is not allowed to use location {}",
+                    location
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the key may operate on an existing order, going by the location the order was
+    /// started for.
+    ///
+    /// # Arguments
+    /// * `order` - The order
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if allowed or the order has no location, `Forbidden`
+    ///   otherwise
+    pub fn check_order_location(&self, order: &Order) -> AppResult<()> {
+        match &order.location {
+            Some(location) => self.check_location(location),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that the key is not bound to locations, for endpoints spanning every location.
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success for keys without locations, `Forbidden` otherwise
+    pub fn check_all_locations(&self) -> AppResult<()> {
+        if self.locations.is_some() {
+            info!("Location-bound API key used for an endpoint spanning every location");
+            return Err(AppError::Forbidden(
+                "API key is bound to locations and cannot use this endpoint".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the key may make changes, for `GET` endpoints exposing internals.
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success for read-write keys, `Forbidden` otherwise
+    pub fn check_read_write(&self) -> AppResult<()> {
+        if self.scope == ApiKeyScope::ReadOnly {
+            info!("Read-only API key used for an endpoint requiring read-write");
+            return Err(AppError::Forbidden(
+                "API key is read-only and cannot use this endpoint".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Parses the `API_KEYS` value into keys, their scopes and their locations.
+///
+/// Keys are comma-separated and may end in `:ro` or `:rw`. Keys without a scope can do
+/// everything, as before scopes existed. A key may be bound to locations with `@`, separating
+/// several locations with `|`, e.g. `key1@store-a|store-b:rw`. Keys without locations can use
+/// every location.
+///
+/// # Arguments
+/// * `value` - The comma-separated keys, e.g. `key1:rw,key2:ro,key3@store-a`
+///
+/// # Returns
+/// * `AppResult<HashMap<String, ApiKey>>` - Each key and what it may do, or `InvalidInput` for
+///   an unknown scope or empty location
+pub fn parse_api_keys(value: &str) -> AppResult<HashMap<String, ApiKey>> {
+    value
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (key, scope) = match entry.rsplit_once(':') {
+                Some((key, "ro")) => (key, ApiKeyScope::ReadOnly),
+                Some((key, "rw")) => (key, ApiKeyScope::ReadWrite),
+                Some((_, scope)) => {
+                    return Err(AppError::InvalidInput(format!(
+                        "Unknown API key scope: {}",
+                        scope
+                    )))
+                }
+                None => (entry, ApiKeyScope::ReadWrite),
+            };
+            let (key, locations) = match key.split_once('@') {
+                Some((key, locations)) => {
+                    let locations: HashSet<String> =
+                        locations.split('|').map(|l| l.trim().to_string()).collect();
+                    if locations.iter().any(|l| l.is_empty()) {
+                        return Err(AppError::InvalidInput(format!(
+                            "Empty location for API key {}",
+                            key
+                        )));
+                    }
+                    (key, Some(locations))
+                }
+                None => (key, None),
+            };
+            Ok((key.to_string(), ApiKey { scope, locations }))
+        })
+        .collect()
 }
 
 /// Validates the API key from the request headers against the allowed API keys in the application state.
 ///
+/// The key's scope and locations are attached to the request extensions. Read-only keys may
+/// only make `GET` requests.
+///
 /// # Arguments
 /// * `state` - Application state containing allowed API keys
 /// * `req` - The incoming HTTP request
 /// * `next` - The next middleware function to call if validation succeeds
 ///
 /// # Returns
-/// * `Result<Response, StatusCode>` - Success response if validated, UNAUTHORIZED status if invalid
+/// * `AppResult<Response>` - Success response if validated, `Unauthorized` if the key is missing
+///   or invalid, `Forbidden` if the key's scope does not allow the request
 async fn validate_api_key<B>(
     State(state): State<AppState>,
-    req: Request<B>,
+    mut req: Request<B>,
     next: Next<B>,
-) -> Result<Response, StatusCode> {
+) -> AppResult<Response> {
     debug!("Validating API key from request headers");
     let auth_header = req
         .headers()
         .get("x-api-key")
         .and_then(|header| header.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+        .ok_or_else(|| AppError::Unauthorized("Missing API key".to_string()))?;
 
     if !auth_header.starts_with("Bearer ") {
         info!("Invalid API key format - missing Bearer prefix");
-        return Err(StatusCode::UNAUTHORIZED);
+        return Err(AppError::Unauthorized(
+            "API key must use the Bearer format".to_string(),
+        ));
     }
 
     let token = auth_header.trim_start_matches("Bearer ").trim();
 
-    if state.api_keys.contains(token) {
-        debug!("API key validated successfully");
-        Ok(next.run(req).await)
-    } else {
-        info!("Invalid API key provided");
-        Err(StatusCode::UNAUTHORIZED)
+    let api_key = match state.api_keys.get(token) {
+        Some(api_key) => api_key.clone(),
+        None => {
+            info!("Invalid API key provided");
+            return Err(AppError::Unauthorized("Invalid API key".to_string()));
+        }
+    };
+
+    if api_key.scope == ApiKeyScope::ReadOnly && req.method() != Method::GET {
+        info!(
+            "Read-only API key used for {} {}",
+            req.method(),
+            req.uri().path()
+        );
+        return Err(AppError::Forbidden(
+            "Read-only API keys can only make GET requests".to_string(),
+        ));
     }
+
+    debug!(
+        "API key validated successfully with scope {:?} and locations {:?}",
+        api_key.scope, api_key.locations
+    );
+    req.extensions_mut().insert(api_key);
+    Ok(next.run(req).await)
 }
 
 /// Application state shared across all requests
 #[derive(Clone)]
 pub struct AppState {
-    /// Set of valid API keys
-    pub api_keys: Arc<HashSet<String>>,
+    /// Valid API keys and what each may do
+    pub api_keys: Arc<HashMap<String, ApiKey>>,
     /// Storage interface for orders
     pub store: Arc<OrderStore>,
-    /// Restaurant menu configuration
-    pub menu: Arc<Menu>,
-    /// AI assistant for order management
-    pub assistant: Arc<TokioMutex<OrderAssistant>>,
+    /// Restaurant menu configuration, swapped out when the menu is reloaded
+    pub menu: Arc<ArcSwap<Menu>>,
+    /// AI assistant for order management, swapped out when the menu is reloaded
+    pub assistant: Arc<ArcSwap<Box<dyn AssistantBackend>>>,
+    /// Per-order locks serializing changes to the same order
+    pub order_locks: Arc<OrderLocks>,
+    /// Cumulative OpenAI token usage
+    pub usage_counters: Arc<UsageCounters>,
+    /// Maximum number of characters accepted in a chat input
+    pub max_input_chars: usize,
+    /// Limits applied to every order
+    pub order_limits: OrderLimits,
+    /// How the menu is loaded and validated, kept for reloads
+    pub menu_settings: MenuSettings,
+    /// Seconds a repeated chat input returns the previous response, 0 to process every input
+    pub chat_dedupe_seconds: usize,
+    /// Currency of all prices, e.g. "USD"
+    pub currency: String,
+    /// Whether error bodies carry a machine-readable code, instead of only the message
+    pub structured_errors: bool,
+    /// Per-location order constraints
+    pub locations: Arc<Locations>,
+    /// Webhook notified when an order is completed, if configured
+    pub completion_webhook: Option<Arc<CompletionWebhook>>,
 }
 
 /// Creates and configures the application router with all routes and middleware.
 ///
+/// # Arguments
+/// * `config` - The validated startup configuration
+///
 /// # Returns
 /// * `Router` - Configured router with all routes and middleware attached
-pub async fn create_router() -> Router {
+pub async fn create_router(config: &Config) -> Router {
     info!("Initializing application router");
-    let api_keys: HashSet<String> = std::env::var("API_KEYS")
-        .expect("API_KEYS environment variable is required")
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .collect();
-    debug!("Loaded {} API keys", api_keys.len());
-
-    let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
-    debug!("Connecting to Redis at {}", redis_url);
-    let redis_client = RedisClient::open(redis_url).expect("Failed to connect to Redis");
-    let store = OrderStore::new(redis_client);
+    debug!(
+        "Connecting to Redis at {} (database {})",
+        config.redis.addr, config.redis.redis.db
+    );
+    let redis_client = RedisClient::open(config.redis.clone()).expect("Failed to connect to Redis");
+    let store = OrderStore::new(redis_client, config).expect("Failed to connect to Redis");
+    debug!("Limiting request bodies to {} bytes", config.max_body_bytes);
 
     info!("Loading menu configuration");
-    let menu = Menu::new().expect("Failed to load menu");
+    let menu = Menu::new(&config.menu_settings).expect("Failed to load menu");
 
-    debug!("Initializing OpenAI client");
-    let openai_config = OpenAIConfig::new()
-        .with_api_key(std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY is required"));
-    let openai_client = OpenAIClient::with_config(openai_config);
-    let assistant = OrderAssistant::new(openai_client);
+    let assistant: Box<dyn AssistantBackend> = if config.openai_mock {
+        Box::new(MockAssistant::new(config.order_limits))
+    } else {
+        Box::new(create_openai_assistant(&menu, &store, config).await)
+    };
 
-    let assistant = Arc::new(TokioMutex::new(assistant));
-    {
-        info!("Initializing AI assistant");
-        let mut locked_assistant = assistant.lock().await;
-        locked_assistant
-            .initialize_assistant(&menu)
-            .await
-            .expect("Failed to initialize assistant");
-    }
+    create_router_with_assistant(config, store, menu, assistant)
+}
 
+/// Creates the application router around an already created store, menu and assistant.
+///
+/// # Arguments
+/// * `config` - The validated startup configuration
+/// * `store` - Store the orders are kept in
+/// * `menu` - The restaurant menu
+/// * `assistant` - Assistant handling the chat messages
+///
+/// # Returns
+/// * `Router` - Configured router with all routes and middleware attached
+pub(crate) fn create_router_with_assistant(
+    config: &Config,
+    store: OrderStore,
+    menu: Menu,
+    assistant: Box<dyn AssistantBackend>,
+) -> Router {
+    let max_body_bytes = config.max_body_bytes;
+    let locations = Locations::new(&config.locations_file).expect("Failed to load locations");
     let state = AppState {
-        api_keys: Arc::new(api_keys),
+        api_keys: Arc::new(config.api_keys.clone()),
         store: Arc::new(store),
-        menu: Arc::new(menu),
-        assistant,
+        menu: Arc::new(ArcSwap::from_pointee(menu)),
+        assistant: Arc::new(ArcSwap::from_pointee(assistant)),
+        order_locks: Arc::new(OrderLocks::default()),
+        usage_counters: Arc::new(UsageCounters::default()),
+        max_input_chars: config.max_input_chars,
+        order_limits: config.order_limits,
+        menu_settings: config.menu_settings.clone(),
+        chat_dedupe_seconds: config.chat_dedupe_seconds,
+        currency: config.currency.clone(),
+        structured_errors: config.structured_errors,
+        locations: Arc::new(locations),
+        completion_webhook: config.completion_webhook_url.clone().map(|url| {
+            Arc::new(CompletionWebhook::new(
+                url,
+                config.completion_webhook_secret.clone(),
+                config.completion_webhook_max_retries,
+            ))
+        }),
     };
 
-    Router::new()
+    if config.thread_cleanup {
+        cleanup::spawn_thread_cleanup(
+            config.redis.clone(),
+            state.store.clone(),
+            state.assistant.clone(),
+        );
+    }
+
+    telemetry::install_recorder();
+
+    let router = Router::new()
         .route("/start", post(start_order))
         .route("/chat", post(send_chat_message))
         .route("/order/:order_id", get(get_order))
+        .route("/order/:order_id/complete", post(complete_order))
+        .route("/order/:order_id/undo", post(undo_order_change))
+        .route("/order/:order_id/clear", post(clear_order))
+        .route("/order/:order_id/duplicate", post(duplicate_order))
+        .route("/order/:order_id/items", post(add_order_item))
+        .route(
+            "/order/:order_id/item/:item_id/breakdown",
+            get(get_item_breakdown),
+        )
+        .route("/order/:order_id/audit", get(get_order_audit))
+        .route("/orders/archived", get(list_archived_orders))
+        .route("/orders/export", get(export_orders))
+        .route("/stats", get(get_stats))
+        .route("/menu", get(get_menu))
+        .route("/menu/validate", post(validate_menu_item))
+        .route("/menu/:item_name", get(get_menu_item))
+        .route("/admin/menu/reload", post(reload_menu))
+        .route("/admin/assistant", get(get_assistant_info))
+        .route("/admin/order/:order_id/cancel", post(cancel_order))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             validate_api_key,
         ))
+        // NOTE(dev): Routes added after the auth layer are not authenticated
+        .route("/health", get(health_check))
+        // NOTE(dev): Browsers can't set headers on WebSockets, the socket checks its key itself
+        .route("/ws/chat", get(chat_socket))
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(max_body_bytes));
+
+    // NOTE(dev): With METRICS_PORT set, main serves the metrics router on its own port instead
+    let router = if config.metrics_addr.is_some() {
+        router
+    } else {
+        router.merge(create_metrics_router())
+    };
+    // NOTE(dev): CORS is the outermost layer so preflight requests never reach the API key check
+    // NOTE(dev): The charset layer goes after the merge so unknown routes get a JSON error too
+    router
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            set_json_charset,
+        ))
+        .layer(middleware::from_fn(propagate_request_id))
+        .layer(create_cors_layer(&config.allowed_origins))
         .with_state(state)
 }
 
+/// Runs the request inside a tracing span tagged with its request ID, so every log line of a
+/// request can be correlated. The ID is taken from the `X-Request-Id` header if present,
+/// generated otherwise, and echoed back in the response headers.
+///
+/// # Arguments
+/// * `req` - The incoming HTTP request
+/// * `next` - The next middleware function to call
+///
+/// # Returns
+/// * `Response` - The response with the `X-Request-Id` header set
+async fn propagate_request_id<B>(req: Request<B>, next: Next<B>) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|header| header.to_str().ok())
+        .filter(|id| !id.trim().is_empty())
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        uri = %req.uri()
+    );
+    let mut response = next.run(req).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Content type of every JSON response
+const JSON_CONTENT_TYPE: &str = "application/json; charset=utf-8";
+
+/// Content type of the order export
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Sets the charset on JSON responses, and turns every error response into JSON, so every
+/// response with a body can be parsed as JSON.
+///
+/// Errors produced outside the handlers, like unknown routes, rejected request bodies or bodies
+/// over the size limit, are plain text or empty until they get here. Errors are sent as
+/// `{"error": {"code": ..., "message": ...}}` with `STRUCTURED_ERRORS`, and as the message alone
+/// otherwise. The order export and the metrics keep their own content types.
+///
+/// # Arguments
+/// * `state` - Application state holding the error body format
+/// * `req` - The incoming HTTP request
+/// * `next` - The next middleware function to call
+///
+/// # Returns
+/// * `Response` - The response with `Content-Type: application/json; charset=utf-8` if it is
+///   JSON or an error
+async fn set_json_charset<B>(
+    State(state): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(req).await;
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|header| header.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"));
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        if is_json {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(JSON_CONTENT_TYPE));
+        }
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+    // NOTE(dev): Handler errors already carry the structured body, see `AppError::into_response`
+    let error = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(body) if is_json && body["error"].is_object() => body["error"].clone(),
+        _ => {
+            let message = match String::from_utf8_lossy(&body).trim() {
+                "" => status.canonical_reason().unwrap_or_default().to_string(),
+                message => message.to_string(),
+            };
+            let code = status
+                .canonical_reason()
+                .unwrap_or("ERROR")
+                .to_uppercase()
+                .replace([' ', '-'], "_");
+            serde_json::json!({ "code": code, "message": message })
+        }
+    };
+    // NOTE(dev): The code changes the response shape, so it is opt-in until clients migrate
+    let body = if state.structured_errors {
+        serde_json::json!({ "error": error })
+    } else {
+        error["message"].clone()
+    };
+    let mut response = (status, Json(body)).into_response();
+    for (name, value) in &parts.headers {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+            response.headers_mut().insert(name, value.clone());
+        }
+    }
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(JSON_CONTENT_TYPE));
+    response
+}
+
+/// Creates the CORS layer for the origins in `ALLOWED_ORIGINS`.
+///
+/// No origins are allowed when the variable is unset or empty.
+///
+/// # Arguments
+/// * `allowed_origins` - Origins allowed to call the API from a browser
+///
+/// # Returns
+/// * `CorsLayer` - Layer allowing the configured origins to call the API
+fn create_cors_layer(allowed_origins: &[HeaderValue]) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(allowed_origins.iter().cloned()))
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([
+            CONTENT_TYPE,
+            HeaderName::from_static("x-api-key"),
+            HeaderName::from_static("idempotency-key"),
+            HeaderName::from_static(REQUEST_ID_HEADER),
+        ])
+        .expose_headers([HeaderName::from_static(REQUEST_ID_HEADER)])
+}
+
+/// Creates the router serving Prometheus metrics.
+///
+/// # Returns
+/// * `Router<S>` - Router with the unauthenticated `/metrics` route
+pub fn create_metrics_router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let handle = telemetry::install_recorder();
+    Router::new()
+        .route("/metrics", get(render_metrics))
+        .with_state(handle)
+}
+
 /// Initializes a new order and returns the order ID.
 ///
 /// # Arguments
 /// * `state` - Application state containing the order store
+/// * `api_key` - The caller's API key, checked against the location
+/// * `headers` - Request headers, checked for an `Idempotency-Key`
 /// * `request` - The start order request containing location
 ///
 /// # Returns
-/// * `AppResult<Json<StartOrderResponse>>` - JSON response containing the new order ID
+/// * `AppResult<Json<StartOrderResponse>>` - JSON response containing the new order ID, or
+///   `403` if the API key is bound to other locations
 async fn start_order(
     State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    headers: HeaderMap,
     Json(request): Json<StartOrderRequest>,
 ) -> AppResult<Json<StartOrderResponse>> {
     info!("Starting new order for location: {}", request.location);
+    api_key.check_location(&request.location)?;
     let order_id = Uuid::new_v4().to_string();
     debug!("Generated order ID: {}", order_id);
 
     let mut conn = state.store.get_connection()?;
-    let order = Order::new(order_id.clone());
-    order.save(&mut conn).await?;
+    if let Some(key) = headers
+        .get("idempotency-key")
+        .and_then(|header| header.to_str().ok())
+    {
+        if let Some(existing) = state
+            .store
+            .claim_idempotency_key(&mut conn, key, &order_id)?
+        {
+            info!("Returning existing order {} for idempotency key", existing);
+            return Ok(Json(StartOrderResponse { order_id: existing }));
+        }
+    }
 
+    let mut order = Order::new(order_id.clone(), request.location.clone());
+    state.store.save_order(&mut conn, &mut order).await?;
+
+    counter!(telemetry::ORDERS_STARTED).increment(1);
     info!("Created new order: {}", order_id);
     Ok(Json(StartOrderResponse { order_id }))
 }
+/// Creates the OpenAI assistant from the configuration and makes sure it matches the menu.
+///
+/// # Arguments
+/// * `menu` - The restaurant menu to train the assistant with
+/// * `store` - Storage holding the shared assistant id
+/// * `config` - The startup configuration holding the OpenAI credentials
+///
+/// # Returns
+/// * `OrderAssistant` - The initialized assistant
+async fn create_openai_assistant(
+    menu: &Menu,
+    store: &OrderStore,
+    config: &Config,
+) -> OrderAssistant {
+    debug!("Initializing OpenAI client");
+    let mut openai_config = OpenAIConfig::new().with_api_key(
+        config
+            .openai_api_key
+            .clone()
+            .expect("OPENAI_API_KEY is required"),
+    );
+    // NOTE(dev): Any alternative endpoint must implement the Assistants API
+    if let Some(api_base) = config.openai_api_base.clone() {
+        openai_config = openai_config.with_api_base(api_base);
+    }
+    if let Some(org_id) = config.openai_org_id.clone() {
+        debug!("Using OpenAI organization {}", org_id);
+        openai_config = openai_config.with_org_id(org_id);
+    }
+    info!("Using OpenAI API base {}", openai_config.api_base());
+    let openai_client = OpenAIClient::with_config(openai_config);
+    let mut assistant = OrderAssistant::new(openai_client, config);
+
+    info!("Initializing AI assistant");
+    assistant
+        .load_or_create(menu, store)
+        .await
+        .expect("Failed to initialize assistant");
+    assistant
+}
+
 /// Processes a chat message for an order and returns the updated order state.
 ///
 /// # Arguments
 /// * `state` - Application state containing assistant and stores
+/// * `api_key` - The caller's API key, checked against the location
+/// * `headers` - Request headers, holding the optional `Idempotency-Key`
 /// * `request` - The chat request containing order ID and message
 ///
 /// # Returns
-/// * `AppResult<Json<ChatResponse>>` - JSON response with updated order and chat messages
+/// * `AppResult<Json<ChatResponse>>` - JSON response with updated order and chat messages, the
+///   previous response for a repeated request, or `403` if the API key is bound to other
+///   locations
 async fn send_chat_message(
     State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    headers: HeaderMap,
     Json(request): Json<ChatRequest>,
 ) -> AppResult<Json<ChatResponse>> {
     info!("Processing chat message for order: {}", request.order_id);
     debug!("Chat message: {}", request.input);
 
-    let assistant_lock = state.assistant.lock().await;
-    let res = handle_chat_message(&state.store, &state.menu, &assistant_lock, &request).await?;
+    request.validate(state.max_input_chars)?;
+    let dedupe = chat_dedupe_key(&state, &headers, &request);
+    Ok(Json(
+        process_chat(&state, &api_key, &request, dedupe).await?,
+    ))
+}
+
+/// Runs a validated chat message through the assistant and completes the order if the
+/// customer confirmed it.
+///
+/// Shared by `POST /chat` and the chat WebSocket.
+///
+/// # Arguments
+/// * `state` - Application state containing assistant and stores
+/// * `api_key` - The caller's API key, checked against the locations
+/// * `request` - The chat request
+/// * `dedupe` - Key and lifetime the response is stored under for repeated requests, if any
+///
+/// # Returns
+/// * `AppResult<ChatResponse>` - The updated order and chat messages, or the previous response
+///   for a repeated request
+async fn process_chat(
+    state: &AppState,
+    api_key: &ApiKey,
+    request: &ChatRequest,
+    dedupe: Option<(String, usize)>,
+) -> AppResult<ChatResponse> {
+    api_key.check_location(&request.location)?;
+
+    let _order_lock = state.order_locks.lock(&request.order_id).await?;
+    // NOTE(dev): The order's own location is checked too, otherwise a key bound to one store
+    //            could chat on another store's order by naming its own location
+    if api_key.locations.is_some() {
+        let mut conn = state.store.get_connection()?;
+        let order = state.store.get_order(&mut conn, &request.order_id)?;
+        api_key.check_order_location(&order)?;
+    }
+    // NOTE(dev): Checked under the order lock, so a retry racing the original waits for its response
+    if let Some((key, _)) = &dedupe {
+        let mut conn = state.store.get_connection()?;
+        if let Some(response) = state.store.get_cached_response(&mut conn, key)? {
+            info!(
+                "Returning previous response for repeated chat on order {}",
+                request.order_id
+            );
+            return Ok(serde_json::from_str(&response)?);
+        }
+    }
+    counter!(telemetry::CHAT_MESSAGES).increment(1);
+    // NOTE(dev): Hold on to the current menu and assistant so a reload can't change them mid-chat
+    let menu = state.menu.load_full();
+    let assistant = state.assistant.load_full();
+    let started = Instant::now();
+    let res = handle_chat_message(
+        &state.store,
+        &menu,
+        &**assistant,
+        &state.usage_counters,
+        &state.order_limits,
+        request,
+    )
+    .await;
+    histogram!(telemetry::CHAT_LATENCY).record(started.elapsed().as_secs_f64());
+    let res = res?;
+
+    if res.status == OrderStatus::Completed {
+        info!("Order {} was confirmed through chat", request.order_id);
+        state
+            .locations
+            .check_total(res.location.as_deref(), res.total())?;
+        finish_order(state, res.clone())?;
+    }
 
     debug!(
         "Chat response generated with {} messages",
         res.messages.len()
     );
-    Ok(Json(ChatResponse {
-        order_id: request.order_id,
+    let response = ChatResponse {
+        order_id: request.order_id.clone(),
         order: res
             .order
             .iter()
             .map(|item| (*item).clone().into())
             .collect(),
+        total: res.total(),
+        carts: res.carts(),
+        needs_human: res.needs_human(state.order_limits.max_invalid_turns),
+        status: res.status,
+        awaiting_confirmation: res.awaiting_confirmation,
+        incomplete: res.incomplete_reply,
+        suggestions: res.suggestions,
+        summary: res.summary,
         messages: res.messages,
-    }))
+        currency: state.currency.clone(),
+    };
+    if let Some((key, ttl_seconds)) = dedupe {
+        let mut conn = state.store.get_connection()?;
+        state.store.cache_response(
+            &mut conn,
+            &key,
+            &serde_json::to_string(&response)?,
+            ttl_seconds,
+        )?;
+    }
+    Ok(response)
 }
 
-/// Retrieves an existing order by ID.
+/// How long a chat WebSocket opened without `apiKey` waits for the key in its first message
+const CHAT_SOCKET_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Opens a chat WebSocket for an order.
+///
+/// The socket is authenticated with the `apiKey` query parameter, or with a first message of
+/// `{"apiKey": "..."}` for clients that can't put the key in the URL. Every following text
+/// message is a chat input, answered with a frame in the shape of the `POST /chat` response.
 ///
 /// # Arguments
-/// * `state` - Application state containing the order store
-/// * `order_id` - The ID of the order to retrieve
+/// * `state` - Application state containing assistant and stores
+/// * `query` - The order, its location and optionally the API key
+/// * `ws` - The WebSocket upgrade
 ///
 /// # Returns
-/// * `AppResult<Json<GetOrderResponse>>` - JSON response containing the order details
-async fn get_order(
+/// * `Response` - The protocol switch
+async fn chat_socket(
     State(state): State<AppState>,
-    Path(order_id): Path<String>,
-) -> AppResult<Json<GetOrderResponse>> {
-    info!("Retrieving order: {}", order_id);
-    let mut conn = state.store.get_connection()?;
-    let order = Order::get(&mut conn, &order_id)?;
+    Query(query): Query<ChatSocketQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    info!("Opening chat socket for order: {}", query.order_id);
+    // NOTE(dev): Same bound as MAX_BODY_BYTES puts on POST /chat, inputs are checked again per message
+    let max_message_size = state.max_input_chars * 4 + 1024;
+    ws.max_message_size(max_message_size)
+        .on_upgrade(move |socket| run_chat_socket(state, query, socket))
+}
 
-    debug!("Retrieved order with {} items", order.order.len());
-    Ok(Json(GetOrderResponse {
-        order: order
-            .order
-            .iter()
-            .map(|item| (*item).clone().into())
-            .collect(),
-        messages: order.messages,
-    }))
+/// Serves a chat WebSocket until the client goes away.
+///
+/// Orders are saved at the end of every turn, so a client that disconnects loses nothing. A
+/// turn already running when the client disconnects is finished and saved before the socket
+/// is dropped.
+///
+/// # Arguments
+/// * `state` - Application state containing assistant and stores
+/// * `query` - The order, its location and optionally the API key
+/// * `socket` - The upgraded connection
+async fn run_chat_socket(state: AppState, query: ChatSocketQuery, mut socket: WebSocket) {
+    let api_key = match authenticate_chat_socket(&state, &query, &mut socket).await {
+        Ok(api_key) => api_key,
+        Err(e) => {
+            info!("Closing chat socket for order {}: {:?}", query.order_id, e);
+            let (_, reason) = e.into_parts();
+            let _ = socket
+                .send(Message::Close(Some(CloseFrame {
+                    code: close_code::POLICY,
+                    reason: reason.into(),
+                })))
+                .await;
+            return;
+        }
+    };
+    debug!("Chat socket for order {} authenticated", query.order_id);
+
+    loop {
+        let text = match socket.recv().await {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | None => break,
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => {
+                info!("Chat socket for order {} failed: {}", query.order_id, e);
+                break;
+            }
+        };
+        let frame = match chat_socket_turn(&state, &api_key, &query, &text).await {
+            Ok(response) => serde_json::to_string(&response)
+                .unwrap_or_else(|e| chat_socket_error(AppError::JsonSerializationError(e))),
+            Err(e) => chat_socket_error(e),
+        };
+        if socket.send(Message::Text(frame)).await.is_err() {
+            info!(
+                "Client of order {} disconnected before the reply was sent",
+                query.order_id
+            );
+            break;
+        }
+    }
+    info!("Chat socket for order {} closed", query.order_id);
+}
+
+/// Finds the API key of a chat WebSocket in the query or the first message.
+///
+/// # Arguments
+/// * `state` - Application state containing the API keys
+/// * `query` - The socket's query parameters
+/// * `socket` - The upgraded connection, read for the key if the query has none
+///
+/// # Returns
+/// * `AppResult<ApiKey>` - The key, or `Forbidden` if it is missing, unknown or read-only
+async fn authenticate_chat_socket(
+    state: &AppState,
+    query: &ChatSocketQuery,
+    socket: &mut WebSocket,
+) -> AppResult<ApiKey> {
+    let token = match &query.api_key {
+        Some(token) => token.clone(),
+        None => match tokio::time::timeout(CHAT_SOCKET_AUTH_TIMEOUT, socket.recv()).await {
+            Ok(Some(Ok(Message::Text(text)))) => {
+                serde_json::from_str::<ChatSocketAuth>(&text)
+                    .map_err(|_| {
+                        AppError::Forbidden(
+                            "The first message must be {\"apiKey\": \"...\"}".to_string(),
+                        )
+                    })?
+                    .api_key
+            }
+            _ => {
+                return Err(AppError::Forbidden(
+                    "No API key received before the timeout".to_string(),
+                ))
+            }
+        },
+    };
+    let api_key = state
+        .api_keys
+        .get(token.trim_start_matches("Bearer ").trim())
+        .cloned()
+        .ok_or_else(|| AppError::Forbidden("Invalid API key".to_string()))?;
+    // NOTE(dev): Chatting changes the order, which read-only keys may only do through GET
+    api_key.check_read_write()?;
+    api_key.check_location(&query.location)?;
+    Ok(api_key)
+}
+
+/// Handles one message received on a chat WebSocket.
+///
+/// # Arguments
+/// * `state` - Application state containing assistant and stores
+/// * `api_key` - The socket's API key
+/// * `query` - The order and its location
+/// * `text` - The message, `{"input": "...", "language": "es"}`
+///
+/// # Returns
+/// * `AppResult<ChatResponse>` - The updated order and chat messages
+async fn chat_socket_turn(
+    state: &AppState,
+    api_key: &ApiKey,
+    query: &ChatSocketQuery,
+    text: &str,
+) -> AppResult<ChatResponse> {
+    let message: ChatSocketMessage = serde_json::from_str(text)
+        .map_err(|e| AppError::InvalidInput(format!("Invalid chat message: {}", e)))?;
+    let request = ChatRequest {
+        order_id: query.order_id.clone(),
+        input: message.input,
+        location: query.location.clone(),
+        language: message.language,
+    };
+    info!(
+        "Processing socket chat message for order: {}",
+        request.order_id
+    );
+    request.validate(state.max_input_chars)?;
+    process_chat(state, api_key, &request, None).await
+}
+
+/// Builds the frame reporting an error on a chat WebSocket, which stays open afterwards.
+///
+/// # Arguments
+/// * `error` - The error of the turn
+///
+/// # Returns
+/// * `String` - `{"error": {"code": "...", "message": "..."}}`
+fn chat_socket_error(error: AppError) -> String {
+    info!("Chat socket turn failed: {:?}", error);
+    let code = error.code();
+    let (_, message) = error.into_parts();
+    serde_json::json!({
+        "error": {
+            "code": code,
+            "message": message,
+        }
+    })
+    .to_string()
+}
+
+/// Redis key and lifetime of the stored response of a chat request.
+///
+/// Requests with an `Idempotency-Key` header are keyed by it for 24 hours, others by a hash of
+/// their order and input for `CHAT_DEDUPE_SECONDS`.
+///
+/// # Arguments
+/// * `state` - Application state holding the dedupe window
+/// * `headers` - The request headers
+/// * `request` - The chat request
+///
+/// # Returns
+/// * `Option<(String, usize)>` - The key and its lifetime in seconds, or None if repeated
+///   messages are processed again
+fn chat_dedupe_key(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: &ChatRequest,
+) -> Option<(String, usize)> {
+    if let Some(key) = headers
+        .get("idempotency-key")
+        .and_then(|header| header.to_str().ok())
+    {
+        return Some((
+            format!("chat:idempotency:{}:{}", request.order_id, key),
+            IDEMPOTENCY_KEY_TTL_SECONDS,
+        ));
+    }
+    let ttl_seconds = state.chat_dedupe_seconds;
+    if ttl_seconds == 0 {
+        return None;
+    }
+    Some((
+        chat_dedupe_hash(&request.order_id, &request.input),
+        ttl_seconds,
+    ))
+}
+
+/// Redis key of the stored response of a chat input sent without an `Idempotency-Key`.
+///
+/// # Arguments
+/// * `order_id` - The ID of the order
+/// * `input` - The chat input
+///
+/// # Returns
+/// * `String` - The key, the same for the same input on the same order
+fn chat_dedupe_hash(order_id: &str, input: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(input.as_bytes());
+    format!(
+        "chat:dedupe:{}:{}",
+        order_id,
+        hex::encode(hasher.finalize())
+    )
+}
+
+/// Retrieves an existing order by ID.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store
+/// * `api_key` - The caller's API key, checked against the order's location
+/// * `order_id` - The ID of the order to retrieve
+/// * `query` - Where to read the chat messages from
+///
+/// # Returns
+/// * `AppResult<Json<GetOrderResponse>>` - JSON response containing the order details, or `403`
+///   if the API key is bound to other locations
+async fn get_order(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Path(order_id): Path<String>,
+    Query(query): Query<GetOrderQuery>,
+) -> AppResult<Json<GetOrderResponse>> {
+    info!("Retrieving order: {}", order_id);
+    let mut conn = state.store.get_connection()?;
+    let mut order = state.store.get_order(&mut conn, &order_id)?;
+    api_key.check_order_location(&order)?;
+
+    if query.source == MessageSource::Thread {
+        let thread_id = order.thread_id.clone().ok_or_else(|| {
+            AppError::InvalidInput(format!(
+                "Order {} has no OpenAI thread yet, send a chat message first",
+                order_id
+            ))
+        })?;
+        info!(
+            "Reading messages of order {} from thread {}",
+            order_id, thread_id
+        );
+        order.messages = state.assistant.load().thread_messages(&thread_id).await?;
+        order.truncated_messages = 0;
+    }
+
+    debug!("Retrieved order with {} items", order.order.len());
+    Ok(Json(GetOrderResponse::new(order, &state.currency)))
+}
+
+/// Breaks the price of an item in an order down by option value, e.g. for receipts.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store and menu
+/// * `api_key` - The caller's API key, checked against the order's location
+/// * `order_id` - The ID of the order
+/// * `item_id` - The ID of the item in the order
+///
+/// # Returns
+/// * `AppResult<Json<PriceBreakdownResponse>>` - JSON response with the price lines, `404`
+///   if the item is not in the order, or `403` if the API key is bound to other locations
+async fn get_item_breakdown(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Path((order_id, item_id)): Path<(String, String)>,
+) -> AppResult<Json<PriceBreakdownResponse>> {
+    info!(
+        "Breaking down price of item {} in order {}",
+        item_id, order_id
+    );
+    let mut conn = state.store.get_connection()?;
+    let order = state.store.get_order(&mut conn, &order_id)?;
+    api_key.check_order_location(&order)?;
+    let item = order
+        .order
+        .iter()
+        .find(|item| item.id == item_id)
+        .ok_or_else(|| AppError::OrderItemNotFound(item_id.clone()))?;
+
+    let lines = state.menu.load().price_breakdown(item).ok_or_else(|| {
+        AppError::InvalidInput(format!(
+            "Item {} can't be priced from the menu",
+            item.item_name
+        ))
+    })?;
+    let lines: Vec<PriceLine> = lines
+        .into_iter()
+        .map(|(label, price)| PriceLine { label, price })
+        .collect();
+    Ok(Json(PriceBreakdownResponse {
+        item_id,
+        item_name: item.item_name.clone(),
+        total: lines.iter().map(|line| line.price).sum(),
+        lines,
+        currency: state.currency.clone(),
+    }))
+}
+
+/// Completes an order once every item is valid, making it final.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store
+/// * `api_key` - The caller's API key, checked against the order's location
+/// * `order_id` - The ID of the order to complete
+///
+/// # Returns
+/// * `AppResult<Json<CompleteOrderResponse>>` - JSON response containing the completed order,
+///   or `403` if the API key is bound to other locations
+async fn complete_order(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Path(order_id): Path<String>,
+) -> AppResult<Json<CompleteOrderResponse>> {
+    info!("Completing order: {}", order_id);
+    let _order_lock = state.order_locks.lock(&order_id).await?;
+    let mut conn = state.store.get_connection()?;
+    let mut order = state.store.get_order(&mut conn, &order_id)?;
+    api_key.check_order_location(&order)?;
+    order.ensure_active()?;
+    state
+        .locations
+        .check_total(order.location.as_deref(), order.total())?;
+    order.complete()?;
+    Ok(Json(finish_order(&state, order)?))
+}
+
+/// Archives a completed order and notifies the completion webhook.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store and webhook
+/// * `order` - The completed order
+///
+/// # Returns
+/// * `AppResult<CompleteOrderResponse>` - The completed order as sent to the webhook
+fn finish_order(state: &AppState, mut order: Order) -> AppResult<CompleteOrderResponse> {
+    // NOTE(dev): The ETA is fixed at completion so later menu changes don't move archived orders
+    order.prep_seconds = Some(order.eta(
+        &state.menu.load(),
+        state.order_limits.default_prep_seconds,
+        state.order_limits.eta_mode,
+    ));
+    let mut conn = state.store.get_connection()?;
+    order.archive(&mut conn)?;
+
+    info!("Order {} completed", order.order_id);
+    let response: CompleteOrderResponse = (order, state.currency.as_str()).into();
+    if let Some(webhook) = &state.completion_webhook {
+        webhook.notify(&response.order_id, &response);
+    }
+    Ok(response)
+}
+
+/// Returns the audit trail of every function call made on an order.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store
+/// * `api_key` - The caller's API key, checked against the order's location
+/// * `order_id` - The ID of the order
+///
+/// # Returns
+/// * `AppResult<Json<AuditResponse>>` - JSON response with the audit entries, `404` if the
+///   order does not exist, or `403` if the API key is bound to other locations
+async fn get_order_audit(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Path(order_id): Path<String>,
+) -> AppResult<Json<AuditResponse>> {
+    info!("Retrieving audit trail of order: {}", order_id);
+    let mut conn = state.store.get_connection()?;
+    let order = state.store.get_order(&mut conn, &order_id)?;
+    api_key.check_order_location(&order)?;
+    let entries = Order::audit_log(&mut conn, &order_id)?;
+    debug!("Order {} has {} audit entries", order_id, entries.len());
+    Ok(Json(AuditResponse { order_id, entries }))
+}
+
+/// Lists completed orders from the archive.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store
+/// * `api_key` - The caller's API key, which must not be bound to locations
+/// * `query` - Pagination of the orders
+///
+/// # Returns
+/// * `AppResult<Json<ArchivedOrdersResponse>>` - JSON response with the archived orders, or
+///   `403` if the API key is bound to locations
+async fn list_archived_orders(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Query(query): Query<ArchivedOrdersQuery>,
+) -> AppResult<Json<ArchivedOrdersResponse>> {
+    api_key.check_all_locations()?;
+    let limit = query.limit.unwrap_or(50);
+    info!(
+        "Listing {} archived orders from offset {}",
+        limit, query.offset
+    );
+    let mut conn = state.store.get_connection()?;
+    let orders = Order::archived(&mut conn, query.offset, limit)?;
+    Ok(Json(ArchivedOrdersResponse {
+        orders: orders
+            .into_iter()
+            .map(|order| (order, state.currency.as_str()).into())
+            .collect(),
+    }))
+}
+
+/// Streams every stored order as newline-delimited JSON.
+///
+/// Orders are read a SCAN page at a time as the body is sent, so the export never holds more
+/// than one page in memory.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store
+/// * `api_key` - The caller's API key, which must not be bound to locations
+/// * `query` - Optional lower bound on the time of the last change
+///
+/// # Returns
+/// * `AppResult<Response>` - `application/x-ndjson` response with one order per line, or `403`
+///   if the API key is bound to locations
+async fn export_orders(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Query(query): Query<ExportOrdersQuery>,
+) -> AppResult<Response> {
+    api_key.check_all_locations()?;
+    info!("Exporting orders since {:?}", query.since);
+    let conn = state.store.get_connection()?;
+    let since = query.since;
+    let pages = futures::stream::unfold(Some((conn, OrderScan::new())), move |scan| async move {
+        let (mut conn, mut scan) = scan?;
+        match scan.next_page(&mut conn) {
+            Ok(Some(orders)) => {
+                let mut chunk = String::new();
+                for order in orders.iter().filter(|order| {
+                    // NOTE(dev): Orders stored before updatedAt existed fall back to their completion
+                    since.is_none_or(|since| {
+                        order
+                            .updated_at
+                            .or(order.completed_at)
+                            .is_some_and(|at| at >= since)
+                    })
+                }) {
+                    match serde_json::to_string(order) {
+                        Ok(line) => {
+                            chunk.push_str(&line);
+                            chunk.push('\n');
+                        }
+                        Err(e) => warn!("Skipping order {} in export: {}", order.order_id, e),
+                    }
+                }
+                Some((Ok(chunk), Some((conn, scan))))
+            }
+            Ok(None) => {
+                debug!("Order export finished");
+                None
+            }
+            // NOTE(dev): The status line is already sent, so ending the body early is the
+            //            only way left to signal the failure
+            Err(e) => {
+                error!("Order export failed: {:?}", e);
+                Some((Err(std::io::Error::other("order export failed")), None))
+            }
+        }
+    });
+    Ok((
+        [(CONTENT_TYPE, HeaderValue::from_static(NDJSON_CONTENT_TYPE))],
+        StreamBody::new(pages),
+    )
+        .into_response())
+}
+
+/// Computes aggregate statistics over every stored order.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store
+/// * `api_key` - The caller's API key, which must not be bound to locations
+/// * `query` - Number of top items to return
+///
+/// # Returns
+/// * `AppResult<Json<StatsResponse>>` - JSON response with the order counts and top items, or
+///   `403` if the API key is bound to locations
+async fn get_stats(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Query(query): Query<StatsQuery>,
+) -> AppResult<Json<StatsResponse>> {
+    api_key.check_all_locations()?;
+    info!("Computing order statistics");
+    let mut conn = state.store.get_connection()?;
+    let stats = Order::stats(&mut conn)?;
+    Ok(Json(StatsResponse {
+        active_orders: stats.active,
+        completed_orders: stats.completed,
+        cancelled_orders: stats.cancelled,
+        average_items_per_order: stats.average_items(),
+        top_items: stats
+            .top_items(query.top.unwrap_or(10))
+            .into_iter()
+            .map(|(item_name, count)| ItemCount { item_name, count })
+            .collect(),
+    }))
+}
+
+/// Returns the menu the assistant is using.
+///
+/// # Arguments
+/// * `state` - Application state containing the menu
+/// * `query` - Optional tag to filter the items by
+///
+/// # Returns
+/// * `Json<Menu>` - JSON response containing the full or filtered menu
+async fn get_menu(State(state): State<AppState>, Query(query): Query<MenuQuery>) -> Json<Menu> {
+    match query.tag {
+        Some(tag) => {
+            info!("Retrieving menu items tagged {}", tag);
+            Json(state.menu.load().filter_by_tag(&tag))
+        }
+        None => {
+            info!("Retrieving menu");
+            Json((**state.menu.load()).clone())
+        }
+    }
+}
+
+/// Returns a single item from the menu.
+///
+/// # Arguments
+/// * `state` - Application state containing the menu
+/// * `item_name` - The name of the menu item
+///
+/// # Returns
+/// * `AppResult<Json<MenuItem>>` - JSON response containing the menu item
+async fn get_menu_item(
+    State(state): State<AppState>,
+    Path(item_name): Path<String>,
+) -> AppResult<Json<MenuItem>> {
+    info!("Retrieving menu item: {}", item_name);
+    let menu = state.menu.load();
+    let item = menu
+        .get_item(&item_name)
+        .ok_or_else(|| AppError::MenuItemNotFound(item_name.clone()))?;
+    Ok(Json(item.clone()))
+}
+
+/// Validates an item against the menu without adding it to an order or calling the assistant.
+///
+/// # Arguments
+/// * `state` - Application state containing the menu
+/// * `request` - The item to validate
+///
+/// # Returns
+/// * `AppResult<Json<ValidateItemResponse>>` - JSON response with the item status and price
+async fn validate_menu_item(
+    State(state): State<AppState>,
+    Json(request): Json<ValidateItemRequest>,
+) -> AppResult<Json<ValidateItemResponse>> {
+    info!("Validating menu item: {}", request.item_name);
+    let mut item: OrderItem = request.into();
+    let menu = state.menu.load();
+    menu.canonicalize_item(&mut item);
+    let item_status = menu.validate_item(&item)?;
+    let price = menu.price_item(&item);
+    debug!(
+        "Item {} is {:?} at {:?}",
+        item.item_name, item_status, price
+    );
+    Ok(Json(ValidateItemResponse {
+        item_status: item_status.into(),
+        price,
+        currency: state.currency.clone(),
+    }))
+}
+
+/// Re-reads the menu file and updates the assistant to match it, without a restart.
+///
+/// Chats already in progress keep using the menu and assistant they started with.
+///
+/// # Arguments
+/// * `state` - Application state containing the menu and assistant
+/// * `api_key` - The caller's API key, which must not be bound to locations
+///
+/// # Returns
+/// * `AppResult<Json<ReloadMenuResponse>>` - JSON response with the number of items loaded,
+///   `400` if the new menu is invalid, or `403` if the API key is bound to locations
+async fn reload_menu(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+) -> AppResult<Json<ReloadMenuResponse>> {
+    api_key.check_all_locations()?;
+    info!("Reloading menu");
+    let menu = Menu::new(&state.menu_settings).map_err(|e| match e {
+        AppError::JsonSerializationError(e) => {
+            AppError::InvalidInput(format!("Invalid menu: {}", e))
+        }
+        AppError::IoError(e) => AppError::InvalidInput(format!("Could not read menu: {}", e)),
+        AppError::MenuFileMissing(path) => {
+            AppError::InvalidInput(format!("Menu file {} does not exist", path))
+        }
+        e => e,
+    })?;
+
+    let assistant = state.assistant.load().reload(&menu, &state.store).await?;
+
+    let item_count = menu.items.len();
+    state.menu.store(Arc::new(menu));
+    state.assistant.store(Arc::new(assistant));
+    info!("Reloaded menu with {} items", item_count);
+    Ok(Json(ReloadMenuResponse { item_count }))
+}
+
+/// Describes the assistant currently in use.
+///
+/// # Arguments
+/// * `state` - Application state containing the assistant
+/// * `api_key` - The caller's API key, which must be read-write and not bound to locations
+///
+/// # Returns
+/// * `AppResult<Json<AssistantInfo>>` - JSON response with the assistant id, model and menu
+///   hash, `403` for read-only or location-bound keys, or `503` if the assistant isn't
+///   initialized
+async fn get_assistant_info(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+) -> AppResult<Json<AssistantInfo>> {
+    api_key.check_read_write()?;
+    api_key.check_all_locations()?;
+    info!("Describing the assistant in use");
+    let info = state.assistant.load().info().ok_or_else(|| {
+        AppError::AssistantUnavailable("Assistant is not initialized".to_string())
+    })?;
+    debug!(
+        "Assistant {} runs on {} with menu hash {:?}",
+        info.assistant_id, info.model, info.menu_hash
+    );
+    Ok(Json(info))
+}
+
+/// Force-cancels an order stuck mid-conversation, so the customer can no longer change it.
+///
+/// Any run still active on the order's thread is cancelled first, then the order is marked
+/// cancelled.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store and assistant
+/// * `api_key` - The caller's API key, which must be read-write
+/// * `order_id` - The ID of the order to cancel
+///
+/// # Returns
+/// * `AppResult<Json<GetOrderResponse>>` - JSON response containing the cancelled order, or the
+///   stored order as is if it was already completed or cancelled, `403` for read-only keys, or
+///   `404` if the order does not exist
+async fn cancel_order(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Path(order_id): Path<String>,
+) -> AppResult<Json<GetOrderResponse>> {
+    api_key.check_read_write()?;
+    info!("Force-cancelling order: {}", order_id);
+    let order = {
+        let mut conn = state.store.get_connection()?;
+        state.store.get_order(&mut conn, &order_id)?
+    };
+    api_key.check_order_location(&order)?;
+    if order.status != OrderStatus::Active {
+        info!("Order {} is already {}", order_id, order.status);
+        return Ok(Json(GetOrderResponse::new(order, &state.currency)));
+    }
+
+    // NOTE(dev): A stuck chat holds the order lock until its run ends, so the run is cancelled
+    //            before waiting on the lock
+    if let Some(thread_id) = &order.thread_id {
+        match state.assistant.load().cancel_active_run(thread_id).await {
+            Ok(Some(run_id)) => info!("Cancelled run {} of order {}", run_id, order_id),
+            Ok(None) => debug!("Order {} has no active run", order_id),
+            Err(e) => warn!("Could not cancel the run of order {}: {:?}", order_id, e),
+        }
+    }
+
+    let _order_lock = state.order_locks.lock(&order_id).await?;
+    let mut conn = state.store.get_connection()?;
+    let mut order = state.store.get_order(&mut conn, &order_id)?;
+    // NOTE(dev): The order may have been completed while the run was being cancelled
+    if order.status != OrderStatus::Active {
+        info!("Order {} is already {}", order_id, order.status);
+        return Ok(Json(GetOrderResponse::new(order, &state.currency)));
+    }
+    order.set_status(OrderStatus::Cancelled);
+    order.awaiting_confirmation = false;
+    state.store.save_order(&mut conn, &mut order).await?;
+
+    info!("Order {} cancelled", order_id);
+    Ok(Json(GetOrderResponse::new(order, &state.currency)))
+}
+
+/// Reports whether Redis and the OpenAI assistant are available.
+///
+/// # Arguments
+/// * `state` - Application state containing the store and assistant
+///
+/// # Returns
+/// * `(StatusCode, Json<HealthResponse>)` - 200 if all dependencies are up, 503 otherwise
+async fn health_check(State(state): State<AppState>) -> (StatusCode, Json<HealthResponse>) {
+    debug!("Running health check");
+    let redis = match state.store.ping() {
+        Ok(()) => DependencyStatus::Ok,
+        Err(e) => {
+            info!("Health check failed to reach Redis: {:?}", e);
+            DependencyStatus::Down
+        }
+    };
+    let openai = if state.assistant.load().is_initialized() {
+        DependencyStatus::Ok
+    } else {
+        DependencyStatus::Down
+    };
+
+    let status = if redis == DependencyStatus::Ok && openai == DependencyStatus::Ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(HealthResponse { redis, openai }))
+}
+
+/// Renders the current metrics in the Prometheus text format.
+///
+/// # Arguments
+/// * `handle` - Handle to the installed Prometheus recorder
+///
+/// # Returns
+/// * `String` - The rendered metrics
+async fn render_metrics(State(handle): State<PrometheusHandle>) -> String {
+    debug!("Rendering metrics");
+    handle.render()
+}
+
+/// Adds a known item to an order without calling the assistant.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store and menu
+/// * `api_key` - The caller's API key, checked against the order's location
+/// * `order_id` - The ID of the order to add to
+/// * `request` - The item to add, in the same shape as the assistant's `add_item` arguments
+///
+/// # Returns
+/// * `AppResult<Json<GetOrderResponse>>` - JSON response containing the updated order, or `403`
+///   if the API key is bound to other locations
+async fn add_order_item(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Path(order_id): Path<String>,
+    Json(request): Json<AddItemArgs>,
+) -> AppResult<Json<GetOrderResponse>> {
+    info!("Adding item directly to order: {}", order_id);
+    let _order_lock = state.order_locks.lock(&order_id).await?;
+    if api_key.locations.is_some() {
+        let mut conn = state.store.get_connection()?;
+        let order = state.store.get_order(&mut conn, &order_id)?;
+        api_key.check_order_location(&order)?;
+    }
+    let menu = state.menu.load_full();
+    let order =
+        handle_item_selection(&state.store, &menu, &state.order_limits, &order_id, request).await?;
+    Ok(Json(GetOrderResponse::new(order, &state.currency)))
+}
+
+/// Removes every item from an order. The clear can be reverted with undo.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store
+/// * `api_key` - The caller's API key, checked against the order's location
+/// * `order_id` - The ID of the order to clear
+///
+/// # Returns
+/// * `AppResult<Json<GetOrderResponse>>` - JSON response containing the emptied order, or `403`
+///   if the API key is bound to other locations
+async fn clear_order(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Path(order_id): Path<String>,
+) -> AppResult<Json<GetOrderResponse>> {
+    info!("Clearing order: {}", order_id);
+    let _order_lock = state.order_locks.lock(&order_id).await?;
+    let mut conn = state.store.get_connection()?;
+    let mut order = state.store.get_order(&mut conn, &order_id)?;
+    api_key.check_order_location(&order)?;
+    order.ensure_active()?;
+    if !order.order.is_empty() {
+        order.snapshot(order.order.clone());
+    }
+    order.clear();
+    state.store.save_order(&mut conn, &mut order).await?;
+
+    Ok(Json(GetOrderResponse::new(order, &state.currency)))
+}
+
+/// Starts a new order with copies of an existing order's items, e.g. for "same as last time".
+///
+/// The items are validated again against the current menu, so ones that changed since are
+/// flagged. The source order can be active, completed or archived.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store and menu
+/// * `api_key` - The caller's API key, checked against the order's location
+/// * `order_id` - The ID of the order to copy
+///
+/// # Returns
+/// * `AppResult<Json<StartOrderResponse>>` - JSON response containing the new order ID
+async fn duplicate_order(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Path(order_id): Path<String>,
+) -> AppResult<Json<StartOrderResponse>> {
+    info!("Duplicating order: {}", order_id);
+    let mut conn = state.store.get_connection()?;
+    let source = state.store.get_order(&mut conn, &order_id)?;
+    api_key.check_order_location(&source)?;
+
+    let mut order = source.duplicate(Uuid::new_v4().to_string());
+    validate_items(&mut order, &state.menu.load())?;
+    state.store.save_order(&mut conn, &mut order).await?;
+
+    counter!(telemetry::ORDERS_STARTED).increment(1);
+    info!("Created order {} from order {}", order.order_id, order_id);
+    Ok(Json(StartOrderResponse {
+        order_id: order.order_id,
+    }))
+}
+
+/// Reverts the most recent change to an order's items.
+///
+/// # Arguments
+/// * `state` - Application state containing the order store
+/// * `api_key` - The caller's API key, checked against the order's location
+/// * `order_id` - The ID of the order to revert
+///
+/// # Returns
+/// * `AppResult<Json<GetOrderResponse>>` - JSON response containing the restored order, or
+///   `403` if the API key is bound to other locations
+async fn undo_order_change(
+    State(state): State<AppState>,
+    Extension(api_key): Extension<ApiKey>,
+    Path(order_id): Path<String>,
+) -> AppResult<Json<GetOrderResponse>> {
+    info!("Undoing last change to order: {}", order_id);
+    let _order_lock = state.order_locks.lock(&order_id).await?;
+    let mut conn = state.store.get_connection()?;
+    let mut order = state.store.get_order(&mut conn, &order_id)?;
+    api_key.check_order_location(&order)?;
+    order.ensure_active()?;
+    order.undo()?;
+    state.store.save_order(&mut conn, &mut order).await?;
+
+    debug!("Order {} restored to {} items", order_id, order.order.len());
+    Ok(Json(GetOrderResponse::new(order, &state.currency)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        add_args, app, app_with_mock, chat, request, send, serve, start, API_KEY,
+    };
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[test]
+    fn dedupe_keys_are_scoped_to_the_order_and_input() {
+        let key = chat_dedupe_hash("order-1", "add a coke");
+        assert!(key.starts_with("chat:dedupe:order-1:"));
+        assert_eq!(key, chat_dedupe_hash("order-1", "add a coke"));
+        assert_ne!(key, chat_dedupe_hash("order-2", "add a coke"));
+        assert_ne!(key, chat_dedupe_hash("order-1", "add a sprite"));
+    }
+
+    #[tokio::test]
+    async fn a_retried_chat_changes_the_order_once() {
+        let app = app(&[("CHAT_DEDUPE_SECONDS", "30")]).await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+
+        let (status, first) = chat(&app, &order_id, "add Apple Pie").await;
+        assert_eq!(status, StatusCode::OK);
+        // NOTE(dev): Sent after the first one finished, as a client retrying a lost response would
+        let (status, retried) = chat(&app, &order_id, "add Apple Pie").await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(retried, first);
+        let uri = format!("/order/{}", order_id);
+        let (_, _, order) = send(&app, request(Method::GET, &uri, API_KEY, None)).await;
+        assert_eq!(order["order"].as_array().unwrap().len(), 1);
+        assert_eq!(order["messages"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn successful_responses_are_json_with_a_charset() {
+        let app = app(&[]).await;
+        let (status, headers, body) =
+            send(&app, request(Method::GET, "/menu", API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[CONTENT_TYPE], JSON_CONTENT_TYPE);
+        assert!(body["items"].is_array());
+    }
+
+    #[tokio::test]
+    async fn not_found_errors_are_json_with_a_charset() {
+        let app = app(&[]).await;
+        let (status, headers, body) =
+            send(&app, request(Method::GET, "/menu/Nothing", API_KEY, None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers[CONTENT_TYPE], JSON_CONTENT_TYPE);
+        assert_eq!(body["error"]["code"], "MENU_ITEM_NOT_FOUND");
+
+        let (status, headers, body) =
+            send(&app, request(Method::GET, "/nowhere", API_KEY, None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers[CONTENT_TYPE], JSON_CONTENT_TYPE);
+        assert_eq!(body["error"]["code"], "NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn errors_are_the_message_alone_without_structured_errors() {
+        let app = app(&[("STRUCTURED_ERRORS", "false")]).await;
+        for uri in ["/menu/Nothing", "/nowhere"] {
+            let (status, headers, body) =
+                send(&app, request(Method::GET, uri, API_KEY, None)).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+            assert_eq!(headers[CONTENT_TYPE], JSON_CONTENT_TYPE);
+            assert!(body.is_string(), "{}: {}", uri, body);
+        }
+        let (_, _, body) = send(&app, request(Method::GET, "/menu/Nothing", API_KEY, None)).await;
+        assert!(body.as_str().unwrap().contains("Nothing"), "{}", body);
+
+        let (status, _, body) = send(&app, request(Method::GET, "/menu", "wrong-key", None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, "Invalid API key");
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_are_json_with_a_charset() {
+        let app = app(&[]).await;
+        let (status, headers, body) =
+            send(&app, request(Method::GET, "/menu", "wrong-key", None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers[CONTENT_TYPE], JSON_CONTENT_TYPE);
+        assert_eq!(body["error"]["code"], "UNAUTHORIZED");
+
+        let malformed = Request::builder()
+            .method(Method::POST)
+            .uri("/start")
+            .header("x-api-key", format!("Bearer {}", API_KEY))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from("{"))
+            .unwrap();
+        let (status, headers, body) = send(&app, malformed).await;
+        assert!(status.is_client_error());
+        assert_eq!(headers[CONTENT_TYPE], JSON_CONTENT_TYPE);
+        assert!(body["error"]["message"].is_string());
+    }
+
+    #[test]
+    fn keys_bound_to_locations_only_use_those_locations() {
+        let api_keys = parse_api_keys("key1,key2@store-a|store-b:ro").unwrap();
+        let bound = &api_keys["key2"];
+        assert_eq!(bound.scope, ApiKeyScope::ReadOnly);
+        assert!(bound.check_location("store-a").is_ok());
+        assert!(bound.check_location("store-b").is_ok());
+        assert!(matches!(
+            bound.check_location("store-c"),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            bound.check_all_locations(),
+            Err(AppError::Forbidden(_))
+        ));
+
+        let unbound = &api_keys["key1"];
+        assert!(unbound.check_location("store-c").is_ok());
+        assert!(unbound.check_all_locations().is_ok());
+    }
+
+    #[tokio::test]
+    async fn bound_keys_can_use_orders_at_their_locations() {
+        let app = app(&[("API_KEYS", "test-key,store-key@store-a")]).await;
+        let (status, order_id) = start(&app, "store-key", "store-a").await;
+        assert_eq!(status, StatusCode::OK);
+        let uri = format!("/order/{}", order_id.unwrap());
+        let (status, _, _) = send(&app, request(Method::GET, &uri, "store-key", None)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn bound_keys_are_forbidden_elsewhere() {
+        let app = app(&[("API_KEYS", "test-key,store-key@store-a")]).await;
+        let (status, _) = start(&app, "store-key", "store-b").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (_, order_id) = start(&app, API_KEY, "store-b").await;
+        let uri = format!("/order/{}", order_id.unwrap());
+        let (status, _, _) = send(&app, request(Method::GET, &uri, "store-key", None)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        for (method, uri) in [
+            (Method::GET, "/orders/archived"),
+            (Method::GET, "/orders/export"),
+            (Method::GET, "/stats"),
+            (Method::GET, "/admin/assistant"),
+            (Method::POST, "/admin/menu/reload"),
+        ] {
+            let (status, _, body) = send(&app, request(method, uri, "store-key", None)).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", uri);
+            assert_eq!(body["error"]["code"], "FORBIDDEN");
+        }
+    }
+
+    #[tokio::test]
+    async fn read_only_keys_can_read_orders_but_not_chat() {
+        let app = app(&[("API_KEYS", "test-key,viewer:ro")]).await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+        chat(&app, &order_id, "add Apple Pie").await;
+
+        let body = serde_json::json!({ "orderId": order_id, "input": "add Red Bull", "location": "store-a" });
+        let (status, _, body) =
+            send(&app, request(Method::POST, "/chat", "viewer", Some(body))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["code"], "FORBIDDEN");
+
+        let uri = format!("/order/{}", order_id);
+        let (status, _, body) = send(&app, request(Method::GET, &uri, "viewer", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["order"].as_array().unwrap().len(), 1);
+        assert_eq!(body["order"][0]["itemName"], "Apple Pie");
+    }
+
+    #[tokio::test]
+    async fn an_add_and_a_remove_leave_two_audit_entries() {
+        let app = app(&[]).await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+        chat(&app, &order_id, "add Apple Pie").await;
+        chat(&app, &order_id, "remove Apple Pie").await;
+
+        let uri = format!("/order/{}/audit", order_id);
+        let (status, _, body) = send(&app, request(Method::GET, &uri, API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["orderId"], order_id.as_str());
+        let entries = body["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["function"], "add_item");
+        assert_eq!(entries[0]["arguments"]["item"]["itemName"], "Apple Pie");
+        assert_eq!(entries[1]["function"], "remove_item");
+        assert!(entries.iter().all(|entry| entry["success"] == true));
+        assert_eq!(entries[0]["itemIds"], entries[1]["itemIds"]);
+        assert_eq!(entries[0]["itemIds"].as_array().unwrap().len(), 1);
+
+        let uri = format!("/order/{}", order_id);
+        let (_, _, body) = send(&app, request(Method::GET, &uri, API_KEY, None)).await;
+        assert!(body.get("audit").is_none() && body.get("pendingAudit").is_none());
+    }
+
+    #[tokio::test]
+    async fn duplicated_orders_get_new_ids_and_their_own_cart() {
+        let app = app(&[]).await;
+        let (_, source) = start(&app, API_KEY, "store-a").await;
+        let source = source.unwrap();
+        chat(&app, &source, "add Apple Pie").await;
+        chat(&app, &source, "add Red Bull").await;
+
+        let uri = format!("/order/{}/duplicate", source);
+        let (status, _, body) = send(&app, request(Method::POST, &uri, API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let copy = body["orderId"].as_str().unwrap().to_string();
+        assert_ne!(copy, source);
+
+        let get = |order_id: String| {
+            let app = app.clone();
+            async move {
+                let uri = format!("/order/{}", order_id);
+                send(&app, request(Method::GET, &uri, API_KEY, None))
+                    .await
+                    .2
+            }
+        };
+        let (original, duplicate) = (get(source.clone()).await, get(copy.clone()).await);
+        let items = |order: &serde_json::Value, field: &str| -> Vec<String> {
+            order["order"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item[field].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(items(&duplicate, "itemName"), ["Apple Pie", "Red Bull"]);
+        assert_eq!(items(&original, "itemName"), items(&duplicate, "itemName"));
+        assert!(items(&duplicate, "id")
+            .iter()
+            .all(|id| !items(&original, "id").contains(id)));
+        assert!(duplicate["messages"].as_array().unwrap().is_empty());
+
+        chat(&app, &copy, "add Milk").await;
+        chat(&app, &source, "remove Apple Pie").await;
+        assert_eq!(items(&get(source).await, "itemName"), ["Red Bull"]);
+        assert_eq!(
+            items(&get(copy).await, "itemName"),
+            ["Apple Pie", "Red Bull", "Milk"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unbound_keys_can_use_endpoints_spanning_every_location() {
+        let app = app(&[("API_KEYS", "test-key,store-key@store-a")]).await;
+        start(&app, API_KEY, "store-b").await;
+        for uri in ["/orders/archived", "/orders/export", "/stats"] {
+            let (status, _, _) = send(&app, request(Method::GET, uri, API_KEY, None)).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+        }
+        let (_, _, body) = send(&app, request(Method::GET, "/stats", API_KEY, None)).await;
+        assert_eq!(body["activeOrders"], 1);
+    }
+
+    /// Reads the orders exported by `GET /orders/export`, with the content type.
+    async fn export(app: &Router, query: &str) -> (String, Vec<serde_json::Value>) {
+        let uri = format!("/orders/export{}", query);
+        let response = app
+            .clone()
+            .oneshot(request(Method::GET, &uri, API_KEY, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let content_type = response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let orders = String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        (content_type, orders)
+    }
+
+    #[tokio::test]
+    async fn every_order_is_exported_as_a_line() {
+        let app = app(&[]).await;
+        let mut order_ids = vec![];
+        for item in ["Apple Pie", "Red Bull", "Milk"] {
+            let (_, order_id) = start(&app, API_KEY, "store-a").await;
+            let order_id = order_id.unwrap();
+            chat(&app, &order_id, &format!("add {}", item)).await;
+            order_ids.push(order_id);
+        }
+
+        let (content_type, orders) = export(&app, "").await;
+        assert!(content_type.starts_with(NDJSON_CONTENT_TYPE));
+        let mut exported: Vec<&str> = orders
+            .iter()
+            .map(|order| order["orderId"].as_str().unwrap())
+            .collect();
+        exported.sort();
+        order_ids.sort();
+        assert_eq!(exported, order_ids);
+        assert!(orders
+            .iter()
+            .all(|order| order["order"].as_array().unwrap().len() == 1));
+    }
+
+    #[tokio::test]
+    async fn the_export_only_has_orders_changed_since_the_given_time() {
+        let app = app(&[]).await;
+        let (_, old) = start(&app, API_KEY, "store-a").await;
+        chat(&app, &old.unwrap(), "add Apple Pie").await;
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let since = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let (_, new) = start(&app, API_KEY, "store-a").await;
+        let new = new.unwrap();
+        chat(&app, &new, "add Red Bull").await;
+
+        let (_, orders) = export(&app, &format!("?since={}", since)).await;
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0]["orderId"], new.as_str());
+        assert_eq!(export(&app, "").await.1.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn undo_reverts_the_last_change_only() {
+        let app = app(&[]).await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+        chat(&app, &order_id, "add Apple Pie").await;
+        chat(&app, &order_id, "add Red Bull").await;
+
+        let uri = format!("/order/{}/undo", order_id);
+        let (status, _, body) = send(&app, request(Method::POST, &uri, API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let items = body["order"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["itemName"], "Apple Pie");
+        assert!(body.get("undoSnapshots").is_none());
+    }
+
+    #[tokio::test]
+    async fn mock_assistant_takes_an_order_end_to_end() {
+        let app = app(&[]).await;
+        let (status, order_id) = start(&app, API_KEY, "store-a").await;
+        assert_eq!(status, StatusCode::OK);
+        let order_id = order_id.unwrap();
+
+        let (status, body) = chat(&app, &order_id, "add Apple Pie").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["orderId"], order_id);
+        assert_eq!(body["order"][0]["itemName"], "Apple Pie");
+        let reply = body["messages"].as_array().unwrap().last().unwrap().clone();
+        assert_eq!(reply["role"], "assistant");
+        assert!(reply["content"].as_str().unwrap().contains("Apple Pie"));
+
+        let uri = format!("/order/{}", order_id);
+        let (status, _, body) = send(&app, request(Method::GET, &uri, API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let items = body["order"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["itemName"], "Apple Pie");
+        assert_eq!(body["total"], body["order"][0]["price"]);
+    }
+
+    #[tokio::test]
+    async fn each_turn_adds_one_customer_message_to_the_thread() {
+        let app = app(&[]).await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+        let uri = format!("/order/{}?source=thread", order_id);
+
+        for (turn, input) in ["add Apple Pie", "add Red Bull", "list"].iter().enumerate() {
+            chat(&app, &order_id, input).await;
+            let (status, _, body) = send(&app, request(Method::GET, &uri, API_KEY, None)).await;
+            assert_eq!(status, StatusCode::OK);
+            let messages = body["messages"].as_array().unwrap();
+            let count = |role: &str| messages.iter().filter(|m| m["role"] == role).count();
+            assert_eq!(count("user"), turn + 1);
+            // NOTE(dev): The run's reply is in the thread once, never posted back a second time
+            assert_eq!(count("assistant"), turn + 1);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_requests_on_one_order_share_the_pool_without_losing_changes() {
+        let app = app(&[("REDIS_POOL_SIZE", "4"), ("CHAT_DEDUPE_SECONDS", "0")]).await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+        let uri = format!("/order/{}", order_id);
+
+        let requests = (0..50).map(|i| {
+            let request = if i % 2 == 0 {
+                let body = serde_json::json!({
+                    "orderId": order_id,
+                    "input": "add Apple Pie",
+                    "location": "store-a",
+                });
+                request(Method::POST, "/chat", API_KEY, Some(body))
+            } else {
+                request(Method::GET, &uri, API_KEY, None)
+            };
+            let app = app.clone();
+            tokio::spawn(async move { app.oneshot(request).await.unwrap().status() })
+        });
+        for status in futures::future::join_all(requests).await {
+            assert_eq!(status.unwrap(), StatusCode::OK);
+        }
+
+        let (_, _, body) = send(&app, request(Method::GET, &uri, API_KEY, None)).await;
+        assert_eq!(body["order"].as_array().unwrap().len(), 25);
+    }
+
+    #[tokio::test]
+    async fn two_orders_progress_at_the_same_time() {
+        let app = app(&[("CHAT_DEDUPE_SECONDS", "0")]).await;
+        let (_, first) = start(&app, API_KEY, "store-a").await;
+        let (_, second) = start(&app, API_KEY, "store-a").await;
+        let orders = [
+            (first.unwrap(), "add Apple Pie"),
+            (second.unwrap(), "add Milk"),
+        ];
+
+        let requests = (0..10).map(|i| {
+            let (order_id, input) = &orders[i % 2];
+            let body = serde_json::json!({
+                "orderId": order_id,
+                "input": input,
+                "location": "store-a",
+            });
+            let request = request(Method::POST, "/chat", API_KEY, Some(body));
+            let app = app.clone();
+            tokio::spawn(async move { app.oneshot(request).await.unwrap().status() })
+        });
+        for status in futures::future::join_all(requests).await {
+            assert_eq!(status.unwrap(), StatusCode::OK);
+        }
+
+        for (order_id, input) in &orders {
+            let uri = format!("/order/{}", order_id);
+            let (_, _, body) = send(&app, request(Method::GET, &uri, API_KEY, None)).await;
+            let items = body["order"].as_array().unwrap();
+            assert_eq!(items.len(), 5);
+            assert!(items
+                .iter()
+                .all(|item| input.ends_with(item["itemName"].as_str().unwrap())));
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_input_must_be_non_empty_and_within_the_limit() {
+        let app = app(&[("MAX_INPUT_CHARS", "20")]).await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+
+        let (status, body) = chat(&app, &order_id, " \n\t ").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "INVALID_INPUT");
+
+        let (status, body) = chat(&app, &order_id, &"a".repeat(21)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error"]["code"], "INPUT_TOO_LARGE");
+
+        // NOTE(dev): The limit counts characters, not bytes
+        let (status, _) = chat(&app, &order_id, &"é".repeat(20)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = chat(&app, &order_id, "add Apple Pie").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn retried_starts_with_one_idempotency_key_return_one_order() {
+        let app = app(&[]).await;
+        let start_with = |key: &'static str| {
+            let mut request = request(
+                Method::POST,
+                "/start",
+                API_KEY,
+                Some(serde_json::json!({ "location": "store-a" })),
+            );
+            request
+                .headers_mut()
+                .insert("idempotency-key", key.parse().unwrap());
+            send(&app, request)
+        };
+
+        let (status, _, first) = start_with("retry-1").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _, retried) = start_with("retry-1").await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, _, other) = start_with("retry-2").await;
+
+        assert_eq!(first["orderId"], retried["orderId"]);
+        assert_ne!(first["orderId"], other["orderId"]);
+        let (_, without_key) = start(&app, API_KEY, "store-a").await;
+        assert_ne!(first["orderId"].as_str(), without_key.as_deref());
+    }
+
+    #[tokio::test]
+    async fn clearing_an_order_can_be_undone() {
+        let app = app(&[]).await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+        for input in ["add Apple Pie", "add Red Bull", "add Milk"] {
+            chat(&app, &order_id, input).await;
+        }
+
+        let uri = format!("/order/{}/clear", order_id);
+        let (status, _, body) = send(&app, request(Method::POST, &uri, API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["order"].as_array().unwrap().is_empty());
+        assert_eq!(body["messages"].as_array().unwrap().len(), 7);
+
+        let uri = format!("/order/{}/undo", order_id);
+        let (status, _, body) = send(&app, request(Method::POST, &uri, API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["order"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn totals_are_exact_decimal_strings_in_the_configured_currency() {
+        let app = app(&[("CURRENCY", "EUR"), ("CHAT_DEDUPE_SECONDS", "0")]).await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+        for _ in 0..3 {
+            chat(&app, &order_id, "add Apple Pie").await;
+        }
+
+        let uri = format!("/order/{}", order_id);
+        let (_, _, body) = send(&app, request(Method::GET, &uri, API_KEY, None)).await;
+
+        assert_eq!(body["currency"], "EUR");
+        let price: Money = serde_json::from_value(body["order"][0]["price"].clone()).unwrap();
+        assert_eq!(body["total"], (price * 3).to_string());
+    }
+
+    #[tokio::test]
+    async fn checkout_enforces_the_location_totals() {
+        let path = std::env::temp_dir().join(format!("totals-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"store-a": {"minTotal": 3.01}, "store-b": {"maxTotal": 2.99}}"#,
+        )
+        .unwrap();
+        let app = app(&[("LOCATIONS_FILE", &path.to_string_lossy())]).await;
+
+        for (location, expected) in [
+            ("store-a", "below the minimum"),
+            ("store-b", "above the maximum"),
+        ] {
+            let (_, order_id) = start(&app, API_KEY, location).await;
+            let order_id = order_id.unwrap();
+            let uri = format!("/order/{}/items", order_id);
+            let item = add_args("Coke", &[("size", "small")]);
+            let (status, _, _) = send(&app, request(Method::POST, &uri, API_KEY, Some(item))).await;
+            assert_eq!(status, StatusCode::OK);
+
+            let uri = format!("/order/{}/complete", order_id);
+            let (status, _, body) = send(&app, request(Method::POST, &uri, API_KEY, None)).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains(expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn completed_orders_are_posted_to_the_webhook() {
+        let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let hook = Router::new().route(
+            "/hook",
+            post(
+                move |headers: HeaderMap, body: axum::body::Bytes| async move {
+                    sender.send((headers, body)).unwrap();
+                    StatusCode::OK
+                },
+            ),
+        );
+        let url = format!("{}/hook", serve(hook));
+        let app = app(&[
+            ("COMPLETION_WEBHOOK_URL", &url),
+            ("COMPLETION_WEBHOOK_SECRET", "shared"),
+        ])
+        .await;
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+        chat(&app, &order_id, "add Apple Pie").await;
+
+        let uri = format!("/order/{}/complete", order_id);
+        let (status, _, completed) = send(&app, request(Method::POST, &uri, API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (headers, body) = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("webhook must be called")
+            .unwrap();
+        let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(payload, completed);
+        assert_eq!(payload["status"], "completed");
+        assert_eq!(payload["order"][0]["itemName"], "Apple Pie");
+        let expected = CompletionWebhook::new(url, Some("shared".to_string()), 0).sign(&body);
+        assert_eq!(
+            headers[crate::webhook::SIGNATURE_HEADER].to_str().ok(),
+            expected.as_deref()
+        );
+    }
+
+    #[tokio::test]
+    async fn admins_can_cancel_an_active_order() {
+        let (app, mock) = app_with_mock(&[("API_KEYS", "test-key,viewer:ro")]);
+        let (status, _, body) = send(
+            &app,
+            request(Method::POST, "/admin/order/missing/cancel", API_KEY, None),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "ORDER_NOT_FOUND");
+
+        let (_, order_id) = start(&app, API_KEY, "store-a").await;
+        let order_id = order_id.unwrap();
+        chat(&app, &order_id, "add Apple Pie").await;
+        let uri = format!("/admin/order/{}/cancel", order_id);
+        let (status, _, _) = send(&app, request(Method::POST, &uri, "viewer", None)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        assert!(mock.cancelled_threads().unwrap().is_empty());
+
+        let (status, _, body) = send(&app, request(Method::POST, &uri, API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "cancelled");
+        assert_eq!(body["order"][0]["itemName"], "Apple Pie");
+        assert_eq!(
+            mock.cancelled_threads().unwrap(),
+            [format!("mock-{}", order_id)]
+        );
+
+        let (status, body) = chat(&app, &order_id, "add Red Bull").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"]["code"], "ORDER_NOT_ACTIVE");
+        let (status, _, body) = send(&app, request(Method::POST, &uri, API_KEY, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "cancelled");
+        assert_eq!(mock.cancelled_threads().unwrap().len(), 1);
+        let order_uri = format!("/order/{}", order_id);
+        let (_, _, body) = send(&app, request(Method::GET, &order_uri, API_KEY, None)).await;
+        assert_eq!(body["status"], "cancelled");
+        assert_eq!(body["order"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn allowed_origins_get_the_cors_headers() {
+        let app = app(&[("ALLOWED_ORIGINS", "https://kiosk.example")]).await;
+        let preflight = |origin: &str| {
+            Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/chat")
+                .header("origin", origin)
+                .header("access-control-request-method", "POST")
+                .header("access-control-request-headers", "content-type,x-api-key")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let (status, headers, _) = send(&app, preflight("https://kiosk.example")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers["access-control-allow-origin"],
+            "https://kiosk.example"
+        );
+
+        let mut get = request(Method::GET, "/menu", API_KEY, None);
+        get.headers_mut()
+            .insert("origin", HeaderValue::from_static("https://kiosk.example"));
+        let (status, headers, _) = send(&app, get).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers["access-control-allow-origin"],
+            "https://kiosk.example"
+        );
+
+        let (_, headers, _) = send(&app, preflight("https://other.example")).await;
+        assert!(!headers.contains_key("access-control-allow-origin"));
+    }
 }
@@ -1,17 +1,27 @@
-use async_openai::{error::OpenAIError, types::FunctionCall};
+use async_openai::{
+    error::OpenAIError,
+    types::{FunctionCall, RunCompletionUsage},
+};
+use metrics::counter;
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display};
-use tracing::{debug, error, info};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::api::ChatRequest;
 use crate::error::{AppError, AppResult};
 use crate::functions::{
-    AddItemArgs, FunctionArgs, FunctionName, ListItemsArgs, ModifyItemArgs, OrderAssistant,
-    RemoveItemArgs,
+    AddItemArgs, AddItemsArgs, AssistantBackend, ClearOrderArgs, ConfirmOrderArgs, FinalizeArgs,
+    FunctionArgs, FunctionName, GetMenuInfoArgs, ListItemsArgs, MergeItemsArgs, ModifiedItemArgs,
+    ModifyItemArgs, PatchItemArgs, ReadOrderArgs, RemoveItemArgs, RemoveItemsArgs, SingleItemArgs,
+    SplitItemArgs, UndoLastItemArgs,
 };
-use crate::menu::Menu;
-use crate::order::{Order, OrderItem, OrderStore};
+use crate::menu::{ItemMatch, ItemStatus, Menu};
+use crate::money::Money;
+use crate::order::{ItemStatusResponse, Order, OrderItem, OrderLimits, OrderStore};
+use crate::telemetry;
 
 /// Represents a single message in the chat conversation
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -40,20 +50,64 @@ impl Display for ChatRole {
     }
 }
 
+/// Cumulative OpenAI token usage across all chat requests
+#[derive(Debug, Default)]
+pub struct UsageCounters {
+    runs: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+}
+
+impl UsageCounters {
+    /// Adds the usage of a single run to the totals.
+    ///
+    /// # Arguments
+    /// * `usage` - Token usage reported for the run, if any
+    pub fn record(&self, usage: Option<&RunCompletionUsage>) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        if let Some(usage) = usage {
+            self.prompt_tokens
+                .fetch_add(usage.prompt_tokens as u64, Ordering::Relaxed);
+            self.completion_tokens
+                .fetch_add(usage.completion_tokens as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of runs recorded so far
+    pub fn runs(&self) -> u64 {
+        self.runs.load(Ordering::Relaxed)
+    }
+
+    /// Prompt tokens used so far
+    pub fn prompt_tokens(&self) -> u64 {
+        self.prompt_tokens.load(Ordering::Relaxed)
+    }
+
+    /// Completion tokens used so far
+    pub fn completion_tokens(&self) -> u64 {
+        self.completion_tokens.load(Ordering::Relaxed)
+    }
+}
+
 /// Processes a chat message and updates the order state accordingly.
 ///
 /// # Arguments
 /// * `store` - The order storage interface
 /// * `menu` - The restaurant menu
 /// * `assistant` - The AI assistant instance
+/// * `usage_counters` - Cumulative token usage to add this run to
+/// * `limits` - Limits applied to the order
 /// * `request` - The chat request containing the message
 ///
 /// # Returns
-/// * `AppResult<Order>` - The updated order after processing the message
+/// * `AppResult<Order>` - The updated order after processing the message, completed but not
+///   yet archived if the message confirmed it
 pub async fn handle_chat_message(
     store: &OrderStore,
     menu: &Menu,
-    assistant: &OrderAssistant,
+    assistant: &dyn AssistantBackend,
+    usage_counters: &UsageCounters,
+    limits: &OrderLimits,
     request: &ChatRequest,
 ) -> AppResult<Order> {
     info!("Processing chat message for order: {}", request.order_id);
@@ -61,33 +115,248 @@ pub async fn handle_chat_message(
 
     let mut conn = store.get_connection()?;
     debug!("Retrieving order from storage");
-    let mut order = Order::get(&mut conn, &request.order_id)?;
+    let mut order = store.get_order(&mut conn, &request.order_id)?;
+    order.ensure_active()?;
+
+    if order.awaiting_confirmation {
+        if is_confirmation(&request.input) {
+            info!("Customer confirmed order {}", request.order_id);
+            order.messages.push(ChatMessage {
+                role: ChatRole::User.to_string(),
+                content: request.input.clone(),
+            });
+            order.complete()?;
+            order.messages.push(ChatMessage {
+                role: ChatRole::Assistant.to_string(),
+                content: format!(
+                    "Thank you, your order is confirmed. Your total is {}.",
+                    order.total()
+                ),
+            });
+            // NOTE(dev): The caller archives completed orders, so nothing is saved here
+            return Ok(order);
+        }
+        // NOTE(dev): Anything but a yes needs the order to be read back again
+        debug!("Order {} was not confirmed", request.order_id);
+        order.awaiting_confirmation = false;
+    }
+    // NOTE(dev): Checked after the confirmation so a customer can always confirm a read back order
+    order.ensure_token_budget(limits.max_tokens)?;
 
     info!("Handling message with AI assistant");
-    assistant
-        .handle_message(&request.input, &request.location, &mut order, menu)
+    let started = Instant::now();
+    let usage = assistant
+        .handle_message(
+            &request.input,
+            &request.location,
+            request.language.as_deref(),
+            &mut order,
+            menu,
+        )
         .await?;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    usage_counters.record(usage.as_ref());
+    if let Some(usage) = usage.as_ref() {
+        order.record_tokens(usage.prompt_tokens, usage.completion_tokens);
+    }
+    order.track_invalid_items(limits.max_invalid_turns);
+    order.truncate_messages(limits.max_thread_messages);
 
     debug!("Saving updated order to storage");
-    order.save(&mut conn).await?;
-    info!("Chat message processing completed");
+    store.save_order(&mut conn, &mut order).await?;
+    info!(
+        order_id = %request.order_id,
+        location = %request.location,
+        latency_ms,
+        prompt_tokens = usage.as_ref().map(|usage| usage.prompt_tokens),
+        completion_tokens = usage.as_ref().map(|usage| usage.completion_tokens),
+        total_tokens = usage.as_ref().map(|usage| usage.total_tokens),
+        "Chat message processing completed"
+    );
     Ok(order.clone())
 }
 
+/// Adds a known item to an order without calling the assistant, e.g. from a kiosk button.
+///
+/// The item goes through the same function call path as the assistant's `add_item` calls, and
+/// a synthetic assistant message is appended to the chat history.
+///
+/// # Arguments
+/// * `store` - The order storage interface
+/// * `menu` - The restaurant menu
+/// * `limits` - Limits applied to the order
+/// * `order_id` - The ID of the order to add to
+/// * `item` - The item to add
+///
+/// # Returns
+/// * `AppResult<Order>` - The updated order
+pub async fn handle_item_selection(
+    store: &OrderStore,
+    menu: &Menu,
+    limits: &OrderLimits,
+    order_id: &str,
+    item: AddItemArgs,
+) -> AppResult<Order> {
+    info!(
+        "Adding selected item '{}' to order {}",
+        item.item_name, order_id
+    );
+    let mut conn = store.get_connection()?;
+    let mut order = store.get_order(&mut conn, order_id)?;
+    order.ensure_active()?;
+    order.ensure_capacity(item.quantity() as usize, limits.max_items)?;
+
+    let function_call = FunctionCall {
+        name: FunctionName::AddItem.to_string(),
+        arguments: serde_json::to_string(&SingleItemArgs { item: item.clone() })?,
+    };
+    handle_function_call(&function_call, menu, limits, &mut order).await?;
+
+    // NOTE(dev): The message is only kept locally, the assistant sees the item through list_items
+    order.messages.push(ChatMessage {
+        role: ChatRole::Assistant.to_string(),
+        content: format!("Added {} to your order.", item.item_name),
+    });
+    order.truncate_messages(limits.max_thread_messages);
+
+    debug!("Saving updated order to storage");
+    store.save_order(&mut conn, &mut order).await?;
+    Ok(order)
+}
+
+/// Result of a function call reported back to the assistant as the tool output
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FunctionResult {
+    /// Whether the call changed the order as requested
+    pub success: bool,
+    /// Items added or changed by the call, with their validation status
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub items: Vec<ItemResult>,
+    /// IDs of the items removed by the call
+    #[serde(
+        rename = "removedItemIds",
+        skip_serializing_if = "Vec::is_empty",
+        default
+    )]
+    pub removed_item_ids: Vec<String>,
+    /// Items in the order with every option, reported by `list_items`
+    #[serde(rename = "listedItems", skip_serializing_if = "Vec::is_empty", default)]
+    pub listed_items: Vec<OrderItem>,
+    /// Whether `list_items` left items out of `listed_items` because of its limit
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub truncated: bool,
+    /// Short kind of the failure, e.g. "item not found"
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+    /// How the assistant can recover from the failure
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub message: Option<String>,
+    /// Items the customer could be offered with the ones just added, which the assistant may
+    /// or may not bring up
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub suggestions: Vec<String>,
+    /// Number of items in the order after the call
+    #[serde(rename = "itemCount")]
+    pub item_count: usize,
+    /// Order total after the call
+    pub total: Money,
+}
+
+/// An item touched by a function call
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ItemResult {
+    /// ID of the order item
+    #[serde(rename = "itemId")]
+    pub item_id: String,
+    /// Name of the menu item
+    #[serde(rename = "itemName")]
+    pub item_name: String,
+    /// Price of a single item of the line
+    pub price: Money,
+    /// Number of identical items on the line
+    pub quantity: u32,
+    /// Validation status of the item
+    pub status: Option<ItemStatusResponse>,
+}
+
+impl FunctionResult {
+    /// Builds the result of a call that changed the order.
+    ///
+    /// # Arguments
+    /// * `order` - The order after the call, with its items validated
+    /// * `changed_ids` - IDs of the items added or changed
+    /// * `removed_ids` - IDs of the items removed
+    ///
+    /// # Returns
+    /// * `Self` - The result
+    pub fn success(order: &Order, changed_ids: &[String], removed_ids: Vec<String>) -> Self {
+        let items = order
+            .order
+            .iter()
+            .filter(|item| changed_ids.contains(&item.id))
+            .map(|item| ItemResult {
+                item_id: item.id.clone(),
+                item_name: item.item_name.clone(),
+                price: item.price,
+                quantity: item.quantity,
+                status: item.item_status.clone().map(Into::into),
+            })
+            .collect();
+        FunctionResult {
+            success: true,
+            items,
+            removed_item_ids: removed_ids,
+            listed_items: vec![],
+            truncated: false,
+            suggestions: vec![],
+            error: None,
+            message: None,
+            item_count: order.item_count(),
+            total: order.total(),
+        }
+    }
+
+    /// Builds the result of a call that could not change the order.
+    ///
+    /// # Arguments
+    /// * `order` - The unchanged order
+    /// * `error` - Short kind of the failure
+    /// * `message` - How the assistant can recover
+    ///
+    /// # Returns
+    /// * `Self` - The result
+    pub fn error(order: &Order, error: &str, message: String) -> Self {
+        FunctionResult {
+            success: false,
+            items: vec![],
+            removed_item_ids: vec![],
+            listed_items: vec![],
+            truncated: false,
+            suggestions: vec![],
+            error: Some(error.to_string()),
+            message: Some(message),
+            item_count: order.item_count(),
+            total: order.total(),
+        }
+    }
+}
+
 /// Handles function calls from the AI assistant and updates the order accordingly.
 ///
 /// # Arguments
 /// * `function_call` - The function call details from the assistant
 /// * `menu` - The restaurant menu
+/// * `limits` - Limits applied to the order
 /// * `order` - The current order state
 ///
 /// # Returns
-/// * `AppResult<&mut Order>` - The updated order after executing the function
-pub async fn handle_function_call<'a>(
+/// * `AppResult<String>` - The tool output to report back to the assistant
+pub async fn handle_function_call(
     function_call: &FunctionCall,
     menu: &Menu,
-    order: &'a mut Order,
-) -> AppResult<&'a mut Order> {
+    limits: &OrderLimits,
+    order: &mut Order,
+) -> AppResult<String> {
     info!("Processing function call: {}", function_call.name);
     let function_name = function_call.name.clone();
     let function_args = function_call.arguments.clone();
@@ -96,76 +365,937 @@ pub async fn handle_function_call<'a>(
     let function_name: FunctionName = serde_plain::from_str(&function_name)?;
 
     debug!("Parsing function arguments: {}", function_args);
-    let function_args = match function_name {
+    let parsed = match function_name {
         FunctionName::AddItem => {
             debug!("Parsing AddItem arguments");
-            FunctionArgs::AddItem(serde_json::from_str::<AddItemArgs>(&function_args)?)
+            serde_json::from_str::<SingleItemArgs>(&function_args)
+                .map(|args| FunctionArgs::AddItem(args.item))
+        }
+        FunctionName::AddItems => {
+            debug!("Parsing AddItems arguments");
+            serde_json::from_str::<AddItemsArgs>(&function_args).map(FunctionArgs::AddItems)
         }
         FunctionName::RemoveItem => {
             debug!("Parsing RemoveItem arguments");
-            FunctionArgs::RemoveItem(serde_json::from_str::<RemoveItemArgs>(&function_args)?)
+            serde_json::from_str::<RemoveItemArgs>(&function_args).map(FunctionArgs::RemoveItem)
+        }
+        FunctionName::RemoveItems => {
+            debug!("Parsing RemoveItems arguments");
+            serde_json::from_str::<RemoveItemsArgs>(&function_args).map(FunctionArgs::RemoveItems)
         }
         FunctionName::ModifyItem => {
             debug!("Parsing ModifyItem arguments");
-            FunctionArgs::ModifyItem(serde_json::from_str::<ModifyItemArgs>(&function_args)?)
+            serde_json::from_str::<ModifyItemArgs>(&function_args).map(FunctionArgs::ModifyItem)
         }
         FunctionName::ListItems => {
             debug!("Parsing ListItems arguments");
-            FunctionArgs::ListItems(serde_json::from_str::<ListItemsArgs>(&function_args)?)
+            serde_json::from_str::<ListItemsArgs>(&function_args).map(FunctionArgs::ListItems)
+        }
+        FunctionName::ClearOrder => {
+            debug!("Parsing ClearOrder arguments");
+            serde_json::from_str::<ClearOrderArgs>(&function_args).map(FunctionArgs::ClearOrder)
+        }
+        FunctionName::PatchItem => {
+            debug!("Parsing PatchItem arguments");
+            serde_json::from_str::<PatchItemArgs>(&function_args).map(FunctionArgs::PatchItem)
+        }
+        FunctionName::GetMenuInfo => {
+            debug!("Parsing GetMenuInfo arguments");
+            serde_json::from_str::<GetMenuInfoArgs>(&function_args).map(FunctionArgs::GetMenuInfo)
+        }
+        FunctionName::ConfirmOrder => {
+            debug!("Parsing ConfirmOrder arguments");
+            serde_json::from_str::<ConfirmOrderArgs>(&function_args).map(FunctionArgs::ConfirmOrder)
+        }
+        FunctionName::ReadOrder => {
+            debug!("Parsing ReadOrder arguments");
+            serde_json::from_str::<ReadOrderArgs>(&function_args).map(FunctionArgs::ReadOrder)
+        }
+        FunctionName::UndoLastItem => {
+            debug!("Parsing UndoLastItem arguments");
+            serde_json::from_str::<UndoLastItemArgs>(&function_args).map(FunctionArgs::UndoLastItem)
+        }
+        FunctionName::Finalize => {
+            debug!("Parsing Finalize arguments");
+            serde_json::from_str::<FinalizeArgs>(&function_args).map(FunctionArgs::Finalize)
+        }
+        FunctionName::SplitItem => {
+            debug!("Parsing SplitItem arguments");
+            serde_json::from_str::<SplitItemArgs>(&function_args).map(FunctionArgs::SplitItem)
+        }
+        FunctionName::MergeItems => {
+            debug!("Parsing MergeItems arguments");
+            serde_json::from_str::<MergeItemsArgs>(&function_args).map(FunctionArgs::MergeItems)
         }
     };
 
-    info!("Executing function: {:?}", function_name.clone());
-    match (function_name.clone(), function_args.clone()) {
-        (FunctionName::AddItem, FunctionArgs::AddItem { .. }) => {
-            handle_add_function(&function_args, order).await?
+    let function_args = match parsed {
+        Ok(function_args) => function_args,
+        Err(e) => {
+            order.record_audit(
+                &function_name.to_string(),
+                &function_call.arguments,
+                vec![],
+                false,
+            );
+            return invalid_arguments(&function_name, &e, order);
         }
-        (FunctionName::RemoveItem, FunctionArgs::RemoveItem { .. }) => {
-            handle_remove_function(&function_args, order).await?
+    };
+
+    let items_before = function_name.mutates_order().then(|| order.order.clone());
+
+    counter!(telemetry::FUNCTION_CALLS, "function" => function_name.to_string()).increment(1);
+    info!("Executing function: {:?}", function_name.clone());
+    let mut changed_ids: Vec<String> = vec![];
+    let mut removed_ids: Vec<String> = vec![];
+    let mut values_not_removed: Vec<String> = vec![];
+    // NOTE(dev): Each arm reports whether the call did what was asked, failed calls still get a
+    //            tool output so the assistant can recover
+    // NOTE(dev): Errors end the turn, so a batch failing midway must not stay half applied
+    let outcome: AppResult<(Option<String>, bool)> = async {
+        Ok(match (function_name.clone(), function_args.clone()) {
+            (FunctionName::AddItem, FunctionArgs::AddItem { .. }) => {
+                match handle_add_function(&function_args, limits.max_items, order).await {
+                    Ok(id) => {
+                        changed_ids.push(id);
+                        (None, true)
+                    }
+                    Err(AppError::OrderFull(msg)) => (Some(order_full(&msg, order)?), false),
+                    Err(e) => return Err(e),
+                }
+            }
+            (FunctionName::AddItems, FunctionArgs::AddItems(AddItemsArgs { items })) => {
+                debug!("Adding {} items in a single call", items.len());
+                // NOTE(dev): Refuse the whole batch up front so a full cart never ends up half added
+                let quantity = items.iter().map(|item| item.quantity() as usize).sum();
+                match order.ensure_capacity(quantity, limits.max_items) {
+                    Ok(_) => {
+                        for item in items {
+                            changed_ids.push(
+                                handle_add_function(
+                                    &FunctionArgs::AddItem(item),
+                                    limits.max_items,
+                                    order,
+                                )
+                                .await?,
+                            );
+                        }
+                        (None, true)
+                    }
+                    Err(AppError::OrderFull(msg)) => (Some(order_full(&msg, order)?), false),
+                    Err(e) => return Err(e),
+                }
+            }
+            (FunctionName::RemoveItem, FunctionArgs::RemoveItem { .. }) => {
+                match handle_remove_function(&function_args, order).await {
+                    Ok(id) => {
+                        removed_ids.push(id);
+                        (None, true)
+                    }
+                    Err(AppError::OrderItemNotFound(id)) => {
+                        (Some(item_not_found(&id, order)?), false)
+                    }
+                    Err(AppError::AmbiguousOrderItem(msg)) => {
+                        (Some(ambiguous_item(&msg, order)?), false)
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            (FunctionName::RemoveItems, FunctionArgs::RemoveItems(RemoveItemsArgs { items })) => {
+                debug!("Removing {} items in a single call", items.len());
+                let mut failures = vec![];
+                for item in items {
+                    match handle_remove_function(&FunctionArgs::RemoveItem(item), order).await {
+                        Ok(id) => removed_ids.push(id),
+                        Err(AppError::OrderItemNotFound(id)) => {
+                            failures.push(format!("Item {} is not in the order", id))
+                        }
+                        Err(AppError::AmbiguousOrderItem(msg)) => failures.push(msg),
+                        Err(e) => return Err(e),
+                    }
+                }
+                if failures.is_empty() {
+                    (None, true)
+                } else {
+                    (
+                        Some(items_not_removed(&failures, removed_ids.clone(), order)?),
+                        false,
+                    )
+                }
+            }
+            (FunctionName::ModifyItem, FunctionArgs::ModifyItem { .. }) => {
+                match handle_modify_function(&function_args, order).await {
+                    Ok(id) => {
+                        changed_ids.push(id);
+                        (None, true)
+                    }
+                    Err(AppError::OrderItemNotFound(id)) => {
+                        (Some(item_not_found(&id, order)?), false)
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            (FunctionName::ListItems, FunctionArgs::ListItems { .. }) => (
+                Some(handle_list_function(&function_args, order).await?),
+                true,
+            ),
+            (FunctionName::ClearOrder, FunctionArgs::ClearOrder { .. }) => {
+                removed_ids = order.order.iter().map(|item| item.id.clone()).collect();
+                order.clear();
+                (None, true)
+            }
+            (
+                FunctionName::GetMenuInfo,
+                FunctionArgs::GetMenuInfo(GetMenuInfoArgs { item_name }),
+            ) => {
+                let (output, success) =
+                    handle_menu_info_function(item_name.as_deref(), menu, order)?;
+                (Some(output), success)
+            }
+            (FunctionName::ConfirmOrder, FunctionArgs::ConfirmOrder { .. }) => {
+                let (output, success) = handle_confirm_function(order)?;
+                (Some(output), success)
+            }
+            (FunctionName::ReadOrder, FunctionArgs::ReadOrder { .. }) => (
+                Some(serde_json::to_string(&OrderSummary::new(order))?),
+                true,
+            ),
+            (FunctionName::Finalize, FunctionArgs::Finalize { .. }) => {
+                let (output, success) = handle_finalize_function(order)?;
+                (Some(output), success)
+            }
+            (FunctionName::UndoLastItem, FunctionArgs::UndoLastItem { .. }) => {
+                // NOTE(dev): Items are appended as they are added, so the last one is the newest
+                match order.order.pop() {
+                    Some(item) => {
+                        info!(
+                            "Removed last added item {} ({}) from order {}",
+                            item.item_name, item.id, order.order_id
+                        );
+                        removed_ids.push(item.id);
+                        (None, true)
+                    }
+                    None => (Some(order_empty(order)?), false),
+                }
+            }
+            (FunctionName::PatchItem, FunctionArgs::PatchItem { .. }) => {
+                match handle_patch_function(&function_args, menu, order).await {
+                    Ok((id, not_removed)) => {
+                        changed_ids.push(id);
+                        let success = not_removed.is_empty();
+                        values_not_removed = not_removed;
+                        (None, success)
+                    }
+                    Err(AppError::OrderItemNotFound(id)) => {
+                        (Some(item_not_found(&id, order)?), false)
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            (FunctionName::SplitItem, FunctionArgs::SplitItem { .. }) => {
+                match handle_split_function(&function_args, order).await {
+                    Ok((id, new_ids)) => {
+                        changed_ids = new_ids;
+                        removed_ids.push(id);
+                        (None, true)
+                    }
+                    Err(AppError::OrderItemNotFound(id)) => {
+                        (Some(item_not_found(&id, order)?), false)
+                    }
+                    Err(AppError::InvalidInput(msg)) => (Some(not_split(&msg, order)?), false),
+                    Err(e) => return Err(e),
+                }
+            }
+            (FunctionName::MergeItems, FunctionArgs::MergeItems { .. }) => {
+                match handle_merge_function(&function_args, order).await {
+                    Ok((id, merged_ids)) => {
+                        changed_ids.push(id);
+                        removed_ids = merged_ids;
+                        (None, true)
+                    }
+                    Err(AppError::OrderItemNotFound(id)) => {
+                        (Some(item_not_found(&id, order)?), false)
+                    }
+                    Err(AppError::InvalidInput(msg)) => (Some(not_merged(&msg, order)?), false),
+                    Err(e) => return Err(e),
+                }
+            }
+            _ => {
+                error!("Invalid function call combination: {:?}", function_name);
+                return Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
+                    "Invalid function call".to_string(),
+                )));
+            }
+        })
+    }
+    .await;
+    let (tool_output, success) = match outcome {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            if let Some(items_before) = items_before {
+                warn!(
+                    "{} failed on order {}, restoring its items",
+                    function_name, order.order_id
+                );
+                order.order = items_before;
+            }
+            return Err(e);
         }
-        (FunctionName::ModifyItem, FunctionArgs::ModifyItem { .. }) => {
-            handle_modify_function(&function_args, order).await?
+    };
+    // NOTE(dev): Calls that changed nothing leave the undo history and the confirmation alone
+    if let Some(items_before) = items_before {
+        if !changed_ids.is_empty() || !removed_ids.is_empty() {
+            debug!("Saving undo snapshot from before {}", function_name);
+            order.snapshot(items_before);
+            order.awaiting_confirmation = false;
+            order.summary = None;
         }
-        (FunctionName::ListItems, FunctionArgs::ListItems { .. }) => {
-            handle_list_function(&function_args, order).await?
+    }
+    validate_items(order, menu)?;
+    // NOTE(dev): patch_item prices from the menu itself, only the model-supplied prices are replaced
+    if matches!(
+        function_name,
+        FunctionName::AddItem | FunctionName::AddItems | FunctionName::ModifyItem
+    ) {
+        for item in order
+            .order
+            .iter_mut()
+            .filter(|item| changed_ids.contains(&item.id))
+        {
+            apply_menu_price(&function_name, item, menu);
         }
-        _ => {
-            error!("Invalid function call combination: {:?}", function_name);
-            return Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
-                "Invalid function call".to_string(),
-            )));
+    }
+
+    info!("Function execution completed successfully");
+    let item_ids = changed_ids
+        .iter()
+        .chain(removed_ids.iter())
+        .cloned()
+        .collect();
+    let tool_output = match tool_output {
+        Some(tool_output) => tool_output,
+        None => {
+            let mut result = FunctionResult::success(order, &changed_ids, removed_ids);
+            // NOTE(dev): The rest of the patch still applies, the result says what was left out
+            if !values_not_removed.is_empty() {
+                info!(
+                    "Could not remove {:?} from order {}",
+                    values_not_removed, order.order_id
+                );
+                result.success = false;
+                result.error = Some("values not removed".to_string());
+                result.message = Some(format!(
+                    "The item has no {}, so nothing was removed for them. The rest of the patch was applied, use list_items to see the item's current options",
+                    values_not_removed.join("; ")
+                ));
+            }
+            if matches!(
+                function_name,
+                FunctionName::AddItem | FunctionName::AddItems
+            ) {
+                let order_items: Vec<&str> = order
+                    .order
+                    .iter()
+                    .map(|item| item.item_name.as_str())
+                    .collect();
+                result.suggestions = menu.upsells(
+                    result.items.iter().map(|item| item.item_name.as_str()),
+                    &order_items,
+                );
+                if !result.suggestions.is_empty() {
+                    debug!(
+                        "Suggesting {:?} for order {}",
+                        result.suggestions, order.order_id
+                    );
+                    order.suggestions = result.suggestions.clone();
+                }
+            }
+            serde_json::to_string(&result)?
         }
     };
-    debug!("Validating order items {:?}", order);
-    for item in &mut order.order {
-        item.item_status = Some(menu.validate_item(&item.to_owned())?);
+    order.record_audit(
+        &function_name.to_string(),
+        &function_call.arguments,
+        item_ids,
+        success,
+    );
+    Ok(tool_output)
+}
+
+/// Largest difference between a model-supplied price and the menu price that is not reported
+const PRICE_MISMATCH_TOLERANCE: Money = Money::from_cents(1);
+
+/// Replaces the price the model gave an item with the price computed from the menu, logging a
+/// warning and counting a mismatch when they differ.
+///
+/// The model's price is only kept for items the menu can't price, e.g. with a choice that
+/// isn't on the menu, so totals always agree with the price breakdown.
+///
+/// # Arguments
+/// * `function_name` - The function that set the price
+/// * `item` - The item with the model-supplied price
+/// * `menu` - The restaurant menu
+fn apply_menu_price(function_name: &FunctionName, item: &mut OrderItem, menu: &Menu) {
+    let Some(menu_price) = menu.price_item(item) else {
+        debug!(
+            "Item {} (ID: {}) can't be priced from the menu, keeping the model's price {}",
+            item.item_name, item.id, item.price
+        );
+        return;
+    };
+    let difference = if item.price > menu_price {
+        item.price - menu_price
+    } else {
+        menu_price - item.price
+    };
+    if difference > PRICE_MISMATCH_TOLERANCE {
+        warn!(
+            "Model priced item {} (ID: {}) at {} but the menu price is {}",
+            item.item_name, item.id, item.price, menu_price
+        );
+        counter!(telemetry::PRICE_MISMATCHES, "function" => function_name.to_string()).increment(1);
     }
-    debug!("Validated order items {:?}", order);
+    item.price = menu_price;
+}
 
-    info!("Function execution completed successfully");
-    Ok(order)
+/// Overview of a menu item returned by `get_menu_info` without an item name
+#[derive(Debug, Serialize)]
+struct MenuItemSummary<'a> {
+    /// Name of the item
+    #[serde(rename = "itemName")]
+    item_name: &'a str,
+    /// Category of the item
+    #[serde(rename = "itemType")]
+    item_type: &'a str,
+    /// Whether the item can currently be ordered
+    available: bool,
+    /// Allergen and dietary tags
+    tags: &'a [String],
+}
+
+/// Looks an item up on the live menu for the assistant, or summarizes every item.
+///
+/// # Arguments
+/// * `item_name` - The item to describe, or None for an overview
+/// * `menu` - The restaurant menu
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<(String, bool)>` - The tool output to report back to the assistant, and whether
+///   the item was found
+fn handle_menu_info_function(
+    item_name: Option<&str>,
+    menu: &Menu,
+    order: &Order,
+) -> AppResult<(String, bool)> {
+    let Some(item_name) = item_name else {
+        debug!("Summarizing {} menu items", menu.items.len());
+        let items: Vec<MenuItemSummary> = menu
+            .items
+            .iter()
+            .map(|item| MenuItemSummary {
+                item_name: &item.item_name,
+                item_type: &item.item_type,
+                available: item.is_available(),
+                tags: &item.tags,
+            })
+            .collect();
+        return Ok((serde_json::to_string(&items)?, true));
+    };
+
+    info!("Looking up menu item '{}'", item_name);
+    let result = match menu.find_item(item_name) {
+        ItemMatch::Found(item) => return Ok((serde_json::to_string(item)?, true)),
+        ItemMatch::Ambiguous(candidates) => FunctionResult::error(
+            order,
+            "ambiguous item",
+            format!(
+                "{} could be any of: {}. Ask the customer which one they mean",
+                item_name,
+                candidates.join(", ")
+            ),
+        ),
+        ItemMatch::NotFound => FunctionResult::error(
+            order,
+            "item not found",
+            format!(
+                "{} is not on the menu, call get_menu_info without an item name to see every item",
+                item_name
+            ),
+        ),
+    };
+    Ok((serde_json::to_string(&result)?, false))
+}
+
+/// Itemized order returned by `read_order` and recorded by `finalize`, built from the stored
+/// items
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrderSummary {
+    /// Identical items grouped into a single line
+    pub lines: Vec<OrderLine>,
+    /// Number of items in the order
+    #[serde(rename = "itemCount")]
+    pub item_count: usize,
+    /// Order total
+    pub total: Money,
+}
+
+/// Identical items of an order
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrderLine {
+    /// Name of the menu item
+    #[serde(rename = "itemName")]
+    pub item_name: String,
+    /// Selected options, e.g. "Toppings: bacon, 2x cheese"
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub options: Vec<String>,
+    /// Items filling the slots of a combo, with their options
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub components: Vec<String>,
+    /// Special instructions
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub notes: Option<String>,
+    /// Person the items are for in a group order
+    #[serde(rename = "cartLabel", skip_serializing_if = "Option::is_none", default)]
+    pub cart_label: Option<String>,
+    /// Number of identical items
+    pub quantity: u32,
+    /// Price of a single item
+    #[serde(rename = "unitPrice")]
+    pub unit_price: Money,
+    /// Price of every item of the line
+    #[serde(rename = "linePrice")]
+    pub line_price: Money,
+}
+
+impl OrderSummary {
+    /// Summarizes the items of an order, in the order they were added.
+    ///
+    /// # Arguments
+    /// * `order` - The order to summarize
+    ///
+    /// # Returns
+    /// * `Self` - The itemized summary
+    pub fn new(order: &Order) -> Self {
+        debug!("Summarizing order {}", order.order_id);
+        let mut lines: Vec<OrderLine> = Vec::new();
+        for item in &order.order {
+            let line = OrderLine {
+                item_name: item.item_name.clone(),
+                options: describe_options(item),
+                components: item
+                    .components
+                    .iter()
+                    .map(|component| {
+                        let options = describe_options(component);
+                        if options.is_empty() {
+                            component.item_name.clone()
+                        } else {
+                            format!("{} ({})", component.item_name, options.join("; "))
+                        }
+                    })
+                    .collect(),
+                notes: item.notes.clone(),
+                cart_label: item.cart_label.clone(),
+                quantity: item.quantity,
+                unit_price: item.price,
+                line_price: item.line_price(),
+            };
+            match lines.iter_mut().find(|existing| {
+                existing.item_name == line.item_name
+                    && existing.options == line.options
+                    && existing.components == line.components
+                    && existing.notes == line.notes
+                    && existing.cart_label == line.cart_label
+                    && existing.unit_price == line.unit_price
+            }) {
+                Some(existing) => {
+                    existing.quantity += line.quantity;
+                    existing.line_price += line.line_price;
+                }
+                None => lines.push(line),
+            }
+        }
+        OrderSummary {
+            lines,
+            item_count: order.item_count(),
+            total: order.total(),
+        }
+    }
+}
+
+/// Describes the selected options of an item, one entry per option.
+///
+/// # Arguments
+/// * `item` - The item to describe
+///
+/// # Returns
+/// * `Vec<String>` - The options, e.g. "Toppings: bacon, 2x cheese"
+fn describe_options(item: &OrderItem) -> Vec<String> {
+    item.option_keys
+        .iter()
+        .zip(&item.option_values)
+        .map(|(key, values)| {
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            format!("{}: {}", key, values.join(", "))
+        })
+        .collect()
+}
+
+/// Marks the order as waiting for the customer's yes and returns it itemized for the assistant
+/// to read back.
+///
+/// # Arguments
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<(String, bool)>` - The tool output to report back to the assistant, and whether
+///   the order was ready
+fn handle_confirm_function(order: &mut Order) -> AppResult<(String, bool)> {
+    if let Err(AppError::InvalidInput(reason)) = order.ensure_ready() {
+        info!(
+            "Order {} is not ready to confirm: {}",
+            order.order_id, reason
+        );
+        let result = FunctionResult::error(
+            order,
+            "order not ready",
+            format!(
+                "{}. Fix or remove these items with the customer before confirming",
+                reason
+            ),
+        );
+        return Ok((serde_json::to_string(&result)?, false));
+    }
+
+    info!("Order {} is awaiting confirmation", order.order_id);
+    order.awaiting_confirmation = true;
+    let item_ids: Vec<String> = order.order.iter().map(|item| item.id.clone()).collect();
+    let mut result = FunctionResult::success(order, &item_ids, vec![]);
+    result.message = Some(
+        "Read every item and the total back to the customer and ask them to confirm with a yes"
+            .to_string(),
+    );
+    Ok((serde_json::to_string(&result)?, true))
+}
+
+/// Records the itemized summary of the order for front ends, e.g. a payment screen, leaving the
+/// spoken recap to the assistant.
+///
+/// # Arguments
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<(String, bool)>` - The tool output to report back to the assistant, and whether
+///   the order was ready
+fn handle_finalize_function(order: &mut Order) -> AppResult<(String, bool)> {
+    if let Err(AppError::InvalidInput(reason)) = order.ensure_ready() {
+        info!(
+            "Order {} is not ready to finalize: {}",
+            order.order_id, reason
+        );
+        let result = FunctionResult::error(
+            order,
+            "order not ready",
+            format!(
+                "{}. Fix or remove these items with the customer before finalizing",
+                reason
+            ),
+        );
+        return Ok((serde_json::to_string(&result)?, false));
+    }
+
+    let summary = OrderSummary::new(order);
+    info!(
+        "Order {} finalized with {} lines for {}",
+        order.order_id,
+        summary.lines.len(),
+        summary.total
+    );
+    order.summary = Some(summary);
+    let mut result = FunctionResult::success(order, &[], vec![]);
+    result.message = Some(
+        "The summary is shown to the customer, don't read anything from this result".to_string(),
+    );
+    Ok((serde_json::to_string(&result)?, true))
+}
+
+/// Whether a customer's message confirms an order that was read back to them.
+///
+/// # Arguments
+/// * `input` - The customer's message
+///
+/// # Returns
+/// * `bool` - True for a plain yes, e.g. "Yes please!" or "that's right"
+pub fn is_confirmation(input: &str) -> bool {
+    let normalized: String = input
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace() || *c == '\'')
+        .collect();
+    matches!(
+        normalized
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .as_str(),
+        "yes"
+            | "yes please"
+            | "yeah"
+            | "yep"
+            | "yup"
+            | "sure"
+            | "correct"
+            | "confirm"
+            | "that's right"
+            | "that's correct"
+            | "yes that's right"
+            | "yes that's correct"
+    )
+}
+
+/// Builds the tool output telling the assistant that the arguments of a call could not be
+/// parsed, so it can retry the call within the same run.
+///
+/// # Arguments
+/// * `function_name` - The function that was called
+/// * `error` - The error from parsing the arguments
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<String>` - The tool output to report back to the assistant
+fn invalid_arguments(
+    function_name: &FunctionName,
+    error: &serde_json::Error,
+    order: &Order,
+) -> AppResult<String> {
+    warn!(
+        "Invalid arguments for {} in order {}: {}",
+        function_name, order.order_id, error
+    );
+    counter!(telemetry::FUNCTION_CALLS, "function" => function_name.to_string()).increment(1);
+    let result = FunctionResult::error(
+        order,
+        "invalid arguments",
+        format!(
+            "The arguments for {} could not be parsed: {}. Fix them to match the function's schema and call it again",
+            function_name, error
+        ),
+    );
+    Ok(serde_json::to_string(&result)?)
+}
+
+/// Builds the tool output for a function call that failed, so the other calls of the same run
+/// are still reported and the assistant can recover from the failed one.
+///
+/// # Arguments
+/// * `function_call` - The function call that failed
+/// * `error` - The error it failed with
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<String>` - The tool output to report back to the assistant
+pub fn failed_function_output(
+    function_call: &FunctionCall,
+    error: &AppError,
+    order: &Order,
+) -> AppResult<String> {
+    error!(
+        "Function {} failed in order {}: {:?}",
+        function_call.name, order.order_id, error
+    );
+    let result = FunctionResult::error(
+        order,
+        "function failed",
+        format!(
+            "{} could not be completed because of an internal error. Tell the customer it didn't go through and offer to try again",
+            function_call.name
+        ),
+    );
+    Ok(serde_json::to_string(&result)?)
+}
+
+/// Builds the tool output telling the assistant that an order item does not exist, so it can
+/// recover instead of failing the run.
+///
+/// # Arguments
+/// * `item_id` - The ID of the missing item
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<String>` - The tool output to report back to the assistant
+fn item_not_found(item_id: &str, order: &Order) -> AppResult<String> {
+    info!("Item {} not found in order {}", item_id, order.order_id);
+    let result = FunctionResult::error(
+        order,
+        "item not found",
+        format!(
+            "Item {} is not in the order, use list_items to get the current item ids",
+            item_id
+        ),
+    );
+    Ok(serde_json::to_string(&result)?)
+}
+
+/// Builds the tool output telling the assistant which items of a bulk removal were not removed,
+/// alongside the ones that were.
+///
+/// # Arguments
+/// * `failures` - Why each item could not be removed
+/// * `removed_ids` - IDs of the items that were removed
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<String>` - The tool output to report back to the assistant
+fn items_not_removed(
+    failures: &[String],
+    removed_ids: Vec<String>,
+    order: &Order,
+) -> AppResult<String> {
+    info!(
+        "Could not remove {} items from order {}: {:?}",
+        failures.len(),
+        order.order_id,
+        failures
+    );
+    let mut result = FunctionResult::success(order, &[], removed_ids);
+    result.success = false;
+    result.error = Some("items not removed".to_string());
+    result.message = Some(format!(
+        "{}. Use list_items to get the current item ids, or ask the customer which one they mean",
+        failures.join("; ")
+    ));
+    Ok(serde_json::to_string(&result)?)
+}
+
+/// Builds the tool output asking the assistant to clarify which of several items was meant.
+///
+/// # Arguments
+/// * `message` - Description of the matching items
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<String>` - The tool output to report back to the assistant
+fn ambiguous_item(message: &str, order: &Order) -> AppResult<String> {
+    info!("Ambiguous item in order {}: {}", order.order_id, message);
+    let result = FunctionResult::error(
+        order,
+        "ambiguous item",
+        format!(
+            "{}. Ask the customer which one they mean and retry with its orderId",
+            message
+        ),
+    );
+    Ok(serde_json::to_string(&result)?)
+}
+
+/// Builds the tool output telling the assistant that the order has no item to undo.
+///
+/// # Arguments
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<String>` - The tool output to report back to the assistant
+fn order_empty(order: &Order) -> AppResult<String> {
+    info!("Order {} has no item to undo", order.order_id);
+    let result = FunctionResult::error(
+        order,
+        "order empty",
+        "The order has no items, so there is nothing to remove".to_string(),
+    );
+    Ok(serde_json::to_string(&result)?)
+}
+
+/// Builds the tool output telling the assistant that the order cannot hold any more items.
+///
+/// # Arguments
+/// * `message` - Description of the item limit
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<String>` - The tool output to report back to the assistant
+fn order_full(message: &str, order: &Order) -> AppResult<String> {
+    info!("Order {} is full: {}", order.order_id, message);
+    let result = FunctionResult::error(
+        order,
+        "order full",
+        format!(
+            "{}. Tell the customer the cart is full and no more items can be added",
+            message
+        ),
+    );
+    Ok(serde_json::to_string(&result)?)
+}
+
+/// Builds the tool output telling the assistant that an item could not be split.
+///
+/// # Arguments
+/// * `message` - Why the item could not be split
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<String>` - The tool output to report back to the assistant
+fn not_split(message: &str, order: &Order) -> AppResult<String> {
+    info!("Item not split in order {}: {}", order.order_id, message);
+    let result = FunctionResult::error(
+        order,
+        "item not split",
+        format!("{}. Change the item as it is instead", message),
+    );
+    Ok(serde_json::to_string(&result)?)
+}
+
+/// Builds the tool output telling the assistant that items could not be merged.
+///
+/// # Arguments
+/// * `message` - Why the items could not be merged
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<String>` - The tool output to report back to the assistant
+fn not_merged(message: &str, order: &Order) -> AppResult<String> {
+    info!("Items not merged in order {}: {}", order.order_id, message);
+    let result = FunctionResult::error(
+        order,
+        "items not merged",
+        format!(
+            "{}. Use list_items to find the lines holding the exact same item",
+            message
+        ),
+    );
+    Ok(serde_json::to_string(&result)?)
 }
 
 /// Processes an add item function call.
 ///
 /// # Arguments
 /// * `function_args` - The arguments for adding an item
+/// * `max_items` - Largest number of items the order can hold
 /// * `order` - The current order state
 ///
 /// # Returns
-/// * `AppResult<&mut Order>` - The updated order with the new item
-pub async fn handle_add_function<'a>(
+/// * `AppResult<String>` - The ID of the new item, or `OrderFull`
+pub async fn handle_add_function(
     function_args: &FunctionArgs,
-    order: &'a mut Order,
-) -> AppResult<&'a mut Order> {
-    if let FunctionArgs::AddItem(AddItemArgs {
-        item_name,
-        option_keys,
-        option_values,
-        price,
-    }) = function_args
+    max_items: usize,
+    order: &mut Order,
+) -> AppResult<String> {
+    if let FunctionArgs::AddItem(
+        args @ AddItemArgs {
+            item_name,
+            option_keys,
+            option_values,
+            price,
+            components,
+            notes,
+            cart_label,
+            ..
+        },
+    ) = function_args
     {
         info!("Adding item '{}' to order", item_name);
+        let quantity = args.quantity();
+        order.ensure_capacity(quantity as usize, max_items)?;
         debug!(
             "Item details - Price: {}, Options: {:?}",
             price, option_keys
@@ -187,9 +1317,13 @@ pub async fn handle_add_function<'a>(
             },
             price: *price,
             item_status: None,
+            components: components.iter().flatten().map(OrderItem::from).collect(),
+            notes: notes.clone(),
+            cart_label: cart_label.clone(),
+            quantity,
         });
         info!("Successfully added item {} to order", item_id);
-        return Ok(order);
+        return Ok(item_id);
     }
     error!("Invalid arguments for add_item function");
     Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
@@ -197,25 +1331,112 @@ pub async fn handle_add_function<'a>(
     )))
 }
 
+/// Canonicalizes every item of an order against the menu and records its validation status.
+///
+/// # Arguments
+/// * `order` - The order whose items are validated
+/// * `menu` - The restaurant menu
+///
+/// # Returns
+/// * `AppResult<()>` - Success once every item has a status
+pub fn validate_items(order: &mut Order, menu: &Menu) -> AppResult<()> {
+    debug!("Validating order items {:?}", order);
+    for item in &mut order.order {
+        menu.canonicalize_item(item);
+        let status = menu.validate_item(&item.to_owned())?;
+        for component in &mut item.components {
+            component.item_status = Some(menu.validate_item(&component.to_owned())?);
+        }
+        let label = match &status {
+            ItemStatus::Incomplete(_) => "incomplete",
+            ItemStatus::Complete(_) => "complete",
+            ItemStatus::Invalid(_) => "invalid",
+        };
+        counter!(telemetry::ITEM_VALIDATIONS, "status" => label).increment(1);
+        item.item_status = Some(status);
+    }
+    debug!("Validated order items {:?}", order);
+    Ok(())
+}
+
 /// Processes a remove item function call.
 ///
+/// Items are matched by ID first. If no ID matches, the most recently added item with the given
+/// name is removed, unless items with that name differ in their options.
+///
 /// # Arguments
 /// * `function_args` - The arguments for removing an item
 /// * `order` - The current order state
 ///
 /// # Returns
-/// * `AppResult<&mut Order>` - The updated order with the item removed
-pub async fn handle_remove_function<'a>(
+/// * `AppResult<String>` - The ID of the removed item, `OrderItemNotFound` or
+///   `AmbiguousOrderItem`
+pub async fn handle_remove_function(
     function_args: &FunctionArgs,
-    order: &'a mut Order,
-) -> AppResult<&'a mut Order> {
-    if let FunctionArgs::RemoveItem(RemoveItemArgs { order_id }) = function_args {
-        info!("Removing item {} from order", order_id);
-        let initial_count = order.order.len();
-        order.order.retain(|item| item.id != *order_id);
-        let removed_count = initial_count - order.order.len();
-        debug!("Removed {} items from order", removed_count);
-        return Ok(order);
+    order: &mut Order,
+) -> AppResult<String> {
+    if let FunctionArgs::RemoveItem(RemoveItemArgs {
+        order_id,
+        item_name,
+    }) = function_args
+    {
+        if let Some(order_id) = order_id {
+            info!("Removing item {} from order", order_id);
+            if let Some(index) = order.order.iter().position(|item| item.id == *order_id) {
+                order.order.remove(index);
+                debug!("Removed item {} from order", order_id);
+                return Ok(order_id.clone());
+            }
+        }
+
+        let item_name = match item_name {
+            Some(item_name) => item_name,
+            None => {
+                return Err(AppError::OrderItemNotFound(
+                    order_id.clone().unwrap_or_default(),
+                ))
+            }
+        };
+        info!("Removing item by name '{}' from order", item_name);
+        let matches: Vec<usize> = order
+            .order
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.item_name.eq_ignore_ascii_case(item_name))
+            .map(|(index, _)| index)
+            .collect();
+        let last = match matches.last() {
+            Some(last) => *last,
+            None => return Err(AppError::OrderItemNotFound(item_name.clone())),
+        };
+
+        let latest = &order.order[last];
+        let ambiguous = matches.iter().any(|index| {
+            let item = &order.order[*index];
+            item.option_keys != latest.option_keys || item.option_values != latest.option_values
+        });
+        if ambiguous {
+            let candidates: Vec<String> = matches
+                .iter()
+                .map(|index| {
+                    let item = &order.order[*index];
+                    format!(
+                        "{} ({:?}: {:?})",
+                        item.id, item.option_keys, item.option_values
+                    )
+                })
+                .collect();
+            return Err(AppError::AmbiguousOrderItem(format!(
+                "'{}' matches {} items with different options: {}",
+                item_name,
+                matches.len(),
+                candidates.join(", ")
+            )));
+        }
+
+        let removed = order.order.remove(last);
+        debug!("Removed item {} by name '{}'", removed.id, item_name);
+        return Ok(removed.id);
     }
     error!("Invalid arguments for remove_item function");
     Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
@@ -230,17 +1451,22 @@ pub async fn handle_remove_function<'a>(
 /// * `order` - The current order state
 ///
 /// # Returns
-/// * `AppResult<&mut Order>` - The updated order with the modified item
-pub async fn handle_modify_function<'a>(
+/// * `AppResult<String>` - The ID of the modified item, or `OrderItemNotFound`
+pub async fn handle_modify_function(
     function_args: &FunctionArgs,
-    order: &'a mut Order,
-) -> AppResult<&'a mut Order> {
+    order: &mut Order,
+) -> AppResult<String> {
     if let FunctionArgs::ModifyItem(ModifyItemArgs {
         order_id,
-        item_name,
-        option_keys,
-        option_values,
-        price,
+        item:
+            ModifiedItemArgs {
+                item_name,
+                option_keys,
+                option_values,
+                price,
+                components,
+                notes,
+            },
     }) = function_args
     {
         info!("Modifying item {} in order", order_id);
@@ -250,9 +1476,7 @@ pub async fn handle_modify_function<'a>(
             .order
             .iter_mut()
             .find(|item| item.id == *order_id)
-            .ok_or(AppError::OpenAIError(OpenAIError::InvalidArgument(
-                "Item not found".to_string(),
-            )))?;
+            .ok_or_else(|| AppError::OrderItemNotFound(order_id.clone()))?;
 
         debug!("Updating item properties");
         item.item_name = item_name.clone();
@@ -265,8 +1489,10 @@ pub async fn handle_modify_function<'a>(
             None => vec![],
         };
         item.price = *price;
+        item.components = components.iter().flatten().map(OrderItem::from).collect();
+        item.notes = notes.clone();
         info!("Successfully modified item {}", order_id);
-        return Ok(order);
+        return Ok(order_id.clone());
     }
     error!("Invalid arguments for modify_item function");
     Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
@@ -274,25 +1500,1289 @@ pub async fn handle_modify_function<'a>(
     )))
 }
 
+/// Processes a split item function call, replacing a line of several identical items with one
+/// line per item.
+///
+/// Each new line keeps the options, components, notes and cart label of the original and gets a
+/// new ID, in the original line's place.
+///
+/// # Arguments
+/// * `function_args` - The arguments for splitting an item
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<(String, Vec<String>)>` - The ID of the split line and the IDs of the new lines,
+///   `OrderItemNotFound`, or `InvalidInput` if the line holds a single item
+pub async fn handle_split_function(
+    function_args: &FunctionArgs,
+    order: &mut Order,
+) -> AppResult<(String, Vec<String>)> {
+    if let FunctionArgs::SplitItem(SplitItemArgs { order_id }) = function_args {
+        info!("Splitting item {} in order", order_id);
+        let index = order
+            .order
+            .iter()
+            .position(|item| item.id == *order_id)
+            .ok_or_else(|| AppError::OrderItemNotFound(order_id.clone()))?;
+        let quantity = order.order[index].quantity;
+        if quantity < 2 {
+            return Err(AppError::InvalidInput(format!(
+                "Item {} is a single item, so there is nothing to split",
+                order_id
+            )));
+        }
+
+        let item = order.order.remove(index);
+        let lines: Vec<OrderItem> = (0..quantity)
+            .map(|_| OrderItem {
+                quantity: 1,
+                ..item.with_new_id()
+            })
+            .collect();
+        let new_ids: Vec<String> = lines.iter().map(|line| line.id.clone()).collect();
+        order.order.splice(index..index, lines);
+        info!("Split item {} into {} lines", order_id, quantity);
+        return Ok((order_id.clone(), new_ids));
+    }
+    error!("Invalid arguments for split_item function");
+    Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
+        "Invalid function arguments".to_string(),
+    )))
+}
+
+/// Processes a merge items function call, combining identical lines into the first of them.
+///
+/// # Arguments
+/// * `function_args` - The arguments for merging items
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<(String, Vec<String>)>` - The ID of the line the others were merged into and
+///   the IDs of the merged lines, `OrderItemNotFound`, or `InvalidInput` if fewer than two
+///   lines were given or they don't hold the same item
+pub async fn handle_merge_function(
+    function_args: &FunctionArgs,
+    order: &mut Order,
+) -> AppResult<(String, Vec<String>)> {
+    if let FunctionArgs::MergeItems(MergeItemsArgs { order_ids }) = function_args {
+        info!("Merging items {:?} in order", order_ids);
+        let mut ids: Vec<&String> = vec![];
+        for id in order_ids {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        let Some((kept_id, merged_ids)) = ids.split_first() else {
+            return Err(AppError::InvalidInput(
+                "No items were given to merge".to_string(),
+            ));
+        };
+        if merged_ids.is_empty() {
+            return Err(AppError::InvalidInput(
+                "Only one item was given, at least two are needed to merge".to_string(),
+            ));
+        }
+
+        let find = |id: &String| {
+            order
+                .order
+                .iter()
+                .find(|item| item.id == *id)
+                .ok_or_else(|| AppError::OrderItemNotFound(id.clone()))
+        };
+        let kept = find(kept_id)?;
+        let mut quantity = kept.quantity;
+        for id in merged_ids {
+            let item = find(id)?;
+            if !kept.is_identical(item) {
+                return Err(AppError::InvalidInput(format!(
+                    "Item {} is not the same as item {}, only lines with the same item, options, components, notes and cart label can be merged",
+                    id, kept_id
+                )));
+            }
+            quantity = quantity.saturating_add(item.quantity);
+        }
+
+        order.order.retain(|item| !merged_ids.contains(&&item.id));
+        if let Some(kept) = order.order.iter_mut().find(|item| item.id == **kept_id) {
+            kept.quantity = quantity;
+        }
+        info!(
+            "Merged {} lines into item {} with quantity {}",
+            merged_ids.len(),
+            kept_id,
+            quantity
+        );
+        return Ok((
+            (*kept_id).clone(),
+            merged_ids.iter().map(|id| (*id).clone()).collect(),
+        ));
+    }
+    error!("Invalid arguments for merge_items function");
+    Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
+        "Invalid function arguments".to_string(),
+    )))
+}
+
+/// Processes a patch item function call, merging the given options into an existing item.
+///
+/// Options in `set` replace the values of that option, options in `unset` are removed, and
+/// every other option is kept. The item is canonicalized and its price recomputed from the menu
+/// afterwards.
+///
+/// # Arguments
+/// * `function_args` - The arguments for patching an item
+/// * `menu` - The restaurant menu
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<(String, Vec<String>)>` - The ID of the patched item and the values in
+///   `remove_values` the item didn't have, or `OrderItemNotFound`
+pub async fn handle_patch_function(
+    function_args: &FunctionArgs,
+    menu: &Menu,
+    order: &mut Order,
+) -> AppResult<(String, Vec<String>)> {
+    if let FunctionArgs::PatchItem(PatchItemArgs {
+        order_id,
+        set,
+        unset,
+        remove_values,
+    }) = function_args
+    {
+        info!("Patching item {} in order", order_id);
+        debug!(
+            "Setting {:?}, unsetting {:?}, removing values {:?}",
+            set, unset, remove_values
+        );
+
+        let item = order
+            .order
+            .iter_mut()
+            .find(|item| item.id == *order_id)
+            .ok_or_else(|| AppError::OrderItemNotFound(order_id.clone()))?;
+
+        // NOTE(dev): Keep keys and values aligned so the indexes below stay in bounds
+        item.option_values
+            .resize(item.option_keys.len(), Vec::new());
+        for key in unset {
+            if let Some(index) = item.option_keys.iter().position(|k| k == key) {
+                item.option_keys.remove(index);
+                item.option_values.remove(index);
+            }
+        }
+        for patch in set {
+            match item.option_keys.iter().position(|k| *k == patch.option_key) {
+                Some(index) => item.option_values[index] = patch.option_values.clone(),
+                None => {
+                    item.option_keys.push(patch.option_key.clone());
+                    item.option_values.push(patch.option_values.clone());
+                }
+            }
+        }
+        let mut not_removed = vec![];
+        for removal in remove_values {
+            let Some(index) = item
+                .option_keys
+                .iter()
+                .position(|k| *k == removal.option_key)
+            else {
+                debug!(
+                    "Item {} has no option {} to remove {} from",
+                    order_id, removal.option_key, removal.value
+                );
+                not_removed.push(format!("{}: {}", removal.option_key, removal.value));
+                continue;
+            };
+            // NOTE(dev): Stored values are canonical, so the value is resolved the same way before
+            //            comparing, e.g. "No Onion" removes "no onions"
+            let value = menu
+                .get_item(&item.item_name)
+                .and_then(|menu_item| menu_item.options.get(&removal.option_key))
+                .and_then(|option| option.resolve_choice(&removal.value))
+                .unwrap_or(&removal.value);
+            let before = item.option_values[index].len();
+            item.option_values[index].retain(|v| v.value != value);
+            if item.option_values[index].len() == before {
+                debug!(
+                    "Item {} has no value {} for option {}",
+                    order_id, removal.value, removal.option_key
+                );
+                not_removed.push(format!("{}: {}", removal.option_key, removal.value));
+            }
+            // NOTE(dev): Dropping the emptied option lets validation report a required option as
+            //            incomplete instead of failing the call
+            if item.option_values[index].is_empty() {
+                item.option_keys.remove(index);
+                item.option_values.remove(index);
+            }
+        }
+
+        // NOTE(dev): Patched values may be spelled like the customer said them, resolve them to
+        //            the menu's choices first or the item can't be priced
+        menu.canonicalize_item(item);
+        if let Some(price) = menu.price_item(item) {
+            debug!(
+                "Repriced item {} from {} to {}",
+                order_id, item.price, price
+            );
+            item.price = price;
+        }
+        info!("Successfully patched item {}", order_id);
+        return Ok((order_id.clone(), not_removed));
+    }
+    error!("Invalid arguments for patch_item function");
+    Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
+        "Invalid function arguments".to_string(),
+    )))
+}
+
 /// Processes a list items function call.
 ///
+/// Listing is read-only: the order itself is never modified, a `limit` only
+/// truncates the view that is reported back to the assistant.
+///
 /// # Arguments
 /// * `function_args` - The arguments for listing items
 /// * `order` - The current order state
 ///
 /// # Returns
-/// * `AppResult<&mut Order>` - The order with potentially filtered items
-pub async fn handle_list_function<'a>(
+/// * `AppResult<String>` - The `FunctionResult` with the listed items, serialized for the
+///   assistant
+pub async fn handle_list_function(
     function_args: &FunctionArgs,
-    order: &'a mut Order,
-) -> AppResult<&'a mut Order> {
+    order: &Order,
+) -> AppResult<String> {
     if let FunctionArgs::ListItems(ListItemsArgs { limit }) = function_args {
-        if let Some(limit) = limit {
-            order.order = order.order.iter().take(*limit).cloned().collect();
-        }
-        return Ok(order);
+        let limit = limit.unwrap_or(order.order.len());
+        debug!("Listing {} of {} items", limit, order.order.len());
+        let mut result = FunctionResult::success(order, &[], vec![]);
+        result.listed_items = order.order.iter().take(limit).cloned().collect();
+        result.truncated = result.listed_items.len() < order.order.len();
+        return Ok(serde_json::to_string(&result)?);
     }
     Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
         "Invalid function arguments".to_string(),
     )))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::AssistantInfo;
+    use crate::mock::MockAssistant;
+    use crate::order::{ItemState, MAX_UNDO_SNAPSHOTS};
+    use crate::testing::{add_args, call, item, limits, menu, order, store};
+    use async_trait::async_trait;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use metrics_exporter_prometheus::PrometheusHandle;
+    use serde_json::{json, Value};
+
+    /// Adds three small Cokes as a single line and returns the line's ID.
+    async fn add_three_cokes(menu: &Menu, order: &mut Order) -> String {
+        let mut args = add_args("Coke", &[("size", "small")]);
+        args["quantity"] = json!(3);
+        let result = call(menu, order, FunctionName::AddItem, json!({ "item": args })).await;
+        assert!(result.success);
+        result.items[0].item_id.clone()
+    }
+
+    #[tokio::test]
+    async fn add_item_with_a_quantity_prices_the_whole_line() {
+        let menu = menu();
+        let mut order = order();
+        add_three_cokes(&menu, &mut order).await;
+
+        assert_eq!(order.order.len(), 1);
+        assert_eq!(order.order[0].quantity, 3);
+        assert_eq!(order.item_count(), 3);
+        assert_eq!(order.total(), order.order[0].price * 3);
+    }
+
+    #[tokio::test]
+    async fn split_item_turns_three_into_three_single_lines() {
+        let menu = menu();
+        let mut order = order();
+        let id = add_three_cokes(&menu, &mut order).await;
+        let total = order.total();
+
+        let result = call(
+            &menu,
+            &mut order,
+            FunctionName::SplitItem,
+            json!({ "orderId": id }),
+        )
+        .await;
+
+        assert!(result.success);
+        assert_eq!(result.removed_item_ids, vec![id.clone()]);
+        assert_eq!(result.items.len(), 3);
+        assert_eq!(order.order.len(), 3);
+        for item in &order.order {
+            assert_eq!(item.quantity, 1);
+            assert_eq!(item.item_name, "Coke");
+            assert_eq!(item.option_keys, vec!["size".to_string()]);
+            assert_eq!(item.option_values[0][0].value, "small");
+            assert_ne!(item.id, id);
+        }
+        let mut ids: Vec<&str> = order.order.iter().map(|item| item.id.as_str()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(order.item_count(), 3);
+        assert_eq!(order.total(), total);
+    }
+
+    #[tokio::test]
+    async fn merge_items_combines_the_split_lines_back() {
+        let menu = menu();
+        let mut order = order();
+        let id = add_three_cokes(&menu, &mut order).await;
+        let total = order.total();
+        call(
+            &menu,
+            &mut order,
+            FunctionName::SplitItem,
+            json!({ "orderId": id }),
+        )
+        .await;
+        let ids: Vec<String> = order.order.iter().map(|item| item.id.clone()).collect();
+
+        let result = call(
+            &menu,
+            &mut order,
+            FunctionName::MergeItems,
+            json!({ "orderIds": ids }),
+        )
+        .await;
+
+        assert!(result.success);
+        assert_eq!(result.items.len(), 1);
+        assert_eq!(result.items[0].item_id, ids[0]);
+        assert_eq!(result.items[0].quantity, 3);
+        assert_eq!(result.removed_item_ids, ids[1..].to_vec());
+        assert_eq!(order.order.len(), 1);
+        assert_eq!(order.order[0].id, ids[0]);
+        assert_eq!(order.order[0].quantity, 3);
+        assert_eq!(order.total(), total);
+    }
+
+    #[tokio::test]
+    async fn split_item_refuses_a_single_item() {
+        let menu = menu();
+        let mut order = order();
+        let added = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Coke", &[("size", "small")]) }),
+        )
+        .await;
+        let snapshots = order.undo_snapshots.len();
+
+        let result = call(
+            &menu,
+            &mut order,
+            FunctionName::SplitItem,
+            json!({ "orderId": added.items[0].item_id }),
+        )
+        .await;
+
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("item not split"));
+        assert_eq!(order.order.len(), 1);
+        assert_eq!(order.undo_snapshots.len(), snapshots);
+    }
+
+    #[tokio::test]
+    async fn merge_items_refuses_different_items() {
+        let menu = menu();
+        let mut order = order();
+        let small = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Coke", &[("size", "small")]) }),
+        )
+        .await;
+        let large = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Coke", &[("size", "large")]) }),
+        )
+        .await;
+
+        let result = call(
+            &menu,
+            &mut order,
+            FunctionName::MergeItems,
+            json!({ "orderIds": [small.items[0].item_id, large.items[0].item_id] }),
+        )
+        .await;
+
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("items not merged"));
+        assert_eq!(order.order.len(), 2);
+
+        let result = call(
+            &menu,
+            &mut order,
+            FunctionName::MergeItems,
+            json!({ "orderIds": [small.items[0].item_id, small.items[0].item_id] }),
+        )
+        .await;
+        assert!(!result.success);
+        assert_eq!(order.order.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn read_order_reports_line_quantities() {
+        let menu = menu();
+        let mut order = order();
+        add_three_cokes(&menu, &mut order).await;
+
+        let summary = OrderSummary::new(&order);
+
+        assert_eq!(summary.lines.len(), 1);
+        assert_eq!(summary.lines[0].quantity, 3);
+        assert_eq!(summary.lines[0].line_price, summary.lines[0].unit_price * 3);
+        assert_eq!(summary.item_count, 3);
+    }
+
+    #[tokio::test]
+    async fn undo_after_two_adds_keeps_only_the_first() {
+        let menu = menu();
+        let mut order = order();
+        let first = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Apple Pie", &[]) }),
+        )
+        .await;
+        call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Red Bull", &[]) }),
+        )
+        .await;
+
+        order.undo().unwrap();
+
+        assert_eq!(order.order.len(), 1);
+        assert_eq!(order.order[0].id, first.items[0].item_id);
+        assert_eq!(order.order[0].item_name, "Apple Pie");
+    }
+
+    #[tokio::test]
+    async fn calls_that_change_nothing_keep_the_confirmation() {
+        let menu = menu();
+        let mut order = order();
+        call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Apple Pie", &[]) }),
+        )
+        .await;
+        order.awaiting_confirmation = true;
+        let snapshots = order.undo_snapshots.len();
+
+        let result = call(
+            &menu,
+            &mut order,
+            FunctionName::RemoveItem,
+            json!({ "orderId": "missing", "itemName": null }),
+        )
+        .await;
+
+        assert!(!result.success);
+        assert!(order.awaiting_confirmation);
+        assert_eq!(order.undo_snapshots.len(), snapshots);
+    }
+
+    #[tokio::test]
+    async fn adding_an_item_with_upsells_suggests_them() {
+        let mut menu = menu();
+        let pie = menu
+            .items
+            .iter_mut()
+            .find(|item| item.item_name == "Apple Pie")
+            .unwrap();
+        pie.upsells = vec!["Milk".to_string(), "Red Bull".to_string()];
+        let mut order = order();
+
+        let milk = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Milk", &[]) }),
+        )
+        .await;
+        assert!(milk.suggestions.is_empty());
+
+        let pie = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Apple Pie", &[]) }),
+        )
+        .await;
+        assert!(pie.success);
+        assert_eq!(pie.suggestions, ["Red Bull"]);
+        assert_eq!(order.suggestions, ["Red Bull"]);
+    }
+
+    #[tokio::test]
+    async fn finalize_records_the_summary() {
+        let menu = menu();
+        let mut order = order();
+        add_three_cokes(&menu, &mut order).await;
+        call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Apple Pie", &[]) }),
+        )
+        .await;
+        assert!(order.summary.is_none());
+
+        let result = call(&menu, &mut order, FunctionName::Finalize, json!({})).await;
+
+        assert!(result.success);
+        let summary = order.summary.clone().unwrap();
+        assert_eq!(summary, OrderSummary::new(&order));
+        assert_eq!(summary.item_count, 4);
+        assert_eq!(summary.total, order.total());
+        let lines: Vec<(&str, u32)> = summary
+            .lines
+            .iter()
+            .map(|line| (line.item_name.as_str(), line.quantity))
+            .collect();
+        assert_eq!(lines, [("Coke", 3), ("Apple Pie", 1)]);
+    }
+
+    #[tokio::test]
+    async fn changes_drop_the_confirmation() {
+        let menu = menu();
+        let mut order = order();
+        call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Apple Pie", &[]) }),
+        )
+        .await;
+        order.awaiting_confirmation = true;
+
+        call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Red Bull", &[]) }),
+        )
+        .await;
+
+        assert!(!order.awaiting_confirmation);
+        assert!(order.summary.is_none());
+    }
+
+    #[tokio::test]
+    async fn undo_last_item_removes_only_the_last_of_three() {
+        let menu = menu();
+        let mut order = order();
+        for item_name in ["Apple Pie", "Red Bull", "Milk"] {
+            call(
+                &menu,
+                &mut order,
+                FunctionName::AddItem,
+                json!({ "item": add_args(item_name, &[]) }),
+            )
+            .await;
+        }
+        let last_id = order.order[2].id.clone();
+
+        let result = call(&menu, &mut order, FunctionName::UndoLastItem, json!({})).await;
+
+        assert!(result.success);
+        assert_eq!(result.removed_item_ids, vec![last_id]);
+        let names: Vec<&str> = order
+            .order
+            .iter()
+            .map(|item| item.item_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Apple Pie", "Red Bull"]);
+
+        order.undo().unwrap();
+        assert_eq!(order.order.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn failed_calls_keep_a_full_undo_history() {
+        let menu = menu();
+        let mut order = order();
+        for _ in 0..=MAX_UNDO_SNAPSHOTS {
+            call(
+                &menu,
+                &mut order,
+                FunctionName::AddItem,
+                json!({ "item": add_args("Apple Pie", &[]) }),
+            )
+            .await;
+        }
+        assert_eq!(order.undo_snapshots.len(), MAX_UNDO_SNAPSHOTS);
+        let history = order.undo_snapshots.clone();
+
+        let missing = json!({ "orderId": "missing", "itemName": null });
+        call(&menu, &mut order, FunctionName::RemoveItem, missing).await;
+        order.order.clear();
+        call(&menu, &mut order, FunctionName::UndoLastItem, json!({})).await;
+
+        assert_eq!(order.undo_snapshots.len(), MAX_UNDO_SNAPSHOTS);
+        let lengths = |snapshots: &[Vec<OrderItem>]| -> Vec<usize> {
+            snapshots.iter().map(Vec::len).collect()
+        };
+        assert_eq!(lengths(&order.undo_snapshots), lengths(&history));
+    }
+
+    #[tokio::test]
+    async fn list_items_with_a_limit_leaves_the_order_alone() {
+        let menu = menu();
+        let mut order = order();
+        for item_name in ["Apple Pie", "Red Bull", "Milk"] {
+            call(
+                &menu,
+                &mut order,
+                FunctionName::AddItem,
+                json!({ "item": add_args(item_name, &[]) }),
+            )
+            .await;
+        }
+
+        let result = call(
+            &menu,
+            &mut order,
+            FunctionName::ListItems,
+            json!({ "limit": 2 }),
+        )
+        .await;
+
+        assert!(result.success);
+        assert!(result.truncated);
+        assert_eq!(result.item_count, 3);
+        let listed: Vec<&str> = result
+            .listed_items
+            .iter()
+            .map(|item| item.item_name.as_str())
+            .collect();
+        assert_eq!(listed, vec!["Apple Pie", "Red Bull"]);
+        assert_eq!(order.order.len(), 3);
+
+        let result = call(
+            &menu,
+            &mut order,
+            FunctionName::ListItems,
+            json!({ "limit": null }),
+        )
+        .await;
+        assert!(!result.truncated);
+        assert_eq!(result.listed_items.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn stale_item_ids_are_reported_to_the_model() {
+        let menu = menu();
+        let mut order = order();
+        add_three_cokes(&menu, &mut order).await;
+
+        let modify =
+            json!({ "orderId": "stale-id", "item": add_args("Coke", &[("size", "large")]) });
+        let modified = call(&menu, &mut order, FunctionName::ModifyItem, modify).await;
+        let removed = call(
+            &menu,
+            &mut order,
+            FunctionName::RemoveItem,
+            json!({ "orderId": "stale-id", "itemName": null }),
+        )
+        .await;
+
+        for result in [modified, removed] {
+            assert!(!result.success);
+            assert_eq!(result.error.as_deref(), Some("item not found"));
+            assert!(result.message.unwrap().contains("stale-id"));
+            assert_eq!(result.item_count, 3);
+        }
+        assert_eq!(order.order.len(), 1);
+        assert_eq!(order.order[0].option_values[0][0].value, "small");
+    }
+
+    #[tokio::test]
+    async fn clear_order_empties_three_items_and_keeps_the_conversation() {
+        let menu = menu();
+        let mut order = order();
+        order.thread_id = Some("thread-1".to_string());
+        order.messages.push(ChatMessage {
+            role: ChatRole::User.to_string(),
+            content: "start over".to_string(),
+        });
+        for item_name in ["Apple Pie", "Red Bull", "Milk"] {
+            call(
+                &menu,
+                &mut order,
+                FunctionName::AddItem,
+                json!({ "item": add_args(item_name, &[]) }),
+            )
+            .await;
+        }
+
+        let result = call(&menu, &mut order, FunctionName::ClearOrder, json!({})).await;
+
+        assert!(result.success);
+        assert_eq!(result.removed_item_ids.len(), 3);
+        assert_eq!(result.item_count, 0);
+        assert!(order.order.is_empty());
+        assert_eq!(order.thread_id.as_deref(), Some("thread-1"));
+        assert_eq!(order.messages.len(), 1);
+        assert_eq!(order.undo_snapshots.last().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn patch_item_merges_a_new_option_into_the_existing_ones() {
+        let menu = menu();
+        let mut order = order();
+        let added = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args(
+                "Grilled Organic Chicken Breast Sandwich",
+                &[("sauce", "mayo"), ("meal option", "meal")],
+            ) }),
+        )
+        .await;
+        let item_id = added.items[0].item_id.clone();
+
+        let patched = call(
+            &menu,
+            &mut order,
+            FunctionName::PatchItem,
+            json!({
+                "orderId": item_id,
+                "set": [{ "optionKey": "drink options", "optionValues": ["banana shake"] }],
+                "unset": [],
+                "removeValues": [],
+            }),
+        )
+        .await;
+
+        assert!(patched.success);
+        let item = &order.order[0];
+        assert_eq!(item.option_keys, ["sauce", "meal option", "drink options"]);
+        assert_eq!(item.option_values[0][0].value, "mayo");
+        assert_eq!(item.option_values[1][0].value, "meal");
+        assert_eq!(item.option_values[2][0].value, "banana shake");
+        assert_eq!(item.price, Money::from_cents(1450 + 323));
+        assert_eq!(patched.items[0].price, item.price);
+    }
+
+    #[tokio::test]
+    async fn patch_item_unsets_one_option_and_keeps_the_rest() {
+        let menu = menu();
+        let mut order = order();
+        let added = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args(
+                "Grilled Organic Chicken Breast Sandwich",
+                &[
+                    ("sauce", "mayo"),
+                    ("meal option", "a la carte"),
+                    ("drink options", "banana shake"),
+                ],
+            ) }),
+        )
+        .await;
+
+        call(
+            &menu,
+            &mut order,
+            FunctionName::PatchItem,
+            json!({
+                "orderId": added.items[0].item_id,
+                "set": [],
+                "unset": ["drink options"],
+                "removeValues": [],
+            }),
+        )
+        .await;
+
+        let item = &order.order[0];
+        assert_eq!(item.option_keys, ["sauce", "meal option"]);
+        assert_eq!(item.price, Money::from_cents(850));
+    }
+
+    /// Runs a function call against an order holding at most three items.
+    async fn call_with_three_items_max(
+        menu: &Menu,
+        order: &mut Order,
+        name: FunctionName,
+        arguments: Value,
+    ) -> FunctionResult {
+        let limits = OrderLimits {
+            max_items: 3,
+            ..limits()
+        };
+        let function_call = FunctionCall {
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        };
+        let output = handle_function_call(&function_call, menu, &limits, order)
+            .await
+            .unwrap();
+        serde_json::from_str(&output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn adds_past_the_item_limit_are_refused() {
+        let menu = menu();
+        let mut order = order();
+        for _ in 0..3 {
+            let args = add_args("Apple Pie", &[]);
+            let result = call_with_three_items_max(
+                &menu,
+                &mut order,
+                FunctionName::AddItem,
+                json!({ "item": args }),
+            )
+            .await;
+            assert!(result.success);
+        }
+
+        let args = add_args("Apple Pie", &[]);
+        let result = call_with_three_items_max(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": args }),
+        )
+        .await;
+
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("order full"));
+        assert!(result.message.unwrap().contains("cart is full"));
+        assert_eq!(order.order.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn batches_and_quantities_past_the_item_limit_are_refused_whole() {
+        let menu = menu();
+        let mut order = order();
+        let args = add_args("Apple Pie", &[]);
+        call_with_three_items_max(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": args }),
+        )
+        .await;
+
+        let batch = json!({ "items": [add_args("Milk", &[]), add_args("Red Bull", &[]), add_args("Apple Pie", &[])] });
+        let batched =
+            call_with_three_items_max(&menu, &mut order, FunctionName::AddItems, batch).await;
+        let mut args = add_args("Milk", &[]);
+        args["quantity"] = json!(3);
+        let quantity = call_with_three_items_max(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": args }),
+        )
+        .await;
+
+        assert!(!batched.success);
+        assert!(!quantity.success);
+        assert_eq!(order.order.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn malformed_arguments_get_a_recoverable_tool_output() {
+        let menu = menu();
+        let mut order = order();
+        for arguments in [
+            r#"{"itemName": "Coke", "optionKeys": ["size""#,
+            r#"{"itemName": 3}"#,
+        ] {
+            let function_call = FunctionCall {
+                name: FunctionName::AddItem.to_string(),
+                arguments: arguments.to_string(),
+            };
+
+            let output = handle_function_call(&function_call, &menu, &limits(), &mut order)
+                .await
+                .expect("malformed arguments must not fail the run");
+
+            let result: FunctionResult = serde_json::from_str(&output).unwrap();
+            assert!(!result.success);
+            assert_eq!(result.error.as_deref(), Some("invalid arguments"));
+            let message = result.message.unwrap();
+            assert!(message.contains("add_item"), "{}", message);
+            assert!(message.contains("line 1 column"), "{}", message);
+        }
+        assert!(order.order.is_empty());
+        assert_eq!(order.pending_audit.len(), 2);
+        assert!(order.pending_audit.iter().all(|entry| !entry.success));
+        assert_eq!(
+            order.pending_audit[0].arguments,
+            json!(r#"{"itemName": "Coke", "optionKeys": ["size""#)
+        );
+
+        // NOTE(dev): The model can retry within the same run once it fixed the arguments
+        let retried = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args("Coke", &[("size", "small")]) }),
+        )
+        .await;
+        assert!(retried.success);
+    }
+
+    /// Removes single option values from an item through `patch_item`.
+    async fn remove_values(
+        menu: &Menu,
+        order: &mut Order,
+        item_id: &str,
+        removals: &[(&str, &str)],
+    ) -> FunctionResult {
+        let removals: Vec<Value> = removals
+            .iter()
+            .map(|(key, value)| json!({ "optionKey": key, "value": value }))
+            .collect();
+        call(
+            menu,
+            order,
+            FunctionName::PatchItem,
+            json!({ "orderId": item_id, "set": [], "unset": [], "removeValues": removals }),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn remove_values_drops_one_value_and_reprices() {
+        let menu = menu();
+        let mut order = order();
+        let mut args = add_args("Cheese Burger", &[("meal option", "a la carte")]);
+        args["optionKeys"] = json!(["customizations", "meal option"]);
+        args["optionValues"] = json!([["american cheese", "ketchup"], ["a la carte"]]);
+        let added = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": args }),
+        )
+        .await;
+        let item_id = added.items[0].item_id.clone();
+        let with_cheese = order.order[0].price;
+
+        let removed = remove_values(
+            &menu,
+            &mut order,
+            &item_id,
+            &[("customizations", "American Cheese")],
+        )
+        .await;
+
+        assert!(removed.success);
+        let item = &order.order[0];
+        assert_eq!(item.option_keys, ["customizations", "meal option"]);
+        assert_eq!(item.option_values[0].len(), 1);
+        assert_eq!(item.option_values[0][0].value, "ketchup");
+        assert_eq!(item.option_values[1][0].value, "a la carte");
+        assert_eq!(item.price, with_cheese - Money::from_cents(123));
+        assert_eq!(removed.items[0].price, item.price);
+    }
+
+    #[tokio::test]
+    async fn removing_the_last_value_of_a_required_option_leaves_the_item_incomplete() {
+        let menu = menu();
+        let mut order = order();
+        let added = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args(
+                "Cheese Burger",
+                &[("customizations", "ketchup"), ("meal option", "a la carte")],
+            ) }),
+        )
+        .await;
+        let item_id = added.items[0].item_id.clone();
+
+        let removed = remove_values(
+            &menu,
+            &mut order,
+            &item_id,
+            &[("customizations", "ketchup")],
+        )
+        .await;
+
+        assert!(removed.success);
+        assert_eq!(order.order[0].option_keys, ["meal option"]);
+        let status = removed.items[0].status.as_ref().unwrap();
+        assert!(
+            matches!(status.state, ItemState::Incomplete),
+            "{:?}",
+            status
+        );
+        assert!(
+            status.message.contains("customizations"),
+            "{}",
+            status.message
+        );
+    }
+
+    #[tokio::test]
+    async fn remove_values_reports_values_the_item_did_not_have() {
+        let menu = menu();
+        let mut order = order();
+        let added = call(
+            &menu,
+            &mut order,
+            FunctionName::AddItem,
+            json!({ "item": add_args(
+                "Cheese Burger",
+                &[("customizations", "ketchup"), ("meal option", "a la carte")],
+            ) }),
+        )
+        .await;
+        let item_id = added.items[0].item_id.clone();
+
+        let removed = remove_values(
+            &menu,
+            &mut order,
+            &item_id,
+            &[("customizations", "mayo"), ("meal option", "a la carte")],
+        )
+        .await;
+
+        assert!(!removed.success);
+        assert_eq!(removed.error.as_deref(), Some("values not removed"));
+        let message = removed.message.unwrap();
+        assert!(message.contains("customizations: mayo"), "{}", message);
+        // NOTE(dev): The rest of the patch still applies
+        assert_eq!(order.order[0].option_keys, ["customizations"]);
+    }
+
+    /// The mock assistant, reporting that every message used `tokens` prompt and completion
+    /// tokens each.
+    struct Metered {
+        inner: MockAssistant,
+        tokens: u32,
+    }
+
+    #[async_trait]
+    impl AssistantBackend for Metered {
+        fn is_initialized(&self) -> bool {
+            self.inner.is_initialized()
+        }
+
+        async fn thread_messages(&self, thread_id: &str) -> AppResult<Vec<ChatMessage>> {
+            self.inner.thread_messages(thread_id).await
+        }
+
+        async fn handle_message(
+            &self,
+            message: &str,
+            location: &str,
+            language: Option<&str>,
+            order: &mut Order,
+            menu: &Menu,
+        ) -> AppResult<Option<RunCompletionUsage>> {
+            self.inner
+                .handle_message(message, location, language, order, menu)
+                .await?;
+            Ok(Some(RunCompletionUsage {
+                prompt_tokens: self.tokens,
+                completion_tokens: self.tokens,
+                total_tokens: 2 * self.tokens,
+            }))
+        }
+
+        async fn reload(
+            &self,
+            menu: &Menu,
+            store: &OrderStore,
+        ) -> AppResult<Box<dyn AssistantBackend>> {
+            self.inner.reload(menu, store).await
+        }
+
+        async fn delete_thread(&self, thread_id: &str) -> AppResult<()> {
+            self.inner.delete_thread(thread_id).await
+        }
+
+        async fn cancel_active_run(&self, thread_id: &str) -> AppResult<Option<String>> {
+            self.inner.cancel_active_run(thread_id).await
+        }
+
+        fn info(&self) -> Option<AssistantInfo> {
+            self.inner.info()
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_is_refused_once_the_order_crossed_its_token_budget() {
+        let store = store();
+        let mut conn = store.get_connection().unwrap();
+        store.save_order(&mut conn, &mut order()).await.unwrap();
+        let menu = menu();
+        let limits = OrderLimits {
+            max_tokens: 250,
+            ..limits()
+        };
+        let assistant = Metered {
+            inner: MockAssistant::new(limits),
+            tokens: 100,
+        };
+        let usage_counters = UsageCounters::default();
+        let request = ChatRequest {
+            order_id: "order-1".to_string(),
+            input: "add Apple Pie".to_string(),
+            location: "store-a".to_string(),
+            language: None,
+        };
+        let chat = || {
+            handle_chat_message(
+                &store,
+                &menu,
+                &assistant,
+                &usage_counters,
+                &limits,
+                &request,
+            )
+        };
+
+        // NOTE(dev): The second run starts under the budget and crosses it
+        assert_eq!(chat().await.unwrap().total_tokens(), 200);
+        assert_eq!(chat().await.unwrap().total_tokens(), 400);
+        assert_eq!(
+            store
+                .get_order(&mut conn, "order-1")
+                .unwrap()
+                .total_tokens(),
+            400
+        );
+
+        let refused = chat().await.unwrap_err();
+        assert!(matches!(&refused, AppError::TokenBudgetExceeded(message)
+            if message.contains("budget of 250 tokens")));
+        assert_eq!(
+            refused.into_response().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(usage_counters.runs(), 2);
+        assert_eq!(
+            store.get_order(&mut conn, "order-1").unwrap().order.len(),
+            2
+        );
+    }
+
+    /// Price mismatches counted so far for `function`.
+    fn price_mismatches(handle: &PrometheusHandle, function: &FunctionName) -> u64 {
+        let series = format!(
+            "{}{{function=\"{}\"}} ",
+            telemetry::PRICE_MISMATCHES,
+            function
+        );
+        handle
+            .render()
+            .lines()
+            .find_map(|line| line.strip_prefix(&series))
+            .map_or(0, |count| count.parse().unwrap())
+    }
+
+    #[test]
+    fn model_prices_off_by_more_than_a_cent_are_counted_and_replaced() {
+        let handle = telemetry::install_recorder();
+        let menu = menu();
+        // NOTE(dev): Counters are global, only this test prices items as split_item
+        let function = FunctionName::SplitItem;
+        let coke = |cents| OrderItem {
+            price: Money::from_cents(cents),
+            ..item("Coke", &[("size", "small")])
+        };
+
+        let mut exact = coke(300);
+        apply_menu_price(&function, &mut exact, &menu);
+        let mut within_a_cent = coke(301);
+        apply_menu_price(&function, &mut within_a_cent, &menu);
+        assert_eq!(price_mismatches(&handle, &function), 0);
+        assert_eq!(within_a_cent.price, Money::from_cents(300));
+
+        let mut mispriced = coke(250);
+        apply_menu_price(&function, &mut mispriced, &menu);
+        assert_eq!(price_mismatches(&handle, &function), 1);
+        assert_eq!(mispriced.price, Money::from_cents(300));
+    }
+
+    #[tokio::test]
+    async fn menu_info_returns_the_choices_of_the_item() {
+        let menu = menu();
+        let mut order = order();
+        let function_call = FunctionCall {
+            name: FunctionName::GetMenuInfo.to_string(),
+            arguments: json!({ "itemName": "Coke" }).to_string(),
+        };
+
+        let output = handle_function_call(&function_call, &menu, &limits(), &mut order)
+            .await
+            .unwrap();
+
+        let info: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(info["itemName"], "Coke");
+        let choices = info["options"]["size"]["choices"].as_object().unwrap();
+        let mut names: Vec<&str> = choices.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["large", "medium", "small"]);
+        assert_eq!(choices["large"]["price"], "4.16");
+        assert!(order.order.is_empty());
+    }
+
+    #[tokio::test]
+    async fn remove_items_removes_several_items_in_one_call() {
+        let menu = menu();
+        let mut order = order();
+        for name in ["Coke", "Diet Coke", "Sprite"] {
+            let args = add_args(name, &[("size", "small")]);
+            call(
+                &menu,
+                &mut order,
+                FunctionName::AddItem,
+                json!({ "item": args }),
+            )
+            .await;
+        }
+        let ids: Vec<String> = order.order.iter().map(|item| item.id.clone()).collect();
+        assert_eq!(ids.len(), 3);
+
+        let result = call(
+            &menu,
+            &mut order,
+            FunctionName::RemoveItems,
+            json!({ "items": [
+                { "orderId": ids[0], "itemName": null },
+                { "orderId": ids[2], "itemName": null },
+            ] }),
+        )
+        .await;
+
+        assert!(result.success);
+        assert_eq!(result.removed_item_ids, [ids[0].clone(), ids[2].clone()]);
+        assert_eq!(result.item_count, 1);
+        assert_eq!(order.order.len(), 1);
+        assert_eq!(order.order[0].id, ids[1]);
+    }
+}
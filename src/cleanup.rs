@@ -0,0 +1,197 @@
+use arc_swap::ArcSwap;
+use futures::StreamExt;
+use redis::ConnectionInfo;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::error::AppResult;
+use crate::functions::AssistantBackend;
+use crate::order::{OrderStore, ORDER_KEY_PREFIX};
+
+/// Delay before subscribing again after the notification connection was lost
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Channel Redis publishes the keys expiring in a database on.
+///
+/// # Arguments
+/// * `db` - Index of the database holding the orders
+///
+/// # Returns
+/// * `String` - The channel, e.g. `__keyevent@0__:expired`
+fn expired_channel(db: i64) -> String {
+    format!("__keyevent@{}__:expired", db)
+}
+
+/// Deletes the OpenAI thread of every order that expires, in the background.
+///
+/// Subscribes to Redis keyspace notifications for expired keys, which need
+/// `notify-keyspace-events` to include `Ex`. The subscription is restored if the connection
+/// is lost; orders expiring while it is down keep their thread.
+///
+/// # Arguments
+/// * `redis` - Connection to the Redis server holding the orders
+/// * `store` - Storage holding the thread index of each order
+/// * `assistant` - The assistant owning the threads, swapped out when the menu is reloaded
+pub fn spawn_thread_cleanup(
+    redis: ConnectionInfo,
+    store: Arc<OrderStore>,
+    assistant: Arc<ArcSwap<Box<dyn AssistantBackend>>>,
+) {
+    info!("Deleting the OpenAI threads of expired orders");
+    tokio::spawn(async move {
+        loop {
+            match listen_for_expired_orders(&redis, &store, &assistant).await {
+                Ok(()) => warn!("Expiry notifications ended, subscribing again"),
+                Err(e) => error!("Expiry notifications failed: {:?}", e),
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    });
+}
+
+/// Deletes the thread of each order whose key expires until the subscription ends.
+///
+/// # Arguments
+/// * `redis` - Connection to the Redis server holding the orders
+/// * `store` - Storage holding the thread index of each order
+/// * `assistant` - The assistant owning the threads
+///
+/// # Returns
+/// * `AppResult<()>` - Success when the subscription ends, or the error that ended it
+async fn listen_for_expired_orders(
+    redis: &ConnectionInfo,
+    store: &OrderStore,
+    assistant: &ArcSwap<Box<dyn AssistantBackend>>,
+) -> AppResult<()> {
+    warn_if_notifications_disabled(store);
+    let client = redis::Client::open(redis.clone())?;
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    // NOTE(dev): Only the orders' database, another database may use the same key names
+    let channel = expired_channel(redis.redis.db);
+    pubsub.subscribe(&channel).await?;
+    info!("Subscribed to expired key notifications on {}", channel);
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let key: String = match message.get_payload() {
+            Ok(key) => key,
+            Err(e) => {
+                warn!("Ignoring unreadable expiry notification: {}", e);
+                continue;
+            }
+        };
+        let Some(order_id) = key.strip_prefix(ORDER_KEY_PREFIX) else {
+            continue;
+        };
+        if let Err(e) = delete_expired_thread(order_id, store, assistant).await {
+            error!(
+                "Failed to delete the thread of expired order {}: {:?}",
+                order_id, e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the thread of an order that expired.
+///
+/// # Arguments
+/// * `order_id` - The ID of the expired order
+/// * `store` - Storage holding the thread index of the order
+/// * `assistant` - The assistant owning the thread
+///
+/// # Returns
+/// * `AppResult<()>` - Success if the thread was deleted or the order had none
+async fn delete_expired_thread(
+    order_id: &str,
+    store: &OrderStore,
+    assistant: &ArcSwap<Box<dyn AssistantBackend>>,
+) -> AppResult<()> {
+    let thread_id = {
+        let mut conn = store.get_connection()?;
+        store.take_thread_index(&mut conn, order_id)?
+    };
+    let Some(thread_id) = thread_id else {
+        debug!("Expired order {} had no thread", order_id);
+        return Ok(());
+    };
+    info!(
+        "Order {} expired, deleting its thread {}",
+        order_id, thread_id
+    );
+    assistant.load().delete_thread(&thread_id).await
+}
+
+/// Logs a warning if Redis is not configured to publish expired keys.
+///
+/// # Arguments
+/// * `store` - Storage to read the Redis configuration through
+fn warn_if_notifications_disabled(store: &OrderStore) {
+    // NOTE(dev): Managed Redis often disables CONFIG, in which case the setting can't be checked
+    let setting = store.get_connection().and_then(|mut conn| {
+        let reply: Vec<String> = redis::cmd("CONFIG")
+            .arg("GET")
+            .arg("notify-keyspace-events")
+            .query(&mut *conn)?;
+        Ok(reply.get(1).cloned().unwrap_or_default())
+    });
+    match setting {
+        Ok(flags) if flags.contains('E') && (flags.contains('x') || flags.contains('A')) => {
+            debug!("notify-keyspace-events is {:?}", flags)
+        }
+        Ok(flags) => warn!(
+            "notify-keyspace-events is {:?}, set it to include Ex or the threads of expired orders are never deleted",
+            flags
+        ),
+        Err(e) => debug!("Could not read notify-keyspace-events: {:?}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockAssistant;
+    use crate::testing::{limits, menu, order, store};
+
+    #[test]
+    fn only_the_orders_database_is_listened_to() {
+        assert_eq!(expired_channel(0), "__keyevent@0__:expired");
+        assert_eq!(expired_channel(3), "__keyevent@3__:expired");
+    }
+
+    #[tokio::test]
+    async fn expired_orders_have_their_thread_deleted() {
+        let store = store();
+        let mock = MockAssistant::new(limits());
+        let mut order = order();
+        mock.handle_message("list", "store-a", None, &mut order, &menu())
+            .await
+            .unwrap();
+        let thread_id = order.thread_id.clone().unwrap();
+        let mut conn = store.get_connection().unwrap();
+        store.save_order(&mut conn, &mut order).await.unwrap();
+        let assistant = ArcSwap::from_pointee(Box::new(mock.clone()) as Box<dyn AssistantBackend>);
+
+        delete_expired_thread("order-1", &store, &assistant)
+            .await
+            .unwrap();
+
+        assert!(mock.thread_messages(&thread_id).await.is_err());
+        assert_eq!(store.take_thread_index(&mut conn, "order-1").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn expired_orders_without_a_thread_are_ignored() {
+        let store = store();
+        let mut conn = store.get_connection().unwrap();
+        store.save_order(&mut conn, &mut order()).await.unwrap();
+        let assistant = ArcSwap::from_pointee(
+            Box::new(MockAssistant::new(limits())) as Box<dyn AssistantBackend>
+        );
+
+        assert!(delete_expired_thread("order-1", &store, &assistant)
+            .await
+            .is_ok());
+    }
+}
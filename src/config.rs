@@ -0,0 +1,835 @@
+use axum::http::HeaderValue;
+use redis::{ConnectionInfo, IntoConnectionInfo};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{debug, info};
+
+use crate::api::{parse_api_keys, ApiKey};
+use crate::error::{AppError, AppResult};
+use crate::functions::ToolChoiceMode;
+use crate::menu::MenuSettings;
+use crate::order::OrderLimits;
+use crate::webhook;
+
+/// Startup configuration read from the environment
+///
+/// Every variable is parsed and checked at once, so a bad deployment reports all of its
+/// problems at startup instead of panicking on the first one, or on the first request that
+/// needs it.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Address the API listens on, from `HOST` and `PORT`
+    pub addr: SocketAddr,
+    /// Address the metrics are served on, from `HOST` and `METRICS_PORT`
+    pub metrics_addr: Option<SocketAddr>,
+    /// Allowed API keys with their scopes and locations
+    pub api_keys: HashMap<String, ApiKey>,
+    /// Redis connection from `REDIS_URL`, with `REDIS_DB`, `REDIS_USERNAME` and
+    /// `REDIS_PASSWORD` applied over it
+    pub redis: ConnectionInfo,
+    /// Number of pooled Redis connections
+    pub redis_pool_size: u32,
+    /// How long to wait for a Redis connection
+    pub redis_connect_timeout: Duration,
+    /// Retries on a new connection when one is lost
+    pub redis_max_retries: u32,
+    /// Origins allowed to call the API from a browser
+    pub allowed_origins: Vec<HeaderValue>,
+    /// Maximum number of characters accepted in a chat input
+    pub max_input_chars: usize,
+    /// Maximum size of a request body in bytes
+    pub max_body_bytes: usize,
+    /// Currency of all prices, e.g. "USD"
+    pub currency: String,
+    /// Whether error bodies carry a machine-readable code, instead of only the message
+    pub structured_errors: bool,
+    /// Whether the mock assistant is used instead of OpenAI
+    pub openai_mock: bool,
+    /// OpenAI API key, None with the mock assistant
+    pub openai_api_key: Option<String>,
+    /// Alternative OpenAI API base URL
+    pub openai_api_base: Option<String>,
+    /// OpenAI organization ID
+    pub openai_org_id: Option<String>,
+    /// Sampling temperature of the assistant, between 0 and 2
+    pub openai_temperature: f32,
+    /// Nucleus sampling of the assistant, greater than 0 and at most 1
+    pub openai_top_p: f32,
+    /// How long a run may take before it is cancelled
+    pub openai_poll_timeout: Duration,
+    /// Initial delay between run status polls
+    pub openai_poll_interval: Duration,
+    /// Cap on the backed off delay between polls, at least `openai_poll_interval`
+    pub openai_max_poll_interval: Duration,
+    /// Retries for rate-limited or failed OpenAI calls
+    pub openai_max_retries: u32,
+    /// How eagerly runs are made to call functions
+    pub openai_tool_choice: ToolChoiceMode,
+    /// Whether run events are streamed instead of polled
+    pub openai_stream_runs: bool,
+    /// Models to use, later ones when earlier are unavailable, never empty
+    pub openai_models: Vec<String>,
+    /// Whether each turn runs on a new thread that is deleted afterwards
+    pub stateless_threads: bool,
+    /// Whether the threads of expired orders are deleted
+    pub thread_cleanup: bool,
+    /// Limits applied to every order
+    pub order_limits: OrderLimits,
+    /// How the menu is loaded and validated
+    pub menu_settings: MenuSettings,
+    /// Path of the per-location order constraints, which may be missing unless set explicitly
+    pub locations_file: String,
+    /// Path of the persona placed before the menu in the assistant instructions, if any
+    pub system_prompt_file: Option<String>,
+    /// Seconds a repeated chat input returns the previous response, 0 to process every input
+    pub chat_dedupe_seconds: usize,
+    /// Where completed orders are POSTed, if anywhere
+    pub completion_webhook_url: Option<String>,
+    /// Key signing the webhook requests
+    pub completion_webhook_secret: Option<String>,
+    /// Retries for failed webhook deliveries
+    pub completion_webhook_max_retries: u32,
+}
+
+impl Config {
+    /// Reads and validates the startup configuration from the environment.
+    ///
+    /// # Returns
+    /// * `AppResult<Config>` - The configuration, or `InvalidConfig` listing every problem found
+    pub fn from_env() -> AppResult<Config> {
+        info!("Validating configuration");
+        // NOTE(dev): Malformed values fall back to their type's default so the remaining
+        //            variables are still checked, the Config is never returned with errors
+        let mut errors = Vec::new();
+
+        let api_keys = match std::env::var("API_KEYS") {
+            Ok(value) => match parse_api_keys(&value) {
+                Ok(api_keys) if api_keys.is_empty() => {
+                    errors.push("API_KEYS must contain at least one key".to_string());
+                    HashMap::new()
+                }
+                Ok(api_keys) => api_keys,
+                Err(AppError::InvalidInput(reason)) => {
+                    errors.push(format!("API_KEYS is invalid: {}", reason));
+                    HashMap::new()
+                }
+                Err(_) => {
+                    errors.push("API_KEYS is invalid".to_string());
+                    HashMap::new()
+                }
+            },
+            Err(_) => {
+                errors.push("API_KEYS is required".to_string());
+                HashMap::new()
+            }
+        };
+        debug!("Loaded {} API keys", api_keys.len());
+
+        let host = std::env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let port: Option<u16> = parse_var("PORT", "3000", "a port number", &mut errors);
+        let addr = port.and_then(|port| socket_addr(&host, "PORT", port, &mut errors));
+        let metrics_addr = match std::env::var("METRICS_PORT") {
+            Ok(metrics_port) => match metrics_port.parse::<u16>() {
+                Ok(metrics_port) => socket_addr(&host, "METRICS_PORT", metrics_port, &mut errors),
+                Err(_) => {
+                    errors.push(format!(
+                        "METRICS_PORT must be a port number, got {:?}",
+                        metrics_port
+                    ));
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+
+        let redis_url =
+            std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+        let redis = match redis_url.as_str().into_connection_info() {
+            Ok(mut redis) => {
+                // NOTE(dev): Set separately so the URL can stay free of secrets, e.g. in a ConfigMap
+                if let Ok(db) = std::env::var("REDIS_DB") {
+                    match db.parse::<i64>() {
+                        Ok(db) if db >= 0 => redis.redis.db = db,
+                        _ => errors.push(format!(
+                            "REDIS_DB must be a non-negative integer, got {:?}",
+                            db
+                        )),
+                    }
+                }
+                if let Ok(username) = std::env::var("REDIS_USERNAME") {
+                    redis.redis.username = Some(username);
+                }
+                if let Ok(password) = std::env::var("REDIS_PASSWORD") {
+                    redis.redis.password = Some(password);
+                }
+                Some(redis)
+            }
+            Err(e) => {
+                errors.push(format!("REDIS_URL is not a valid Redis URL: {}", e));
+                None
+            }
+        };
+        let redis_pool_size = parse_positive("REDIS_POOL_SIZE", "16", &mut errors);
+        let redis_connect_timeout = Duration::from_secs(parse_positive(
+            "REDIS_CONNECT_TIMEOUT_SECONDS",
+            "5",
+            &mut errors,
+        ));
+        let redis_max_retries: u32 = parse_var(
+            "REDIS_MAX_RETRIES",
+            "2",
+            "a non-negative integer",
+            &mut errors,
+        )
+        .unwrap_or_default();
+        let allowed_origins = parse_origins(&mut errors);
+        debug!("Allowing {} CORS origins", allowed_origins.len());
+
+        let max_input_chars: usize = parse_positive("MAX_INPUT_CHARS", "2000", &mut errors);
+        let max_body_bytes: usize = parse_positive("MAX_BODY_BYTES", "65536", &mut errors);
+        // NOTE(dev): A character is at most 4 bytes in UTF-8, so any input under MAX_INPUT_CHARS has
+        //            to fit in the body or it would be cut off before its own length check
+        if max_body_bytes < max_input_chars.saturating_mul(4).saturating_add(1024) {
+            errors.push("MAX_BODY_BYTES must be at least 4 * MAX_INPUT_CHARS + 1024".to_string());
+        }
+        let order_limits = OrderLimits {
+            max_items: parse_positive("MAX_ITEMS_PER_ORDER", "50", &mut errors),
+            max_thread_messages: parse_var(
+                "MAX_THREAD_MESSAGES",
+                "100",
+                "a non-negative integer",
+                &mut errors,
+            )
+            .unwrap_or_default(),
+            ttl_seconds: parse_var(
+                "ORDER_TTL_SECONDS",
+                "0",
+                "a non-negative integer",
+                &mut errors,
+            )
+            .unwrap_or_default(),
+            max_invalid_turns: parse_var(
+                "MAX_INVALID_TURNS",
+                "3",
+                "a non-negative integer",
+                &mut errors,
+            )
+            .unwrap_or_default(),
+            max_tokens: parse_var(
+                "MAX_TOKENS_PER_ORDER",
+                "0",
+                "a non-negative integer",
+                &mut errors,
+            )
+            .unwrap_or_default(),
+            default_prep_seconds: parse_var(
+                "DEFAULT_PREP_SECONDS",
+                "300",
+                "a non-negative integer",
+                &mut errors,
+            )
+            .unwrap_or_default(),
+            eta_mode: parse_var("ETA_MODE", "max", "max or sum", &mut errors).unwrap_or_default(),
+        };
+        let chat_dedupe_seconds: usize = parse_var(
+            "CHAT_DEDUPE_SECONDS",
+            "30",
+            "a non-negative integer",
+            &mut errors,
+        )
+        .unwrap_or_default();
+        let menu_file =
+            std::env::var("MENU_FILE").unwrap_or_else(|_| "static/menu.json".to_string());
+        if !Path::new(&menu_file).is_file() {
+            errors.push(format!("MENU_FILE {} does not exist", menu_file));
+        }
+        let menu_settings = MenuSettings {
+            path: menu_file,
+            allow_empty: flag("ALLOW_EMPTY_MENU"),
+            max_validation_problems: parse_var(
+                "MAX_VALIDATION_PROBLEMS",
+                "0",
+                "a non-negative integer",
+                &mut errors,
+            )
+            .unwrap_or_default(),
+        };
+        let currency = std::env::var("CURRENCY").unwrap_or_else(|_| "USD".to_string());
+        let structured_errors = flag("STRUCTURED_ERRORS");
+
+        // NOTE(dev): The default file is optional, one set explicitly has to exist
+        let locations_file = match std::env::var("LOCATIONS_FILE") {
+            Ok(locations_file) => {
+                if !Path::new(&locations_file).is_file() {
+                    errors.push(format!("LOCATIONS_FILE {} does not exist", locations_file));
+                }
+                locations_file
+            }
+            Err(_) => "static/locations.json".to_string(),
+        };
+
+        let openai_mock = flag("OPENAI_MOCK");
+        let openai_api_key = std::env::var("OPENAI_API_KEY").ok();
+        if !openai_mock && openai_api_key.as_deref().is_none_or(str::is_empty) {
+            errors.push("OPENAI_API_KEY is required unless OPENAI_MOCK is set".to_string());
+        }
+        let openai_api_base = std::env::var("OPENAI_API_BASE").ok();
+        if let Some(api_base) = &openai_api_base {
+            check_url("OPENAI_API_BASE", api_base, &mut errors);
+        }
+        let openai_org_id = std::env::var("OPENAI_ORG_ID").ok();
+        // NOTE(dev): A low temperature keeps the model to the menu's item names instead of paraphrasing them
+        let openai_temperature: Option<f32> =
+            parse_var("OPENAI_TEMPERATURE", "0.2", "a number", &mut errors);
+        if openai_temperature.is_some_and(|temperature| !(0.0..=2.0).contains(&temperature)) {
+            errors.push("OPENAI_TEMPERATURE must be between 0 and 2".to_string());
+        }
+        let openai_top_p: Option<f32> = parse_var("OPENAI_TOP_P", "1", "a number", &mut errors);
+        if openai_top_p.is_some_and(|top_p| !(top_p > 0.0 && top_p <= 1.0)) {
+            errors.push("OPENAI_TOP_P must be greater than 0 and at most 1".to_string());
+        }
+        let openai_poll_timeout = Duration::from_secs(parse_positive(
+            "OPENAI_POLL_TIMEOUT_SECONDS",
+            "60",
+            &mut errors,
+        ));
+        let openai_poll_interval = Duration::from_millis(parse_positive(
+            "OPENAI_POLL_INTERVAL_MS",
+            "100",
+            &mut errors,
+        ));
+        let openai_max_poll_interval = Duration::from_millis(parse_positive(
+            "OPENAI_MAX_POLL_INTERVAL_MS",
+            "2000",
+            &mut errors,
+        ))
+        .max(openai_poll_interval);
+        let openai_max_retries: u32 = parse_var(
+            "OPENAI_MAX_RETRIES",
+            "3",
+            "a non-negative integer",
+            &mut errors,
+        )
+        .unwrap_or_default();
+        let openai_tool_choice: ToolChoiceMode = parse_var(
+            "OPENAI_TOOL_CHOICE",
+            "auto",
+            "auto, required or action",
+            &mut errors,
+        )
+        .unwrap_or_default();
+        let openai_stream_runs = flag("OPENAI_STREAM_RUNS");
+        // NOTE(dev): OPENAI_MODEL is kept for deployments configured before the fallback list
+        let openai_models: Vec<String> = std::env::var("OPENAI_MODELS")
+            .or_else(|_| std::env::var("OPENAI_MODEL"))
+            .unwrap_or_else(|_| "gpt-4o".to_string())
+            .split(',')
+            .map(|model| model.trim().to_string())
+            .filter(|model| !model.is_empty())
+            .collect();
+        if openai_models.is_empty() {
+            errors.push("OPENAI_MODELS must name at least one model".to_string());
+        }
+        let system_prompt_file = std::env::var("SYSTEM_PROMPT_FILE").ok();
+        if let Some(prompt_file) = &system_prompt_file {
+            if !Path::new(prompt_file).is_file() {
+                errors.push(format!("SYSTEM_PROMPT_FILE {} does not exist", prompt_file));
+            }
+        }
+        let stateless_threads = flag("STATELESS_THREADS");
+        let thread_cleanup = flag("THREAD_CLEANUP");
+
+        let completion_webhook_url = std::env::var("COMPLETION_WEBHOOK_URL").ok();
+        if let Some(webhook_url) = &completion_webhook_url {
+            check_url("COMPLETION_WEBHOOK_URL", webhook_url, &mut errors);
+        }
+        let completion_webhook_secret = std::env::var("COMPLETION_WEBHOOK_SECRET").ok();
+        let completion_webhook_max_retries: u32 = parse_var(
+            "COMPLETION_WEBHOOK_MAX_RETRIES",
+            "3",
+            "a non-negative integer",
+            &mut errors,
+        )
+        .unwrap_or_default();
+        if completion_webhook_max_retries > webhook::MAX_RETRIES {
+            errors.push(format!(
+                "COMPLETION_WEBHOOK_MAX_RETRIES must be at most {}",
+                webhook::MAX_RETRIES
+            ));
+        }
+
+        match (addr, redis, openai_temperature, openai_top_p) {
+            (Some(addr), Some(redis), Some(openai_temperature), Some(openai_top_p))
+                if errors.is_empty() =>
+            {
+                Ok(Config {
+                    addr,
+                    metrics_addr,
+                    api_keys,
+                    redis,
+                    redis_pool_size,
+                    redis_connect_timeout,
+                    redis_max_retries,
+                    allowed_origins,
+                    max_input_chars,
+                    max_body_bytes,
+                    currency,
+                    structured_errors,
+                    openai_mock,
+                    openai_api_key,
+                    openai_api_base,
+                    openai_org_id,
+                    openai_temperature,
+                    openai_top_p,
+                    openai_poll_timeout,
+                    openai_poll_interval,
+                    openai_max_poll_interval,
+                    openai_max_retries,
+                    openai_tool_choice,
+                    openai_stream_runs,
+                    openai_models,
+                    stateless_threads,
+                    thread_cleanup,
+                    order_limits,
+                    menu_settings,
+                    locations_file,
+                    system_prompt_file,
+                    chat_dedupe_seconds,
+                    completion_webhook_url,
+                    completion_webhook_secret,
+                    completion_webhook_max_retries,
+                })
+            }
+            _ => Err(AppError::InvalidConfig(errors)),
+        }
+    }
+}
+
+/// Parses an environment variable, recording an error if it is malformed.
+///
+/// # Arguments
+/// * `name` - Name of the variable
+/// * `default` - Value used when the variable is not set
+/// * `expected` - Description of a valid value, e.g. "a positive integer"
+/// * `errors` - Problems found so far
+///
+/// # Returns
+/// * `Option<T>` - The parsed value, or None if it is malformed
+fn parse_var<T: FromStr>(
+    name: &str,
+    default: &str,
+    expected: &str,
+    errors: &mut Vec<String>,
+) -> Option<T> {
+    let value = std::env::var(name).unwrap_or_else(|_| default.to_string());
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            errors.push(format!("{} must be {}, got {:?}", name, expected, value));
+            None
+        }
+    }
+}
+
+/// Parses an environment variable that must be a positive integer, recording an error if it is
+/// malformed or zero.
+///
+/// # Arguments
+/// * `name` - Name of the variable
+/// * `default` - Value used when the variable is not set
+/// * `errors` - Problems found so far
+///
+/// # Returns
+/// * `T` - The parsed value, or 0 if it is malformed
+fn parse_positive<T: FromStr + Default + PartialEq>(
+    name: &str,
+    default: &str,
+    errors: &mut Vec<String>,
+) -> T {
+    match parse_var(name, default, "a positive integer", errors) {
+        Some(value) if value == T::default() => {
+            errors.push(format!("{} must be a positive integer, got 0", name));
+            value
+        }
+        value => value.unwrap_or_default(),
+    }
+}
+
+/// Whether a flag variable is set to `1` or `true`.
+///
+/// # Arguments
+/// * `name` - Name of the variable
+///
+/// # Returns
+/// * `bool` - True if the flag is on, false if it is unset or anything else
+fn flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| value == "1" || value == "true")
+}
+
+/// Parses the comma-separated `ALLOWED_ORIGINS`, recording an error for each malformed origin.
+///
+/// # Arguments
+/// * `errors` - Problems found so far
+///
+/// # Returns
+/// * `Vec<HeaderValue>` - The valid origins, empty when the variable is unset or empty
+fn parse_origins(errors: &mut Vec<String>) -> Vec<HeaderValue> {
+    let value = std::env::var("ALLOWED_ORIGINS").unwrap_or_default();
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .filter_map(|origin| match origin.parse() {
+            Ok(origin) => Some(origin),
+            Err(_) => {
+                errors.push(format!(
+                    "ALLOWED_ORIGINS must contain valid origins, got {:?}",
+                    origin
+                ));
+                None
+            }
+        })
+        .collect()
+}
+
+/// Builds the address to listen on, recording an error if the host is malformed.
+///
+/// # Arguments
+/// * `host` - Value of `HOST`
+/// * `name` - Name of the port variable
+/// * `port` - The port
+/// * `errors` - Problems found so far
+///
+/// # Returns
+/// * `Option<SocketAddr>` - The address, or None if it is malformed
+fn socket_addr(host: &str, name: &str, port: u16, errors: &mut Vec<String>) -> Option<SocketAddr> {
+    match SocketAddr::from_str(&format!("{}:{}", host, port)) {
+        Ok(addr) => Some(addr),
+        Err(e) => {
+            errors.push(format!("HOST and {} are not a valid address: {}", name, e));
+            None
+        }
+    }
+}
+
+/// Checks that a variable holds an absolute http or https URL.
+///
+/// # Arguments
+/// * `name` - Name of the variable
+/// * `value` - Value of the variable
+/// * `errors` - Problems found so far
+fn check_url(name: &str, value: &str, errors: &mut Vec<String>) {
+    match reqwest::Url::parse(value) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+        Ok(url) => errors.push(format!(
+            "{} must be an http or https URL, got scheme {}",
+            name,
+            url.scheme()
+        )),
+        Err(e) => errors.push(format!("{} is not a valid URL: {}", name, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::EtaMode;
+    use crate::testing::config as from_vars;
+
+    /// Runs `Config::from_env` with the given variables and returns the problems it found.
+    fn errors(vars: &[(&str, &str)]) -> Vec<String> {
+        match from_vars(vars) {
+            Err(AppError::InvalidConfig(errors)) => errors,
+            other => panic!("expected InvalidConfig, got {:?}", other),
+        }
+    }
+
+    /// The smallest valid environment, with the mock assistant
+    const MINIMAL: &[(&str, &str)] = &[("API_KEYS", "key1"), ("OPENAI_MOCK", "true")];
+
+    /// Returns the minimal environment with the given variables added.
+    fn with(vars: &[(&'static str, &'static str)]) -> Vec<(&'static str, &'static str)> {
+        MINIMAL.iter().chain(vars).copied().collect()
+    }
+
+    #[test]
+    fn minimal_environment_uses_defaults() {
+        let config = from_vars(MINIMAL).unwrap();
+        assert_eq!(config.addr, "127.0.0.1:3000".parse().unwrap());
+        assert_eq!(config.metrics_addr, None);
+        assert!(config.api_keys.contains_key("key1"));
+        assert_eq!(config.redis_pool_size, 16);
+        assert_eq!(config.redis_connect_timeout, Duration::from_secs(5));
+        assert!(config.allowed_origins.is_empty());
+        assert!(config.openai_mock);
+        assert_eq!(config.openai_poll_timeout, Duration::from_secs(60));
+        assert_eq!(config.openai_poll_interval, Duration::from_millis(100));
+        assert_eq!(config.openai_max_poll_interval, Duration::from_millis(2000));
+        assert_eq!(config.openai_tool_choice, ToolChoiceMode::Auto);
+        assert_eq!(config.openai_models, vec!["gpt-4o".to_string()]);
+        assert_eq!(
+            config.order_limits,
+            OrderLimits {
+                max_items: 50,
+                max_thread_messages: 100,
+                ttl_seconds: 0,
+                max_invalid_turns: 3,
+                max_tokens: 0,
+                default_prep_seconds: 300,
+                eta_mode: EtaMode::Max,
+            }
+        );
+        assert_eq!(
+            config.menu_settings,
+            MenuSettings {
+                path: "static/menu.json".to_string(),
+                allow_empty: false,
+                max_validation_problems: 0,
+            }
+        );
+        assert_eq!(config.locations_file, "static/locations.json");
+        assert_eq!(config.system_prompt_file, None);
+        assert_eq!(config.chat_dedupe_seconds, 30);
+        assert!(!config.thread_cleanup);
+    }
+
+    #[test]
+    fn missing_required_variables_are_all_reported() {
+        let errors = errors(&[]);
+        assert_eq!(
+            errors,
+            vec![
+                "API_KEYS is required".to_string(),
+                "OPENAI_API_KEY is required unless OPENAI_MOCK is set".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn openai_api_key_replaces_the_mock() {
+        let config = from_vars(&[("API_KEYS", "key1"), ("OPENAI_API_KEY", "sk-test")]).unwrap();
+        assert!(!config.openai_mock);
+        assert_eq!(config.openai_api_key.as_deref(), Some("sk-test"));
+
+        let errors = errors(&[("API_KEYS", "key1"), ("OPENAI_API_KEY", "")]);
+        assert_eq!(
+            errors,
+            vec!["OPENAI_API_KEY is required unless OPENAI_MOCK is set".to_string()]
+        );
+    }
+
+    #[test]
+    fn malformed_variables_are_all_reported() {
+        let errors = errors(&with(&[
+            ("PORT", "http"),
+            ("REDIS_URL", "not a url"),
+            ("REDIS_MAX_RETRIES", "-1"),
+            ("MAX_THREAD_MESSAGES", "many"),
+            ("OPENAI_TEMPERATURE", "3"),
+            ("OPENAI_TOP_P", "0"),
+            ("OPENAI_MAX_RETRIES", "three"),
+            ("OPENAI_TOOL_CHOICE", "sometimes"),
+            ("OPENAI_API_BASE", "ftp://example.com"),
+            ("COMPLETION_WEBHOOK_MAX_RETRIES", "11"),
+            ("MAX_VALIDATION_PROBLEMS", "all"),
+            ("ORDER_TTL_SECONDS", "-5"),
+            ("MAX_INVALID_TURNS", "never"),
+            ("MAX_TOKENS_PER_ORDER", "1e6"),
+            ("DEFAULT_PREP_SECONDS", "5m"),
+            ("ETA_MODE", "average"),
+            ("CHAT_DEDUPE_SECONDS", "30s"),
+        ]));
+        for name in [
+            "PORT",
+            "REDIS_URL",
+            "REDIS_MAX_RETRIES",
+            "MAX_THREAD_MESSAGES",
+            "OPENAI_TEMPERATURE",
+            "OPENAI_TOP_P",
+            "OPENAI_MAX_RETRIES",
+            "OPENAI_TOOL_CHOICE",
+            "OPENAI_API_BASE",
+            "COMPLETION_WEBHOOK_MAX_RETRIES",
+            "MAX_VALIDATION_PROBLEMS",
+            "ORDER_TTL_SECONDS",
+            "MAX_INVALID_TURNS",
+            "MAX_TOKENS_PER_ORDER",
+            "DEFAULT_PREP_SECONDS",
+            "ETA_MODE",
+            "CHAT_DEDUPE_SECONDS",
+        ] {
+            assert!(
+                errors.iter().any(|error| error.starts_with(name)),
+                "no error for {} in {:?}",
+                name,
+                errors
+            );
+        }
+        assert_eq!(errors.len(), 17);
+    }
+
+    #[test]
+    fn zero_is_refused_where_a_positive_integer_is_needed() {
+        let errors = errors(&with(&[
+            ("MAX_ITEMS_PER_ORDER", "0"),
+            ("REDIS_POOL_SIZE", "0"),
+            ("REDIS_CONNECT_TIMEOUT_SECONDS", "0"),
+            ("OPENAI_POLL_TIMEOUT_SECONDS", "0"),
+            ("OPENAI_POLL_INTERVAL_MS", "0"),
+        ]));
+        assert_eq!(
+            errors,
+            vec![
+                "REDIS_POOL_SIZE must be a positive integer, got 0".to_string(),
+                "REDIS_CONNECT_TIMEOUT_SECONDS must be a positive integer, got 0".to_string(),
+                "MAX_ITEMS_PER_ORDER must be a positive integer, got 0".to_string(),
+                "OPENAI_POLL_TIMEOUT_SECONDS must be a positive integer, got 0".to_string(),
+                "OPENAI_POLL_INTERVAL_MS must be a positive integer, got 0".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn body_limit_must_fit_the_longest_input() {
+        let errors = errors(&with(&[("MAX_INPUT_CHARS", "20000")]));
+        assert_eq!(
+            errors,
+            vec!["MAX_BODY_BYTES must be at least 4 * MAX_INPUT_CHARS + 1024".to_string()]
+        );
+
+        let config = from_vars(&with(&[
+            ("MAX_INPUT_CHARS", "20000"),
+            ("MAX_BODY_BYTES", "81024"),
+        ]))
+        .unwrap();
+        assert_eq!(config.max_body_bytes, 81024);
+    }
+
+    #[test]
+    fn files_and_menu_flags_are_read_once_into_the_config() {
+        let config = from_vars(&with(&[
+            ("MENU_FILE", "static/menu.json"),
+            ("ALLOW_EMPTY_MENU", "true"),
+            ("LOCATIONS_FILE", "Cargo.toml"),
+            ("SYSTEM_PROMPT_FILE", "Cargo.toml"),
+        ]))
+        .unwrap();
+        assert!(config.menu_settings.allow_empty);
+        assert_eq!(config.menu_settings.path, "static/menu.json");
+        assert_eq!(config.locations_file, "Cargo.toml");
+        assert_eq!(config.system_prompt_file.as_deref(), Some("Cargo.toml"));
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        let errors = errors(&with(&[
+            ("MENU_FILE", "static/missing.json"),
+            ("LOCATIONS_FILE", "static/missing.json"),
+            ("SYSTEM_PROMPT_FILE", "static/missing.txt"),
+        ]));
+        assert_eq!(
+            errors,
+            vec![
+                "MENU_FILE static/missing.json does not exist".to_string(),
+                "LOCATIONS_FILE static/missing.json does not exist".to_string(),
+                "SYSTEM_PROMPT_FILE static/missing.txt does not exist".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn models_fall_back_to_the_legacy_variable() {
+        let config = from_vars(&with(&[("OPENAI_MODEL", "gpt-4o-mini")])).unwrap();
+        assert_eq!(config.openai_models, vec!["gpt-4o-mini".to_string()]);
+
+        let config = from_vars(&with(&[
+            ("OPENAI_MODELS", "gpt-4o, gpt-4o-mini"),
+            ("OPENAI_MODEL", "gpt-3.5-turbo"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.openai_models,
+            vec!["gpt-4o".to_string(), "gpt-4o-mini".to_string()]
+        );
+
+        let errors = errors(&with(&[("OPENAI_MODELS", " , ")]));
+        assert_eq!(
+            errors,
+            vec!["OPENAI_MODELS must name at least one model".to_string()]
+        );
+    }
+
+    #[test]
+    fn max_poll_interval_is_at_least_the_poll_interval() {
+        let config = from_vars(&with(&[
+            ("OPENAI_POLL_INTERVAL_MS", "500"),
+            ("OPENAI_MAX_POLL_INTERVAL_MS", "200"),
+        ]))
+        .unwrap();
+        assert_eq!(config.openai_max_poll_interval, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn allowed_origins_are_parsed() {
+        let config = from_vars(&with(&[(
+            "ALLOWED_ORIGINS",
+            " https://a.example.com,,https://b.example.com ",
+        )]))
+        .unwrap();
+        assert_eq!(
+            config.allowed_origins,
+            vec![
+                HeaderValue::from_static("https://a.example.com"),
+                HeaderValue::from_static("https://b.example.com"),
+            ]
+        );
+
+        let errors = errors(&with(&[(
+            "ALLOWED_ORIGINS",
+            "https://a.example.com,https://b\u{7f}.example.com",
+        )]));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("ALLOWED_ORIGINS must contain valid origins"));
+    }
+
+    #[test]
+    fn tuning_variables_are_read() {
+        let config = from_vars(&with(&[
+            ("MAX_ITEMS_PER_ORDER", "5"),
+            ("MAX_THREAD_MESSAGES", "0"),
+            ("OPENAI_TOOL_CHOICE", "action"),
+            ("OPENAI_STREAM_RUNS", "1"),
+            ("STATELESS_THREADS", "true"),
+            ("THREAD_CLEANUP", "true"),
+            ("REDIS_MAX_RETRIES", "0"),
+            ("ORDER_TTL_SECONDS", "3600"),
+            ("MAX_INVALID_TURNS", "0"),
+            ("MAX_TOKENS_PER_ORDER", "20000"),
+            ("DEFAULT_PREP_SECONDS", "120"),
+            ("ETA_MODE", "sum"),
+            ("CHAT_DEDUPE_SECONDS", "0"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.order_limits,
+            OrderLimits {
+                max_items: 5,
+                max_thread_messages: 0,
+                ttl_seconds: 3600,
+                max_invalid_turns: 0,
+                max_tokens: 20000,
+                default_prep_seconds: 120,
+                eta_mode: EtaMode::Sum,
+            }
+        );
+        assert_eq!(config.chat_dedupe_seconds, 0);
+        assert_eq!(config.openai_tool_choice, ToolChoiceMode::Action);
+        assert!(config.openai_stream_runs);
+        assert!(config.stateless_threads);
+        assert!(config.thread_cleanup);
+        assert_eq!(config.redis_max_retries, 0);
+    }
+}
@@ -2,6 +2,7 @@ use async_openai::error::OpenAIError;
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
+    Json,
 };
 use redis::RedisError;
 use std::io;
@@ -12,14 +13,44 @@ use std::sync::PoisonError;
 pub enum AppError {
     /// Redis operation errors
     RedisError(RedisError),
+    /// Redis connection pool errors
+    PoolError(r2d2::Error),
     /// JSON serialization/deserialization errors
     JsonSerializationError(serde_json::Error),
     /// Plain text serialization errors
     PlainSerializationError(serde_plain::Error),
     /// Error when an order cannot be found
     OrderNotFound(String),
+    /// Error when an order is no longer active and cannot be changed
+    OrderNotActive(String),
+    /// Error when the menu file does not exist
+    MenuFileMissing(String),
+    /// Error when a menu item cannot be found
+    MenuItemNotFound(String),
+    /// Error when an item cannot be found in an order
+    OrderItemNotFound(String),
+    /// Error when an item name matches several different items in an order
+    AmbiguousOrderItem(String),
+    /// Error when an order already holds the maximum number of items
+    OrderFull(String),
     /// Invalid input parameters
     InvalidInput(String),
+    /// Input that exceeds the configured size limit
+    InputTooLarge(String),
+    /// Request without a valid API key
+    Unauthorized(String),
+    /// Request the API key is not allowed to make
+    Forbidden(String),
+    /// Error when the assistant is not ready to be used
+    AssistantUnavailable(String),
+    /// Error when an assistant run failed or was cancelled upstream
+    RunFailed(String),
+    /// Error when an assistant run expired or took longer than the poll timeout
+    RunTimedOut(String),
+    /// Error when an order has used up its token budget
+    TokenBudgetExceeded(String),
+    /// Problems found in the environment configuration at startup
+    InvalidConfig(Vec<String>),
     /// File I/O errors
     IoError(io::Error),
     /// Mutex lock acquisition errors
@@ -38,6 +69,13 @@ impl From<RedisError> for AppError {
     }
 }
 
+impl From<r2d2::Error> for AppError {
+    /// Converts connection pool errors into AppError
+    fn from(err: r2d2::Error) -> Self {
+        AppError::PoolError(err)
+    }
+}
+
 impl From<serde_json::Error> for AppError {
     /// Converts JSON serialization errors into AppError
     fn from(err: serde_json::Error) -> Self {
@@ -73,14 +111,47 @@ impl<T> From<PoisonError<T>> for AppError {
     }
 }
 
-impl IntoResponse for AppError {
-    /// Converts AppError into an HTTP response
+impl AppError {
+    /// Stable machine-readable code identifying the kind of error.
     ///
     /// # Returns
-    /// * `Response` - HTTP response with appropriate status code and error message
-    fn into_response(self) -> Response {
-        let (status, message) = match self {
+    /// * `&'static str` - The error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::RedisError(_) => "REDIS_ERROR",
+            AppError::PoolError(_) => "REDIS_POOL_ERROR",
+            AppError::JsonSerializationError(_) => "JSON_SERIALIZATION_ERROR",
+            AppError::PlainSerializationError(_) => "PLAIN_SERIALIZATION_ERROR",
+            AppError::OrderNotFound(_) => "ORDER_NOT_FOUND",
+            AppError::OrderNotActive(_) => "ORDER_NOT_ACTIVE",
+            AppError::MenuFileMissing(_) => "MENU_FILE_MISSING",
+            AppError::MenuItemNotFound(_) => "MENU_ITEM_NOT_FOUND",
+            AppError::OrderItemNotFound(_) => "ORDER_ITEM_NOT_FOUND",
+            AppError::AmbiguousOrderItem(_) => "AMBIGUOUS_ORDER_ITEM",
+            AppError::OrderFull(_) => "ORDER_FULL",
+            AppError::InvalidInput(_) => "INVALID_INPUT",
+            AppError::InputTooLarge(_) => "INPUT_TOO_LARGE",
+            AppError::AssistantUnavailable(_) => "ASSISTANT_UNAVAILABLE",
+            AppError::RunFailed(_) => "RUN_FAILED",
+            AppError::RunTimedOut(_) => "RUN_TIMED_OUT",
+            AppError::TokenBudgetExceeded(_) => "TOKEN_BUDGET_EXCEEDED",
+            AppError::InvalidConfig(_) => "INVALID_CONFIG",
+            AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::Forbidden(_) => "FORBIDDEN",
+            AppError::IoError(_) => "IO_ERROR",
+            AppError::LockError => "LOCK_ERROR",
+            AppError::OpenAIError(_) => "OPENAI_ERROR",
+        }
+    }
+
+    /// HTTP status and human-readable message of the error.
+    ///
+    /// # Returns
+    /// * `(StatusCode, String)` - The status the error maps to and its message
+    pub fn into_parts(self) -> (StatusCode, String) {
+        match self {
             AppError::RedisError(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            AppError::PoolError(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             AppError::JsonSerializationError(e) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
             }
@@ -91,12 +162,55 @@ impl IntoResponse for AppError {
                 StatusCode::NOT_FOUND,
                 format!("Order with id {} not found", id),
             ),
+            AppError::OrderNotActive(msg) => (StatusCode::CONFLICT, msg),
+            AppError::MenuFileMissing(path) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Menu file {} does not exist", path),
+            ),
+            AppError::MenuItemNotFound(name) => (
+                StatusCode::NOT_FOUND,
+                format!("Menu item {} not found", name),
+            ),
+            AppError::OrderItemNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                format!("Order item {} not found", id),
+            ),
+            AppError::AmbiguousOrderItem(msg) => (StatusCode::CONFLICT, msg),
+            AppError::OrderFull(msg) => (StatusCode::CONFLICT, msg),
             AppError::InvalidInput(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::InputTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+            AppError::AssistantUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
+            AppError::RunFailed(msg) => (StatusCode::BAD_GATEWAY, msg),
+            AppError::RunTimedOut(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
+            AppError::TokenBudgetExceeded(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
+            AppError::InvalidConfig(errors) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, errors.join("; "))
+            }
             AppError::IoError(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             AppError::OpenAIError(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             AppError::LockError => (StatusCode::INTERNAL_SERVER_ERROR, "Lock error".to_string()),
-        };
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    /// Converts AppError into an HTTP response
+    ///
+    /// # Returns
+    /// * `Response` - HTTP response with appropriate status code and a JSON error body, reduced
+    ///   to the message unless `STRUCTURED_ERRORS` is set, see `set_json_charset`
+    fn into_response(self) -> Response {
+        let code = self.code();
+        let (status, message) = self.into_parts();
 
-        (status, message).into_response()
+        let body = serde_json::json!({
+            "error": {
+                "code": code,
+                "message": message,
+            }
+        });
+        (status, Json(body)).into_response()
     }
 }
@@ -2,20 +2,33 @@ use async_openai::{
     config::OpenAIConfig,
     error::OpenAIError,
     types::{
+        AssistantStreamEvent, AssistantsApiToolChoiceOption, CreateAssistantRequest,
         CreateAssistantRequestArgs, CreateMessageRequest, CreateRunRequest, CreateThreadRequest,
-        FunctionObject, MessageContent, MessageRole, RunObject, RunStatus,
-        SubmitToolOutputsRunRequest, ToolsOutputs,
+        FunctionObject, MessageContent, MessageRole, ModifyAssistantRequest, RunCompletionUsage,
+        RunObject, RunStatus, SubmitToolOutputsRunRequest, ToolsOutputs, TruncationObject,
+        TruncationObjectType,
     },
     Client,
 };
+use async_trait::async_trait;
+use futures::StreamExt;
+use metrics::{counter, histogram};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fmt::{self, Display};
-use tracing::{debug, error, info};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
-use crate::chat::{handle_function_call, ChatMessage, ChatRole};
+use crate::chat::{failed_function_output, handle_function_call, ChatMessage, ChatRole};
+use crate::config::Config;
 use crate::error::{AppError, AppResult};
-use crate::menu::Menu;
-use crate::order::Order;
+use crate::menu::{Menu, OptionGroup};
+use crate::money::Money;
+use crate::order::{OptionValue, Order, OrderItem, OrderLimits, OrderStore};
+use crate::telemetry;
 
 // TODO(siyer): Build a macro to do this whole process for each of the functions
 //              Something similar to https://github.com/frankfralick/openai-func-enums
@@ -27,28 +40,89 @@ pub enum FunctionName {
     ///
     #[serde(rename = "add_item")]
     AddItem,
+    /// Function to add several items to the order in a single call
+    #[serde(rename = "add_items")]
+    AddItems,
     /// Function to remove an item from the order
     #[serde(rename = "remove_item")]
     RemoveItem,
+    /// Function to remove several items from the order in a single call
+    #[serde(rename = "remove_items")]
+    RemoveItems,
     /// Function to modify an existing item
     #[serde(rename = "modify_item")]
     ModifyItem,
     /// Function to list items in the order
     #[serde(rename = "list_items")]
     ListItems,
+    /// Function to remove every item from the order
+    #[serde(rename = "clear_order")]
+    ClearOrder,
+    /// Function to set or unset individual options of an existing item
+    #[serde(rename = "patch_item")]
+    PatchItem,
+    /// Function to read the order back before the customer confirms it
+    #[serde(rename = "confirm_order")]
+    ConfirmOrder,
+    /// Function to look up items and their options on the live menu
+    #[serde(rename = "get_menu_info")]
+    GetMenuInfo,
+    /// Function to read the stored order back to the customer
+    #[serde(rename = "read_order")]
+    ReadOrder,
+    /// Function to remove the most recently added item
+    #[serde(rename = "undo_last_item")]
+    UndoLastItem,
+    /// Function to turn a line of several identical items into one line per item
+    #[serde(rename = "split_item")]
+    SplitItem,
+    /// Function to combine identical lines into one line
+    #[serde(rename = "merge_items")]
+    MergeItems,
+    /// Function to record the structured summary of the finished order
+    #[serde(rename = "finalize")]
+    Finalize,
 }
 
 impl Display for FunctionName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             FunctionName::AddItem => write!(f, "add_item"),
+            FunctionName::AddItems => write!(f, "add_items"),
             FunctionName::RemoveItem => write!(f, "remove_item"),
+            FunctionName::RemoveItems => write!(f, "remove_items"),
             FunctionName::ModifyItem => write!(f, "modify_item"),
             FunctionName::ListItems => write!(f, "list_items"),
+            FunctionName::ClearOrder => write!(f, "clear_order"),
+            FunctionName::PatchItem => write!(f, "patch_item"),
+            FunctionName::ConfirmOrder => write!(f, "confirm_order"),
+            FunctionName::GetMenuInfo => write!(f, "get_menu_info"),
+            FunctionName::ReadOrder => write!(f, "read_order"),
+            FunctionName::UndoLastItem => write!(f, "undo_last_item"),
+            FunctionName::SplitItem => write!(f, "split_item"),
+            FunctionName::MergeItems => write!(f, "merge_items"),
+            FunctionName::Finalize => write!(f, "finalize"),
         }
     }
 }
 
+impl FunctionName {
+    /// Whether calling this function changes the items in the order.
+    ///
+    /// # Returns
+    /// * `bool` - True if the function adds, removes, or modifies items
+    pub fn mutates_order(&self) -> bool {
+        !matches!(
+            self,
+            FunctionName::ListItems
+                | FunctionName::ConfirmOrder
+                | FunctionName::GetMenuInfo
+                | FunctionName::ReadOrder
+                | FunctionName::Finalize
+        )
+    }
+}
+
 // NOTE(dev): Extra verbosity in structs is to enable strict deserialization based on function name
 /// Arguments for adding an item to the order
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,10 +135,77 @@ pub struct AddItemArgs {
     pub option_keys: Option<Vec<String>>,
     /// Values for the customization options
     #[serde(rename = "optionValues")]
-    pub option_values: Option<Vec<Vec<String>>>,
+    pub option_values: Option<Vec<Vec<OptionValue>>>,
     // TODO(siyer): Could just calculate price using menu.rs, but trusting GPT for now
     /// Price of the item with options
-    pub price: f64,
+    pub price: Money,
+    /// Items filling the slots of a combo
+    #[serde(default)]
+    pub components: Option<Vec<ComponentArgs>>,
+    /// Special instructions that aren't menu options
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Person the item is for in a group order
+    #[serde(rename = "cartLabel", default)]
+    pub cart_label: Option<String>,
+    /// Number of identical items to add as one line, 1 if not given
+    #[serde(default)]
+    pub quantity: Option<u32>,
+}
+
+impl AddItemArgs {
+    /// Number of items the call adds.
+    ///
+    /// # Returns
+    /// * `u32` - The quantity, 1 if it is missing or 0
+    pub fn quantity(&self) -> u32 {
+        self.quantity.filter(|quantity| *quantity > 0).unwrap_or(1)
+    }
+}
+
+/// Arguments describing a component of a combo item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentArgs {
+    /// Name of the menu item filling the slot
+    #[serde(rename = "itemName")]
+    pub item_name: String,
+    /// Customization keys for the component
+    #[serde(rename = "optionKeys")]
+    pub option_keys: Option<Vec<String>>,
+    /// Values for the component's customization options
+    #[serde(rename = "optionValues")]
+    pub option_values: Option<Vec<Vec<OptionValue>>>,
+}
+
+impl From<&ComponentArgs> for OrderItem {
+    fn from(val: &ComponentArgs) -> Self {
+        OrderItem {
+            id: Uuid::new_v4().to_string(),
+            item_name: val.item_name.clone(),
+            option_keys: val.option_keys.clone().unwrap_or_default(),
+            option_values: val.option_values.clone().unwrap_or_default(),
+            price: Money::ZERO,
+            item_status: None,
+            components: vec![],
+            notes: None,
+            cart_label: None,
+            quantity: 1,
+        }
+    }
+}
+
+/// Arguments for adding a single item to the order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SingleItemArgs {
+    /// The item to add, nested so its schema can differ per menu item
+    pub item: AddItemArgs,
+}
+
+/// Arguments for adding several items to the order at once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddItemsArgs {
+    /// Items to add, each with the same shape as `add_item`
+    pub items: Vec<AddItemArgs>,
 }
 
 /// Arguments for removing an item from the order
@@ -72,7 +213,17 @@ pub struct AddItemArgs {
 pub struct RemoveItemArgs {
     /// ID of the order item to remove
     #[serde(rename = "orderId")]
-    pub order_id: String,
+    pub order_id: Option<String>,
+    /// Name of the item to remove, used when the ID is missing or unknown
+    #[serde(rename = "itemName", default)]
+    pub item_name: Option<String>,
+}
+
+/// Arguments for removing several items from the order at once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoveItemsArgs {
+    /// Items to remove, each with the same shape as `remove_item`
+    pub items: Vec<RemoveItemArgs>,
 }
 
 /// Arguments for modifying an existing item
@@ -81,6 +232,13 @@ pub struct ModifyItemArgs {
     /// ID of the order item to modify
     #[serde(rename = "orderId")]
     pub order_id: String,
+    /// New contents of the item, nested so its schema can differ per menu item
+    pub item: ModifiedItemArgs,
+}
+
+/// New contents of a modified item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModifiedItemArgs {
     /// New name for the item
     #[serde(rename = "itemName")]
     pub item_name: String,
@@ -89,10 +247,16 @@ pub struct ModifyItemArgs {
     pub option_keys: Option<Vec<String>>,
     /// New option values
     #[serde(rename = "optionValues")]
-    pub option_values: Option<Vec<Vec<String>>>,
+    pub option_values: Option<Vec<Vec<OptionValue>>>,
     // TODO(siyer): Could just calculate price using menu.rs, but trusting GPT for now
     /// Updated price
-    pub price: f64,
+    pub price: Money,
+    /// Updated combo components
+    #[serde(default)]
+    pub components: Option<Vec<ComponentArgs>>,
+    /// Updated special instructions
+    #[serde(default)]
+    pub notes: Option<String>,
 }
 
 /// Arguments for listing items in the order
@@ -102,18 +266,214 @@ pub struct ListItemsArgs {
     pub limit: Option<usize>,
 }
 
+/// Arguments for clearing the order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClearOrderArgs {}
+
+/// Arguments for reading the order back for confirmation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmOrderArgs {}
+
+/// Arguments for reading the order back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadOrderArgs {}
+
+/// Arguments for removing the most recently added item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoLastItemArgs {}
+
+/// Arguments for finalizing the order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinalizeArgs {}
+
+/// Arguments for looking up the menu
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetMenuInfoArgs {
+    /// Name of the item to describe, or None for an overview of every item
+    #[serde(rename = "itemName", default)]
+    pub item_name: Option<String>,
+}
+
+/// A single option to set on an item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptionPatch {
+    /// Key of the option
+    #[serde(rename = "optionKey")]
+    pub option_key: String,
+    /// Values replacing any previous values of the option
+    #[serde(rename = "optionValues")]
+    pub option_values: Vec<OptionValue>,
+}
+
+/// Arguments for changing individual options of an existing item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchItemArgs {
+    /// ID of the order item to patch
+    #[serde(rename = "orderId")]
+    pub order_id: String,
+    /// Options to set, leaving every other option untouched
+    #[serde(default)]
+    pub set: Vec<OptionPatch>,
+    /// Keys of the options to remove
+    #[serde(default)]
+    pub unset: Vec<String>,
+    /// Single values to remove from an option, keeping its other values
+    #[serde(rename = "removeValues", default)]
+    pub remove_values: Vec<OptionValueRemoval>,
+}
+
+/// Arguments for splitting a line into one line per item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitItemArgs {
+    /// ID of the order item to split
+    #[serde(rename = "orderId")]
+    pub order_id: String,
+}
+
+/// Arguments for merging identical lines into one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeItemsArgs {
+    /// IDs of the order items to merge, the first one is kept
+    #[serde(rename = "orderIds")]
+    pub order_ids: Vec<String>,
+}
+
+/// A single value to remove from an option of an item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptionValueRemoval {
+    /// Key of the option
+    #[serde(rename = "optionKey")]
+    pub option_key: String,
+    /// The value to remove
+    pub value: String,
+}
+
 /// Possible function arguments for the AI assistant
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum FunctionArgs {
     /// Arguments for adding an item
     AddItem(AddItemArgs),
+    /// Arguments for adding several items
+    AddItems(AddItemsArgs),
+    // NOTE(dev): Keep this before RemoveItem, whose optional fields match any arguments
+    /// Arguments for removing several items
+    RemoveItems(RemoveItemsArgs),
     /// Arguments for removing an item
     RemoveItem(RemoveItemArgs),
     /// Arguments for modifying an item
     ModifyItem(ModifyItemArgs),
     /// Arguments for listing items
     ListItems(ListItemsArgs),
+    /// Arguments for patching an item
+    PatchItem(PatchItemArgs),
+    /// Arguments for splitting an item
+    SplitItem(SplitItemArgs),
+    /// Arguments for merging items
+    MergeItems(MergeItemsArgs),
+    // NOTE(dev): Keep these last, optional or no fields match any arguments when untagged
+    /// Arguments for looking up the menu
+    GetMenuInfo(GetMenuInfoArgs),
+    /// Arguments for clearing the order
+    ClearOrder(ClearOrderArgs),
+    /// Arguments for confirming the order
+    ConfirmOrder(ConfirmOrderArgs),
+    /// Arguments for reading the order back
+    ReadOrder(ReadOrderArgs),
+    /// Arguments for removing the most recently added item
+    UndoLastItem(UndoLastItemArgs),
+    /// Arguments for finalizing the order
+    Finalize(FinalizeArgs),
+}
+
+/// Backend answering chat messages on behalf of the restaurant
+///
+/// Implemented by `OrderAssistant` for OpenAI and by `MockAssistant` for running the API
+/// without network calls.
+#[async_trait]
+pub trait AssistantBackend: Send + Sync {
+    /// Whether the backend is ready to handle messages.
+    ///
+    /// # Returns
+    /// * `bool` - True once the backend is ready
+    fn is_initialized(&self) -> bool;
+
+    /// Fetches every message of a conversation thread, oldest first.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The ID of the thread
+    ///
+    /// # Returns
+    /// * `AppResult<Vec<ChatMessage>>` - The text messages of the thread
+    async fn thread_messages(&self, thread_id: &str) -> AppResult<Vec<ChatMessage>>;
+
+    /// Processes a chat message, updating the order through function calls.
+    ///
+    /// # Arguments
+    /// * `message` - The user's message
+    /// * `location` - The restaurant location
+    /// * `language` - Language to reply in, if the customer asked for one
+    /// * `order` - The current order state
+    /// * `menu` - The restaurant menu
+    ///
+    /// # Returns
+    /// * `AppResult<Option<RunCompletionUsage>>` - Token usage reported for the message, if any
+    async fn handle_message(
+        &self,
+        message: &str,
+        location: &str,
+        language: Option<&str>,
+        order: &mut Order,
+        menu: &Menu,
+    ) -> AppResult<Option<RunCompletionUsage>>;
+
+    /// Creates a copy of the backend updated to use a new menu.
+    ///
+    /// # Arguments
+    /// * `menu` - The new menu
+    /// * `store` - Storage holding shared backend state
+    ///
+    /// # Returns
+    /// * `AppResult<Box<dyn AssistantBackend>>` - The updated backend
+    async fn reload(&self, menu: &Menu, store: &OrderStore)
+        -> AppResult<Box<dyn AssistantBackend>>;
+
+    /// Deletes a conversation thread that is no longer needed.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The ID of the thread
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the thread was deleted
+    async fn delete_thread(&self, thread_id: &str) -> AppResult<()>;
+
+    /// Cancels the run in progress on a thread, if there is one.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The ID of the thread
+    ///
+    /// # Returns
+    /// * `AppResult<Option<String>>` - The ID of the cancelled run, or None if no run was active
+    async fn cancel_active_run(&self, thread_id: &str) -> AppResult<Option<String>>;
+
+    /// Describes the assistant in use, for operators checking that a reload took effect.
+    ///
+    /// # Returns
+    /// * `Option<AssistantInfo>` - The assistant's details, or None if it isn't initialized
+    fn info(&self) -> Option<AssistantInfo>;
+}
+
+/// Details of the assistant a backend is using
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssistantInfo {
+    /// ID of the OpenAI assistant
+    #[serde(rename = "assistantId")]
+    pub assistant_id: String,
+    /// Model the assistant runs on
+    pub model: String,
+    /// Hash of the menu the assistant was built from, see `Menu::hash`
+    #[serde(rename = "menuHash")]
+    pub menu_hash: Option<String>,
 }
 
 /// AI assistant for managing orders
@@ -121,6 +481,124 @@ pub enum FunctionArgs {
 pub struct OrderAssistant {
     client: Client<OpenAIConfig>,
     assistant: Option<String>,
+    poll_timeout: Duration,
+    poll_interval: Duration,
+    max_poll_interval: Duration,
+    max_retries: u32,
+    temperature: f32,
+    top_p: f32,
+    tool_choice: ToolChoiceMode,
+    stateless_threads: bool,
+    stream_runs: bool,
+    models: Vec<String>,
+    model: String,
+    order_limits: OrderLimits,
+    system_prompt_file: Option<String>,
+    menu_hash: Option<String>,
+}
+
+/// How eagerly runs are made to call functions, set with `OPENAI_TOOL_CHOICE`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToolChoiceMode {
+    /// The model decides whether to call a function
+    #[default]
+    Auto,
+    /// Every run must call at least one function
+    Required,
+    /// Runs for messages that ask for a change to the order must call a function, the model
+    /// decides for the rest
+    Action,
+}
+
+impl std::str::FromStr for ToolChoiceMode {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ToolChoiceMode::Auto),
+            "required" => Ok(ToolChoiceMode::Required),
+            "action" => Ok(ToolChoiceMode::Action),
+            _ => Err(AppError::InvalidInput(format!(
+                "Unknown tool choice: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl ToolChoiceMode {
+    /// The tool choice to create a run for a message with.
+    ///
+    /// # Arguments
+    /// * `message` - The user's message
+    ///
+    /// # Returns
+    /// * `AssistantsApiToolChoiceOption` - `required` or `auto`
+    fn for_message(&self, message: &str) -> AssistantsApiToolChoiceOption {
+        let required = match self {
+            ToolChoiceMode::Auto => false,
+            ToolChoiceMode::Required => true,
+            ToolChoiceMode::Action => requests_action(message),
+        };
+        if required {
+            AssistantsApiToolChoiceOption::Required
+        } else {
+            AssistantsApiToolChoiceOption::Auto
+        }
+    }
+}
+
+/// First assistant message of every order, kept locally and never sent to the thread.
+///
+/// # Arguments
+/// * `location` - The restaurant location
+///
+/// # Returns
+/// * `ChatMessage` - The welcome message
+fn welcome_message(location: &str) -> ChatMessage {
+    ChatMessage {
+        role: ChatRole::Assistant.to_string(),
+        content: format!("Welcome to {}, what can I get started for you", location),
+    }
+}
+
+/// Reply used when a run ends incomplete before the assistant wrote anything
+const INCOMPLETE_REPLY: &str =
+    "Sorry, I couldn't finish my reply. Your order is up to date, could you say that again?";
+
+/// Phrases marking a message as a request to change the order
+const ACTION_PHRASES: &[&str] = &[
+    "add ",
+    "remove ",
+    "take off",
+    "without ",
+    "change ",
+    "swap ",
+    "make it",
+    "i'll have",
+    "i will have",
+    "i'd like",
+    "i would like",
+    "i want",
+    "can i get",
+    "can i have",
+    "get me",
+    "give me",
+    "start over",
+    "clear ",
+];
+
+/// Whether a message clearly asks for a change to the order, e.g. "add a coke".
+///
+/// # Arguments
+/// * `message` - The user's message
+///
+/// # Returns
+/// * `bool` - True if the message contains one of the action phrases
+fn requests_action(message: &str) -> bool {
+    // NOTE(dev): The trailing space lets phrases like "add " match at the end of a message
+    let message = format!("{} ", message.to_lowercase());
+    ACTION_PHRASES.iter().any(|phrase| message.contains(phrase))
 }
 
 impl OrderAssistant {
@@ -128,12 +606,269 @@ impl OrderAssistant {
     ///
     /// # Arguments
     /// * `client` - The OpenAI API client
-    pub fn new(client: Client<OpenAIConfig>) -> Self {
+    /// * `config` - The validated startup configuration holding the OpenAI settings and order
+    ///   limits
+    pub fn new(client: Client<OpenAIConfig>, config: &Config) -> Self {
         debug!("Creating new OrderAssistant instance");
+        debug!(
+            "Using run poll timeout of {:?}, polling every {:?} and backing off to at most {:?}",
+            config.openai_poll_timeout,
+            config.openai_poll_interval,
+            config.openai_max_poll_interval
+        );
+        debug!(
+            "Retrying transient OpenAI errors up to {} times",
+            config.openai_max_retries
+        );
+        info!(
+            "Using assistant temperature {} and top_p {}",
+            config.openai_temperature, config.openai_top_p
+        );
+        info!("Using tool choice {:?}", config.openai_tool_choice);
+        if config.stateless_threads {
+            info!("Using a new thread for every message and deleting it afterwards");
+        }
+        if config.openai_stream_runs {
+            info!("Streaming run events instead of polling runs");
+        }
+        info!(
+            "Using OpenAI models in order of preference: {:?}",
+            config.openai_models
+        );
         Self {
             client,
             assistant: None,
+            poll_timeout: config.openai_poll_timeout,
+            poll_interval: config.openai_poll_interval,
+            max_poll_interval: config.openai_max_poll_interval,
+            max_retries: config.openai_max_retries,
+            temperature: config.openai_temperature,
+            top_p: config.openai_top_p,
+            tool_choice: config.openai_tool_choice,
+            stateless_threads: config.stateless_threads,
+            stream_runs: config.openai_stream_runs,
+            model: config.openai_models[0].clone(),
+            models: config.openai_models.clone(),
+            order_limits: config.order_limits,
+            system_prompt_file: config.system_prompt_file.clone(),
+            menu_hash: None,
+        }
+    }
+
+    /// Runs an OpenAI call, retrying rate-limit and server errors with exponential backoff.
+    ///
+    /// # Arguments
+    /// * `operation` - Name of the call, used for logging
+    /// * `call` - Closure issuing the OpenAI request
+    ///
+    /// # Returns
+    /// * `Result<T, OpenAIError>` - The call result, or the last error once retries run out
+    async fn with_retry<T, F, Fut>(&self, operation: &str, mut call: F) -> Result<T, OpenAIError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, OpenAIError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match call().await {
+                Ok(result) => return Ok(result),
+                Err(e) if attempt < self.max_retries && is_retryable(&e) => {
+                    counter!(telemetry::OPENAI_ERRORS, "operation" => operation.to_string())
+                        .increment(1);
+                    let delay = retry_delay(attempt);
+                    attempt += 1;
+                    warn!(
+                        "OpenAI {} failed (attempt {} of {}), retrying in {:?}: {}",
+                        operation,
+                        attempt,
+                        self.max_retries + 1,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => {
+                    counter!(telemetry::OPENAI_ERRORS, "operation" => operation.to_string())
+                        .increment(1);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Whether the assistant has been created on the OpenAI platform.
+    ///
+    /// # Returns
+    /// * `bool` - True once `initialize_assistant` has succeeded
+    pub fn is_initialized(&self) -> bool {
+        self.assistant.is_some()
+    }
+
+    /// Fetches every message of a thread from OpenAI, oldest first.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The ID of the thread
+    ///
+    /// # Returns
+    /// * `AppResult<Vec<ChatMessage>>` - The text messages of the thread
+    pub async fn thread_messages(&self, thread_id: &str) -> AppResult<Vec<ChatMessage>> {
+        debug!("Fetching messages of thread {}", thread_id);
+        let mut messages = vec![];
+        let mut after: Option<String> = None;
+        loop {
+            let mut query = vec![("limit", "100".to_string()), ("order", "asc".to_string())];
+            if let Some(after) = &after {
+                query.push(("after", after.clone()));
+            }
+            let page = self
+                .with_retry("list messages", || async {
+                    self.client.threads().messages(thread_id).list(&query).await
+                })
+                .await?;
+
+            for message in &page.data {
+                let role = match message.role {
+                    MessageRole::User => ChatRole::User,
+                    MessageRole::Assistant => ChatRole::Assistant,
+                };
+                let content: Vec<String> = message
+                    .content
+                    .iter()
+                    .filter_map(|content| match content {
+                        MessageContent::Text(text) => Some(text.text.value.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                messages.push(ChatMessage {
+                    role: role.to_string(),
+                    content: content.join("\n"),
+                });
+            }
+
+            if !page.has_more || page.last_id.is_none() {
+                break;
+            }
+            after = page.last_id;
+        }
+        debug!(
+            "Fetched {} messages of thread {}",
+            messages.len(),
+            thread_id
+        );
+        Ok(messages)
+    }
+
+    /// Reuses the assistant stored in Redis, creating or updating it as needed.
+    ///
+    /// The models are tried in order, moving on to the next one when OpenAI reports that a
+    /// model does not exist.
+    ///
+    /// # Arguments
+    /// * `menu` - The restaurant menu to train the assistant with
+    /// * `store` - Storage holding the shared assistant id
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if an assistant is ready to use
+    pub async fn load_or_create(&mut self, menu: &Menu, store: &OrderStore) -> AppResult<()> {
+        let models = self.models.clone();
+        for (index, model) in models.iter().enumerate() {
+            match self.load_or_create_with_model(menu, store, model).await {
+                Ok(()) => {
+                    info!("Assistant is using OpenAI model {}", model);
+                    self.model = model.clone();
+                    self.menu_hash = Some(menu.hash()?);
+                    return Ok(());
+                }
+                Err(AppError::OpenAIError(e))
+                    if is_model_not_found(&e) && index + 1 < models.len() =>
+                {
+                    warn!(
+                        "OpenAI model {} is unavailable, falling back to {}: {}",
+                        model,
+                        models[index + 1],
+                        e
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!("the last model returns from the loop")
+    }
+
+    /// Reuses the assistant stored in Redis with a given model, creating or updating it as needed.
+    ///
+    /// The stored assistant is reused as-is when it was built from the same menu and
+    /// instructions, and updated in place when they changed. A new assistant is only created
+    /// when none is stored or the stored one no longer exists.
+    ///
+    /// # Arguments
+    /// * `menu` - The restaurant menu to train the assistant with
+    /// * `store` - Storage holding the shared assistant id
+    /// * `model` - The model to build the assistant on
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if an assistant is ready to use
+    async fn load_or_create_with_model(
+        &mut self,
+        menu: &Menu,
+        store: &OrderStore,
+        model: &str,
+    ) -> AppResult<()> {
+        let request = self.assistant_request(menu, model)?;
+        let config_hash = format!(
+            "{:x}",
+            Sha256::digest(serde_json::to_string(&request)?.as_bytes())
+        );
+        debug!("Assistant configuration hash: {}", config_hash);
+
+        let mut conn = store.get_connection()?;
+        if let Some((assistant_id, stored_hash)) = store.get_assistant(&mut conn)? {
+            let existing = self
+                .with_retry("retrieve assistant", || async {
+                    self.client.assistants().retrieve(&assistant_id).await
+                })
+                .await;
+            match existing {
+                Ok(_) if stored_hash == config_hash => {
+                    info!("Reusing assistant {}", assistant_id);
+                    self.assistant = Some(assistant_id);
+                    return Ok(());
+                }
+                Ok(_) => {
+                    info!("Menu changed, updating assistant {}", assistant_id);
+                    let update = ModifyAssistantRequest {
+                        model: Some(request.model.clone()),
+                        instructions: request.instructions.clone(),
+                        tools: request.tools.clone(),
+                        temperature: request.temperature,
+                        top_p: request.top_p,
+                        ..Default::default()
+                    };
+                    self.with_retry("update assistant", || async {
+                        self.client
+                            .assistants()
+                            .update(&assistant_id, update.clone())
+                            .await
+                    })
+                    .await?;
+                    store.set_assistant(&mut conn, &assistant_id, &config_hash)?;
+                    self.assistant = Some(assistant_id);
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!(
+                        "Stored assistant {} could not be retrieved, creating a new one: {}",
+                        assistant_id, e
+                    );
+                }
+            }
         }
+
+        self.create_assistant(request).await?;
+        if let Some(assistant_id) = &self.assistant {
+            store.set_assistant(&mut conn, assistant_id, &config_hash)?;
+        }
+        Ok(())
     }
 
     /// Initializes the AI assistant with the restaurant menu and function definitions.
@@ -145,18 +880,141 @@ impl OrderAssistant {
     /// * `AppResult<()>` - Success if initialization completes
     pub async fn initialize_assistant(&mut self, menu: &Menu) -> AppResult<()> {
         info!("Initializing AI assistant with menu");
-        let model = std::env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4o".to_string());
+        let request = self.assistant_request(menu, &self.model)?;
+        self.create_assistant(request).await?;
+        self.menu_hash = Some(menu.hash()?);
+        Ok(())
+    }
+
+    /// Creates the assistant on the OpenAI platform.
+    ///
+    /// # Arguments
+    /// * `request` - The assistant definition
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the assistant was created
+    async fn create_assistant(&mut self, request: CreateAssistantRequest) -> AppResult<()> {
+        debug!("Creating assistant with OpenAI API");
+        let assistant = self
+            .with_retry("create assistant", || async {
+                self.client.assistants().create(request.clone()).await
+            })
+            .await?;
+        self.assistant = Some(assistant.id);
+        info!("AI assistant initialized successfully");
+
+        Ok(())
+    }
+
+    /// Builds the assistant definition from the menu, function definitions and sampling settings.
+    ///
+    /// # Arguments
+    /// * `menu` - The restaurant menu to train the assistant with
+    /// * `model` - The OpenAI model to use
+    ///
+    /// # Returns
+    /// * `AppResult<CreateAssistantRequest>` - The assistant definition
+    fn assistant_request(&self, menu: &Menu, model: &str) -> AppResult<CreateAssistantRequest> {
         debug!("Using OpenAI model: {}", model);
+
+        // NOTE(dev): Every group of items offering the same options is its own variant of the item
+        //            schema, so an item can only be sent with its own option keys and choices.
+        //            Strict mode doesn't allow variants at the root, so add_item and modify_item
+        //            nest the item, and each group's choices are defined once per tool in $defs to
+        //            stay under the cap on enum values.
+        let option_keys = menu.option_keys();
+        let choice_names = menu.choice_names();
+        let groups = menu.option_groups();
+        debug!(
+            "Constraining tools to {} option groups, {} option keys and {} choices",
+            groups.len(),
+            option_keys.len(),
+            choice_names.len()
+        );
+        // NOTE(dev): Spelling out the counts keeps the model from guessing between e.g. exactly 2 and 1 to 3
+        let option_counts = menu.option_counts();
+        let exclusions = match menu.exclusions() {
+            Some(exclusions) => format!(" Choices that can't be combined: {}.", exclusions),
+            None => String::new(),
+        };
+        let values_description = format!(
+            "The values for the options, one list per option key. Number of values per option: {}.{}",
+            option_counts, exclusions
+        );
+        let value_description = format!(
+            "The values for the option. Number of values per option: {}.{}",
+            option_counts, exclusions
+        );
+        let mut defs = serde_json::Map::new();
+        for (index, group) in groups.iter().enumerate() {
+            if !group.choice_names.is_empty() {
+                defs.insert(
+                    format!("values{}", index),
+                    value_schema(&group.choice_names),
+                );
+            }
+        }
+        defs.insert(
+            "component".to_string(),
+            item_variants(
+                &groups,
+                "The name of the item filling the combo slot.",
+                &values_description,
+                serde_json::json!({}),
+            ),
+        );
+        let defs = serde_json::Value::Object(defs);
+        let item_properties = serde_json::json!({
+            "price": { "type": "number", "description": "The price of the item." },
+            "components": { "type": "array", "items": { "$ref": "#/$defs/component" }, "description": "The items filling the slots of a combo, empty for other items." },
+            "notes": { "type": ["string", "null"], "description": "Special instructions that are not menu options (e.g. extra crispy), copied verbatim, or null." }
+        });
+        let mut add_properties = item_properties.clone();
+        add_properties["cartLabel"] = serde_json::json!({ "type": ["string", "null"], "description": "The name of the person the item is for in a group order (e.g. Alice), or null." });
+        add_properties["quantity"] = serde_json::json!({ "type": ["integer", "null"], "description": "How many of this exact item to add as one line (e.g. 3 for three identical burgers), or null for one." });
+        let item_schema = item_variants(
+            &groups,
+            "The name of the item to add.",
+            &values_description,
+            add_properties,
+        );
+        let modified_item_schema = item_variants(
+            &groups,
+            "The name of the item to modify.",
+            &values_description,
+            item_properties,
+        );
+
         let create_assistant_request = CreateAssistantRequestArgs::default()
         // TODO(siyer): Consider moving the menu to a file upload call instead of adding it to instructions
-        .instructions(format!("You are an order management assistant.
-                               - Talk as if you were taking orders in a drive thru.
+        .instructions(format!("{}
+
                                - Use the provided functions to manage the items in orders.
                                - Ensure that every item has all of its requirements met and contains the Completed status
+                               - Each function reports whether it succeeded and the status of the items it changed, use the status message to fix incomplete or invalid items
+                               - Never offer items or choices that are marked as unavailable
+                               - Use the tags on each item to answer allergen and dietary questions (e.g. what is vegan)
+                               - When the customer asks what is available (e.g. what toppings do you have), call get_menu_info and answer from its result, the menu below may be out of date
                                - Try to parallelize the tool calls as much as possible (e.g. submit all 5 additions at the same time)
-                               - At the end of the conversation give the final price of the items in the cart
-                               Use the follow menu: \n\n {}", serde_json::to_string_pretty(&menu)?))
+                               - For extra of a choice (e.g. double cheese) give the value a count instead of repeating it
+                               - A choice with priceType percent adds its price as a percentage of the item's other choices (e.g. 10 adds 10% of the flat priced choices), not as an amount
+                               - Put requests that are not menu options (e.g. extra crispy, cut in half) in the item's notes instead of its options
+                               - For combo items, fill every slot listed in the item's components with an item and its own options
+                               - When several people order together, set each item's cartLabel to the name of the person it is for, otherwise leave it null
+                               - When an add reports suggestions, you may offer one of them in a short question (e.g. want fries with that?), but don't repeat an offer the customer turned down
+                               - When adding more than one item, use add_items with every item in a single call instead of repeated add_item calls
+                               - When removing more than one item, use remove_items with every item in a single call instead of repeated remove_item calls
+                               - When the customer takes back the item they just ordered (e.g. scratch that last one), call undo_last_item instead of looking up its id
+                               - When the customer wants to change some of the items on a line with a quantity (e.g. make one of those three without onions), call split_item on the line first and change one of the new lines
+                               - When several lines hold the exact same item, you may call merge_items to combine them into one line
+                               - When the customer asks to hear the order (e.g. read that back), call read_order and read its lines and total exactly instead of repeating them from memory
+                               - When the customer is done ordering, call confirm_order, read back the items and total it returns, and ask the customer to confirm with a yes
+                               - If the customer changes anything after confirm_order, call confirm_order again before asking for a yes
+                               - Whenever you call confirm_order, also call finalize so the customer's screen shows the order, but only read back what confirm_order returns
+                               Use the follow menu: \n\n {}", system_prompt(self.system_prompt_file.as_deref())?, serde_json::to_string_pretty(&menu)?))
         .model(model)
+        .temperature(self.temperature)
+        .top_p(self.top_p)
         .tools(vec![
             FunctionObject {
                 name: FunctionName::AddItem.to_string(),
@@ -164,48 +1022,90 @@ impl OrderAssistant {
                 parameters: Some(serde_json::json!({
                     "type": "object",
                     "properties": {
-                        // TODO(siyer): Consider adding enum validation for these fields
-                        //              https://platform.openai.com/docs/guides/function-calling#function-definitions
-                        // TODO(siyer): Figure out how to force gpt to call functions parallelly (it has the capabilities to do so)
-                        //              If I can't figure out prompting, change the function definition to take an array instead
-                        "itemName": { "type": "string", "description": "The name of the item to add." },
-                        "optionKeys": { "type": "array",  "items": { "type": "string" }, "description": "The options for the item." },
-                        "optionValues": { "type": "array", "items": { "type": "array", "items": {"type": "string"} }, "description": "The values for the options." },
-                        "price": { "type": "number", "description": "The price of the item." }
+                        // NOTE(dev): Multi-item orders should go through add_items, which takes an array instead
+                        "item": item_schema
                     },
-                    "required": ["itemName"]
+                    "required": ["item"],
+                    "additionalProperties": false,
+                    "$defs": defs
                 })),
-                strict: None,
+                strict: Some(true),
             }
             .into(),
             FunctionObject {
-                name: FunctionName::RemoveItem.to_string(),
-                description: Some("Remove an item from the order.".into()),
+                name: FunctionName::AddItems.to_string(),
+                description: Some("Add several items to the order in a single call.".into()),
                 parameters: Some(serde_json::json!({
                     "type": "object",
                     "properties": {
-                        "orderId": { "type": "string", "description": "The id of the order item to remove from the orders list." }
+                        "items": {
+                            "type": "array",
+                            "description": "The items to add to the order.",
+                            "items": item_schema
+                        }
                     },
-                    "required": ["orderId"]
+                    "required": ["items"],
+                    "additionalProperties": false,
+                    "$defs": defs
                 })),
-                strict: None,
+                strict: Some(true),
             }
             .into(),
             FunctionObject {
-                name: FunctionName::ModifyItem.to_string(),
-                description: Some("Modify an item in the order.".into()),
+                name: FunctionName::RemoveItem.to_string(),
+                description: Some("Remove an item from the order.".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "orderId": { "type": ["string", "null"], "description": "The id of the order item to remove from the orders list." },
+                        "itemName": { "type": ["string", "null"], "description": "The name of the item to remove, used if the id is not known." }
+                    },
+                    "required": ["orderId", "itemName"],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }
+            .into(),
+            FunctionObject {
+                name: FunctionName::RemoveItems.to_string(),
+                description: Some("Remove several items from the order in a single call.".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "items": {
+                            "type": "array",
+                            "description": "The items to remove from the order.",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "orderId": { "type": ["string", "null"], "description": "The id of the order item to remove from the orders list." },
+                                    "itemName": { "type": ["string", "null"], "description": "The name of the item to remove, used if the id is not known." }
+                                },
+                                "required": ["orderId", "itemName"],
+                                "additionalProperties": false
+                            }
+                        }
+                    },
+                    "required": ["items"],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }
+            .into(),
+            FunctionObject {
+                name: FunctionName::ModifyItem.to_string(),
+                description: Some("Modify an item in the order.".into()),
                 parameters: Some(serde_json::json!({
                     "type": "object",
                     "properties": {
                         "orderId": { "type": "string", "description": "The id of the order item to modify from the orders list." },
-                        "itemName": { "type": "string", "description": "The name of the item to modify." },
-                        "optionKeys": { "type": "array",  "items": { "type": "string" }, "description": "The options for the item." },
-                        "optionValues": { "type": "array", "items": { "type": "array", "items": {"type": "string"} }, "description": "The values for the options." },
-                        "price": { "type": "number", "description": "The price of the item." }
+                        "item": modified_item_schema
                     },
-                    "required": ["orderId", "itemName"]
+                    "required": ["orderId", "item"],
+                    "additionalProperties": false,
+                    "$defs": defs
                 })),
-                strict: None,
+                strict: Some(true),
             }
             .into(),
             FunctionObject {
@@ -214,47 +1114,275 @@ impl OrderAssistant {
                 parameters: Some(serde_json::json!({
                     "type": "object",
                     "properties": {
-                        "limit": { "type": "number", "description": "Optional field to limit to the amount of items to list that should default to false unless under token pressure" }
+                        "limit": { "type": ["integer", "null"], "description": "Optional field to limit to the amount of items to list that should be null unless under token pressure" }
+                    },
+                    "required": ["limit"],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }.into(),
+            FunctionObject {
+                name: FunctionName::ClearOrder.to_string(),
+                description: Some("Remove every item from the order when the customer wants to start over.".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {},
+                    "required": [],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }.into(),
+            FunctionObject {
+                name: FunctionName::PatchItem.to_string(),
+                description: Some("Set or unset individual options of an item in the order, or remove single values from an option, keeping everything else. Prefer this over modify_item for small changes like \"no onions\".".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "orderId": { "type": "string", "description": "The id of the order item to patch." },
+                        "set": {
+                            "type": "array",
+                            "description": "The options to set, replacing the previous values of each option.",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "optionKey": { "type": "string", "enum": option_keys, "description": "The option to set." },
+                                    "optionValues": { "type": "array", "items": value_schema(&choice_names), "description": value_description }
+                                },
+                                "required": ["optionKey", "optionValues"],
+                                "additionalProperties": false
+                            }
+                        },
+                        "unset": { "type": "array", "items": { "type": "string", "enum": option_keys }, "description": "The options to remove from the item." },
+                        "removeValues": {
+                            "type": "array",
+                            "description": "Single values to remove from an option, keeping its other values (e.g. remove bacon from the customizations).",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "optionKey": { "type": "string", "enum": option_keys, "description": "The option to remove the value from." },
+                                    "value": { "type": "string", "enum": choice_names, "description": "The value to remove." }
+                                },
+                                "required": ["optionKey", "value"],
+                                "additionalProperties": false
+                            }
+                        }
+                    },
+                    "required": ["orderId", "set", "unset", "removeValues"],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }.into(),
+            FunctionObject {
+                name: FunctionName::GetMenuInfo.to_string(),
+                description: Some("Look up the current menu. With an item name, returns the item with its options, choices, prices and availability; without one, returns an overview of every item.".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "itemName": { "type": ["string", "null"], "description": "The name of the item to describe, or null for an overview of the menu." }
+                    },
+                    "required": ["itemName"],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }.into(),
+            FunctionObject {
+                name: FunctionName::ConfirmOrder.to_string(),
+                description: Some("Get the itemized order and total to read back to the customer once they are done ordering. Their next yes completes the order.".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {},
+                    "required": [],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }.into(),
+            FunctionObject {
+                name: FunctionName::UndoLastItem.to_string(),
+                description: Some("Remove the item that was added to the order most recently, e.g. when the customer says \"scratch that last one\".".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {},
+                    "required": [],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }.into(),
+            FunctionObject {
+                name: FunctionName::SplitItem.to_string(),
+                description: Some("Turn a line with a quantity above one into one line per item, each with the same options and a new id, so they can be changed separately.".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "orderId": { "type": "string", "description": "The id of the order item to split." }
+                    },
+                    "required": ["orderId"],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }.into(),
+            FunctionObject {
+                name: FunctionName::MergeItems.to_string(),
+                description: Some("Combine lines holding the exact same item and options into the first of them, adding up their quantities.".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "orderIds": { "type": "array", "items": { "type": "string" }, "description": "The ids of the order items to merge, at least two." }
                     },
-                    "required": []
+                    "required": ["orderIds"],
+                    "additionalProperties": false
                 })),
-                strict: None,
+                strict: Some(true),
+            }.into(),
+            FunctionObject {
+                name: FunctionName::Finalize.to_string(),
+                description: Some("Record the itemized order and total for the customer's screen once the order is read back. Nothing in its result is for the customer.".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {},
+                    "required": [],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            }.into(),
+            FunctionObject {
+                name: FunctionName::ReadOrder.to_string(),
+                description: Some("Get an itemized summary of the order as it is stored, with options, quantities, line prices and the total, to read back to the customer.".into()),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {},
+                    "required": [],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
             }.into(),
         ])
         .build()?;
 
-        debug!("Creating assistant with OpenAI API");
-        let assistant = self
-            .client
-            .assistants()
-            .create(create_assistant_request)
-            .await?;
-        self.assistant = Some(assistant.id);
-        info!("AI assistant initialized successfully");
-
-        Ok(())
+        Ok(create_assistant_request)
     }
 
     /// Creates a new conversation thread with the assistant.
     ///
     /// # Arguments
     /// * `location` - The restaurant location
+    /// * `messages` - Earlier messages to seed the thread with
     ///
     /// # Returns
     /// * `AppResult<String>` - The ID of the created thread
-    pub async fn create_thread(&self, location: &String) -> AppResult<String> {
-        debug!("Creating new thread for location: {}", location);
+    pub async fn create_thread(
+        &self,
+        location: &str,
+        messages: Vec<CreateMessageRequest>,
+    ) -> AppResult<String> {
+        debug!(
+            "Creating new thread for location: {} with {} messages",
+            location,
+            messages.len()
+        );
+        let request = CreateThreadRequest {
+            messages: (!messages.is_empty()).then_some(messages),
+            ..Default::default()
+        };
         let thread = self
-            .client
-            .threads()
-            .create(CreateThreadRequest::default())
+            .with_retry("create thread", || async {
+                self.client.threads().create(request.clone()).await
+            })
             .await?;
         debug!("Created thread with ID: {}", thread.id);
         Ok(thread.id)
     }
 
+    /// Deletes a conversation thread and every message in it.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The conversation thread ID
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the thread was deleted
+    pub async fn delete_thread(&self, thread_id: &str) -> AppResult<()> {
+        debug!("Deleting thread {}", thread_id);
+        self.with_retry("delete thread", || async {
+            self.client.threads().delete(thread_id).await
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Cancels an in-progress run on a thread.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The conversation thread ID
+    /// * `run_id` - The run to cancel
+    ///
+    /// # Returns
+    /// * `AppResult<RunObject>` - The run after the cancellation request
+    pub async fn cancel_run(&self, thread_id: &str, run_id: &str) -> AppResult<RunObject> {
+        info!(
+            "Cancelling run. Thread ID: {}, Run ID: {}",
+            thread_id, run_id
+        );
+        let run = self.client.threads().runs(thread_id).cancel(run_id).await?;
+        debug!("Run {} is now in state: {:?}", run_id, run.status);
+        Ok(run)
+    }
+
+    /// Cancels the run in progress on a thread, if there is one.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The conversation thread ID
+    ///
+    /// # Returns
+    /// * `AppResult<Option<String>>` - The ID of the cancelled run, or None if no run was active
+    pub async fn cancel_active_run(&self, thread_id: &str) -> AppResult<Option<String>> {
+        debug!("Looking for an active run on thread {}", thread_id);
+        // NOTE(dev): A thread only allows one active run at a time, so it is always the latest
+        let runs = self
+            .with_retry("list runs", || async {
+                self.client
+                    .threads()
+                    .runs(thread_id)
+                    .list(&[("limit", "1")])
+                    .await
+            })
+            .await?;
+        match runs.data.first() {
+            Some(run)
+                if matches!(
+                    run.status,
+                    RunStatus::Queued | RunStatus::InProgress | RunStatus::RequiresAction
+                ) =>
+            {
+                self.cancel_run(thread_id, &run.id).await?;
+                Ok(Some(run.id.clone()))
+            }
+            _ => {
+                debug!("Thread {} has no active run", thread_id);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Retrieves the current state of a run.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The conversation thread ID
+    /// * `run_id` - The run to retrieve
+    ///
+    /// # Returns
+    /// * `AppResult<RunObject>` - The current run state
+    async fn retrieve_run(&self, thread_id: &str, run_id: &str) -> AppResult<RunObject> {
+        Ok(self
+            .with_retry("retrieve run", || async {
+                self.client.threads().runs(thread_id).retrieve(run_id).await
+            })
+            .await?)
+    }
+
     /// Polls the assistant thread until completion or action required.
     ///
+    /// Gives up after the configured poll timeout, cancelling the run so that it does not keep
+    /// the thread locked.
+    ///
     /// # Arguments
     /// * `thread_id` - The conversation thread ID
     /// * `run_id` - The current run ID
@@ -265,8 +1393,8 @@ impl OrderAssistant {
     /// * `AppResult<RunObject>` - The final run state
     pub async fn poll_thread(
         &self,
-        thread_id: &String,
-        run_id: &String,
+        thread_id: &str,
+        run_id: &str,
         order: &mut Order,
         menu: &Menu,
     ) -> AppResult<RunObject> {
@@ -274,118 +1402,344 @@ impl OrderAssistant {
             "Starting to poll thread. Thread ID: {}, Run ID: {}, Order ID: {}",
             thread_id, run_id, order.order_id
         );
-        let mut run = self
-            .client
-            .threads()
-            .runs(thread_id)
-            .retrieve(run_id)
-            .await?;
+        let started = Instant::now();
+        let mut polls = 0;
+        let mut run = self.retrieve_run(thread_id, run_id).await?;
         loop {
             match run.status {
-                RunStatus::Completed => {
-                    info!(
-                        "Run completed successfully. Thread ID: {}, Run ID: {}, Order ID: {}",
-                        thread_id, run_id, order.order_id
-                    );
-                    return Ok(run);
-                }
                 RunStatus::Queued | RunStatus::InProgress | RunStatus::Cancelling => {
                     debug!("Run {} in state: {:?}", run_id, run.status);
-                    run = self
-                        .client
-                        .threads()
-                        .runs(thread_id)
-                        .retrieve(run_id)
-                        .await?;
+                    run = self.retrieve_run(thread_id, run_id).await?;
                 }
                 RunStatus::RequiresAction => {
-                    info!(
-                        "Run requires action. Thread ID: {}, Run ID: {}, Order ID: {}",
-                        thread_id, run_id, order.order_id
-                    );
-                    let mut tool_outputs: Vec<ToolsOutputs> = vec![];
-                    if run.required_action.is_none() {
-                        error!("Run {} requires action but no action specified", run.id);
-                        return Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
-                            format!("{:?}", run),
-                        )));
-                    };
-                    let tool_calls = run
-                        .required_action
-                        .to_owned()
-                        .unwrap()
-                        .submit_tool_outputs
-                        .tool_calls;
-                    debug!("Processing {} tool calls", tool_calls.len());
-                    for tool_call in tool_calls {
-                        debug!(
-                            "Executing tool call: {} (ID: {}) for Order ID: {}",
-                            tool_call.function.name, tool_call.id, order.order_id
-                        );
-                        let tool_output =
-                            handle_function_call(&tool_call.function, menu, order).await?;
-                        tool_outputs.push(ToolsOutputs {
-                            tool_call_id: Some(tool_call.id),
-                            output: Some(tool_output.to_string()),
-                        });
-                    }
-                    debug!("Submitting {} tool outputs", tool_outputs.len());
+                    let tool_outputs = self.run_tool_calls(&run, order, menu).await?;
                     let _response = self
-                        .client
-                        .threads()
-                        .runs(thread_id)
-                        .submit_tool_outputs(
-                            run_id,
-                            SubmitToolOutputsRunRequest {
-                                tool_outputs,
-                                stream: Some(false),
-                            },
-                        )
+                        .with_retry("submit tool outputs", || async {
+                            self.client
+                                .threads()
+                                .runs(thread_id)
+                                .submit_tool_outputs(
+                                    run_id,
+                                    SubmitToolOutputsRunRequest {
+                                        tool_outputs: tool_outputs.clone(),
+                                        stream: Some(false),
+                                    },
+                                )
+                                .await
+                        })
                         .await?;
-                    run = self
-                        .client
+                    // NOTE(dev): the run starts working again after tool outputs are submitted,
+                    // so go back to polling quickly instead of keeping the backed off interval
+                    polls = 0;
+                    run = self.retrieve_run(thread_id, run_id).await?;
+                }
+                _ => return finish_run(run, thread_id, order),
+            }
+            if started.elapsed() >= self.poll_timeout {
+                return Err(self.run_timed_out(thread_id, run_id, order).await);
+            }
+            let delay = poll_delay(self.poll_interval, self.max_poll_interval, polls)
+                .min(self.poll_timeout.saturating_sub(started.elapsed()));
+            polls += 1;
+            debug!("Waiting {:?} before next poll for run {}", delay, run_id);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Creates a run and polls it until it ends.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The conversation thread ID
+    /// * `request` - The run to create, on the assistant's own model
+    /// * `order` - The current order state
+    /// * `menu` - The restaurant menu
+    ///
+    /// # Returns
+    /// * `AppResult<RunObject>` - The final run state
+    async fn create_and_poll_run(
+        &self,
+        thread_id: &str,
+        request: &CreateRunRequest,
+        order: &mut Order,
+        menu: &Menu,
+    ) -> AppResult<RunObject> {
+        // NOTE(dev): None runs on the assistant's own model, the later models are only tried if it
+        //            disappeared since the assistant was loaded
+        let mut run_models: Vec<Option<String>> = vec![None];
+        run_models.extend(
+            self.models
+                .iter()
+                .skip_while(|model| **model != self.model)
+                .skip(1)
+                .map(|model| Some(model.clone())),
+        );
+        let mut models = run_models.into_iter().peekable();
+        let response = loop {
+            let model = models.next().flatten();
+            let result = self
+                .with_retry("create run", || async {
+                    self.client
                         .threads()
                         .runs(thread_id)
-                        .retrieve(run_id)
+                        .create(CreateRunRequest {
+                            model: model.clone(),
+                            stream: Some(false),
+                            ..request.clone()
+                        })
+                        .await
+                })
+                .await;
+            match result {
+                Ok(response) => {
+                    if let Some(model) = &model {
+                        warn!(
+                            "Run {} fell back to OpenAI model {}, reload the menu to rebuild the assistant on it",
+                            response.id, model
+                        );
+                    }
+                    break response;
+                }
+                Err(e) if is_model_not_found(&e) && models.peek().is_some() => {
+                    warn!(
+                        "OpenAI model {} is unavailable for run: {}",
+                        model.as_deref().unwrap_or(&self.model),
+                        e
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        debug!("Created run: {}", response.id);
+
+        self.poll_thread(thread_id, &response.id, order, menu).await
+    }
+
+    /// Drives a run from its streamed events until it ends, answering tool calls as soon as
+    /// they are requested instead of polling for them.
+    ///
+    /// If the stream breaks after the run was created, the run is polled to its end instead.
+    /// Gives up after the configured poll timeout like `poll_thread`.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The conversation thread ID
+    /// * `request` - The run to create, on the assistant's own model
+    /// * `order` - The current order state
+    /// * `menu` - The restaurant menu
+    ///
+    /// # Returns
+    /// * `AppResult<Option<RunObject>>` - The final run state, or None if the stream broke
+    ///   before the run was created and it should be created by polling instead
+    async fn stream_run(
+        &self,
+        thread_id: &str,
+        request: &CreateRunRequest,
+        order: &mut Order,
+        menu: &Menu,
+    ) -> AppResult<Option<RunObject>> {
+        debug!(
+            "Streaming run events. Thread ID: {}, Order ID: {}",
+            thread_id, order.order_id
+        );
+        let mut events = self
+            .client
+            .threads()
+            .runs(thread_id)
+            .create_stream(CreateRunRequest {
+                stream: Some(true),
+                ..request.clone()
+            })
+            .await?;
+        let started = Instant::now();
+        let mut run_id: Option<String> = None;
+        loop {
+            let remaining = self.poll_timeout.saturating_sub(started.elapsed());
+            let event = match tokio::time::timeout(remaining, events.next()).await {
+                Ok(Some(event)) => event,
+                Ok(None) => Err(OpenAIError::StreamError(
+                    "stream ended before the run did".to_string(),
+                )),
+                Err(_) => {
+                    return Err(match &run_id {
+                        Some(run_id) => self.run_timed_out(thread_id, run_id, order).await,
+                        None => AppError::OpenAIError(OpenAIError::StreamError(format!(
+                            "Run was not created within {} seconds",
+                            self.poll_timeout.as_secs()
+                        ))),
+                    });
+                }
+            };
+            let event = match (event, &run_id) {
+                (Ok(event), _) => event,
+                (Err(e), Some(run_id)) => {
+                    // NOTE(dev): The run carries on without the stream, so it is finished by
+                    //            polling rather than created again
+                    warn!(
+                        "Run event stream broke, polling run {} instead: {}",
+                        run_id, e
+                    );
+                    counter!(telemetry::OPENAI_ERRORS, "operation" => "run stream").increment(1);
+                    return self
+                        .poll_thread(thread_id, run_id, order, menu)
+                        .await
+                        .map(Some);
+                }
+                (Err(e), None) => {
+                    // NOTE(dev): Creating the run again by polling also falls back across
+                    //            OPENAI_MODELS if the assistant's model is unavailable
+                    warn!("Could not stream a new run, creating it by polling: {}", e);
+                    counter!(telemetry::OPENAI_ERRORS, "operation" => "run stream").increment(1);
+                    return Ok(None);
+                }
+            };
+            match event {
+                AssistantStreamEvent::ThreadRunCreated(run) => {
+                    debug!("Run {} created", run.id);
+                    run_id = Some(run.id);
+                }
+                AssistantStreamEvent::ThreadRunRequiresAction(run) => {
+                    let tool_outputs = self.run_tool_calls(&run, order, menu).await?;
+                    events = self
+                        .with_retry("submit tool outputs", || async {
+                            self.client
+                                .threads()
+                                .runs(thread_id)
+                                .submit_tool_outputs_stream(
+                                    &run.id,
+                                    SubmitToolOutputsRunRequest {
+                                        tool_outputs: tool_outputs.clone(),
+                                        stream: Some(true),
+                                    },
+                                )
+                                .await
+                        })
                         .await?;
                 }
-                _ => {
+                AssistantStreamEvent::ThreadRunCompleted(run)
+                | AssistantStreamEvent::ThreadRunIncomplete(run)
+                | AssistantStreamEvent::ThreadRunFailed(run)
+                | AssistantStreamEvent::ThreadRunCancelled(run)
+                | AssistantStreamEvent::ThreadRunExpired(run) => {
+                    return finish_run(run, thread_id, order).map(Some);
+                }
+                AssistantStreamEvent::ErrorEvent(e) => {
                     error!(
-                        "Run in unexpected state: {:?}. Thread ID: {}, Run ID: {}, Order ID: {}",
-                        run.status, thread_id, run_id, order.order_id
+                        "Run event stream reported an error. Thread ID: {}, Order ID: {}, Error: {:?}",
+                        thread_id, order.order_id, e
                     );
-                    return Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
-                        format!("{:?}", run),
-                    )));
+                    counter!(telemetry::OPENAI_ERRORS, "operation" => "run").increment(1);
+                    return Err(AppError::OpenAIError(OpenAIError::ApiError(e)));
                 }
+                _ => {}
             }
-            debug!("Waiting before next poll for run {}", run_id);
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
     }
 
+    /// Runs the function calls a run is waiting on.
+    ///
+    /// # Arguments
+    /// * `run` - The run requiring action
+    /// * `order` - The current order state
+    /// * `menu` - The restaurant menu
+    ///
+    /// # Returns
+    /// * `AppResult<Vec<ToolsOutputs>>` - The outputs to submit, one per call
+    async fn run_tool_calls(
+        &self,
+        run: &RunObject,
+        order: &mut Order,
+        menu: &Menu,
+    ) -> AppResult<Vec<ToolsOutputs>> {
+        info!(
+            "Run requires action. Run ID: {}, Order ID: {}",
+            run.id, order.order_id
+        );
+        let Some(required_action) = &run.required_action else {
+            error!("Run {} requires action but no action specified", run.id);
+            return Err(AppError::OpenAIError(OpenAIError::InvalidArgument(
+                format!("{:?}", run),
+            )));
+        };
+        let tool_calls = &required_action.submit_tool_outputs.tool_calls;
+        debug!("Processing {} tool calls", tool_calls.len());
+        let mut tool_outputs: Vec<ToolsOutputs> = vec![];
+        for tool_call in tool_calls {
+            debug!(
+                "Executing tool call: {} (ID: {}) for Order ID: {}",
+                tool_call.function.name, tool_call.id, order.order_id
+            );
+            // NOTE(dev): One failing call must not hide the effects of the others from
+            //            the model, so its error is reported as its own output
+            let tool_output =
+                match handle_function_call(&tool_call.function, menu, &self.order_limits, order)
+                    .await
+                {
+                    Ok(tool_output) => tool_output,
+                    Err(e) => failed_function_output(&tool_call.function, &e, order)?,
+                };
+            tool_outputs.push(ToolsOutputs {
+                tool_call_id: Some(tool_call.id.clone()),
+                output: Some(tool_output),
+            });
+        }
+        debug!("Submitting {} tool outputs", tool_outputs.len());
+        Ok(tool_outputs)
+    }
+
+    /// Cancels a run that took longer than the poll timeout.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The conversation thread ID
+    /// * `run_id` - The run that timed out
+    /// * `order` - The current order state
+    ///
+    /// # Returns
+    /// * `AppError` - The error to report for the turn
+    async fn run_timed_out(&self, thread_id: &str, run_id: &str, order: &Order) -> AppError {
+        error!(
+            "Run timed out after {:?}. Thread ID: {}, Run ID: {}, Order ID: {}",
+            self.poll_timeout, thread_id, run_id, order.order_id
+        );
+        counter!(telemetry::OPENAI_ERRORS, "operation" => "run timeout").increment(1);
+        if let Err(e) = self.cancel_run(thread_id, run_id).await {
+            error!("Failed to cancel timed out run {}: {:?}", run_id, e);
+        }
+        AppError::RunTimedOut(format!(
+            "Run {} timed out after {} seconds",
+            run_id,
+            self.poll_timeout.as_secs()
+        ))
+    }
+
     /// Processes a chat message through the AI assistant.
     ///
     /// # Arguments
     /// * `message` - The user's message
     /// * `location` - The restaurant location
+    /// * `language` - Language to reply in, if the customer asked for one
     /// * `order` - The current order state
     /// * `menu` - The restaurant menu
     ///
     /// # Returns
-    /// * `AppResult<&mut Order>` - The updated order after processing
-    pub async fn handle_message<'a>(
+    /// * `AppResult<Option<RunCompletionUsage>>` - Token usage reported for the run, if any
+    pub async fn handle_message(
         &self,
         message: &str,
-        location: &String,
-        order: &'a mut Order,
+        location: &str,
+        language: Option<&str>,
+        order: &mut Order,
         menu: &Menu,
-    ) -> AppResult<&'a mut Order> {
+    ) -> AppResult<Option<RunCompletionUsage>> {
         info!(
             "Processing message for Order ID: {} at location: {}",
             order.order_id, location
         );
 
+        if self.stateless_threads {
+            return self
+                .handle_stateless_message(message, location, language, order, menu)
+                .await;
+        }
+
         let thread_id = match &order.thread_id {
             Some(thread_id) => {
                 debug!(
@@ -399,12 +1753,8 @@ impl OrderAssistant {
                     "Creating new thread for Order ID: {} at location: {}",
                     order.order_id, location
                 );
-                let chat_message = ChatMessage {
-                    role: ChatRole::Assistant.to_string(),
-                    content: format!("Welcome to {}, what can I get started for you", location),
-                };
-                order.messages.push(chat_message);
-                let thread_id = self.create_thread(location).await?;
+                order.messages.push(welcome_message(location));
+                let thread_id = self.create_thread(location, vec![]).await?;
                 debug!(
                     "Created new thread. Thread ID: {}, Order ID: {}",
                     thread_id, order.order_id
@@ -419,74 +1769,1063 @@ impl OrderAssistant {
             role: ChatRole::User.to_string(),
             content: message.to_owned(),
         });
+        self.run_message(&thread_id, message, language, None, order, menu)
+            .await
+    }
 
+    /// Processes a chat message on a throwaway thread, so no conversation is kept on OpenAI's
+    /// servers between turns.
+    ///
+    /// The thread is seeded with the local chat history and the run is told the current items,
+    /// then the thread is deleted whether or not the run succeeded.
+    ///
+    /// # Arguments
+    /// * `message` - The user's message
+    /// * `location` - The restaurant location
+    /// * `language` - Language to reply in, if the customer asked for one
+    /// * `order` - The current order state
+    /// * `menu` - The restaurant menu
+    ///
+    /// # Returns
+    /// * `AppResult<Option<RunCompletionUsage>>` - Token usage reported for the run, if any
+    async fn handle_stateless_message(
+        &self,
+        message: &str,
+        location: &str,
+        language: Option<&str>,
+        order: &mut Order,
+        menu: &Menu,
+    ) -> AppResult<Option<RunCompletionUsage>> {
+        if order.messages.is_empty() {
+            order.messages.push(welcome_message(location));
+        }
+        // NOTE(dev): The local history is already bounded by MAX_THREAD_MESSAGES
+        let history = order
+            .messages
+            .iter()
+            .filter(|message| !message.content.is_empty())
+            .map(|message| CreateMessageRequest {
+                role: if message.role == ChatRole::User.to_string() {
+                    MessageRole::User
+                } else {
+                    MessageRole::Assistant
+                },
+                content: message.content.clone().into(),
+                ..Default::default()
+            })
+            .collect();
+        let thread_id = self.create_thread(location, history).await?;
+        info!(
+            "Using stateless thread {} for Order ID: {}",
+            thread_id, order.order_id
+        );
+
+        order.messages.push(ChatMessage {
+            role: ChatRole::User.to_string(),
+            content: message.to_owned(),
+        });
+        let context = format!(
+            "The customer's current order, with the ids to use in function calls: {}",
+            serde_json::to_string(&order.order)?
+        );
+        let result = self
+            .run_message(&thread_id, message, language, Some(context), order, menu)
+            .await;
+        if let Err(e) = self.delete_thread(&thread_id).await {
+            error!("Failed to delete stateless thread {}: {:?}", thread_id, e);
+        }
+        result
+    }
+
+    /// Adds a message to a thread, runs the assistant on it and copies the reply into the
+    /// order's local history.
+    ///
+    /// # Arguments
+    /// * `thread_id` - The conversation thread ID
+    /// * `message` - The user's message
+    /// * `language` - Language to reply in, if the customer asked for one
+    /// * `context` - Extra instructions for this run only
+    /// * `order` - The current order state
+    /// * `menu` - The restaurant menu
+    ///
+    /// # Returns
+    /// * `AppResult<Option<RunCompletionUsage>>` - Token usage reported for the run, if any, or
+    ///   `AssistantUnavailable` if the assistant isn't initialized
+    async fn run_message(
+        &self,
+        thread_id: &str,
+        message: &str,
+        language: Option<&str>,
+        context: Option<String>,
+        order: &mut Order,
+        menu: &Menu,
+    ) -> AppResult<Option<RunCompletionUsage>> {
+        let assistant_id = self.assistant.clone().ok_or_else(|| {
+            AppError::AssistantUnavailable("Assistant is not initialized".to_string())
+        })?;
         debug!(
             "Creating message in OpenAI thread. Thread ID: {}, Order ID: {}",
             thread_id, order.order_id
         );
         let _response = self
-            .client
-            .threads()
-            .messages(&thread_id)
-            .create(CreateMessageRequest {
-                role: MessageRole::User,
-                content: message.to_owned().into(),
-                ..Default::default()
-            })
-            .await?;
-
-        info!("Creating new run for thread {}", thread_id);
-        let response = self
-            .client
-            .threads()
-            .runs(&thread_id)
-            .create(CreateRunRequest {
-                assistant_id: self.assistant.as_ref().unwrap().to_string(),
-                stream: Some(false),
-                ..Default::default()
+            .with_retry("create message", || async {
+                self.client
+                    .threads()
+                    .messages(thread_id)
+                    .create(CreateMessageRequest {
+                        role: MessageRole::User,
+                        content: message.to_owned().into(),
+                        ..Default::default()
+                    })
+                    .await
             })
             .await?;
-        debug!("Created run: {}", response.id);
 
-        let _run_result = self
-            .poll_thread(&thread_id, &response.id, order, menu)
-            .await?;
+        // NOTE(dev): Function arguments are validated against the menu, so only the replies are localized
+        let language_instructions = language.map(|language| {
+            format!(
+                "Reply to the customer in the language with code {}. Keep item names, option keys and option values exactly as written in the menu when calling functions.",
+                language
+            )
+        });
+        let additional_instructions = match (context, language_instructions) {
+            (Some(context), Some(language)) => Some(format!("{}\n\n{}", context, language)),
+            (context, language) => context.or(language),
+        };
+        // NOTE(dev): The thread itself keeps every message, the run only reads the most recent ones.
+        //            The items are always available to the model through list_items.
+        let truncation_strategy = match self.order_limits.max_thread_messages {
+            0 => None,
+            max => Some(TruncationObject {
+                r#type: TruncationObjectType::LastMessages,
+                last_messages: Some(max as u32),
+            }),
+        };
+        let tool_choice = self.tool_choice.for_message(message);
+        info!(
+            "Creating new run for thread {} (language: {:?}, tool choice: {:?})",
+            thread_id, language, tool_choice
+        );
+        let request = CreateRunRequest {
+            assistant_id,
+            additional_instructions,
+            truncation_strategy,
+            tool_choice: Some(tool_choice),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let streamed = if self.stream_runs {
+            self.stream_run(thread_id, &request, order, menu).await?
+        } else {
+            None
+        };
+        let (run_result, mode) = match streamed {
+            Some(run_result) => (run_result, "stream"),
+            None => (
+                self.create_and_poll_run(thread_id, &request, order, menu)
+                    .await?,
+                "poll",
+            ),
+        };
+        histogram!(telemetry::RUN_DURATION, "mode" => mode).record(started.elapsed().as_secs_f64());
 
         debug!("Retrieving latest message from thread");
         let messages = self
-            .client
-            .threads()
-            .messages(&thread_id)
-            .list(&[("limit", "1")])
+            .with_retry("list messages", || async {
+                self.client
+                    .threads()
+                    .messages(thread_id)
+                    .list(&[("limit", "1")])
+                    .await
+            })
             .await?;
 
-        if let Some(message) = messages.data.first() {
+        // NOTE(dev): The run already added the assistant's reply to the thread, so it is only
+        //            copied into the local history
+        let mut replied = false;
+        if let Some(message) = messages
+            .data
+            .first()
+            .filter(|message| message.role == MessageRole::Assistant)
+        {
             if let Some(MessageContent::Text(content)) = message.content.first() {
                 debug!("Processing assistant response: {}", content.text.value);
-                let _response = self
-                    .client
-                    .threads()
-                    .messages(&thread_id)
-                    .create(CreateMessageRequest {
-                        role: MessageRole::Assistant,
-                        content: content.text.value.clone().into(),
-                        ..Default::default()
-                    })
-                    .await?;
-
                 let chat_message = ChatMessage {
                     role: ChatRole::Assistant.to_string(),
                     content: content.text.value.clone(),
                 };
                 order.messages.push(chat_message);
+                replied = true;
                 debug!("Added assistant response to order history");
             }
         }
+        if order.incomplete_reply && !replied {
+            info!(
+                "Incomplete run left no reply for Order ID: {}",
+                order.order_id
+            );
+            order.messages.push(ChatMessage {
+                role: ChatRole::Assistant.to_string(),
+                content: INCOMPLETE_REPLY.to_string(),
+            });
+        }
 
         info!(
             "Message processing completed. Thread ID: {}, Order ID: {}",
             thread_id, order.order_id
         );
-        Ok(order)
+        Ok(run_result.usage)
+    }
+}
+
+#[async_trait]
+impl AssistantBackend for OrderAssistant {
+    fn is_initialized(&self) -> bool {
+        OrderAssistant::is_initialized(self)
+    }
+
+    async fn thread_messages(&self, thread_id: &str) -> AppResult<Vec<ChatMessage>> {
+        OrderAssistant::thread_messages(self, thread_id).await
+    }
+
+    async fn handle_message(
+        &self,
+        message: &str,
+        location: &str,
+        language: Option<&str>,
+        order: &mut Order,
+        menu: &Menu,
+    ) -> AppResult<Option<RunCompletionUsage>> {
+        OrderAssistant::handle_message(self, message, location, language, order, menu).await
+    }
+
+    async fn reload(
+        &self,
+        menu: &Menu,
+        store: &OrderStore,
+    ) -> AppResult<Box<dyn AssistantBackend>> {
+        let mut assistant = self.clone();
+        assistant.load_or_create(menu, store).await?;
+        Ok(Box::new(assistant))
+    }
+
+    async fn delete_thread(&self, thread_id: &str) -> AppResult<()> {
+        OrderAssistant::delete_thread(self, thread_id).await
+    }
+
+    async fn cancel_active_run(&self, thread_id: &str) -> AppResult<Option<String>> {
+        OrderAssistant::cancel_active_run(self, thread_id).await
+    }
+
+    fn info(&self) -> Option<AssistantInfo> {
+        Some(AssistantInfo {
+            assistant_id: self.assistant.clone()?,
+            model: self.model.clone(),
+            menu_hash: self.menu_hash.clone(),
+        })
+    }
+}
+
+/// Schema of a value of an option, limited to the given choices.
+///
+/// # Arguments
+/// * `choices` - The choices the value may be
+///
+/// # Returns
+/// * `serde_json::Value` - The JSON schema of an `OptionValue`
+fn value_schema(choices: &[String]) -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "value": { "type": "string", "enum": choices, "description": "The chosen value." },
+            "count": { "type": "integer", "description": "How many of the value, 1 unless the customer asks for more (e.g. 2 for double cheese)." }
+        },
+        "required": ["value", "count"],
+        "additionalProperties": false
+    })
+}
+
+/// Schema of an item as one variant per option group, each limiting the item name, option keys
+/// and values to those of the group.
+///
+/// The values refer to `#/$defs/values{index}` of the tool, holding the group's choices.
+///
+/// # Arguments
+/// * `groups` - The option groups of the menu
+/// * `name_description` - Description of the item name
+/// * `values_description` - Description of the option values
+/// * `properties` - Further properties of every variant, all of them required
+///
+/// # Returns
+/// * `serde_json::Value` - The JSON schema of the item
+fn item_variants(
+    groups: &[OptionGroup],
+    name_description: &str,
+    values_description: &str,
+    properties: serde_json::Value,
+) -> serde_json::Value {
+    let variants: Vec<serde_json::Value> = groups
+        .iter()
+        .enumerate()
+        .map(|(index, group)| {
+            // NOTE(dev): Strict mode rejects empty enums, items without options get empty lists
+            let option_keys = if group.option_keys.is_empty() {
+                serde_json::json!({ "type": "array", "items": { "type": "string" }, "maxItems": 0, "description": "The options for the item, none for this item." })
+            } else {
+                serde_json::json!({ "type": "array", "items": { "type": "string", "enum": group.option_keys }, "description": "The options for the item." })
+            };
+            let values = if group.choice_names.is_empty() {
+                serde_json::json!({ "type": "array", "items": { "type": "string" }, "maxItems": 0 })
+            } else {
+                serde_json::json!({ "type": "array", "items": { "$ref": format!("#/$defs/values{}", index) } })
+            };
+            let mut variant = serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "itemName": { "type": "string", "enum": group.item_names, "description": name_description },
+                    "optionKeys": option_keys,
+                    "optionValues": { "type": "array", "items": values, "description": values_description }
+                },
+                "required": ["itemName", "optionKeys", "optionValues"],
+                "additionalProperties": false
+            });
+            if let Some(properties) = properties.as_object() {
+                for (name, schema) in properties {
+                    variant["properties"][name] = schema.clone();
+                    if let Some(required) = variant["required"].as_array_mut() {
+                        required.push(name.clone().into());
+                    }
+                }
+            }
+            variant
+        })
+        .collect();
+    serde_json::json!({ "anyOf": variants })
+}
+
+/// Whether an OpenAI error reports that the requested model does not exist or was retired.
+///
+/// # Arguments
+/// * `err` - The error returned by the OpenAI client
+///
+/// # Returns
+/// * `bool` - True for `model_not_found` errors
+fn is_model_not_found(err: &OpenAIError) -> bool {
+    match err {
+        OpenAIError::ApiError(e) => {
+            e.code.as_deref() == Some("model_not_found")
+                || (e.message.contains("model") && e.message.contains("does not exist"))
+        }
+        _ => false,
+    }
+}
+
+/// Whether an OpenAI error is transient and the call is worth retrying.
+///
+/// # Arguments
+/// * `err` - The error returned by the OpenAI client
+///
+/// # Returns
+/// * `bool` - True for rate limits, server errors, and connection failures
+fn is_retryable(err: &OpenAIError) -> bool {
+    match err {
+        OpenAIError::Reqwest(e) => {
+            e.is_timeout()
+                || e.is_connect()
+                || e.status()
+                    .is_some_and(|status| status.is_server_error() || status.as_u16() == 429)
+        }
+        OpenAIError::ApiError(e) => {
+            let kind = e.r#type.as_deref().or(e.code.as_deref());
+            matches!(kind, Some("server_error") | Some("rate_limit_exceeded"))
+        }
+        _ => false,
+    }
+}
+
+/// Persona used when no `SYSTEM_PROMPT_FILE` is configured
+const DEFAULT_SYSTEM_PROMPT: &str = "You are an order management assistant.
+- Talk as if you were taking orders in a drive thru.";
+
+/// Loads the persona placed before the function rules and menu in the assistant instructions.
+///
+/// # Arguments
+/// * `path` - Path of the prompt file, from `SYSTEM_PROMPT_FILE`
+///
+/// # Returns
+/// * `AppResult<String>` - The contents of the file, or the default persona if there is no file
+///   or it is empty
+fn system_prompt(path: Option<&str>) -> AppResult<String> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(DEFAULT_SYSTEM_PROMPT.to_string()),
+    };
+    debug!("Reading system prompt from: {}", path);
+    let prompt = std::fs::read_to_string(path)?;
+    if prompt.trim().is_empty() {
+        warn!("System prompt file {} is empty, using the default", path);
+        return Ok(DEFAULT_SYSTEM_PROMPT.to_string());
+    }
+    Ok(prompt.trim().to_string())
+}
+
+/// Computes the delay before a retry using capped exponential backoff with jitter.
+///
+/// # Arguments
+/// * `attempt` - Number of retries already made
+///
+/// # Returns
+/// * `Duration` - How long to wait before the next attempt
+fn retry_delay(attempt: u32) -> Duration {
+    let base_ms = 500u64
+        .saturating_mul(2u64.saturating_pow(attempt))
+        .min(8_000);
+    let jitter_ms = rand::thread_rng().gen_range(0..=base_ms / 2);
+    Duration::from_millis(base_ms + jitter_ms)
+}
+
+/// Turns a run that is no longer working into the result of the turn.
+///
+/// # Arguments
+/// * `run` - The run, completed, incomplete or ended in an error state
+/// * `thread_id` - The conversation thread ID
+/// * `order` - The current order state
+///
+/// # Returns
+/// * `AppResult<RunObject>` - The run if it completed or ended incomplete, `RunFailed` if it
+///   failed or was cancelled, `RunTimedOut` if it expired
+fn finish_run(run: RunObject, thread_id: &str, order: &mut Order) -> AppResult<RunObject> {
+    match run.status {
+        RunStatus::Completed => {
+            info!(
+                "Run completed successfully. Thread ID: {}, Run ID: {}, Order ID: {}",
+                thread_id, run.id, order.order_id
+            );
+            Ok(run)
+        }
+        RunStatus::Incomplete => {
+            // NOTE(dev): The tool calls made before the limit already changed the order,
+            //            so the turn is kept with whatever reply was written
+            warn!(
+                "Run ended incomplete: {:?}. Thread ID: {}, Run ID: {}, Order ID: {}",
+                run.incomplete_details, thread_id, run.id, order.order_id
+            );
+            counter!(telemetry::OPENAI_ERRORS, "operation" => "run incomplete").increment(1);
+            order.incomplete_reply = true;
+            Ok(run)
+        }
+        RunStatus::Failed | RunStatus::Cancelled | RunStatus::Expired => {
+            error!(
+                "Run ended in state: {:?}. Thread ID: {}, Run ID: {}, Order ID: {}, Error: {:?}",
+                run.status, thread_id, run.id, order.order_id, run.last_error
+            );
+            counter!(telemetry::OPENAI_ERRORS, "operation" => "run").increment(1);
+            let reason = match &run.last_error {
+                Some(last_error) => last_error.message.clone(),
+                None => "no error details provided".to_string(),
+            };
+            let message = format!("Run {} ended as {:?}: {}", run.id, run.status, reason);
+            match run.status {
+                RunStatus::Expired => Err(AppError::RunTimedOut(message)),
+                _ => Err(AppError::RunFailed(message)),
+            }
+        }
+        // NOTE(dev): Callers only hand over runs that stopped working, so these mean OpenAI
+        //            reported a state the run can't end in
+        RunStatus::Queued
+        | RunStatus::InProgress
+        | RunStatus::RequiresAction
+        | RunStatus::Cancelling => {
+            error!(
+                "Run is still {:?} where it should have ended. Thread ID: {}, Run ID: {}, Order ID: {}",
+                run.status, thread_id, run.id, order.order_id
+            );
+            counter!(telemetry::OPENAI_ERRORS, "operation" => "run").increment(1);
+            Err(AppError::RunFailed(format!(
+                "Run {} did not end, it is still {:?}",
+                run.id, run.status
+            )))
+        }
+    }
+}
+
+/// Computes the delay before polling a run again, growing exponentially from the initial
+/// interval up to the cap with jitter so that short runs return quickly and long runs don't
+/// hammer the API.
+///
+/// # Arguments
+/// * `initial` - Delay before the first poll
+/// * `max` - Upper bound on the delay before jitter
+/// * `polls` - Number of polls already made while waiting
+///
+/// # Returns
+/// * `Duration` - How long to wait before the next poll
+fn poll_delay(initial: Duration, max: Duration, polls: u32) -> Duration {
+    let base_ms = (initial.as_millis() as u64)
+        .saturating_mul(2u64.saturating_pow(polls))
+        .min(max.as_millis() as u64);
+    let jitter_ms = rand::thread_rng().gen_range(0..=base_ms / 4);
+    Duration::from_millis(base_ms + jitter_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{config, menu, openai, order, run};
+    use async_openai::error::ApiError;
+    use async_openai::types::{LastError, LastErrorCode};
+    use axum::extract::Path;
+    use axum::http::StatusCode;
+    use axum::routing::{delete, get, post};
+    use axum::{Json, Router};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn runs_without_an_assistant_are_refused() {
+        let config = config(&[("API_KEYS", "key1"), ("OPENAI_MOCK", "true")]).unwrap();
+        let assistant = OrderAssistant::new(Client::new(), &config);
+        let mut order = order();
+
+        let result = assistant
+            .run_message("thread-1", "add a coke", None, None, &mut order, &menu())
+            .await;
+
+        assert!(matches!(result, Err(AppError::AssistantUnavailable(_))));
+        assert!(order.messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stuck_runs_time_out_and_are_cancelled() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancel = cancelled.clone();
+        let client = openai(
+            Router::new()
+                .route(
+                    "/threads/:thread_id/runs/:run_id",
+                    get(|| async { Json(run("in_progress")) }),
+                )
+                .route(
+                    "/threads/:thread_id/runs/:run_id/cancel",
+                    post(move || async move {
+                        cancel.store(true, Ordering::SeqCst);
+                        Json(run("cancelling"))
+                    }),
+                ),
+        );
+        let config = config(&[
+            ("API_KEYS", "key1"),
+            ("OPENAI_API_KEY", "test"),
+            ("OPENAI_POLL_TIMEOUT_SECONDS", "1"),
+            ("OPENAI_POLL_INTERVAL_MS", "50"),
+            ("OPENAI_MAX_POLL_INTERVAL_MS", "100"),
+        ])
+        .unwrap();
+        let assistant = OrderAssistant::new(client, &config);
+        let mut order = order();
+
+        let started = Instant::now();
+        let result = assistant
+            .poll_thread("thread-1", "run-1", &mut order, &menu())
+            .await;
+
+        assert!(
+            matches!(&result, Err(AppError::RunTimedOut(message)) if message.contains("run-1")),
+            "{:?}",
+            result
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(cancelled.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn failed_cancelled_and_expired_runs_are_errors() {
+        let mut order = order();
+        let ended = |status: &str| serde_json::from_value::<RunObject>(run(status)).unwrap();
+
+        let mut failed = ended("failed");
+        failed.last_error = Some(LastError {
+            code: LastErrorCode::ServerError,
+            message: "model crashed".to_string(),
+        });
+        assert!(matches!(
+            finish_run(failed, "thread-1", &mut order),
+            Err(AppError::RunFailed(message)) if message.contains("model crashed")
+        ));
+        assert!(matches!(
+            finish_run(ended("cancelled"), "thread-1", &mut order),
+            Err(AppError::RunFailed(_))
+        ));
+        assert!(matches!(
+            finish_run(ended("expired"), "thread-1", &mut order),
+            Err(AppError::RunTimedOut(_))
+        ));
+        assert!(finish_run(ended("completed"), "thread-1", &mut order).is_ok());
+    }
+
+    #[test]
+    fn incomplete_runs_are_kept_and_flag_the_reply() {
+        let mut order = order();
+        let mut incomplete = run("incomplete");
+        incomplete["incomplete_details"] = json!({ "reason": "max_completion_tokens" });
+        let incomplete = serde_json::from_value::<RunObject>(incomplete).unwrap();
+
+        let result = finish_run(incomplete, "thread-1", &mut order);
+
+        assert_eq!(result.unwrap().status, RunStatus::Incomplete);
+        assert!(order.incomplete_reply);
+
+        order.incomplete_reply = false;
+        let completed = serde_json::from_value::<RunObject>(run("completed")).unwrap();
+        finish_run(completed, "thread-1", &mut order).unwrap();
+        assert!(!order.incomplete_reply);
+    }
+
+    /// An error OpenAI answers with, of the given type.
+    fn api_error(kind: &str) -> OpenAIError {
+        OpenAIError::ApiError(ApiError {
+            message: kind.to_string(),
+            r#type: Some(kind.to_string()),
+            param: None,
+            code: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried_until_the_call_succeeds() {
+        let config = config(&[("API_KEYS", "key1"), ("OPENAI_MOCK", "true")]).unwrap();
+        let assistant = OrderAssistant::new(Client::new(), &config);
+        let attempts = AtomicU32::new(0);
+
+        let result = assistant
+            .with_retry("test", || async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(api_error("rate_limit_exceeded")),
+                    1 => Err(api_error("server_error")),
+                    _ => Ok("run-1"),
+                }
+            })
+            .await;
+
+        assert_eq!(result.unwrap(), "run-1");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_fail_without_retrying() {
+        let config = config(&[("API_KEYS", "key1"), ("OPENAI_MOCK", "true")]).unwrap();
+        let assistant = OrderAssistant::new(Client::new(), &config);
+        let attempts = AtomicU32::new(0);
+
+        let result: Result<(), _> = assistant
+            .with_retry("test", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(api_error("invalid_request_error"))
+            })
+            .await;
+
+        assert!(matches!(result, Err(OpenAIError::ApiError(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_the_configured_maximum() {
+        let config = config(&[
+            ("API_KEYS", "key1"),
+            ("OPENAI_MOCK", "true"),
+            ("OPENAI_MAX_RETRIES", "1"),
+        ])
+        .unwrap();
+        let assistant = OrderAssistant::new(Client::new(), &config);
+        let attempts = AtomicU32::new(0);
+
+        let result: Result<(), _> = assistant
+            .with_retry("test", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(api_error("server_error"))
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn runs_fall_back_to_the_next_model_when_one_is_not_found() {
+        let requested = Arc::new(Mutex::new(vec![]));
+        let models = requested.clone();
+        let client = openai(
+            Router::new()
+                .route(
+                    "/threads/:thread_id/runs",
+                    post(move |Json(body): Json<serde_json::Value>| async move {
+                        models.lock().unwrap().push(body["model"].clone());
+                        if body["model"] == "gpt-4o-mini" {
+                            return (StatusCode::OK, Json(run("queued")));
+                        }
+                        let error = json!({ "error": {
+                            "message": "The model `gpt-4o` does not exist",
+                            "type": "invalid_request_error",
+                            "param": null,
+                            "code": "model_not_found"
+                        }});
+                        (StatusCode::NOT_FOUND, Json(error))
+                    }),
+                )
+                .route(
+                    "/threads/:thread_id/runs/:run_id",
+                    get(|| async { Json(run("completed")) }),
+                ),
+        );
+        let config = config(&[
+            ("API_KEYS", "key1"),
+            ("OPENAI_API_KEY", "test"),
+            ("OPENAI_MODELS", "gpt-4o,gpt-4o-mini"),
+            ("OPENAI_POLL_INTERVAL_MS", "10"),
+        ])
+        .unwrap();
+        let assistant = OrderAssistant::new(client, &config);
+        let request = CreateRunRequest {
+            assistant_id: "assistant-1".to_string(),
+            ..Default::default()
+        };
+
+        let run = assistant
+            .create_and_poll_run("thread-1", &request, &mut order(), &menu())
+            .await
+            .unwrap();
+
+        assert_eq!(run.status, RunStatus::Completed);
+        assert_eq!(
+            *requested.lock().unwrap(),
+            [serde_json::Value::Null, json!("gpt-4o-mini")]
+        );
+    }
+
+    #[tokio::test]
+    async fn stateless_threads_never_stay_on_the_order() {
+        let created = Arc::new(AtomicU32::new(0));
+        let deleted = Arc::new(Mutex::new(vec![]));
+        let (create, deletions) = (created.clone(), deleted.clone());
+        let client = openai(
+            Router::new()
+                .route(
+                    "/threads",
+                    post(move || async move {
+                        let id = format!("thread-{}", create.fetch_add(1, Ordering::SeqCst) + 1);
+                        Json(json!({ "id": id, "object": "thread", "created_at": 0 }))
+                    }),
+                )
+                .route(
+                    "/threads/:thread_id",
+                    delete(move |Path(thread_id): Path<String>| async move {
+                        deletions.lock().unwrap().push(thread_id.clone());
+                        Json(
+                            json!({ "id": thread_id, "object": "thread.deleted", "deleted": true }),
+                        )
+                    }),
+                )
+                .route(
+                    "/threads/:thread_id/messages",
+                    post(|| async {
+                        Json(json!({
+                            "id": "msg-1",
+                            "object": "thread.message",
+                            "created_at": 0,
+                            "thread_id": "thread-1",
+                            "role": "user",
+                            "content": [],
+                        }))
+                    })
+                    .get(|| async {
+                        Json(json!({
+                            "object": "list",
+                            "data": [{
+                                "id": "msg-2",
+                                "object": "thread.message",
+                                "created_at": 0,
+                                "thread_id": "thread-1",
+                                "role": "assistant",
+                                "content": [{
+                                    "type": "text",
+                                    "text": { "value": "Anything else?", "annotations": [] },
+                                }],
+                            }],
+                            "has_more": false,
+                        }))
+                    }),
+                )
+                .route(
+                    "/threads/:thread_id/runs",
+                    post(|| async { Json(run("queued")) }),
+                )
+                .route(
+                    "/threads/:thread_id/runs/:run_id",
+                    get(|| async { Json(run("completed")) }),
+                ),
+        );
+        let config = config(&[
+            ("API_KEYS", "key1"),
+            ("OPENAI_API_KEY", "test"),
+            ("STATELESS_THREADS", "true"),
+            ("OPENAI_POLL_INTERVAL_MS", "10"),
+        ])
+        .unwrap();
+        let mut assistant = OrderAssistant::new(client, &config);
+        assistant.assistant = Some("assistant-1".to_string());
+        let mut order = order();
+
+        for input in ["hi", "a coke please"] {
+            assistant
+                .handle_message(input, "store-a", None, &mut order, &menu())
+                .await
+                .unwrap();
+            assert!(order.thread_id.is_none());
+        }
+
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+        assert_eq!(*deleted.lock().unwrap(), ["thread-1", "thread-2"]);
+        let replies = order
+            .messages
+            .iter()
+            .filter(|message| message.content == "Anything else?")
+            .count();
+        assert_eq!(replies, 2);
+    }
+
+    #[tokio::test]
+    async fn the_requested_language_is_added_to_the_run_instructions() {
+        let instructions = Arc::new(Mutex::new(vec![]));
+        let runs = instructions.clone();
+        let client = openai(
+            Router::new()
+                .route(
+                    "/threads/:thread_id/messages",
+                    post(|| async {
+                        Json(json!({
+                            "id": "msg-1",
+                            "object": "thread.message",
+                            "created_at": 0,
+                            "thread_id": "thread-1",
+                            "role": "user",
+                            "content": [],
+                        }))
+                    })
+                    .get(|| async {
+                        Json(json!({ "object": "list", "data": [], "has_more": false }))
+                    }),
+                )
+                .route(
+                    "/threads/:thread_id/runs",
+                    post(move |Json(body): Json<serde_json::Value>| async move {
+                        runs.lock()
+                            .unwrap()
+                            .push(body["additional_instructions"].clone());
+                        Json(run("queued"))
+                    }),
+                )
+                .route(
+                    "/threads/:thread_id/runs/:run_id",
+                    get(|| async { Json(run("completed")) }),
+                ),
+        );
+        let config = config(&[
+            ("API_KEYS", "key1"),
+            ("OPENAI_API_KEY", "test"),
+            ("OPENAI_POLL_INTERVAL_MS", "10"),
+        ])
+        .unwrap();
+        let mut assistant = OrderAssistant::new(client, &config);
+        assistant.assistant = Some("assistant-1".to_string());
+        let mut order = order();
+        order.thread_id = Some("thread-1".to_string());
+
+        for language in [Some("es"), None] {
+            assistant
+                .handle_message(
+                    "una coca por favor",
+                    "store-a",
+                    language,
+                    &mut order,
+                    &menu(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let instructions = instructions.lock().unwrap();
+        let spanish = instructions[0].as_str().unwrap();
+        assert!(spanish.contains("language with code es"), "{}", spanish);
+        assert!(instructions[1].is_null());
+        assert_eq!(order.messages[0].content, "una coca por favor");
+    }
+
+    #[tokio::test]
+    async fn runs_are_polled_until_they_complete_with_a_growing_delay() {
+        const TICKS: u32 = 4;
+        let retrieved = Arc::new(AtomicU32::new(0));
+        let retrieve = retrieved.clone();
+        let client = openai(Router::new().route(
+            "/threads/:thread_id/runs/:run_id",
+            get(move || async move {
+                match retrieve.fetch_add(1, Ordering::SeqCst) + 1 {
+                    TICKS => Json(run("completed")),
+                    _ => Json(run("in_progress")),
+                }
+            }),
+        ));
+        let config = config(&[
+            ("API_KEYS", "key1"),
+            ("OPENAI_API_KEY", "test"),
+            ("OPENAI_POLL_INTERVAL_MS", "10"),
+            ("OPENAI_MAX_POLL_INTERVAL_MS", "40"),
+        ])
+        .unwrap();
+        let assistant = OrderAssistant::new(client, &config);
+
+        let run = assistant
+            .poll_thread("thread-1", "run-1", &mut order(), &menu())
+            .await
+            .unwrap();
+
+        assert_eq!(run.status, RunStatus::Completed);
+        assert_eq!(retrieved.load(Ordering::SeqCst), TICKS);
+
+        let (initial, max) = (Duration::from_millis(10), Duration::from_millis(40));
+        for (polls, base) in [(0, 10), (1, 20), (2, 40), (3, 40), (40, 40)] {
+            let delay = poll_delay(initial, max, polls).as_millis() as u64;
+            assert!(
+                (base..=base + base / 4).contains(&delay),
+                "poll {} waited {}ms",
+                polls,
+                delay
+            );
+        }
+    }
+
+    /// Checks the rules strict mode sets for every object in a schema, and counts its enum values.
+    fn check_strict(schema: &serde_json::Value, path: &str, enum_values: &mut usize) {
+        if schema.is_null() {
+            return;
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            assert!(!values.is_empty(), "{} has an empty enum", path);
+            *enum_values += values.len();
+        }
+        if schema["type"] == "object" {
+            let properties = schema["properties"]
+                .as_object()
+                .unwrap_or_else(|| panic!("{} must list its properties", path));
+            let mut required: Vec<&str> = schema["required"]
+                .as_array()
+                .unwrap_or_else(|| panic!("{} must list its required properties", path))
+                .iter()
+                .filter_map(|name| name.as_str())
+                .collect();
+            let mut names: Vec<&str> = properties.keys().map(|name| name.as_str()).collect();
+            required.sort();
+            names.sort();
+            assert_eq!(required, names, "{} must require every property", path);
+            assert_eq!(
+                schema["additionalProperties"], false,
+                "{} must refuse additional properties",
+                path
+            );
+            for (name, property) in properties {
+                check_strict(property, &format!("{}.{}", path, name), enum_values);
+            }
+        }
+        check_strict(&schema["items"], &format!("{}[]", path), enum_values);
+        for (index, variant) in schema["anyOf"].as_array().into_iter().flatten().enumerate() {
+            check_strict(variant, &format!("{}|{}", path, index), enum_values);
+        }
+        for (name, def) in schema["$defs"].as_object().into_iter().flatten() {
+            check_strict(def, &format!("{}#{}", path, name), enum_values);
+        }
+    }
+
+    fn tools() -> Vec<serde_json::Value> {
+        let config = config(&[("API_KEYS", "key1"), ("OPENAI_MOCK", "true")]).unwrap();
+        let assistant = OrderAssistant::new(Client::new(), &config);
+        let request = assistant.assistant_request(&menu(), "gpt-4o").unwrap();
+        serde_json::to_value(&request).unwrap()["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["function"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn tools_meet_the_strict_mode_rules() {
+        for tool in tools() {
+            let name = tool["name"].as_str().unwrap().to_string();
+            assert_eq!(tool["strict"], true, "{} must be strict", name);
+            let parameters = &tool["parameters"];
+            assert_eq!(parameters["type"], "object", "{} must take an object", name);
+            assert!(
+                parameters.get("anyOf").is_none(),
+                "{} can't branch at the root",
+                name
+            );
+            let mut enum_values = 0;
+            check_strict(parameters, &name, &mut enum_values);
+            assert!(
+                enum_values <= 1000,
+                "{} has {} enum values, over the strict mode limit",
+                name,
+                enum_values
+            );
+        }
+    }
+
+    #[test]
+    fn items_can_only_be_sent_with_their_own_options() {
+        let tools = tools();
+        let add_item = tools
+            .iter()
+            .find(|tool| tool["name"] == "add_item")
+            .unwrap();
+        let parameters = &add_item["parameters"];
+        let variant = |item_name: &str| {
+            parameters["properties"]["item"]["anyOf"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|variant| {
+                    variant["properties"]["itemName"]["enum"]
+                        .as_array()
+                        .unwrap()
+                        .contains(&json!(item_name))
+                })
+                .unwrap()
+                .clone()
+        };
+        let choices = |variant: &serde_json::Value| {
+            let reference = variant["properties"]["optionValues"]["items"]["items"]["$ref"]
+                .as_str()
+                .unwrap()
+                .trim_start_matches("#/$defs/")
+                .to_string();
+            parameters["$defs"][reference]["properties"]["value"]["enum"].clone()
+        };
+
+        let coke = variant("Coke");
+        let keys = coke["properties"]["optionKeys"]["items"]["enum"]
+            .as_array()
+            .unwrap();
+        assert!(!keys.contains(&json!("meal option")));
+        assert!(!choices(&coke).as_array().unwrap().contains(&json!("meal")));
+
+        let burger = variant("Cheese Burger");
+        assert!(burger["properties"]["optionKeys"]["items"]["enum"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("meal option")));
+        assert!(choices(&burger)
+            .as_array()
+            .unwrap()
+            .contains(&json!("meal")));
     }
 }
@@ -11,10 +11,16 @@
 //!
 //! * `api` - RESTful API endpoints using Axum framework
 //! * `chat` - Chat message processing and AI interaction handling
+//! * `config` - Startup configuration read and validated from the environment
 //! * `functions` - OpenAI function definitions and assistant management
+//! * `location` - Per-location order constraints
 //! * `menu` - Menu configuration and item validation
+//! * `mock` - Deterministic stand-in for the OpenAI assistant used in test mode
+//! * `money` - Exact money amounts in integer cents
 //! * `order` - Order management and persistence
 //! * `error` - Error handling and HTTP response mapping
+//! * `telemetry` - Prometheus metrics recorder and metric names
+//! * `webhook` - Signed notifications of completed orders
 //!
 //! ## Design
 //!
@@ -22,20 +28,159 @@
 //! - Built with Axum web framework
 //! - RESTful endpoints for order management
 //! - API key authentication middleware
+//! - Keys scoped `:ro` in `API_KEYS` can only make `GET` requests and get `403` otherwise,
+//!   keys scoped `:rw` or without a scope can make any request
+//! - Keys bound to locations with `@` (e.g. `key1@store-a|store-b:rw`) get `403` when starting,
+//!   reading or changing an order at any other location, and on the endpoints spanning every
+//!   location (archive, export, stats and `/admin`). Keys without locations can use every one
+//! - CORS for browser clients, with preflight requests answered before authentication
+//! - Every request is logged in a span tagged with its `X-Request-Id`, which is generated if
+//!   missing and echoed back in the response
+//! - Request bodies larger than `MAX_BODY_BYTES` are rejected with `413` before being read
 //! - Shared application state management
 //!
 //! ### Storage Layer
-//! - Redis for order persistence
+//! - Redis for order persistence, with orders stored under `order:<orderId>` keys
+//! - Completed orders are moved to `archive:order:<orderId>` and kept permanently, indexed by
+//!   completion time in the `archive:orders` sorted set
+//! - Every function call on an order is appended to the `audit:order:<orderId>` list in the same
+//!   transaction that saves the order
+//! - With `ORDER_TTL_SECONDS` set, active orders expire that many seconds after their last
+//!   change. The OpenAI thread of each active order is indexed under `thread:order:<orderId>`,
+//!   and with `THREAD_CLEANUP=true` a background task deletes the thread when its order expires,
+//!   listening to the expiries of the `REDIS_DB` database only.
+//!   This relies on Redis keyspace notifications for expired keys, which are off by default:
+//!   set `notify-keyspace-events` to include `Ex` (e.g. `CONFIG SET notify-keyspace-events Ex`).
+//!   A warning is logged at startup if the setting can be read and is missing
+//! - r2d2 connection pool shared across requests, checking connections on checkout and
+//!   retrying order reads and writes on a new connection if Redis restarts
+//! - `REDIS_DB`, `REDIS_USERNAME` and `REDIS_PASSWORD` override the database index and ACL
+//!   credentials of `REDIS_URL`. Redis is pinged at startup, which fails with Redis's own error
+//!   (e.g. a refused password or database index) instead of a pool timeout
 //! - serde serialization for data storage
 //!
 //! ### AI Integration (`functions.rs`, `chat.rs`)
-//! - Function calling for structured interactions
+//! - Function calling for structured interactions, each call reporting a small JSON result with
+//!   the status of the items it changed instead of the whole order
+//! - `list_items` is read-only, its `limit` only shortens the `listedItems` in its result, which
+//!   are marked `truncated` when items were left out
+//! - `get_menu_info` lets the model look up an item's options and choices, or an overview of
+//!   every item, on the live menu, so answers follow menu reloads
+//! - Group orders tag each item with a `cartLabel` naming the person it is for; responses then
+//!   add a `carts` section per label with its total, items without a label share a `null` cart
+//! - `read_order` returns an itemized summary of the stored order, grouping identical items
+//!   with their quantity, line price and the total, so read backs never rely on the model's
+//!   memory of earlier edits
+//! - Each order line carries a `quantity` of identical items, priced at `price` each, so
+//!   "three cokes" is one line; totals, item limits and read backs count every item of a line
+//! - `split_item` turns a line with a quantity above one into one line per item, each with the
+//!   same options and a new id, so one of them can be changed on its own; `merge_items` folds
+//!   lines holding the exact same item back into the first of them, adding up their quantities
+//! - Items can name other items as `upsells`; adding the item reports the available ones not
+//!   yet in the order as `suggestions` in the call's result and the chat response, and the
+//!   model decides whether to offer them
+//! - `finalize` records the itemized order and total as a structured `summary` on the order for
+//!   payment screens, in the same shape as `read_order`, while the model recites the recap
+//!   itself. The model calls it alongside `confirm_order`, and any later change to the items
+//!   drops the summary until it is finalized again
+//! - `undo_last_item` removes the most recently added item, for "scratch that last one",
+//!   without the model having to look up its id
+//! - `patch_item` sets or unsets single options, or removes single values such as bacon from an
+//!   item's customizations while keeping the rest; removing the last value of a required option
+//!   leaves the item incomplete rather than failing the call
+//! - Calls with malformed arguments are answered with an `invalid arguments` result echoing the
+//!   parse error, so the model can retry within the same run instead of failing the request
+//! - When a run makes several calls at once and one of them fails, that call is answered with a
+//!   `function failed` result while the others are submitted as usual, so their changes are
+//!   still reported to the model
 //! - Asynchronous message processing
 //! - Thread-based conversation management
+//! - `OPENAI_TOOL_CHOICE` sets how eagerly runs call functions. `auto` lets the model decide,
+//!   which suits conversational turns but can miss an explicit "add a coke". `required` forces a
+//!   call on every run, so greetings and questions also cost a call (usually `list_items` or
+//!   `get_menu_info`) before the reply. `action` only forces a call when the message contains a
+//!   phrase asking for a change (e.g. "add", "I'd like", "remove") and is `auto` otherwise
+//! - Long conversations are bounded by `MAX_THREAD_MESSAGES`: runs only read that many recent
+//!   messages of the thread and older messages are dropped from the order's local copy. This
+//!   trades the model forgetting early small talk for bounded cost and context; the items are
+//!   kept in full and the model can always read them back with `list_items`
+//! - With `STATELESS_THREADS=true` no conversation is kept on OpenAI's servers between turns:
+//!   every message gets a new thread seeded with the local chat history, the run is told the
+//!   current items, and the thread is deleted afterwards. Orders never get a `thread_id`, so
+//!   `?source=thread` is unavailable. The model only sees the local history, which loses some
+//!   nuance such as the results of earlier function calls, in exchange for data minimization
+//! - `OPENAI_MODELS` lists models in order of preference (`OPENAI_MODEL` is read if it is not
+//!   set). When OpenAI reports a model as not found, building the assistant moves on to the
+//!   next one, and runs fall back to the later models if the assistant's model disappears
+//!   after startup. The model in use is logged
+//! - With `OPENAI_STREAM_RUNS=true` runs are driven by their streamed events instead of polling:
+//!   function calls are answered as soon as the run asks for them and the reply is read as
+//!   soon as the run completes. A run whose stream cannot be opened is created by polling
+//!   instead, with the model fallback above, and a run whose stream breaks is polled to its end.
+//!   `cargo bench --bench run_modes` compares both modes on a run with two rounds of function
+//!   calls against a local stand-in for OpenAI
+//! - Prices the model gives in `add_item`, `add_items` and `modify_item` are replaced with the
+//!   menu price, and logged and counted in `price_mismatch_total` when they differ, to measure
+//!   how often the model misprices. The model's price is only kept for items the menu can't
+//!   price
+//! - Runs that end `incomplete` on a token limit keep the changes made by their function calls
+//!   and whatever reply was written, with `incomplete` set in the chat response, instead of
+//!   failing the request
+//! - When an order ends `MAX_INVALID_TURNS` chat turns in a row with an invalid item, the
+//!   customer is told a team member will help, `needsHuman` is set in the chat response while
+//!   the item stays invalid, and the order and its invalid items are appended to the
+//!   `dead_letter:orders` Redis list so operators can find menu and model mismatches
+//! - Spend is capped by `MAX_TOKENS_PER_ORDER`: the prompt and completion tokens of every run
+//!   are added to the order, and once the budget is used up `/chat` returns `429` until the
+//!   order is completed. A read back order can still be confirmed
+//! - One assistant shared by all replicas, stored in Redis and updated when the menu, model,
+//!   temperature or top_p change
+//! - The persona in the instructions can be replaced with the contents of `SYSTEM_PROMPT_FILE`,
+//!   the rules for using the functions and the menu are always appended after it
+//! - With `OPENAI_MOCK=1` chats are answered by a mock assistant that never calls OpenAI, for
+//!   end-to-end tests: `add <item>`, `remove <item>`, `list` and `clear` make the matching
+//!   function calls and anything else gets a fixed reply
+//! - The tools use strict mode, and an item's name, option keys and choices are limited to the
+//!   ones the menu gives that item, so e.g. a Coke can't be sent with a meal option. `add_item`
+//!   and `modify_item` nest the item under `item` because strict schemas can't branch at the
+//!   root. The `status="Invalid"` count of `item_validations_total` measures what is left for
+//!   `validate_item` to catch
 //!
 //! ### Menu System (`menu.rs`)
-//! - JSON-based menu configuration
+//! - JSON-based menu configuration, checked for consistency at startup and on reload
+//! - Options take between `minimum` and `maximum` choices, or set `exact` for exactly that
+//!   many; the counts are spelled out in the tool schemas for the model
 //! - Rule Validation for orders
+//! - Item names are matched ignoring case, extra whitespace and small typos, and replaced with
+//!   the menu's spelling; names close to several items are rejected as ambiguous
+//! - Prices are exact integer cents, serialized as decimal strings
+//! - Items and choices can be marked unavailable with `"available": false`
+//! - Choices can list other choices of the item they `excludes` (e.g. two kinds of bun); items
+//!   with both are invalid, and the exclusions are spelled out in the tool schemas
+//! - Option values match choices ignoring case, and through the choice's `aliases` (e.g.
+//!   "no onion" for "no onions"); they are stored under the menu's choice name before validation
+//! - Items can carry allergen and dietary `tags` for the assistant to reason over
+//! - Combo items declare `components` filled by other menu items, validated recursively
+//! - Validation lists every problem with an item's options in one status message, separated
+//!   by semicolons, so the model can fix them all at once; `MAX_VALIDATION_PROBLEMS` caps how
+//!   many are listed
+//! - Option values are choice names, or `{ "value": "cheese", "count": 2 }` for extra of a
+//!   choice, priced as `count` times the choice price
+//! - Options can cap what their chosen values add to the price with `maxUpcharge`; items over
+//!   the cap (e.g. 20 extra cheeses) are invalid
+//! - Choices with `"priceType": "percent"` add their `price` as a percentage (e.g. `10` for
+//!   +10% for premium) of the item's flat priced choices, rounded to the cent. Percentages
+//!   never compound on each other, and flat remains the default
+//! - Required options can name a `default` choice (e.g. a regular size). When an item is missing
+//!   the option, the default is filled in and logged instead of leaving the item incomplete;
+//!   options without one are still asked about
+//! - A missing menu file fails with `MENU_FILE_MISSING`, and an empty menu is refused unless
+//!   `ALLOW_EMPTY_MENU` is set, in which case it loads with a warning
+//!
+//! ### Locations (`location.rs`)
+//! - Optional JSON file mapping location names to order limits, e.g.
+//!   `{ "Downtown": { "minTotal": "15.00", "maxTotal": "250.00" } }`
+//! - Checked when an order is completed, locations without an entry are unconstrained
 //!
 //! # Environment Configuration
 //!
@@ -43,25 +188,88 @@
 //!
 //! ```bash
 //! REDIS_URL=redis://localhost:6379    # Redis connection URL
-//! OPENAI_API_KEY=your-key-here        # OpenAI API key
-//! API_KEYS=key1,key2                  # Comma-separated API keys
+//! REDIS_DB=0                          # Database index, overrides the one in REDIS_URL (optional)
+//! REDIS_USERNAME=default              # ACL username, overrides the one in REDIS_URL (optional)
+//! REDIS_PASSWORD=secret               # Password, overrides the one in REDIS_URL (optional)
+//! REDIS_POOL_SIZE=16                  # Maximum pooled Redis connections
+//! REDIS_CONNECT_TIMEOUT_SECONDS=5     # Seconds to wait for a Redis connection
+//! REDIS_MAX_RETRIES=2                 # Retries on a new connection when one is lost
+//! OPENAI_API_KEY=your-key-here        # OpenAI API key, not needed with OPENAI_MOCK
+//! OPENAI_MOCK=0                       # Set to 1 to answer chats with the mock assistant
+//! OPENAI_API_BASE=https://api.openai.com/v1  # Alternative endpoint, must support Assistants
+//! OPENAI_ORG_ID=org-id                # OpenAI organization (optional)
+//! API_KEYS=key1:rw,key2:ro,key3@store-a  # Comma-separated API keys, optionally scoped and bound to locations
 //! MENU_FILE=static/menu.json          # Path to menu configuration
+//! ALLOW_EMPTY_MENU=0                  # Set to 1 to start with a menu that has no items
+//! LOCATIONS_FILE=static/locations.json  # Per-location order limits (optional)
 //! HOST=127.0.0.1                      # Server host
+//! ALLOWED_ORIGINS=https://example.com  # Comma-separated CORS origins (none by default)
 //! PORT=3000                           # Server port
-//! OPENAI_MODEL=gpt-4                  # OpenAI model to use
+//! METRICS_PORT=9090                   # Serve /metrics on this port instead of PORT (optional)
+//! OPENAI_MODELS=gpt-4o,gpt-4o-mini    # Models to use, later ones when earlier are unavailable
+//! SYSTEM_PROMPT_FILE=static/prompt.txt  # Persona placed before the menu (optional)
+//! OPENAI_POLL_TIMEOUT_SECONDS=60      # Seconds to wait on a run before cancelling it
+//! OPENAI_POLL_INTERVAL_MS=100         # Initial delay between run status polls
+//! OPENAI_MAX_POLL_INTERVAL_MS=2000    # Cap on the backed off delay between polls
+//! OPENAI_STREAM_RUNS=false            # Set to true to stream run events instead of polling
+//! OPENAI_MAX_RETRIES=3                # Retries for rate-limited or failed OpenAI calls
+//! OPENAI_TEMPERATURE=0.2              # Sampling temperature of the assistant, 0 to 2
+//! OPENAI_TOP_P=1                      # Nucleus sampling of the assistant, above 0 up to 1
+//! OPENAI_TOOL_CHOICE=auto             # auto, required, or action to force calls for changes
+//! STATELESS_THREADS=false             # Set to true to delete each turn's thread after the run
+//! MAX_INPUT_CHARS=2000                # Longest chat input accepted by /chat
+//! MAX_BODY_BYTES=65536                # Largest request body, at least 4 * MAX_INPUT_CHARS + 1024
+//! MAX_ITEMS_PER_ORDER=50              # Most items a single order can hold
+//! MAX_THREAD_MESSAGES=100             # Messages kept locally and read by each run, 0 for all
+//! ORDER_TTL_SECONDS=0                 # Seconds an active order is kept after its last change, 0 for ever
+//! THREAD_CLEANUP=false                # Set to true to delete the threads of expired orders
+//! MAX_TOKENS_PER_ORDER=0              # Tokens an order may use across all runs, 0 for no limit
+//! MAX_INVALID_TURNS=3                 # Turns in a row with invalid items before handing over, 0 for never
+//! MAX_VALIDATION_PROBLEMS=0           # Problems listed per item status message, 0 for all
+//! CHAT_DEDUPE_SECONDS=30              # Seconds a repeated chat input returns the last response
+//! DEFAULT_PREP_SECONDS=300            # Preparation time of items without prepSeconds
+//! ETA_MODE=max                        # max or sum of item preparation times for the order ETA
+//! CURRENCY=USD                        # Currency reported alongside prices
+//! STRUCTURED_ERRORS=false             # Return errors with machine-readable codes
+//! COMPLETION_WEBHOOK_URL=https://pos.example.com/orders  # POST completed orders here (optional)
+//! COMPLETION_WEBHOOK_SECRET=secret    # Key of the HMAC-SHA256 X-Signature header
+//! COMPLETION_WEBHOOK_MAX_RETRIES=3    # Retries for failed webhook deliveries, at most 10
 //! RUST_LOG=info                       # Logging level
 //! ```
 //!
+//! The variables (API keys, Redis, host and ports, request and order limits, the menu, locations
+//! and prompt files, the OpenAI settings, CORS origins and the webhook) are validated together by
+//! `Config::from_env` at startup. If any is missing or malformed, the server prints every problem
+//! and exits with a non-zero status instead of starting.
+//!
 //! # Error Handling
 //!
 //! The service uses a custom error type (`AppError`) that handles:
 //! - Redis operations
 //! - JSON serialization
-//! - OpenAI API calls
+//! - OpenAI API calls, with runs that fail or are cancelled returned as `502` and runs that
+//!   expire or outlast `OPENAI_POLL_TIMEOUT_SECONDS` as `503`
 //! - Input validation
 //! - Resource not found
+//! - Orders that are no longer active
+//! - Orders that have used up their token budget
+//! - Requests without a valid API key, or that the API key is not allowed to make
 //! - System errors
 //!
+//! Every response, success or error, is JSON with `Content-Type: application/json; charset=utf-8`,
+//! apart from the NDJSON order export and the Prometheus metrics. Errors carry the matching
+//! status code and, by default, the message as a JSON string, e.g. `"Order with id ... not
+//! found"`. With `STRUCTURED_ERRORS=true` they carry a stable code per error kind instead:
+//!
+//! ```json
+//! {
+//!   "error": {
+//!     "code": "ORDER_NOT_FOUND",
+//!     "message": "Order with id ... not found"
+//!   }
+//! }
+//! ```
+//!
 //! # Docker Support
 //!
 //! Run the service using:
@@ -71,8 +279,30 @@
 //!
 //! # API Endpoints
 //!
+//! ## GET /health
+//! Unauthenticated health check. Returns `200` when Redis responds to `PING` and the OpenAI
+//! assistant is initialized, `503` otherwise.
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "redis": "ok" | "down",
+//!   "openai": "ok" | "down"
+//! }
+//! ```
+//!
+//! ## GET /metrics
+//! Unauthenticated Prometheus metrics: orders started, chat messages processed, function calls
+//! by function, item validations by status, OpenAI errors by operation, model-supplied prices
+//! differing from the menu by more than a cent (`price_mismatch_total`) by function, a
+//! histogram of chat latency and one of OpenAI run durations by `mode` (`stream` or `poll`),
+//! for comparing the two run modes. Served on
+//! `METRICS_PORT` when it is set, otherwise alongside the API on `PORT`.
+//!
 //! ## POST /start
 //! Initializes a new chat session for a given location with an empty order and chat state.
+//! Send an optional `Idempotency-Key` header to make retries safe: requests repeating a key
+//! within 24 hours return the order ID created by the first request.
 //!
 //! ### Request
 //! ```json
@@ -90,13 +320,30 @@
 //!
 //! ## POST /chat
 //! Generate the next response and update the order accordingly based on your input.
+//! Returns `409` if the order is no longer `active` (i.e. `completed` or `cancelled`), `400` if
+//! the input is empty and `413` if it is longer than `MAX_INPUT_CHARS`. With a `language` the
+//! assistant replies in that language while still using the menu's names for items and
+//! options. Returns `400` if the language is not a language tag such as `es` or `pt-BR`, and
+//! `429` once the order has used `MAX_TOKENS_PER_ORDER` tokens.
+//!
+//! Double submits are safe: the same input sent again on the same order within
+//! `CHAT_DEDUPE_SECONDS` returns the stored response instead of running the assistant again,
+//! whether it races the original or is a retry after a lost response. Set it to 0 to process
+//! every message. Send an `Idempotency-Key` header to key the response on it for 24 hours
+//! instead.
+//!
+//! When the customer is done the assistant calls `confirm_order` and reads the order back,
+//! setting `awaitingConfirmation`. If the next input is a plain yes the order is completed and
+//! archived as with `POST /order/:order_id/complete`, without calling the assistant. Any other
+//! input or change to the order needs the order to be read back again.
 //!
 //! ### Request
 //! ```json
 //! {
 //!   "orderId": "string",  // ID of the order to update
 //!   "input": "string",    // Customer's message
-//!   "location": "string"  // Restaurant location
+//!   "location": "string", // Restaurant location
+//!   "language": "string"  // Optional language to reply in, e.g. "es"
 //! }
 //! ```
 //!
@@ -108,9 +355,17 @@
 //!     {
 //!       "itemName": "string",
 //!       "optionKeys": ["string"],
-//!       "optionValues": [["string"]],
+//!       "optionValues": [["string"]],  // Or { "value": "string", "count": 2 } for extras
 //!       "id": "string",
-//!       "price": number
+//!       "price": "string",  // Decimal amount, e.g. "9.99"
+//!       "itemStatus": {     // Omitted if the item has not been validated
+//!         "state": "incomplete" | "complete" | "invalid",
+//!         "message": "string"
+//!       },
+//!       "components": [],   // Items filling the slots of a combo, in the same shape
+//!       "quantity": 1,      // Number of identical items on the line, priced at price each
+//!       "notes": "string",  // Special instructions, omitted if there are none
+//!       "cartLabel": "string"  // Person the item is for, omitted if it has no label
 //!     }
 //!   ],
 //!   "messages": [
@@ -118,12 +373,60 @@
 //!       "role": "user" | "assistant",
 //!       "content": "string"
 //!     }
-//!   ]
+//!   ],
+//!   "carts": [             // Items grouped by cartLabel, omitted if no item has a label
+//!     { "cartLabel": "string", "itemIds": ["string"], "total": "string" }
+//!   ],
+//!   "total": "string",     // Sum of the item prices, e.g. "18.48"
+//!   "currency": "string",  // Value of CURRENCY, e.g. "USD"
+//!   "status": "active" | "completed",  // "completed" once the customer confirmed the order
+//!   "awaitingConfirmation": false,     // Whether the next yes completes the order
+//!   "incomplete": false,  // Whether the reply was cut off by a token limit
+//!   "needsHuman": false,  // Whether the order kept ending with invalid items, see MAX_INVALID_TURNS
+//!   "suggestions": ["string"],  // Upsells of the items just added, omitted if there are none
+//!   "summary": {           // Recorded by the assistant's finalize call, omitted until then
+//!     "lines": [
+//!       {
+//!         "itemName": "string",
+//!         "options": ["meal option: meal"],
+//!         "quantity": 2,
+//!         "unitPrice": "string",
+//!         "linePrice": "string"
+//!       }
+//!     ],
+//!     "itemCount": 2,
+//!     "total": "string"
+//!   }
+//! }
+//! ```
+//!
+//! ## GET /ws/chat
+//! Full duplex chat for kiosks: a WebSocket held open for one order, so the client doesn't
+//! reconnect or re-authenticate each turn. Open it with the `orderId` and `location` query
+//! parameters, and the API key either as `apiKey` in the query or as a first message of
+//! `{"apiKey": "..."}` within 10 seconds. The key must be read-write and allowed at the
+//! location, otherwise the socket is closed with code `1008` (policy violation).
+//!
+//! Each text message is a chat input, handled exactly like `POST /chat` except that repeated
+//! inputs are not deduplicated. Each is answered with a frame in the shape of the
+//! `POST /chat` response, or with an error frame in the shape of the structured errors, after
+//! which the socket stays open. The order is saved after every turn, and a turn in progress
+//! when the client disconnects is still finished and saved.
+//!
+//! ### Message
+//! ```json
+//! {
+//!   "input": "string",    // Customer's message
+//!   "language": "string"  // Optional language to reply in, e.g. "es"
 //! }
 //! ```
 //!
 //! ## GET /order/:order_id
 //! Retrieves the current state of the order and associated chat messages for a given orderId.
+//! Pass `?source=thread` to read the messages from the order's OpenAI thread instead of the
+//! local copy, e.g. to debug drift between the two. Returns `400` if the order has no thread yet.
+//! The local copy only keeps the last `MAX_THREAD_MESSAGES` messages, so the thread is also the
+//! way to get the full history of a long conversation.
 //!
 //! ### Response
 //! ```json
@@ -132,9 +435,17 @@
 //!     {
 //!       "itemName": "string",
 //!       "optionKeys": ["string"],
-//!       "optionValues": [["string"]],
+//!       "optionValues": [["string"]],  // Or { "value": "string", "count": 2 } for extras
 //!       "id": "string",
-//!       "price": number
+//!       "price": "string",  // Decimal amount, e.g. "9.99"
+//!       "itemStatus": {     // Omitted if the item has not been validated
+//!         "state": "incomplete" | "complete" | "invalid",
+//!         "message": "string"
+//!       },
+//!       "components": [],   // Items filling the slots of a combo, in the same shape
+//!       "quantity": 1,      // Number of identical items on the line, priced at price each
+//!       "notes": "string",  // Special instructions, omitted if there are none
+//!       "cartLabel": "string"  // Person the item is for, omitted if it has no label
 //!     }
 //!   ],
 //!   "messages": [
@@ -142,13 +453,306 @@
 //!       "role": "user" | "assistant",
 //!       "content": "string"
 //!     }
+//!   ],
+//!   "carts": [             // Items grouped by cartLabel, omitted if no item has a label
+//!     { "cartLabel": "string", "itemIds": ["string"], "total": "string" }
+//!   ],
+//!   "total": "string",     // Sum of the item prices, e.g. "18.48"
+//!   "currency": "string",  // Value of CURRENCY, e.g. "USD"
+//!   "status": "active" | "completed" | "cancelled",
+//!   "truncatedMessages": 0, // Older messages dropped from the local copy, 0 with ?source=thread
+//!   "createdAt": "2024-12-28T07:40:12Z",  // When the order was started
+//!   "updatedAt": "2024-12-28T07:45:30Z",  // When the order was last saved
+//!   "summary": {}          // Same as in the chat response, omitted until the order is finalized
+//! }
+//! ```
+//!
+//! `createdAt` and `updatedAt` are RFC 3339 timestamps, omitted for orders stored before they
+//! were recorded.
+//!
+//! ## POST /order/:order_id/complete
+//! Completes the order once every item is valid and moves it to the archive. Completed orders
+//! can no longer be changed through `/chat`, but can still be retrieved with
+//! `GET /order/:order_id`. Returns `400` listing the offending items if any item is incomplete or invalid,
+//! or if the total is outside the `minTotal`/`maxTotal` configured for the order's location.
+//! If `COMPLETION_WEBHOOK_URL` is set, the response body is also POSTed there in the background
+//! with an `X-Signature: sha256=<hex>` header holding the HMAC-SHA256 of the body keyed with
+//! `COMPLETION_WEBHOOK_SECRET`. Failed deliveries are retried with a backoff doubling from half a
+//! second up to 30 seconds, and logged without affecting the response.
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "orderId": "string",
+//!   "status": "completed",
+//!   "completedAt": "2024-12-28T07:48:57Z",
+//!   "order": [
+//!     {
+//!       "itemName": "string",
+//!       "optionKeys": ["string"],
+//!       "optionValues": [["string"]],  // Or { "value": "string", "count": 2 } for extras
+//!       "id": "string",
+//!       "price": "string",  // Decimal amount, e.g. "9.99"
+//!       "itemStatus": {     // Omitted if the item has not been validated
+//!         "state": "incomplete" | "complete" | "invalid",
+//!         "message": "string"
+//!       },
+//!       "components": [],   // Items filling the slots of a combo, in the same shape
+//!       "quantity": 1,      // Number of identical items on the line, priced at price each
+//!       "notes": "string",  // Special instructions, omitted if there are none
+//!       "cartLabel": "string"  // Person the item is for, omitted if it has no label
+//!     }
+//!   ],
+//!   "carts": [             // Items grouped by cartLabel, omitted if no item has a label
+//!     { "cartLabel": "string", "itemIds": ["string"], "total": "string" }
+//!   ],
+//!   "total": "string",
+//!   "currency": "string",
+//!   "prepSeconds": number,  // Seconds the kitchen needs to prepare the order
+//!   "readyAt": "2024-12-28T08:03:57Z"  // completedAt plus prepSeconds
+//! }
+//! ```
+//!
+//! The ETA is estimated from the `prepSeconds` of each item's menu entry, or
+//! `DEFAULT_PREP_SECONDS` for items that set none. With `ETA_MODE=max` the order is ready when
+//! its slowest item is, with `ETA_MODE=sum` the items are prepared one after another. Combo
+//! components are combined with their combo the same way.
+//!
+//! ## GET /order/:order_id/audit
+//! Returns the permanent audit trail of an order: every function call made on it, whether by
+//! the assistant or through `POST /order/:order_id/items`, oldest first. The trail is kept in
+//! the `audit:order:<orderId>` list, is never trimmed and is not part of the order responses.
+//! Returns `404` if the order does not exist.
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "orderId": "string",
+//!   "entries": [
+//!     {
+//!       "timestamp": "2024-01-01T12:00:00Z",
+//!       "function": "add_item",
+//!       "arguments": {},       // The call's arguments, a string if they were not valid JSON
+//!       "itemIds": ["string"], // Items added, changed or removed by the call
+//!       "success": true
+//!     }
 //!   ]
 //! }
 //! ```
 //!
+//! ## GET /orders/archived
+//! Lists completed orders from the archive, most recently completed first. Accepts `offset`
+//! (default `0`) and `limit` (default `50`) query parameters.
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "orders": []  // Each in the same shape as the response of /order/:order_id/complete
+//! }
+//! ```
+//!
+//! ## GET /orders/export
+//! Streams every order in Redis, active and archived, as newline-delimited JSON
+//! (`application/x-ndjson`) for bulk analytics. Each line is one order as it is stored. Orders
+//! are read a SCAN page at a time while the body is sent, so the export never loads every
+//! order at once. Accepts `since` (an RFC 3339 timestamp) to only export orders whose
+//! `updatedAt` is at or after that time. Orders stored before `updatedAt` was recorded are
+//! matched on their `completedAt`, or left out if they were never completed. If Redis fails
+//! mid-export the body ends early.
+//!
+//! ## GET /stats
+//! Aggregate statistics over every order in Redis, active and archived, for dashboards.
+//! Accepts `top` (default `10`) for the number of most ordered items to return. Orders are read
+//! a SCAN page at a time, so the cost grows with the number of stored orders.
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "activeOrders": 3,
+//!   "completedOrders": 120,
+//!   "cancelledOrders": 4,
+//!   "averageItemsPerOrder": 2.5,
+//!   "topItems": [{ "itemName": "string", "count": 42 }]
+//! }
+//! ```
+//!
+//! ## POST /order/:order_id/undo
+//! Reverts the most recent change made to the order's items. Up to five changes are kept.
+//! Returns `400` if there is nothing to undo. The response has the same shape as
+//! `GET /order/:order_id`.
+//!
+//! ## POST /order/:order_id/items
+//! Adds a known item to an active order without calling the assistant, e.g. from a kiosk
+//! button. The item is validated like the assistant's additions and a short assistant message
+//! is appended to the chat history. Returns `409` if the order already holds
+//! `MAX_ITEMS_PER_ORDER` items. The response has the same shape as `GET /order/:order_id`.
+//!
+//! ### Request
+//! ```json
+//! {
+//!   "itemName": "string",
+//!   "optionKeys": ["string"],
+//!   "optionValues": [["string"]],  // Or { "value": "string", "count": 2 } for extras
+//!   "price": "string",
+//!   "components": [],      // Optional, combo components
+//!   "quantity": 1,         // Optional, number of identical items on the line, 1 if omitted
+//!   "notes": "string",     // Optional, special instructions such as "cut in half"
+//!   "cartLabel": "string"  // Optional, person the item is for in a group order
+//! }
+//! ```
+//!
+//! ## GET /order/:order_id/item/:item_id/breakdown
+//! Breaks the price of an item down by chosen option value, priced from the current menu, e.g.
+//! for receipts. Returns `404` if the item is not in the order and `400` if it can't be priced
+//! from the menu.
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "itemId": "string",
+//!   "itemName": "string",
+//!   "lines": [
+//!     { "label": "meal option: meal", "price": "13.46" },
+//!     { "label": "customizations: 2x bacon", "price": "2.46" }
+//!   ],
+//!   "total": "string",
+//!   "currency": "string"
+//! }
+//! ```
+//!
+//! ## POST /order/:order_id/clear
+//! Removes every item from an active order while keeping its chat history, so the customer can
+//! start over. The clear can be reverted with `/order/:order_id/undo`. The response has the
+//! same shape as `GET /order/:order_id`.
+//!
+//! ## POST /order/:order_id/duplicate
+//! Starts a new order at the same location with copies of the order's items, e.g. for "same as
+//! last time" or splitting a check. The copies get new item IDs and are validated again against
+//! the current menu. The chat history and thread are not copied; the new order starts its own
+//! conversation on its first chat message. The order being copied can be active, completed or
+//! archived.
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "orderId": "string"
+//! }
+//! ```
+//!
+//! ## GET /menu
+//! Returns the menu the assistant validates orders against. Pass `?tag=vegan` to only return
+//! items with that tag (case-insensitive).
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "items": [
+//!     {
+//!       "itemName": "string",
+//!       "itemType": "string",
+//!       "description": "string",
+//!       "options": {
+//!         "string": {
+//!           "required": true | { "option": "string", "value": "string" },
+//!           "minimum": number,
+//!           "maximum": number,
+//!           "exact": number,  // Optional, requires exactly this many choices instead
+//!           "maxUpcharge": "string",  // Optional, most the chosen values may add to the price together
+//!           "default": "string",  // Optional, choice filled in when the required option is missing
+//!           "choices": {
+//!             "string": {
+//!               "price": "string",
+//!               "priceType": "flat" | "percent",  // Optional, flat by default
+//!               "available": boolean,
+//!               "excludes": ["string"],  // Optional, choices of the item it can't be combined with
+//!               "aliases": ["string"]    // Optional, other names of the choice, matched ignoring case
+//!             }
+//!           }
+//!         }
+//!       },
+//!       "available": boolean,
+//!       "tags": ["string"],  // Allergen and dietary tags, e.g. "vegan"
+//!       "prepSeconds": number,  // Optional, seconds the kitchen needs to prepare the item
+//!       "upsells": ["string"],  // Optional, items to suggest when this one is added
+//!       "components": [      // Slots of a combo, filled by other menu items
+//!         { "name": "string", "items": ["string"], "required": boolean }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! ## GET /menu/:item_name
+//! Returns a single menu item in the same shape as an entry of `items` above, or `404` if the
+//! item does not exist.
+//!
+//! ## POST /menu/validate
+//! Validates an item against the menu without adding it to an order or calling the assistant,
+//! e.g. for a manual "add to cart" button.
+//!
+//! ### Request
+//! ```json
+//! {
+//!   "itemName": "string",
+//!   "optionKeys": ["string"],       // Optional
+//!   "optionValues": [["string"]],   // Optional
+//!   "components": []                // Optional, combo components in the same shape
+//! }
+//! ```
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "itemStatus": {
+//!     "state": "incomplete" | "complete" | "invalid",
+//!     "message": "string"
+//!   },
+//!   "price": "string" | null,  // Price from the menu, null if the item can't be priced
+//!   "currency": "string"
+//! }
+//! ```
+//!
+//! ## POST /admin/menu/reload
+//! Re-reads `MENU_FILE` and updates the assistant to match it without restarting. Chats in
+//! progress finish with the previous menu. Returns `400` describing the problem if the new
+//! menu can't be read, is empty or is invalid, in which case the previous menu stays in use.
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "itemCount": 42
+//! }
+//! ```
+//!
+//! ## GET /admin/assistant
+//! Describes the assistant currently in use, to confirm a menu reload actually updated it.
+//! Requires a read-write API key and returns `403` for read-only keys. Returns `503` if the
+//! assistant isn't initialized. With `OPENAI_MOCK=1` the id and model are `mock` and
+//! `menuHash` is `null`.
+//!
+//! ### Response
+//! ```json
+//! {
+//!   "assistantId": "asst_abc123",
+//!   "model": "gpt-4o",
+//!   "menuHash": "string"  // SHA-256 of the menu the assistant was built from
+//! }
+//! ```
+//!
+//! ## POST /admin/order/:order_id/cancel
+//! Force-cancels an order stuck mid-conversation. Any OpenAI run still active on the order's
+//! thread is cancelled, then the order's status becomes `cancelled` and further chat messages
+//! are refused with `409`. Requires a read-write API key and returns `403` for read-only keys.
+//! Returns `404` if the order does not exist. Cancelling an order that is already completed or
+//! cancelled changes nothing and returns it as stored.
+//!
+//! ### Response
+//! Same as `GET /order/:order_id`, with `"status": "cancelled"` unless the order was already
+//! `completed`.
+//!
 //! # Example Usage
 //!
-//! ```rust
+//! ```rust,ignore
 //! use reqwest::Client;
 //! use serde_json::json;
 //!
@@ -191,7 +795,16 @@
 
 pub mod api;
 pub mod chat;
+pub mod cleanup;
+pub mod config;
 pub mod error;
 pub mod functions;
+pub mod location;
 pub mod menu;
+pub mod mock;
+pub mod money;
 pub mod order;
+pub mod telemetry;
+#[cfg(test)]
+mod testing;
+pub mod webhook;
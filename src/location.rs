@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use tracing::{debug, info};
+
+use crate::error::{AppError, AppResult};
+use crate::money::Money;
+
+/// Order constraints for a single restaurant location
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LocationConfig {
+    /// Smallest order total accepted at checkout, e.g. a delivery minimum
+    #[serde(rename = "minTotal", default)]
+    pub min_total: Option<Money>,
+    /// Largest order total accepted at checkout
+    #[serde(rename = "maxTotal", default)]
+    pub max_total: Option<Money>,
+}
+
+/// Per-location configuration keyed by location name
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Locations {
+    /// Configuration for each location
+    pub locations: HashMap<String, LocationConfig>,
+}
+
+impl Locations {
+    /// Loads the location configuration from the locations file.
+    ///
+    /// Locations without an entry, or every location if the file does not exist, have no
+    /// constraints.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the locations file, from `LOCATIONS_FILE`
+    ///
+    /// # Returns
+    /// * `AppResult<Self>` - The loaded configuration or an error
+    pub fn new(path: &str) -> AppResult<Self> {
+        debug!("Reading locations from: {}", path);
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("No locations file at {}, orders are unconstrained", path);
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let locations: HashMap<String, LocationConfig> = serde_json::from_str(&content)?;
+        debug!("Loaded configuration for {} locations", locations.len());
+        Ok(Locations { locations })
+    }
+
+    /// Looks up the configuration of a location.
+    ///
+    /// # Arguments
+    /// * `location` - The name of the location
+    ///
+    /// # Returns
+    /// * `Option<&LocationConfig>` - The configuration if the location has one
+    pub fn get(&self, location: &str) -> Option<&LocationConfig> {
+        self.locations.get(location)
+    }
+
+    /// Checks an order total against the minimum and maximum of a location.
+    ///
+    /// # Arguments
+    /// * `location` - The name of the location, if the order has one
+    /// * `total` - The order total
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the total is within the location's limits
+    pub fn check_total(&self, location: Option<&str>, total: Money) -> AppResult<()> {
+        let (location, config) = match location.and_then(|l| self.get(l).map(|c| (l, c))) {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+        if let Some(min_total) = config.min_total {
+            if total < min_total {
+                info!(
+                    "Order total {} is below the minimum {} at {}",
+                    total, min_total, location
+                );
+                return Err(AppError::InvalidInput(format!(
+                    "Order total {} is below the minimum of {} at {}",
+                    total, min_total, location
+                )));
+            }
+        }
+        if let Some(max_total) = config.max_total {
+            if total > max_total {
+                info!(
+                    "Order total {} is above the maximum {} at {}",
+                    total, max_total, location
+                );
+                return Err(AppError::InvalidInput(format!(
+                    "Order total {} is above the maximum of {} at {}",
+                    total, max_total, location
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations_are_read_from_the_configured_file() {
+        let path = std::env::temp_dir().join(format!("locations-{}.json", std::process::id()));
+        fs::write(&path, r#"{"store-a": {"minTotal": 10}}"#).unwrap();
+        let locations = Locations::new(&path.to_string_lossy()).unwrap();
+        assert!(locations
+            .check_total(Some("store-a"), Money::from_f64(5.0))
+            .is_err());
+        assert!(locations
+            .check_total(Some("store-a"), Money::from_f64(10.0))
+            .is_ok());
+        assert!(locations
+            .check_total(Some("store-b"), Money::from_f64(5.0))
+            .is_ok());
+    }
+
+    #[test]
+    fn a_missing_file_leaves_every_location_unconstrained() {
+        let locations = Locations::new("static/missing.json").unwrap();
+        assert!(locations.locations.is_empty());
+    }
+
+    #[test]
+    fn totals_are_checked_against_both_boundaries() {
+        let mut locations = Locations::default();
+        locations.locations.insert(
+            "store-a".to_string(),
+            LocationConfig {
+                min_total: Some(Money::from_cents(1000)),
+                max_total: Some(Money::from_cents(5000)),
+            },
+        );
+        let check = |cents| locations.check_total(Some("store-a"), Money::from_cents(cents));
+
+        assert!(check(1000).is_ok());
+        assert!(check(5000).is_ok());
+        assert!(matches!(
+            check(999),
+            Err(AppError::InvalidInput(message))
+                if message == "Order total 9.99 is below the minimum of 10.00 at store-a"
+        ));
+        assert!(matches!(
+            check(5001),
+            Err(AppError::InvalidInput(message))
+                if message == "Order total 50.01 is above the maximum of 50.00 at store-a"
+        ));
+        assert!(locations.check_total(None, Money::ZERO).is_ok());
+    }
+}
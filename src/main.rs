@@ -1,7 +1,7 @@
 use customer_agent::api;
+use customer_agent::config::Config;
+use customer_agent::error::AppError;
 use dotenv::dotenv;
-use std::net::SocketAddr;
-use std::str::FromStr;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
@@ -10,8 +10,10 @@ use tracing_subscriber::FmtSubscriber;
 /// This function:
 /// 1. Creates a logger
 /// 2. Loads environment variables from .env file
-/// 3. Creates and configures the API router
-/// 4. Starts the HTTP server (defaulting to localhost:3000)
+/// 3. Validates the configuration, exiting with every problem found if it is invalid
+/// 4. Creates and configures the API router
+/// 5. Starts the metrics server when `METRICS_PORT` is set
+/// 6. Starts the HTTP server (defaulting to localhost:3000)
 #[tokio::main]
 async fn main() {
     FmtSubscriber::builder()
@@ -28,13 +30,34 @@ async fn main() {
 
     dotenv().ok();
 
-    let app = api::create_router().await;
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(AppError::InvalidConfig(errors)) => {
+            eprintln!("Invalid configuration:");
+            for error in errors {
+                eprintln!("  - {}", error);
+            }
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to read configuration: {:?}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let host = std::env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let addr = format!("{}:{}", host, port);
-    let addr = SocketAddr::from_str(&addr).expect("Invalid address format");
+    let app = api::create_router(&config).await;
 
+    if let Some(metrics_addr) = config.metrics_addr {
+        info!("Metrics listening on {}", metrics_addr);
+        tokio::spawn(async move {
+            axum::Server::bind(&metrics_addr)
+                .serve(api::create_metrics_router::<()>().into_make_service())
+                .await
+                .unwrap();
+        });
+    }
+
+    let addr = config.addr;
     info!("Server listening on {}", addr);
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
@@ -1,9 +1,13 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use tracing::{debug, info};
+use std::io;
+use tracing::{debug, info, warn};
 
-use crate::error::AppResult;
-use crate::order::OrderItem;
+use crate::error::{AppError, AppResult};
+use crate::money::Money;
+use crate::order::{OptionValue, OrderItem};
 
 /// Represents a single item on the menu
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -18,6 +22,63 @@ pub struct MenuItem {
     pub description: String,
     /// Available customization options
     pub options: std::collections::HashMap<String, OptionConfig>,
+    /// Whether the item can currently be ordered
+    #[serde(default = "default_available")]
+    pub available: Option<bool>,
+    /// Allergen and dietary tags, e.g. "vegan" or "gluten-free"
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Slots filled by other menu items when this item is a combo
+    #[serde(default)]
+    pub components: Vec<ComponentConfig>,
+    /// Other items to suggest when this one is added, e.g. fries with a burger
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub upsells: Vec<String>,
+    /// Seconds the kitchen needs to prepare the item, `DEFAULT_PREP_SECONDS` if not set
+    #[serde(
+        rename = "prepSeconds",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub prep_seconds: Option<u32>,
+}
+
+/// Configuration for a slot in a combo item, e.g. the side of a meal
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ComponentConfig {
+    /// Name of the slot
+    pub name: String,
+    /// Names of the menu items that can fill the slot
+    pub items: Vec<String>,
+    /// Whether the slot must be filled
+    #[serde(default = "default_component_required")]
+    pub required: bool,
+}
+
+/// Combo slots must be filled unless the menu says otherwise
+fn default_component_required() -> bool {
+    true
+}
+
+impl MenuItem {
+    /// Whether the item carries a tag, ignoring case.
+    ///
+    /// # Arguments
+    /// * `tag` - The tag to look for
+    ///
+    /// # Returns
+    /// * `bool` - True if the item has the tag
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// Whether the item can currently be ordered.
+    ///
+    /// # Returns
+    /// * `bool` - False only if the item has been marked unavailable
+    pub fn is_available(&self) -> bool {
+        self.available.unwrap_or(true)
+    }
 }
 
 /// Configuration for a customization option
@@ -26,11 +87,92 @@ pub struct OptionConfig {
     /// Whether and how the option is required
     pub required: RequirementConfig,
     /// Minimum number of choices required
+    #[serde(default)]
     pub minimum: i32,
     /// Maximum number of choices allowed
+    #[serde(default)]
     pub maximum: i32,
+    /// Exact number of choices required, in place of `minimum` and `maximum`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exact: Option<i32>,
     /// Available choices for this option
     pub choices: std::collections::HashMap<String, Choice>,
+    /// Most the chosen values of this option may add to the item's price together
+    #[serde(
+        rename = "maxUpcharge",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_upcharge: Option<Money>,
+    /// Choice filled in when the option is required but missing, leaving it to the customer
+    /// otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+impl OptionConfig {
+    /// Minimum number of choices required, taking `exact` into account.
+    ///
+    /// # Returns
+    /// * `i32` - The lower bound
+    pub fn min_choices(&self) -> i32 {
+        self.exact.unwrap_or(self.minimum)
+    }
+
+    /// Resolves a value to the name of one of the option's choices.
+    ///
+    /// An exact match wins, otherwise the value is compared ignoring case against the choice
+    /// names and then their aliases.
+    ///
+    /// # Arguments
+    /// * `value` - The value as given, e.g. "No Onion"
+    ///
+    /// # Returns
+    /// * `Option<&str>` - The canonical choice name, or None if nothing matches
+    pub fn resolve_choice(&self, value: &str) -> Option<&str> {
+        if let Some((name, _)) = self.choices.get_key_value(value) {
+            return Some(name);
+        }
+        let value = value.trim().to_lowercase();
+        self.choices
+            .keys()
+            .find(|name| name.to_lowercase() == value)
+            .or_else(|| {
+                self.choices
+                    .iter()
+                    .find(|(_, choice)| {
+                        choice
+                            .aliases
+                            .iter()
+                            .any(|alias| alias.to_lowercase() == value)
+                    })
+                    .map(|(name, _)| name)
+            })
+            .map(String::as_str)
+    }
+
+    /// Maximum number of choices allowed, taking `exact` into account.
+    ///
+    /// # Returns
+    /// * `i32` - The upper bound
+    pub fn max_choices(&self) -> i32 {
+        self.exact.unwrap_or(self.maximum)
+    }
+
+    /// Describes how many choices the option takes, e.g. "exactly 2" or "up to 3".
+    ///
+    /// # Returns
+    /// * `String` - The description
+    pub fn count_description(&self) -> String {
+        let (minimum, maximum) = (self.min_choices(), self.max_choices());
+        if minimum == maximum {
+            format!("exactly {}", minimum)
+        } else if minimum == 0 {
+            format!("up to {}", maximum)
+        } else {
+            format!("{} to {}", minimum, maximum)
+        }
+    }
 }
 
 /// Requirement configuration for an option
@@ -46,8 +188,228 @@ pub enum RequirementConfig {
 /// Price configuration for an option choice
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Choice {
-    /// Additional price for this choice
-    pub price: f64,
+    /// Additional price for this choice, or the percentage it adds with `priceType` percent
+    pub price: Money,
+    /// How `price` is applied, a flat amount unless the menu says otherwise
+    #[serde(
+        rename = "priceType",
+        default,
+        skip_serializing_if = "PriceType::is_flat"
+    )]
+    pub price_type: PriceType,
+    /// Whether the choice can currently be selected
+    #[serde(default = "default_available")]
+    pub available: Option<bool>,
+    /// Choices of the same item that can't be selected together with this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excludes: Vec<String>,
+    /// Other names customers use for the choice, e.g. "no onion" for "no onions"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+/// How the price of a choice is applied to an item
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceType {
+    /// The price is added as is
+    #[default]
+    Flat,
+    /// The price is a percentage of the item's flat priced choices, e.g. 10 for +10%
+    Percent,
+}
+
+impl PriceType {
+    /// Whether prices are added as is.
+    ///
+    /// # Returns
+    /// * `bool` - True for flat prices
+    pub fn is_flat(&self) -> bool {
+        *self == PriceType::Flat
+    }
+}
+
+impl Choice {
+    /// Price a single pick of the choice adds to an item.
+    ///
+    /// # Arguments
+    /// * `base` - Sum of the item's flat priced choices, what percentages are taken of
+    ///
+    /// # Returns
+    /// * `Money` - The amount added
+    pub fn price_for(&self, base: Money) -> Money {
+        match self.price_type {
+            PriceType::Flat => self.price,
+            PriceType::Percent => base.percent(self.price),
+        }
+    }
+
+    /// Whether the choice can currently be selected.
+    ///
+    /// # Returns
+    /// * `bool` - False only if the choice has been marked unavailable
+    pub fn is_available(&self) -> bool {
+        self.available.unwrap_or(true)
+    }
+}
+
+/// Lowercases a name and collapses runs of whitespace into single spaces.
+///
+/// # Arguments
+/// * `name` - The name to normalize
+///
+/// # Returns
+/// * `String` - The normalized name
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Number of single character insertions, deletions or substitutions turning one string into
+/// another.
+///
+/// # Arguments
+/// * `a` - The first string
+/// * `b` - The second string
+///
+/// # Returns
+/// * `usize` - The edit distance
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Menu entries are available unless the menu says otherwise
+fn default_available() -> Option<bool> {
+    Some(true)
+}
+
+/// Sums the flat priced choices of an item, the base that percentage priced choices are taken of.
+///
+/// # Arguments
+/// * `choices` - Every chosen choice with how many times it was chosen
+///
+/// # Returns
+/// * `Money` - The sum of the flat prices
+fn flat_price<'a>(choices: impl IntoIterator<Item = (&'a Choice, u32)>) -> Money {
+    choices
+        .into_iter()
+        .filter(|(choice, _)| choice.price_type.is_flat())
+        .map(|(choice, count)| choice.price * count)
+        .sum()
+}
+
+/// Whether an order item has chosen a value for an option.
+///
+/// # Arguments
+/// * `item` - The order item
+/// * `option` - Name of the option
+/// * `value` - Name of the choice
+///
+/// # Returns
+/// * `bool` - True if the value is among the option's chosen values
+fn is_chosen(item: &OrderItem, option: &str, value: &str) -> bool {
+    item.option_keys
+        .iter()
+        .position(|key| key == option)
+        .and_then(|index| item.option_values.get(index))
+        .is_some_and(|values| values.iter().any(|v| v.value == value))
+}
+
+/// Fills in the missing required options of an order item that have a `default`, so the
+/// customer isn't asked about them.
+///
+/// Dependent options are only filled once the triggering value is chosen, possibly by
+/// another default. A default whose choice is unavailable is left for the customer.
+///
+/// # Arguments
+/// * `menu_item` - The menu definition of the item
+/// * `item` - The order item to fill
+fn fill_defaults(menu_item: &MenuItem, item: &mut OrderItem) {
+    if item.option_keys.len() != item.option_values.len() {
+        return;
+    }
+    // NOTE(dev): Plain required options go first, their defaults can trigger dependent options
+    let mut options: Vec<(&String, &OptionConfig)> = menu_item
+        .options
+        .iter()
+        .filter(|(_, option)| option.default.is_some())
+        .collect();
+    options.sort_by_key(|(option_name, option)| {
+        (
+            matches!(option.required, RequirementConfig::Dependent { .. }),
+            *option_name,
+        )
+    });
+    for (option_name, option) in options {
+        let Some(default) = &option.default else {
+            continue;
+        };
+        if item.option_keys.contains(option_name) {
+            continue;
+        }
+        let required = match &option.required {
+            RequirementConfig::Simple(required) => *required,
+            RequirementConfig::Dependent { option, value } => is_chosen(item, option, value),
+        };
+        if !required
+            || !option
+                .choices
+                .get(default)
+                .is_some_and(Choice::is_available)
+        {
+            continue;
+        }
+        info!(
+            "Applied default '{}' for option '{}' of item {} (ID: {})",
+            default, option_name, item.item_name, item.id
+        );
+        item.option_keys.push(option_name.clone());
+        item.option_values
+            .push(vec![OptionValue::new(default.clone())]);
+    }
+}
+
+/// Joins the problems found with an item into one validation message.
+///
+/// # Arguments
+/// * `problems` - The problems, in the order they were found
+/// * `limit` - Problems listed before the rest are only counted, 0 for all of them
+///
+/// # Returns
+/// * `String` - The problems separated by semicolons, capped at `limit`
+fn join_problems(problems: &[String], limit: usize) -> String {
+    if limit == 0 || problems.len() <= limit {
+        return problems.join("; ");
+    }
+    format!(
+        "{}; and {} more",
+        problems[..limit].join("; "),
+        problems.len() - limit
+    )
+}
+
+/// How the menu is loaded and validated, read from the environment by `Config`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MenuSettings {
+    /// Path of the menu file, from `MENU_FILE`
+    pub path: String,
+    /// Whether a menu without items is loaded with a warning instead of refused, from
+    /// `ALLOW_EMPTY_MENU`
+    pub allow_empty: bool,
+    /// Problems listed in a single validation message, 0 for all of them, from
+    /// `MAX_VALIDATION_PROBLEMS`
+    pub max_validation_problems: usize,
 }
 
 /// Complete menu configuration
@@ -55,6 +417,31 @@ pub struct Choice {
 pub struct Menu {
     /// List of available menu items
     pub items: Vec<MenuItem>,
+    /// Problems listed in a single validation message, 0 for all of them
+    #[serde(skip)]
+    pub max_validation_problems: usize,
+}
+
+/// Result of resolving a possibly misspelled item name against the menu
+#[derive(Debug)]
+pub enum ItemMatch<'a> {
+    /// The name resolves to a single menu item
+    Found(&'a MenuItem),
+    /// The name is close to several menu items
+    Ambiguous(Vec<&'a str>),
+    /// The name is not close to any menu item
+    NotFound,
+}
+
+/// Available items offering the same option keys and available choices
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionGroup {
+    /// Names of the items, in menu order
+    pub item_names: Vec<String>,
+    /// Option keys of the items, sorted
+    pub option_keys: Vec<String>,
+    /// Available choices across all options of the items, sorted
+    pub choice_names: Vec<String>,
 }
 
 /// Status of an item's validation against menu requirements
@@ -68,30 +455,723 @@ pub enum ItemStatus {
     Invalid(String),
 }
 
-impl Menu {
-    /// Creates a new Menu instance from the menu file.
-    ///
-    /// # Returns
-    /// * `AppResult<Self>` - The loaded menu or an error
-    pub fn new() -> AppResult<Self> {
-        info!("Loading menu configuration");
-        let menu_path =
-            std::env::var("MENU_FILE").unwrap_or_else(|_| "static/menu.json".to_string());
-        debug!("Reading menu from: {}", menu_path);
-        let content = fs::read_to_string(menu_path)?;
-        let items: Vec<MenuItem> = serde_json::from_str(&content)?;
-        debug!("Loaded {} menu items", items.len());
-        Ok(Menu { items })
+impl Menu {
+    /// SHA-256 of the menu, independent of the order of its option maps.
+    ///
+    /// # Returns
+    /// * `AppResult<String>` - Hex digest of the menu's JSON
+    pub fn hash(&self) -> AppResult<String> {
+        // NOTE(dev): Going through Value sorts the keys of the HashMaps, so equal menus hash equally
+        let json = serde_json::to_string(&serde_json::to_value(self)?)?;
+        Ok(hex::encode(Sha256::digest(json.as_bytes())))
+    }
+
+    /// Creates a new Menu instance from the menu file.
+    ///
+    /// An empty menu is refused unless `ALLOW_EMPTY_MENU` is set, since every item ordered
+    /// from it would be invalid.
+    ///
+    /// # Arguments
+    /// * `settings` - Where the menu is read from and how it is validated
+    ///
+    /// # Returns
+    /// * `AppResult<Self>` - The loaded menu, `MenuFileMissing` if the file does not exist, or
+    ///   `InvalidInput` if the menu is empty or inconsistent
+    pub fn new(settings: &MenuSettings) -> AppResult<Self> {
+        info!("Loading menu configuration");
+        let menu_path = settings.path.clone();
+        debug!("Reading menu from: {}", menu_path);
+        let content = match fs::read_to_string(&menu_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(AppError::MenuFileMissing(menu_path));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let items: Vec<MenuItem> = serde_json::from_str(&content)?;
+        debug!("Loaded {} menu items", items.len());
+        if items.is_empty() {
+            if !settings.allow_empty {
+                info!("Refusing empty menu from {}", menu_path);
+                return Err(AppError::InvalidInput(format!(
+                    "Invalid menu: {} has no items, set ALLOW_EMPTY_MENU=true to start without any",
+                    menu_path
+                )));
+            }
+            warn!(
+                "Menu {} has no items, every item ordered will be invalid",
+                menu_path
+            );
+        }
+        let menu = Menu {
+            items,
+            max_validation_problems: settings.max_validation_problems,
+        };
+        menu.validate_config()?;
+        Ok(menu)
+    }
+
+    /// Checks that the menu configuration is consistent, so mistakes in the menu file fail at
+    /// startup instead of showing up as confusing validation results.
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success, or `InvalidInput` naming the offending item and option
+    pub fn validate_config(&self) -> AppResult<()> {
+        debug!("Validating menu configuration");
+        let invalid = |message: String| {
+            info!("Invalid menu configuration: {}", message);
+            Err(AppError::InvalidInput(format!("Invalid menu: {}", message)))
+        };
+
+        let mut seen_items = BTreeSet::new();
+        for item in &self.items {
+            if !seen_items.insert(&item.item_name) {
+                return invalid(format!("item {} is defined more than once", item.item_name));
+            }
+
+            for (option_name, option) in &item.options {
+                if let Some(exact) = option.exact {
+                    if exact < 0 {
+                        return invalid(format!(
+                            "option {} of item {} has a negative exact count",
+                            option_name, item.item_name
+                        ));
+                    }
+                    // NOTE(dev): Bounds left at their default of 0 are fine, exact replaces them
+                    if (option.minimum != 0 && option.minimum != exact)
+                        || (option.maximum != 0 && option.maximum != exact)
+                    {
+                        return invalid(format!(
+                            "option {} of item {} has exact {} but minimum {} and maximum {}",
+                            option_name, item.item_name, exact, option.minimum, option.maximum
+                        ));
+                    }
+                }
+                if option.minimum < 0 {
+                    return invalid(format!(
+                        "option {} of item {} has a negative minimum",
+                        option_name, item.item_name
+                    ));
+                }
+                if option.exact.is_none() && option.minimum > option.maximum {
+                    return invalid(format!(
+                        "option {} of item {} has minimum {} greater than maximum {}",
+                        option_name, item.item_name, option.minimum, option.maximum
+                    ));
+                }
+                if option.choices.is_empty() {
+                    return invalid(format!(
+                        "option {} of item {} has no choices",
+                        option_name, item.item_name
+                    ));
+                }
+                if option
+                    .max_upcharge
+                    .is_some_and(|max_upcharge| max_upcharge < Money::ZERO)
+                {
+                    return invalid(format!(
+                        "option {} of item {} has a negative maxUpcharge",
+                        option_name, item.item_name
+                    ));
+                }
+                if let Some(default) = &option.default {
+                    if !option.choices.contains_key(default) {
+                        return invalid(format!(
+                            "default {} of option {} of item {} is not one of its choices",
+                            default, option_name, item.item_name
+                        ));
+                    }
+                    if matches!(option.required, RequirementConfig::Simple(false)) {
+                        return invalid(format!(
+                            "option {} of item {} has a default but is not required",
+                            option_name, item.item_name
+                        ));
+                    }
+                    if option.min_choices() > 1 {
+                        return invalid(format!(
+                            "option {} of item {} has a default but needs {} choices",
+                            option_name,
+                            item.item_name,
+                            option.count_description()
+                        ));
+                    }
+                }
+                let mut spellings = BTreeMap::new();
+                for (choice_name, choice) in &option.choices {
+                    for spelling in std::iter::once(choice_name).chain(&choice.aliases) {
+                        if let Some(other) = spellings.insert(spelling.to_lowercase(), choice_name)
+                        {
+                            if other != choice_name {
+                                return invalid(format!(
+                                    "{} of option {} of item {} names both choice {} and {}",
+                                    spelling, option_name, item.item_name, other, choice_name
+                                ));
+                            }
+                        }
+                    }
+                }
+                for (choice_name, choice) in &option.choices {
+                    for excluded in &choice.excludes {
+                        if excluded == choice_name {
+                            return invalid(format!(
+                                "choice {} of item {} excludes itself",
+                                choice_name, item.item_name
+                            ));
+                        }
+                        if !item
+                            .options
+                            .values()
+                            .any(|option| option.choices.contains_key(excluded))
+                        {
+                            return invalid(format!(
+                                "choice {} of item {} excludes {}, which is not one of its choices",
+                                choice_name, item.item_name, excluded
+                            ));
+                        }
+                    }
+                }
+                if let RequirementConfig::Dependent {
+                    option: dependency,
+                    value,
+                } = &option.required
+                {
+                    let Some(dependency_option) = item.options.get(dependency) else {
+                        return invalid(format!(
+                            "option {} of item {} depends on option {} which does not exist",
+                            option_name, item.item_name, dependency
+                        ));
+                    };
+                    if dependency == option_name {
+                        return invalid(format!(
+                            "option {} of item {} depends on itself",
+                            option_name, item.item_name
+                        ));
+                    }
+                    if !dependency_option.choices.contains_key(value) {
+                        return invalid(format!(
+                            "option {} of item {} depends on {} being {}, which is not one of its choices",
+                            option_name, item.item_name, dependency, value
+                        ));
+                    }
+                }
+            }
+
+            for component in &item.components {
+                if component.items.is_empty() {
+                    return invalid(format!(
+                        "component {} of item {} has no items",
+                        component.name, item.item_name
+                    ));
+                }
+                if let Some(missing) = component
+                    .items
+                    .iter()
+                    .find(|name| self.get_item(name).is_none())
+                {
+                    return invalid(format!(
+                        "component {} of item {} refers to item {} which does not exist",
+                        component.name, item.item_name, missing
+                    ));
+                }
+            }
+
+            for upsell in &item.upsells {
+                if *upsell == item.item_name {
+                    return invalid(format!("item {} upsells itself", item.item_name));
+                }
+                if self.get_item(upsell).is_none() {
+                    return invalid(format!(
+                        "item {} upsells item {} which does not exist",
+                        item.item_name, upsell
+                    ));
+                }
+            }
+        }
+        debug!("Menu configuration is valid");
+        Ok(())
+    }
+
+    /// Looks up a menu item by its exact name.
+    ///
+    /// # Arguments
+    /// * `item_name` - The name of the menu item
+    ///
+    /// # Returns
+    /// * `Option<&MenuItem>` - The menu item if it exists
+    pub fn get_item(&self, item_name: &str) -> Option<&MenuItem> {
+        self.items.iter().find(|i| i.item_name == item_name)
+    }
+
+    /// Resolves an item name to a menu item, tolerating differences in case, whitespace and a
+    /// few typos, e.g. "cheese burger" for "Cheese Burger" or "Cheeseburger".
+    ///
+    /// # Arguments
+    /// * `item_name` - The item name to resolve
+    ///
+    /// # Returns
+    /// * `ItemMatch` - The matching item, or the close candidates if there is more than one
+    pub fn find_item(&self, item_name: &str) -> ItemMatch<'_> {
+        if let Some(item) = self.get_item(item_name) {
+            return ItemMatch::Found(item);
+        }
+
+        let normalized = normalize_name(item_name);
+        if let Some(item) = self
+            .items
+            .iter()
+            .find(|i| normalize_name(&i.item_name) == normalized)
+        {
+            return ItemMatch::Found(item);
+        }
+
+        // NOTE(dev): Spaces are dropped before comparing so "Cheese Burger" matches "Cheeseburger"
+        let compact: Vec<char> = normalized.chars().filter(|c| *c != ' ').collect();
+        let tolerance = match compact.len() {
+            0..=4 => 0,
+            5..=8 => 1,
+            _ => 2,
+        };
+        let candidates: Vec<&MenuItem> = self
+            .items
+            .iter()
+            .filter(|i| {
+                let other: Vec<char> = normalize_name(&i.item_name)
+                    .chars()
+                    .filter(|c| *c != ' ')
+                    .collect();
+                levenshtein(&compact, &other) <= tolerance
+            })
+            .collect();
+        debug!(
+            "Fuzzy matched '{}' to {} menu items",
+            item_name,
+            candidates.len()
+        );
+        match candidates.as_slice() {
+            [] => ItemMatch::NotFound,
+            [item] => ItemMatch::Found(item),
+            _ => ItemMatch::Ambiguous(candidates.iter().map(|i| i.item_name.as_str()).collect()),
+        }
+    }
+
+    /// Replaces the item name of an order item and its components with the menu's spelling when
+    /// it resolves to a single menu item, and each option value with the name of the choice it
+    /// matches ignoring case or through an alias. Missing required options with a default are
+    /// then filled in, see `fill_defaults`.
+    ///
+    /// # Arguments
+    /// * `item` - The order item to canonicalize
+    pub fn canonicalize_item(&self, item: &mut OrderItem) {
+        if let ItemMatch::Found(menu_item) = self.find_item(&item.item_name) {
+            if menu_item.item_name != item.item_name {
+                info!(
+                    "Resolved item name '{}' to '{}' (ID: {})",
+                    item.item_name, menu_item.item_name, item.id
+                );
+                item.item_name = menu_item.item_name.clone();
+            }
+            for (key, values) in item.option_keys.iter().zip(&mut item.option_values) {
+                let Some(option) = menu_item.options.get(key) else {
+                    continue;
+                };
+                for value in values {
+                    match option.resolve_choice(&value.value) {
+                        Some(choice) if choice != value.value => {
+                            info!(
+                                "Resolved {} value '{}' to '{}' (ID: {})",
+                                key, value.value, choice, item.id
+                            );
+                            value.value = choice.to_string();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            fill_defaults(menu_item, item);
+        }
+        for component in &mut item.components {
+            self.canonicalize_item(component);
+        }
+    }
+
+    /// Items worth suggesting after some items were added to an order.
+    ///
+    /// Only available items that aren't in the order yet are suggested, each once.
+    ///
+    /// # Arguments
+    /// * `added` - Names of the menu items that were added
+    /// * `order_items` - Names of every item in the order
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The suggested item names, in the order the added items list them
+    pub fn upsells<'a>(
+        &self,
+        added: impl IntoIterator<Item = &'a str>,
+        order_items: &[&str],
+    ) -> Vec<String> {
+        let mut suggestions: Vec<String> = vec![];
+        for upsell in added
+            .into_iter()
+            .filter_map(|name| self.get_item(name))
+            .flat_map(|item| &item.upsells)
+        {
+            let available = self
+                .get_item(upsell)
+                .is_some_and(|item| item.is_available());
+            if available && !order_items.contains(&upsell.as_str()) && !suggestions.contains(upsell)
+            {
+                suggestions.push(upsell.clone());
+            }
+        }
+        suggestions
+    }
+
+    /// Names of the items that can currently be ordered.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The item names in menu order
+    pub fn item_names(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .filter(|i| i.is_available())
+            .map(|i| i.item_name.clone())
+            .collect()
+    }
+
+    /// Every option key used by any item on the menu.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The sorted, deduplicated option keys
+    pub fn option_keys(&self) -> Vec<String> {
+        let keys: BTreeSet<String> = self
+            .items
+            .iter()
+            .flat_map(|i| i.options.keys().cloned())
+            .collect();
+        keys.into_iter().collect()
+    }
+
+    /// Describes the choices that can't be selected together, e.g.
+    /// "gluten-free bun with pretzel bun (Burger)".
+    ///
+    /// # Returns
+    /// * `Option<String>` - The exclusions separated by semicolons, or None if there are none
+    pub fn exclusions(&self) -> Option<String> {
+        let mut exclusions: BTreeMap<(&str, &str), Vec<&str>> = BTreeMap::new();
+        for item in &self.items {
+            for option in item.options.values() {
+                for (choice_name, choice) in &option.choices {
+                    for excluded in &choice.excludes {
+                        // NOTE(dev): Ordered pairs so an exclusion listed on both choices is described once
+                        let pair = if choice_name.as_str() <= excluded.as_str() {
+                            (choice_name.as_str(), excluded.as_str())
+                        } else {
+                            (excluded.as_str(), choice_name.as_str())
+                        };
+                        let items = exclusions.entry(pair).or_default();
+                        if !items.contains(&item.item_name.as_str()) {
+                            items.push(&item.item_name);
+                        }
+                    }
+                }
+            }
+        }
+        if exclusions.is_empty() {
+            return None;
+        }
+        let exclusions: Vec<String> = exclusions
+            .into_iter()
+            .map(|((first, second), items)| {
+                format!("{} with {} ({})", first, second, items.join(", "))
+            })
+            .collect();
+        Some(exclusions.join("; "))
+    }
+
+    /// Describes how many choices each option key takes, listing the items when the count
+    /// differs between items, e.g. "size: exactly 1; sauce: up to 3 (Buffalo Chicken Wings)".
+    ///
+    /// # Returns
+    /// * `String` - The count of every option key, separated by semicolons
+    pub fn option_counts(&self) -> String {
+        let mut counts: BTreeMap<&str, BTreeMap<String, Vec<&str>>> = BTreeMap::new();
+        for item in &self.items {
+            for (option_key, option) in &item.options {
+                counts
+                    .entry(option_key)
+                    .or_default()
+                    .entry(option.count_description())
+                    .or_default()
+                    .push(&item.item_name);
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(option_key, by_count)| {
+                if by_count.len() == 1 {
+                    let count = by_count.into_keys().next().unwrap_or_default();
+                    return format!("{}: {}", option_key, count);
+                }
+                let rules: Vec<String> = by_count
+                    .into_iter()
+                    .map(|(count, items)| format!("{} ({})", count, items.join(", ")))
+                    .collect();
+                format!("{}: {}", option_key, rules.join(", "))
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// Every available choice offered by any option on the menu.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The sorted, deduplicated choice names
+    pub fn choice_names(&self) -> Vec<String> {
+        let choices: BTreeSet<String> = self
+            .items
+            .iter()
+            .flat_map(|i| i.options.values())
+            .flat_map(|o| o.choices.iter())
+            .filter(|(_, choice)| choice.is_available())
+            .map(|(name, _)| name.clone())
+            .collect();
+        choices.into_iter().collect()
+    }
+
+    /// Groups the available items by their option keys and available choices, so each item can
+    /// be limited to its own options without repeating them for items offering the same ones.
+    ///
+    /// # Returns
+    /// * `Vec<OptionGroup>` - The groups in the menu order of their first item
+    pub fn option_groups(&self) -> Vec<OptionGroup> {
+        let mut groups: Vec<OptionGroup> = vec![];
+        for item in self.items.iter().filter(|i| i.is_available()) {
+            let option_keys: BTreeSet<String> = item.options.keys().cloned().collect();
+            let choice_names: BTreeSet<String> = item
+                .options
+                .values()
+                .flat_map(|o| o.choices.iter())
+                .filter(|(_, choice)| choice.is_available())
+                .map(|(name, _)| name.clone())
+                .collect();
+            let option_keys: Vec<String> = option_keys.into_iter().collect();
+            let choice_names: Vec<String> = choice_names.into_iter().collect();
+            match groups
+                .iter_mut()
+                .find(|g| g.option_keys == option_keys && g.choice_names == choice_names)
+            {
+                Some(group) => group.item_names.push(item.item_name.clone()),
+                None => groups.push(OptionGroup {
+                    item_names: vec![item.item_name.clone()],
+                    option_keys,
+                    choice_names,
+                }),
+            }
+        }
+        groups
+    }
+
+    /// Returns a menu containing only the items with a given tag.
+    ///
+    /// # Arguments
+    /// * `tag` - The tag to filter by
+    ///
+    /// # Returns
+    /// * `Menu` - The matching items
+    pub fn filter_by_tag(&self, tag: &str) -> Menu {
+        Menu {
+            items: self
+                .items
+                .iter()
+                .filter(|i| i.has_tag(tag))
+                .cloned()
+                .collect(),
+            max_validation_problems: self.max_validation_problems,
+        }
+    }
+
+    /// Prices an order item from the menu by summing the prices of its chosen options, each
+    /// multiplied by how many of it were chosen.
+    ///
+    /// Percentage priced choices add their percentage of the sum of the flat priced choices, so
+    /// they never compound. Components of a combo are priced as part of the combo and not added.
+    ///
+    /// # Arguments
+    /// * `item` - The order item to price
+    ///
+    /// # Returns
+    /// * `Option<Money>` - The price, or `None` if the item or a choice is not on the menu
+    pub fn price_item(&self, item: &OrderItem) -> Option<Money> {
+        let price = self
+            .price_breakdown(item)?
+            .into_iter()
+            .map(|(_, price)| price)
+            .sum();
+        debug!(
+            "Priced item {} (ID: {}) at {}",
+            item.item_name, item.id, price
+        );
+        Some(price)
+    }
+
+    /// Breaks the price of an order item down into the contribution of each chosen option value.
+    ///
+    /// # Arguments
+    /// * `item` - The order item to price
+    ///
+    /// # Returns
+    /// * `Option<Vec<(String, Money)>>` - A label such as `meal option: meal` or
+    ///   `customizations: 2x bacon` with its price for every chosen value, or `None` if the item
+    ///   or a choice is not on the menu
+    pub fn price_breakdown(&self, item: &OrderItem) -> Option<Vec<(String, Money)>> {
+        let menu_item = self.get_item(&item.item_name)?;
+        let mut choices = vec![];
+        for (option_key, option_values) in
+            Iterator::zip(item.option_keys.iter(), item.option_values.iter())
+        {
+            let option = menu_item.options.get(option_key)?;
+            for value in option_values {
+                choices.push((option_key, value, option.choices.get(&value.value)?));
+            }
+        }
+        let base = flat_price(
+            choices
+                .iter()
+                .map(|(_, value, choice)| (*choice, value.count)),
+        );
+        let lines = choices
+            .into_iter()
+            .map(|(option_key, value, choice)| {
+                (
+                    format!("{}: {}", option_key, value),
+                    choice.price_for(base) * value.count,
+                )
+            })
+            .collect();
+        Some(lines)
+    }
+
+    /// Validates an order item against the menu requirements.
+    ///
+    /// # Arguments
+    /// * `item` - The order item to validate
+    ///
+    /// # Returns
+    /// * `AppResult<ItemStatus>` - The validation status of the item
+    pub fn validate_item(&self, item: &OrderItem) -> AppResult<ItemStatus> {
+        let status = self.validate_options(item)?;
+        if !matches!(status, ItemStatus::Complete(_)) {
+            return Ok(status);
+        }
+        match self.get_item(&item.item_name) {
+            Some(menu_item) => self.validate_components(item, menu_item),
+            // NOTE(dev): Unreachable, validate_options rejects items missing from the menu
+            None => Ok(status),
+        }
+    }
+
+    /// Validates the components of a combo item, aggregating their statuses.
+    ///
+    /// An invalid component makes the combo invalid, otherwise an incomplete component or an
+    /// unfilled required slot makes it incomplete.
+    ///
+    /// # Arguments
+    /// * `item` - The combo order item
+    /// * `menu_item` - The menu definition of the combo
+    ///
+    /// # Returns
+    /// * `AppResult<ItemStatus>` - The aggregated validation status
+    fn validate_components(&self, item: &OrderItem, menu_item: &MenuItem) -> AppResult<ItemStatus> {
+        if item.components.is_empty() && menu_item.components.is_empty() {
+            return Ok(ItemStatus::Complete("Item is valid".to_string()));
+        }
+        debug!(
+            "Validating {} components for item {} (ID: {})",
+            item.components.len(),
+            item.item_name,
+            item.id
+        );
+
+        let mut incomplete = None;
+        let mut filled: HashMap<&str, usize> = HashMap::new();
+        for component in &item.components {
+            let slot = menu_item
+                .components
+                .iter()
+                .find(|slot| slot.items.contains(&component.item_name));
+            let slot = match slot {
+                Some(slot) => slot,
+                None => {
+                    info!(
+                        "Component {} is not part of {} (ID: {})",
+                        component.item_name, item.item_name, item.id
+                    );
+                    return Ok(ItemStatus::Invalid(format!(
+                        "{} is not a component of {}",
+                        component.item_name, item.item_name
+                    )));
+                }
+            };
+            *filled.entry(slot.name.as_str()).or_default() += 1;
+
+            match self.validate_item(component)? {
+                ItemStatus::Invalid(reason) => {
+                    return Ok(ItemStatus::Invalid(format!(
+                        "Component {} ({}): {}",
+                        slot.name, component.item_name, reason
+                    )));
+                }
+                ItemStatus::Incomplete(reason) => {
+                    incomplete.get_or_insert(format!(
+                        "Component {} ({}): {}",
+                        slot.name, component.item_name, reason
+                    ));
+                }
+                ItemStatus::Complete(_) => {}
+            }
+        }
+
+        for slot in &menu_item.components {
+            match filled.get(slot.name.as_str()) {
+                Some(count) if *count > 1 => {
+                    info!(
+                        "Component {} filled {} times for {} (ID: {})",
+                        slot.name, count, item.item_name, item.id
+                    );
+                    return Ok(ItemStatus::Invalid(format!(
+                        "Too many components for {}",
+                        slot.name
+                    )));
+                }
+                None if slot.required => {
+                    info!(
+                        "Missing component {} for {} (ID: {})",
+                        slot.name, item.item_name, item.id
+                    );
+                    incomplete.get_or_insert(format!(
+                        "Component missing {} (one of: {})",
+                        slot.name,
+                        slot.items.join(", ")
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(match incomplete {
+            Some(reason) => ItemStatus::Incomplete(reason),
+            None => ItemStatus::Complete("Item is valid".to_string()),
+        })
     }
 
-    /// Validates an order item against the menu requirements.
+    /// Validates the options of a single order item, ignoring its components.
     ///
     /// # Arguments
     /// * `item` - The order item to validate
     ///
     /// # Returns
     /// * `AppResult<ItemStatus>` - The validation status of the item
-    pub fn validate_item(&self, item: &OrderItem) -> AppResult<ItemStatus> {
+    fn validate_options(&self, item: &OrderItem) -> AppResult<ItemStatus> {
         // NOTE(dev): This function essentially provides hints to GPT on what is needs to be changed
         //            The wording/information could be improved/expanded to prompt GPT better
         debug!(
@@ -112,23 +1192,68 @@ impl Menu {
             ));
         }
 
-        let menu_item = self.items.iter().find(|i| i.item_name == item.item_name);
+        let mut seen_keys = BTreeSet::new();
+        if let Some(duplicate) = item.option_keys.iter().find(|key| !seen_keys.insert(*key)) {
+            info!(
+                "Invalid item: Duplicate option key '{}' for {} (ID: {})",
+                duplicate, item.item_name, item.id
+            );
+            return Ok(ItemStatus::Invalid(format!(
+                "Duplicate option key: {}",
+                duplicate
+            )));
+        }
+
+        let menu_item = self.get_item(&item.item_name);
         debug!("Found menu item definition: {}", menu_item.is_some());
 
-        for (option_key, option_values) in
-            Iterator::zip(item.option_keys.iter(), item.option_values.iter())
-        {
-            if menu_item.is_none() {
+        let menu_item = match menu_item {
+            Some(menu_item) => menu_item,
+            None => {
                 info!(
                     "Item not found in menu: {} (ID: {})",
                     item.item_name, item.id
                 );
+                if let ItemMatch::Ambiguous(candidates) = self.find_item(&item.item_name) {
+                    return Ok(ItemStatus::Invalid(format!(
+                        "Item name {} is ambiguous, use one of: {}",
+                        item.item_name,
+                        candidates.join(", ")
+                    )));
+                }
                 return Ok(ItemStatus::Invalid(format!(
                     "Item does not exist: {}",
                     item.item_name
                 )));
             }
-            let option = menu_item.unwrap().options.get(option_key);
+        };
+
+        if !menu_item.is_available() {
+            info!("Item unavailable: {} (ID: {})", item.item_name, item.id);
+            return Ok(ItemStatus::Invalid(format!(
+                "Item unavailable: {}",
+                item.item_name
+            )));
+        }
+
+        // NOTE(dev): Every problem is collected instead of returning at the first one, so the
+        //            model can fix the whole item in one follow-up
+        let mut invalid: Vec<String> = vec![];
+        let mut incomplete: Vec<String> = vec![];
+        let mut selected: Vec<(&Choice, &str)> = vec![];
+        // NOTE(dev): Unknown options and choices are reported below, they just add nothing here
+        let base = flat_price(item.option_keys.iter().zip(&item.option_values).flat_map(
+            |(key, values)| {
+                values.iter().filter_map(move |value| {
+                    let choice = menu_item.options.get(key)?.choices.get(&value.value)?;
+                    Some((choice, value.count))
+                })
+            },
+        ));
+        for (option_key, option_values) in
+            Iterator::zip(item.option_keys.iter(), item.option_values.iter())
+        {
+            let option = menu_item.options.get(option_key);
             debug!(
                 "Validating option '{}' for item {} (ID: {}). Option exists: {}",
                 option_key,
@@ -137,62 +1262,134 @@ impl Menu {
                 option.is_some()
             );
 
-            if option.is_none() {
+            let Some(option) = option else {
                 info!(
                     "Invalid option '{}' for item {} (ID: {})",
                     option_key, item.item_name, item.id
                 );
-                return Ok(ItemStatus::Invalid(format!(
-                    "Option does not exist: {}",
-                    option_key
-                )));
-            }
-            let option = option.unwrap();
+                invalid.push(format!("Option does not exist: {}", option_key));
+                continue;
+            };
 
             for value in option_values {
                 debug!(
                     "Checking value '{}' for option '{}' in item {} (ID: {})",
                     value, option_key, item.item_name, item.id
                 );
-                if !option.choices.contains_key(value) {
+                if value.count == 0 {
+                    info!(
+                        "Zero count for choice '{}' of option '{}' in item {} (ID: {})",
+                        value.value, option_key, item.item_name, item.id
+                    );
+                    invalid.push(format!(
+                        "Count must be at least 1 for option {}: {}",
+                        option_key, value.value
+                    ));
+                }
+                let Some(choice) = option.choices.get(&value.value) else {
                     info!(
                         "Invalid choice '{}' for option '{}' in item {} (ID: {})",
                         value, option_key, item.item_name, item.id
                     );
-                    return Ok(ItemStatus::Invalid(format!(
+                    invalid.push(format!(
                         "Invalid choice for option {}: {}",
                         option_key, value
-                    )));
+                    ));
+                    continue;
+                };
+                if !choice.is_available() {
+                    info!(
+                        "Unavailable choice '{}' for option '{}' in item {} (ID: {})",
+                        value, option_key, item.item_name, item.id
+                    );
+                    invalid.push(format!(
+                        "Choice unavailable for option {}: {}",
+                        option_key, value
+                    ));
                 }
+                selected.push((choice, value.value.as_str()));
             }
 
             debug!(
                 "Checking option count for '{}'. Min: {}, Max: {}, Current: {}",
                 option_key,
-                option.minimum,
-                option.maximum,
+                option.min_choices(),
+                option.max_choices(),
                 option_values.len()
             );
 
-            if option_values.len() < option.minimum as usize {
+            if option_values.len() < option.min_choices() as usize {
                 info!(
                     "Too few options for {} (ID: {}). Required: {}, Found: {}",
                     item.item_name,
                     item.id,
-                    option.minimum,
+                    option.min_choices(),
                     option_values.len()
                 );
-                return Ok(ItemStatus::Incomplete("Too few options".to_string()));
+                incomplete.push(format!(
+                    "Too few options for {} ({} needed)",
+                    option_key,
+                    option.count_description()
+                ));
             }
-            if option_values.len() > option.maximum as usize {
+            if option_values.len() > option.max_choices() as usize {
                 info!(
                     "Too many options for {} (ID: {}). Maximum: {}, Found: {}",
                     item.item_name,
                     item.id,
-                    option.maximum,
+                    option.max_choices(),
                     option_values.len()
                 );
-                return Ok(ItemStatus::Invalid("Too many options".to_string()));
+                invalid.push(format!(
+                    "Too many options for {} ({} allowed)",
+                    option_key,
+                    option.count_description()
+                ));
+            }
+            if let Some(max_upcharge) = option.max_upcharge {
+                let upcharge: Money = option_values
+                    .iter()
+                    .filter_map(|value| {
+                        option
+                            .choices
+                            .get(&value.value)
+                            .map(|choice| choice.price_for(base) * value.count)
+                    })
+                    .sum();
+                if upcharge > max_upcharge {
+                    info!(
+                        "Upcharge {} over the cap of {} for option '{}' in item {} (ID: {})",
+                        upcharge, max_upcharge, option_key, item.item_name, item.id
+                    );
+                    invalid.push(format!(
+                        "Choices for {} add {}, the most allowed is {}",
+                        option_key, upcharge, max_upcharge
+                    ));
+                }
+            }
+        }
+
+        let mut conflicts = BTreeSet::new();
+        for (choice, choice_name) in &selected {
+            for (_, excluded) in selected
+                .iter()
+                .filter(|(_, other)| choice.excludes.iter().any(|excluded| excluded == other))
+            {
+                let pair = if choice_name < excluded {
+                    (*choice_name, *excluded)
+                } else {
+                    (*excluded, *choice_name)
+                };
+                if conflicts.insert(pair) {
+                    info!(
+                        "Conflicting choices '{}' and '{}' for item {} (ID: {})",
+                        choice_name, excluded, item.item_name, item.id
+                    );
+                    invalid.push(format!(
+                        "{} can't be combined with {}",
+                        choice_name, excluded
+                    ));
+                }
             }
         }
 
@@ -200,7 +1397,7 @@ impl Menu {
             "Validating required options for item {} (ID: {})",
             item.item_name, item.id
         );
-        for (option_name, option_config) in menu_item.unwrap().options.iter() {
+        for (option_name, option_config) in menu_item.options.iter() {
             match &option_config.required {
                 RequirementConfig::Simple(true) => {
                     debug!(
@@ -212,10 +1409,7 @@ impl Menu {
                             "Missing required option '{}' for item {} (ID: {})",
                             option_name, item.item_name, item.id
                         );
-                        return Ok(ItemStatus::Incomplete(format!(
-                            "Required option missing {}",
-                            option_name
-                        )));
+                        incomplete.push(format!("Required option missing {}", option_name));
                     }
                 }
                 RequirementConfig::Dependent { option, value } => {
@@ -223,50 +1417,49 @@ impl Menu {
                         "Checking dependent option '{}' (depends on '{}' = '{}') for item {} (ID: {})",
                         option_name, option, value, item.item_name, item.id
                     );
-                    let item_option_index = item.option_keys.iter().position(|x| x == option_name);
-                    #[allow(clippy::single_match)]
-                    match item_option_index {
-                        None => {
-                            let dependent_option_index =
-                                item.option_keys.iter().position(|x| x == option);
-                            if dependent_option_index.is_none() {
-                                info!(
-                                    "Missing dependent option '{}' for item {} (ID: {})",
-                                    option, item.item_name, item.id
-                                );
-                                return Ok(ItemStatus::Incomplete(format!(
-                                    "Dependent option missing {}",
-                                    option
-                                )));
-                            }
+                    if item.option_keys.contains(option_name) {
+                        continue;
+                    }
 
-                            let dependent_option_value = item.option_values
-                                .get(dependent_option_index.unwrap())
-                                .expect("The dependent option value should exist if the dependent option exists");
+                    // NOTE(dev): The dependent option is only required once the triggering
+                    //            option has been chosen with the triggering value
+                    let triggered = is_chosen(item, option, value);
 
-                            debug!(
-                                "Checking dependent value '{}' against current values {:?}",
-                                value, dependent_option_value
-                            );
+                    debug!(
+                        "Dependent option '{}' triggered by '{}' = '{}': {}",
+                        option_name, option, value, triggered
+                    );
 
-                            if dependent_option_value.contains(value) {
-                                info!(
-                                    "Missing required dependent option '{}' for item {} (ID: {})",
-                                    option_name, item.item_name, item.id
-                                );
-                                return Ok(ItemStatus::Incomplete(format!(
-                                    "Dependent option missing {}",
-                                    option_name
-                                )));
-                            }
-                        }
-                        _ => {}
+                    if triggered {
+                        info!(
+                            "Missing required dependent option '{}' for item {} (ID: {})",
+                            option_name, item.item_name, item.id
+                        );
+                        incomplete.push(format!(
+                            "Dependent option missing {} (required when {} is {})",
+                            option_name, option, value
+                        ));
                     }
                 }
                 _ => {}
             }
         }
 
+        if !invalid.is_empty() {
+            // NOTE(dev): The missing options are listed too, the item needs both fixed anyway
+            invalid.append(&mut incomplete);
+            return Ok(ItemStatus::Invalid(join_problems(
+                &invalid,
+                self.max_validation_problems,
+            )));
+        }
+        if !incomplete.is_empty() {
+            return Ok(ItemStatus::Incomplete(join_problems(
+                &incomplete,
+                self.max_validation_problems,
+            )));
+        }
+
         debug!(
             "Validation successful for item {} (ID: {})",
             item.item_name, item.id
@@ -274,3 +1467,839 @@ impl Menu {
         Ok(ItemStatus::Complete("Item is valid".to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{item, menu};
+
+    #[test]
+    fn every_missing_required_option_is_reported_at_once() {
+        let menu = menu();
+        let sandwich = item("Grilled Organic Chicken Breast Sandwich", &[]);
+
+        let status = menu.validate_item(&sandwich).unwrap();
+
+        let ItemStatus::Incomplete(message) = status else {
+            panic!("expected Incomplete, got {:?}", status);
+        };
+        assert!(message.contains("Required option missing sauce"));
+        assert!(message.contains("Required option missing meal option"));
+        assert_eq!(message.matches("; ").count(), 1);
+    }
+
+    #[test]
+    fn validation_messages_are_capped_at_the_configured_problems() {
+        let menu = Menu {
+            max_validation_problems: 1,
+            ..menu()
+        };
+        let sandwich = item("Grilled Organic Chicken Breast Sandwich", &[]);
+
+        let status = menu.validate_item(&sandwich).unwrap();
+
+        let ItemStatus::Incomplete(message) = status else {
+            panic!("expected Incomplete, got {:?}", status);
+        };
+        assert!(message.starts_with("Required option missing "));
+        assert!(message.ends_with("; and 1 more"));
+    }
+
+    const SANDWICH: &str = "Grilled Organic Chicken Breast Sandwich";
+
+    #[test]
+    fn dependent_options_filled_in_after_their_trigger_are_complete() {
+        let menu = menu();
+        let meal = item(
+            SANDWICH,
+            &[
+                ("sauce", "mayo"),
+                ("meal option", "meal"),
+                ("side options", "regular fries"),
+                ("drink options", "coke"),
+            ],
+        );
+
+        let status = menu.validate_item(&meal).unwrap();
+
+        assert!(matches!(status, ItemStatus::Complete(_)), "{:?}", status);
+    }
+
+    #[test]
+    fn dependent_options_missing_after_their_trigger_are_incomplete() {
+        let menu = menu();
+        let meal = item(SANDWICH, &[("sauce", "mayo"), ("meal option", "meal")]);
+
+        let status = menu.validate_item(&meal).unwrap();
+
+        let ItemStatus::Incomplete(message) = status else {
+            panic!("expected Incomplete, got {:?}", status);
+        };
+        assert!(message
+            .contains("Dependent option missing side options (required when meal option is meal)"));
+        assert!(message.contains("Dependent option missing drink options"));
+    }
+
+    #[test]
+    fn dependent_options_are_not_required_without_their_trigger() {
+        let menu = menu();
+        let a_la_carte = item(
+            SANDWICH,
+            &[("sauce", "mayo"), ("meal option", "a la carte")],
+        );
+
+        let status = menu.validate_item(&a_la_carte).unwrap();
+
+        assert!(matches!(status, ItemStatus::Complete(_)), "{:?}", status);
+    }
+
+    /// The shipped menu with a change made to one of its items.
+    fn menu_with(item_name: &str, change: impl FnOnce(&mut MenuItem)) -> Menu {
+        let mut menu = menu();
+        let menu_item = menu
+            .items
+            .iter_mut()
+            .find(|menu_item| menu_item.item_name == item_name)
+            .unwrap();
+        change(menu_item);
+        menu
+    }
+
+    #[test]
+    fn unavailable_items_are_never_complete() {
+        let menu = menu_with("Coke", |coke| coke.available = Some(false));
+        let coke = item("Coke", &[("size", "small")]);
+
+        let status = menu.validate_item(&coke).unwrap();
+
+        assert!(
+            matches!(&status, ItemStatus::Invalid(message) if message == "Item unavailable: Coke"),
+            "{:?}",
+            status
+        );
+    }
+
+    #[test]
+    fn unavailable_choices_are_invalid() {
+        let menu = menu_with("Coke", |coke| {
+            let size = coke.options.get_mut("size").unwrap();
+            size.choices.get_mut("large").unwrap().available = Some(false);
+        });
+
+        let large = menu
+            .validate_item(&item("Coke", &[("size", "large")]))
+            .unwrap();
+        let small = menu
+            .validate_item(&item("Coke", &[("size", "small")]))
+            .unwrap();
+
+        assert!(
+            matches!(&large, ItemStatus::Invalid(message) if message.contains("Choice unavailable for option size: large")),
+            "{:?}",
+            large
+        );
+        assert!(matches!(small, ItemStatus::Complete(_)), "{:?}", small);
+    }
+
+    #[test]
+    fn availability_is_part_of_the_served_menu() {
+        let menu = menu_with("Coke", |coke| coke.available = Some(false));
+
+        let served = serde_json::to_value(&menu).unwrap();
+
+        let items = served["items"].as_array().unwrap();
+        let coke = items
+            .iter()
+            .find(|item| item["itemName"] == "Coke")
+            .unwrap();
+        let sprite = items
+            .iter()
+            .find(|item| item["itemName"] == "Sprite")
+            .unwrap();
+        assert_eq!(coke["available"], false);
+        assert_eq!(sprite["available"], true);
+    }
+
+    /// The shipped menu with a combo of a sandwich, a side and a drink.
+    fn combo_menu() -> Menu {
+        let mut menu = menu();
+        let combo: MenuItem = serde_json::from_value(serde_json::json!({
+            "itemName": "Sandwich Combo",
+            "itemType": "combo",
+            "description": "",
+            "options": {},
+            "components": [
+                { "name": "main", "items": [SANDWICH] },
+                { "name": "side", "items": ["Regular Fries"] },
+                { "name": "drink", "items": ["Coke", "Sprite"] },
+                { "name": "dessert", "items": ["Apple Pie"], "required": false },
+            ],
+        }))
+        .unwrap();
+        menu.items.push(combo);
+        menu
+    }
+
+    /// A combo filled with the given components.
+    fn combo(components: Vec<OrderItem>) -> OrderItem {
+        OrderItem {
+            components,
+            ..item("Sandwich Combo", &[])
+        }
+    }
+
+    /// The sandwich, a la carte.
+    fn sandwich() -> OrderItem {
+        item(
+            SANDWICH,
+            &[("sauce", "mayo"), ("meal option", "a la carte")],
+        )
+    }
+
+    /// Small regular fries.
+    fn fries() -> OrderItem {
+        item("Regular Fries", &[("size", "small")])
+    }
+
+    #[test]
+    fn combos_with_every_slot_filled_are_complete() {
+        let menu = combo_menu();
+        let meal = combo(vec![
+            sandwich(),
+            fries(),
+            item("Sprite", &[("size", "large")]),
+        ]);
+
+        let status = menu.validate_item(&meal).unwrap();
+
+        assert!(matches!(status, ItemStatus::Complete(_)), "{:?}", status);
+    }
+
+    #[test]
+    fn combos_with_an_incomplete_component_are_incomplete() {
+        let menu = combo_menu();
+        let meal = combo(vec![sandwich(), fries(), item("Coke", &[])]);
+
+        let status = menu.validate_item(&meal).unwrap();
+
+        let ItemStatus::Incomplete(message) = status else {
+            panic!("expected Incomplete, got {:?}", status);
+        };
+        assert_eq!(
+            message,
+            "Component drink (Coke): Required option missing size"
+        );
+    }
+
+    #[test]
+    fn combos_with_an_invalid_component_are_invalid() {
+        let menu = combo_menu();
+        let meal = combo(vec![sandwich(), fries(), item("Coke", &[("size", "huge")])]);
+
+        let status = menu.validate_item(&meal).unwrap();
+
+        assert!(
+            matches!(&status, ItemStatus::Invalid(message) if message.starts_with("Component drink (Coke): ")),
+            "{:?}",
+            status
+        );
+    }
+
+    #[test]
+    fn combos_report_missing_required_slots_only() {
+        let menu = combo_menu();
+        let meal = combo(vec![sandwich(), item("Coke", &[("size", "small")])]);
+
+        let status = menu.validate_item(&meal).unwrap();
+
+        let ItemStatus::Incomplete(message) = status else {
+            panic!("expected Incomplete, got {:?}", status);
+        };
+        assert_eq!(message, "Component missing side (one of: Regular Fries)");
+    }
+
+    #[test]
+    fn combos_refuse_foreign_and_repeated_components() {
+        let menu = combo_menu();
+        let foreign = combo(vec![sandwich(), fries(), item("Milk", &[])]);
+        let repeated = combo(vec![
+            sandwich(),
+            fries(),
+            item("Coke", &[("size", "small")]),
+            item("Sprite", &[("size", "small")]),
+        ]);
+
+        let foreign = menu.validate_item(&foreign).unwrap();
+        let repeated = menu.validate_item(&repeated).unwrap();
+
+        assert!(
+            matches!(&foreign, ItemStatus::Invalid(message) if message == "Milk is not a component of Sandwich Combo"),
+            "{:?}",
+            foreign
+        );
+        assert!(
+            matches!(&repeated, ItemStatus::Invalid(message) if message == "Too many components for drink"),
+            "{:?}",
+            repeated
+        );
+    }
+
+    #[test]
+    fn several_choices_are_priced_to_the_exact_cent() {
+        let menu = menu();
+        let meal = item(
+            SANDWICH,
+            &[
+                ("sauce", "mayo"),
+                ("meal option", "meal"),
+                ("side options", "chili cheese fries"),
+                ("drink options", "banana shake"),
+            ],
+        );
+
+        let price = menu.price_item(&meal).unwrap();
+
+        assert_eq!(price, Money::from_cents(1450 + 264 + 323));
+        assert_eq!(price.to_string(), "20.37");
+    }
+
+    #[test]
+    fn duplicate_option_keys_are_invalid() {
+        let menu = menu();
+        let coke = item("Coke", &[("size", "small"), ("size", "large")]);
+
+        let status = menu.validate_item(&coke).unwrap();
+
+        assert!(
+            matches!(&status, ItemStatus::Invalid(message) if message == "Duplicate option key: size"),
+            "{:?}",
+            status
+        );
+    }
+
+    #[test]
+    fn the_shipped_menu_is_consistent() {
+        assert!(menu().validate_config().is_ok());
+    }
+
+    #[test]
+    fn inconsistent_menus_are_refused_naming_the_item_and_option() {
+        fn size(coke: &mut MenuItem) -> &mut OptionConfig {
+            coke.options.get_mut("size").unwrap()
+        }
+        fn sides(sandwich: &mut MenuItem) -> &mut OptionConfig {
+            sandwich.options.get_mut("side options").unwrap()
+        }
+        let cases: Vec<(Menu, &str)> = vec![
+            (
+                menu_with("Coke", |coke| {
+                    size(coke).minimum = 2;
+                    size(coke).maximum = 1;
+                }),
+                "option size of item Coke has minimum 2 greater than maximum 1",
+            ),
+            (
+                menu_with("Coke", |coke| size(coke).choices.clear()),
+                "option size of item Coke has no choices",
+            ),
+            (
+                menu_with("Coke", |coke| size(coke).default = Some("huge".to_string())),
+                "default huge of option size of item Coke is not one of its choices",
+            ),
+            (
+                menu_with("Coke", |coke| {
+                    let small = size(coke).choices.get_mut("small").unwrap();
+                    small.excludes = vec!["diet".to_string()];
+                }),
+                "choice small of item Coke excludes diet, which is not one of its choices",
+            ),
+            (
+                menu_with(SANDWICH, |sandwich| {
+                    sides(sandwich).required = RequirementConfig::Dependent {
+                        option: "combo".to_string(),
+                        value: "meal".to_string(),
+                    }
+                }),
+                "option side options of item Grilled Organic Chicken Breast Sandwich depends on option combo which does not exist",
+            ),
+            (
+                menu_with(SANDWICH, |sandwich| {
+                    sides(sandwich).required = RequirementConfig::Dependent {
+                        option: "meal option".to_string(),
+                        value: "family".to_string(),
+                    }
+                }),
+                "depends on meal option being family, which is not one of its choices",
+            ),
+            (
+                menu_with("Coke", |coke| coke.upsells = vec!["Onion Rings XL".to_string()]),
+                "item Coke upsells item Onion Rings XL which does not exist",
+            ),
+            (
+                {
+                    let mut menu = menu();
+                    let coke = menu.get_item("Coke").unwrap().clone();
+                    menu.items.push(coke);
+                    menu
+                },
+                "item Coke is defined more than once",
+            ),
+        ];
+
+        for (menu, expected) in cases {
+            match menu.validate_config() {
+                Err(AppError::InvalidInput(message)) => {
+                    assert!(
+                        message.contains(expected),
+                        "{} should name {}",
+                        message,
+                        expected
+                    )
+                }
+                other => panic!("expected {} to be refused, got {:?}", expected, other),
+            }
+        }
+    }
+
+    #[test]
+    fn join_problems_lists_everything_without_a_limit() {
+        let problems = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(join_problems(&problems, 0), "a; b; c");
+        assert_eq!(join_problems(&problems, 3), "a; b; c");
+        assert_eq!(join_problems(&problems, 2), "a; b; and 1 more");
+    }
+
+    /// Settings reading the menu from a new file holding `content`.
+    fn settings_for(name: &str, content: &str, allow_empty: bool) -> MenuSettings {
+        let path = std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        MenuSettings {
+            path: path.to_string_lossy().into_owned(),
+            allow_empty,
+            max_validation_problems: 0,
+        }
+    }
+
+    #[test]
+    fn empty_menus_are_refused_unless_allowed() {
+        let refused = settings_for("empty-menu-refused", "[]", false);
+        assert!(matches!(
+            Menu::new(&refused),
+            Err(AppError::InvalidInput(_))
+        ));
+
+        let allowed = settings_for("empty-menu-allowed", "[]", true);
+        assert!(Menu::new(&allowed).unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn menus_are_read_from_the_configured_file() {
+        let settings = MenuSettings {
+            path: "static/menu.json".to_string(),
+            allow_empty: false,
+            max_validation_problems: 2,
+        };
+        let menu = Menu::new(&settings).unwrap();
+        assert_eq!(menu.items.len(), crate::testing::menu().items.len());
+        assert_eq!(menu.max_validation_problems, 2);
+
+        let missing = MenuSettings {
+            path: "static/missing.json".to_string(),
+            ..settings
+        };
+        assert!(matches!(
+            Menu::new(&missing),
+            Err(AppError::MenuFileMissing(path)) if path == "static/missing.json"
+        ));
+    }
+
+    #[test]
+    fn item_names_resolve_exactly_or_within_a_few_typos() {
+        let menu = menu();
+
+        for name in [
+            "Cheese Burger",
+            "cheese  burger",
+            "Cheeseburger",
+            "Cheese Burgr",
+        ] {
+            assert!(
+                matches!(menu.find_item(name), ItemMatch::Found(item) if item.item_name == "Cheese Burger"),
+                "{} must resolve to Cheese Burger",
+                name
+            );
+        }
+        // NOTE(dev): Short names get no tolerance, "Cake" must not become "Coke"
+        assert!(matches!(menu.find_item("Cake"), ItemMatch::NotFound));
+        assert!(matches!(menu.find_item("Pizza"), ItemMatch::NotFound));
+    }
+
+    #[test]
+    fn near_item_names_are_canonicalized_before_validation() {
+        let menu = menu();
+        let mut burger = item("cheese burgr", &[]);
+        assert!(matches!(
+            menu.validate_item(&burger).unwrap(),
+            ItemStatus::Invalid(_)
+        ));
+
+        menu.canonicalize_item(&mut burger);
+
+        assert_eq!(burger.item_name, "Cheese Burger");
+        assert!(!matches!(
+            menu.validate_item(&burger).unwrap(),
+            ItemStatus::Invalid(_)
+        ));
+    }
+
+    #[test]
+    fn item_names_close_to_several_items_are_ambiguous() {
+        let menu = menu();
+        let name = "Chicken Breast Nuggets (8 Pc)";
+
+        let ItemMatch::Ambiguous(candidates) = menu.find_item(name) else {
+            panic!("{} must be ambiguous", name);
+        };
+        assert_eq!(
+            candidates,
+            [
+                "Chicken Breast Nuggets (6 Pc)",
+                "Chicken Breast Nuggets (10 Pc)"
+            ]
+        );
+
+        let mut nuggets = item(name, &[]);
+        menu.canonicalize_item(&mut nuggets);
+        assert_eq!(nuggets.item_name, name);
+        let status = menu.validate_item(&nuggets).unwrap();
+        assert!(
+            matches!(&status, ItemStatus::Invalid(message) if message.contains("is ambiguous")
+                && message.contains("(6 Pc)")
+                && message.contains("(10 Pc)")),
+            "{:?}",
+            status
+        );
+    }
+
+    #[test]
+    fn exact_counts_refuse_too_few_and_too_many_choices() {
+        let menu = menu_with("Buffalo Chicken Wings", |wings| {
+            let sauce = wings.options.get_mut("sauce").unwrap();
+            (sauce.minimum, sauce.maximum, sauce.exact) = (0, 0, Some(2));
+        });
+        menu.validate_config().unwrap();
+        let wings = |sauces: &[&str]| {
+            let mut wings = item(
+                "Buffalo Chicken Wings",
+                &[
+                    ("size", "6 piece"),
+                    ("meal option", "a la carte"),
+                    ("sauce", "ranch"),
+                ],
+            );
+            wings.option_values[2] = sauces
+                .iter()
+                .map(|sauce| OptionValue::new(*sauce))
+                .collect();
+            wings
+        };
+
+        let under = menu.validate_item(&wings(&["ranch"])).unwrap();
+        assert!(
+            matches!(&under, ItemStatus::Incomplete(message)
+                if message.contains("Too few options for sauce (exactly 2 needed)")),
+            "{:?}",
+            under
+        );
+        let at = menu.validate_item(&wings(&["ranch", "buffalo"])).unwrap();
+        assert!(matches!(at, ItemStatus::Complete(_)), "{:?}", at);
+        let over = menu
+            .validate_item(&wings(&["ranch", "buffalo", "honey mustard"]))
+            .unwrap();
+        assert!(
+            matches!(&over, ItemStatus::Invalid(message)
+                if message.contains("Too many options for sauce (exactly 2 allowed)")),
+            "{:?}",
+            over
+        );
+
+        assert!(
+            menu.option_counts()
+                .contains("exactly 2 (Buffalo Chicken Wings)"),
+            "{}",
+            menu.option_counts()
+        );
+    }
+
+    #[test]
+    fn option_values_resolve_ignoring_case_and_through_aliases() {
+        let menu = menu_with("Cheese Burger", |burger| {
+            let customizations = burger.options.get_mut("customizations").unwrap();
+            customizations.choices.get_mut("onions").unwrap().aliases =
+                vec!["onion".to_string(), "Raw Onions".to_string()];
+        });
+        let burger = |customization: &str| {
+            let mut burger = item(
+                "Cheese Burger",
+                &[
+                    ("customizations", customization),
+                    ("meal option", "A La Carte"),
+                ],
+            );
+            menu.canonicalize_item(&mut burger);
+            burger
+        };
+
+        for (said, canonical) in [
+            ("onion", "onions"),
+            ("raw onions", "onions"),
+            ("Onions", "onions"),
+            ("  PICKLES ", "pickles"),
+        ] {
+            let burger = burger(said);
+            assert_eq!(burger.option_values[0][0].value, canonical, "{}", said);
+            assert_eq!(burger.option_values[1][0].value, "a la carte");
+            let status = menu.validate_item(&burger).unwrap();
+            assert!(
+                matches!(status, ItemStatus::Complete(_)),
+                "{}: {:?}",
+                said,
+                status
+            );
+        }
+
+        // NOTE(dev): Aliases only belong to their own choice, a near miss is still wrong
+        for wrong in ["onionz", "anchovies", "no onion"] {
+            let burger = burger(wrong);
+            assert_eq!(burger.option_values[0][0].value, wrong);
+            let status = menu.validate_item(&burger).unwrap();
+            assert!(
+                matches!(&status, ItemStatus::Invalid(message)
+                    if message.contains(&format!("Invalid choice for option customizations: {}", wrong))),
+                "{}: {:?}",
+                wrong,
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn missing_options_with_a_default_are_filled_in_and_complete() {
+        let menu = menu_with("Coke", |coke| {
+            coke.options.get_mut("size").unwrap().default = Some("medium".to_string());
+        });
+        let mut coke = item("Coke", &[]);
+
+        menu.canonicalize_item(&mut coke);
+
+        assert_eq!(coke.option_keys, ["size"]);
+        assert_eq!(coke.option_values[0][0].value, "medium");
+        let status = menu.validate_item(&coke).unwrap();
+        assert!(matches!(status, ItemStatus::Complete(_)), "{:?}", status);
+        assert_eq!(menu.price_item(&coke), Some(Money::from_cents(350)));
+
+        // NOTE(dev): A choice the customer made is never replaced by the default
+        let mut small = item("Coke", &[("size", "small")]);
+        menu.canonicalize_item(&mut small);
+        assert_eq!(small.option_values, [[OptionValue::new("small")]]);
+    }
+
+    #[test]
+    fn options_without_a_default_are_left_to_the_customer() {
+        let unavailable = menu_with("Coke", |coke| {
+            let size = coke.options.get_mut("size").unwrap();
+            size.default = Some("medium".to_string());
+            size.choices.get_mut("medium").unwrap().available = Some(false);
+        });
+        for menu in [menu(), unavailable] {
+            let mut coke = item("Coke", &[]);
+
+            menu.canonicalize_item(&mut coke);
+
+            assert!(coke.option_keys.is_empty());
+            let status = menu.validate_item(&coke).unwrap();
+            assert!(matches!(status, ItemStatus::Incomplete(_)), "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn dependent_defaults_are_filled_once_triggered() {
+        let menu = menu_with("Cheese Burger", |burger| {
+            let sides = burger.options.get_mut("side options").unwrap();
+            sides.default = Some("regular fries".to_string());
+        });
+        let burger = |meal_option: &str| {
+            let mut burger = item(
+                "Cheese Burger",
+                &[("customizations", "ketchup"), ("meal option", meal_option)],
+            );
+            menu.canonicalize_item(&mut burger);
+            burger
+        };
+
+        let a_la_carte = burger("a la carte");
+        assert_eq!(a_la_carte.option_keys, ["customizations", "meal option"]);
+
+        let meal = burger("meal");
+        assert_eq!(
+            meal.option_keys,
+            ["customizations", "meal option", "side options"]
+        );
+        assert_eq!(meal.option_values[2][0].value, "regular fries");
+        let status = menu.validate_item(&meal).unwrap();
+        assert!(
+            matches!(&status, ItemStatus::Incomplete(message)
+                if message.contains("drink options") && !message.contains("side options")),
+            "{:?}",
+            status
+        );
+    }
+
+    #[test]
+    fn percent_choices_are_taken_of_the_flat_priced_choices() {
+        let menu = menu_with("Cheese Burger", |burger| {
+            let customizations = burger.options.get_mut("customizations").unwrap();
+            for (choice, percent) in [("bacon", 10), ("mushrooms", 15)] {
+                let choice = customizations.choices.get_mut(choice).unwrap();
+                (choice.price, choice.price_type) =
+                    (Money::from_cents(percent * 100), PriceType::Percent);
+            }
+        });
+        let mut burger = item(
+            "Cheese Burger",
+            &[
+                ("customizations", "american cheese"),
+                ("meal option", "a la carte"),
+            ],
+        );
+        burger.option_values[0].extend([OptionValue::new("bacon"), OptionValue::new("mushrooms")]);
+
+        let breakdown = menu.price_breakdown(&burger).unwrap();
+
+        // NOTE(dev): 10% and 15% of 9.88 + 1.23, rounded half away from zero and not compounded
+        assert_eq!(
+            breakdown,
+            [
+                (
+                    "customizations: american cheese".to_string(),
+                    Money::from_cents(123)
+                ),
+                ("customizations: bacon".to_string(), Money::from_cents(111)),
+                (
+                    "customizations: mushrooms".to_string(),
+                    Money::from_cents(167)
+                ),
+                (
+                    "meal option: a la carte".to_string(),
+                    Money::from_cents(988)
+                ),
+            ]
+        );
+        assert_eq!(menu.price_item(&burger), Some(Money::from_cents(1389)));
+    }
+
+    #[test]
+    fn choices_are_flat_unless_the_menu_says_percent() {
+        let flat: Choice = serde_json::from_value(serde_json::json!({ "price": 1.5 })).unwrap();
+        let percent: Choice =
+            serde_json::from_value(serde_json::json!({ "price": 10, "priceType": "percent" }))
+                .unwrap();
+
+        assert_eq!(flat.price_type, PriceType::Flat);
+        assert_eq!(
+            flat.price_for(Money::from_cents(2000)),
+            Money::from_cents(150)
+        );
+        assert_eq!(percent.price_type, PriceType::Percent);
+        assert_eq!(
+            percent.price_for(Money::from_cents(2000)),
+            Money::from_cents(200)
+        );
+        assert!(serde_json::to_value(&flat)
+            .unwrap()
+            .get("priceType")
+            .is_none());
+    }
+
+    /// A Cheese Burger with `count` american cheese.
+    fn burger_with_cheese(count: u32) -> OrderItem {
+        let mut burger = item(
+            "Cheese Burger",
+            &[
+                ("customizations", "american cheese"),
+                ("meal option", "a la carte"),
+            ],
+        );
+        burger.option_values[0][0].count = count;
+        burger
+    }
+
+    #[test]
+    fn upcharges_up_to_the_cap_are_allowed() {
+        // NOTE(dev): Two american cheese at 1.23 each add exactly the cap
+        let menu = menu_with("Cheese Burger", |burger| {
+            burger
+                .options
+                .get_mut("customizations")
+                .unwrap()
+                .max_upcharge = Some("2.46".parse().unwrap());
+        });
+
+        for count in [1, 2] {
+            let status = menu.validate_item(&burger_with_cheese(count)).unwrap();
+            assert!(matches!(status, ItemStatus::Complete(_)), "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn upcharges_above_the_cap_are_invalid() {
+        let menu = menu_with("Cheese Burger", |burger| {
+            burger
+                .options
+                .get_mut("customizations")
+                .unwrap()
+                .max_upcharge = Some("2.46".parse().unwrap());
+        });
+
+        let status = menu.validate_item(&burger_with_cheese(3)).unwrap();
+
+        let ItemStatus::Invalid(message) = status else {
+            panic!("expected Invalid, got {:?}", status);
+        };
+        assert_eq!(
+            message,
+            "Choices for customizations add 3.69, the most allowed is 2.46"
+        );
+    }
+
+    #[test]
+    fn excluded_choices_are_invalid_together_but_not_with_others() {
+        let menu = menu_with("Cheese Burger", |burger| {
+            let customizations = burger.options.get_mut("customizations").unwrap();
+            let american = customizations.choices.get_mut("american cheese").unwrap();
+            american.excludes = vec!["swiss cheese".to_string()];
+        });
+        let burger_with = |other: &str| {
+            let mut burger = burger_with_cheese(1);
+            burger.option_values[0].push(OptionValue::new(other));
+            burger
+        };
+
+        let status = menu.validate_item(&burger_with("swiss cheese")).unwrap();
+        let ItemStatus::Invalid(message) = status else {
+            panic!("expected Invalid, got {:?}", status);
+        };
+        assert_eq!(
+            message,
+            "american cheese can't be combined with swiss cheese"
+        );
+
+        let status = menu.validate_item(&burger_with("bacon")).unwrap();
+        assert!(matches!(status, ItemStatus::Complete(_)), "{:?}", status);
+
+        assert_eq!(
+            menu.exclusions().unwrap(),
+            "american cheese with swiss cheese (Cheese Burger)"
+        );
+    }
+}
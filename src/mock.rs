@@ -0,0 +1,234 @@
+use async_openai::types::{FunctionCall, RunCompletionUsage};
+use async_trait::async_trait;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info};
+
+use crate::chat::{handle_function_call, ChatMessage, ChatRole, FunctionResult};
+use crate::error::{AppError, AppResult};
+use crate::functions::{AssistantBackend, AssistantInfo, ComponentArgs, FunctionName};
+use crate::menu::Menu;
+use crate::money::Money;
+use crate::order::{Order, OrderItem, OrderLimits, OrderStore};
+
+/// Reply given to inputs the mock assistant doesn't understand
+const UNKNOWN_INPUT_REPLY: &str =
+    "Sorry, in test mode I can only add, remove, list or clear items.";
+
+/// Deterministic stand-in for the OpenAI assistant, enabled with `OPENAI_MOCK=1`
+///
+/// Maps a few canned inputs to the same function calls the assistant would make, so the API
+/// can be exercised end to end without network calls:
+/// * `add <item name>` - `add_item` without options, priced from the menu
+/// * `remove <item name>` - `remove_item` by name
+/// * `list` - `list_items`
+/// * `clear` - `clear_order`
+///
+/// Threads are kept in memory the way OpenAI keeps them: the customer's message is added for
+/// each turn and the run adds its reply.
+#[derive(Debug, Clone)]
+pub struct MockAssistant {
+    order_limits: OrderLimits,
+    threads: Arc<Mutex<HashMap<String, Vec<ChatMessage>>>>,
+    cancelled_threads: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockAssistant {
+    /// Creates a new MockAssistant instance.
+    ///
+    /// # Arguments
+    /// * `order_limits` - Limits applied to the orders it changes
+    pub fn new(order_limits: OrderLimits) -> Self {
+        info!("Using mock assistant, OpenAI will not be called");
+        Self {
+            order_limits,
+            threads: Arc::default(),
+            cancelled_threads: Arc::default(),
+        }
+    }
+
+    /// Threads whose active run was asked to be cancelled, oldest first. Clones share the list.
+    ///
+    /// # Returns
+    /// * `AppResult<Vec<String>>` - The thread IDs, once per cancellation
+    pub fn cancelled_threads(&self) -> AppResult<Vec<String>> {
+        Ok(self.cancelled_threads.lock()?.clone())
+    }
+
+    /// Picks the function call the assistant would make for an input.
+    ///
+    /// # Arguments
+    /// * `message` - The user's message
+    /// * `menu` - The restaurant menu, used to price added items
+    ///
+    /// # Returns
+    /// * `Option<FunctionCall>` - The function call, or None if the input isn't understood
+    fn function_call(message: &str, menu: &Menu) -> Option<FunctionCall> {
+        let message = message.trim();
+        let lowercase = message.to_lowercase();
+        let (name, arguments) = if lowercase == "list" {
+            (FunctionName::ListItems, json!({}))
+        } else if lowercase == "clear" {
+            (FunctionName::ClearOrder, json!({}))
+        } else if lowercase.starts_with("add ") {
+            let item_name = message["add ".len()..].trim();
+            let price = menu
+                .price_item(&OrderItem::from(&ComponentArgs {
+                    item_name: item_name.to_string(),
+                    option_keys: None,
+                    option_values: None,
+                }))
+                .unwrap_or(Money::ZERO);
+            (
+                FunctionName::AddItem,
+                json!({
+                    "item": {
+                        "itemName": item_name,
+                        "optionKeys": [],
+                        "optionValues": [],
+                        "price": price,
+                        "notes": null,
+                        "cartLabel": null,
+                    }
+                }),
+            )
+        } else if lowercase.starts_with("remove ") {
+            let item_name = message["remove ".len()..].trim();
+            (
+                FunctionName::RemoveItem,
+                json!({ "orderId": null, "itemName": item_name }),
+            )
+        } else {
+            return None;
+        };
+        Some(FunctionCall {
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        })
+    }
+
+    /// Describes the order after a function call, as the assistant's reply.
+    ///
+    /// # Arguments
+    /// * `output` - The tool output of the function call
+    /// * `order` - The order after the call
+    ///
+    /// # Returns
+    /// * `String` - The reply
+    fn reply(output: &str, order: &Order) -> String {
+        if let Ok(result) = serde_json::from_str::<FunctionResult>(output) {
+            if !result.success {
+                return result
+                    .message
+                    .or(result.error)
+                    .unwrap_or_else(|| UNKNOWN_INPUT_REPLY.to_string());
+            }
+        }
+        if order.order.is_empty() {
+            return "Your order is empty.".to_string();
+        }
+        let items: Vec<String> = order
+            .order
+            .iter()
+            .map(|item| item.item_name.clone())
+            .collect();
+        format!(
+            "Your order: {}. Your total is {}.",
+            items.join(", "),
+            order.total()
+        )
+    }
+}
+
+#[async_trait]
+impl AssistantBackend for MockAssistant {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    async fn thread_messages(&self, thread_id: &str) -> AppResult<Vec<ChatMessage>> {
+        self.threads
+            .lock()?
+            .get(thread_id)
+            .cloned()
+            .ok_or_else(|| AppError::InvalidInput(format!("Thread {} does not exist", thread_id)))
+    }
+
+    async fn handle_message(
+        &self,
+        message: &str,
+        location: &str,
+        _language: Option<&str>,
+        order: &mut Order,
+        menu: &Menu,
+    ) -> AppResult<Option<RunCompletionUsage>> {
+        info!(
+            "Processing message with mock assistant for Order ID: {}",
+            order.order_id
+        );
+        if order.thread_id.is_none() {
+            order.messages.push(ChatMessage {
+                role: ChatRole::Assistant.to_string(),
+                content: format!("Welcome to {}, what can I get started for you", location),
+            });
+            order.thread_id = Some(format!("mock-{}", order.order_id));
+        }
+        let user_message = ChatMessage {
+            role: ChatRole::User.to_string(),
+            content: message.to_owned(),
+        };
+        order.messages.push(user_message.clone());
+
+        let reply = match Self::function_call(message, menu) {
+            Some(function_call) => {
+                debug!("Mock assistant calling {}", function_call.name);
+                let output =
+                    handle_function_call(&function_call, menu, &self.order_limits, order).await?;
+                Self::reply(&output, order)
+            }
+            None => UNKNOWN_INPUT_REPLY.to_string(),
+        };
+        let reply = ChatMessage {
+            role: ChatRole::Assistant.to_string(),
+            content: reply,
+        };
+        if let Some(thread_id) = &order.thread_id {
+            self.threads
+                .lock()?
+                .entry(thread_id.clone())
+                .or_default()
+                .extend([user_message, reply.clone()]);
+        }
+        order.messages.push(reply);
+        Ok(None)
+    }
+
+    async fn reload(
+        &self,
+        _menu: &Menu,
+        _store: &OrderStore,
+    ) -> AppResult<Box<dyn AssistantBackend>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    async fn delete_thread(&self, thread_id: &str) -> AppResult<()> {
+        debug!("Deleting mock thread {}", thread_id);
+        self.threads.lock()?.remove(thread_id);
+        Ok(())
+    }
+
+    async fn cancel_active_run(&self, thread_id: &str) -> AppResult<Option<String>> {
+        debug!("Mock thread {} has no run to cancel", thread_id);
+        self.cancelled_threads.lock()?.push(thread_id.to_string());
+        Ok(None)
+    }
+
+    fn info(&self) -> Option<AssistantInfo> {
+        Some(AssistantInfo {
+            assistant_id: "mock".to_string(),
+            model: "mock".to_string(),
+            menu_hash: None,
+        })
+    }
+}
@@ -0,0 +1,229 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul, Sub};
+use std::str::FromStr;
+
+use crate::error::AppError;
+
+/// An exact amount of money, stored as integer cents
+///
+/// Serialized as a decimal string (e.g. `"9.99"`) so that clients never see float drift.
+/// Deserializes from either a decimal string or a JSON number, since the menu file and the
+/// assistant's tool calls use plain numbers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    /// Zero amount
+    pub const ZERO: Money = Money(0);
+
+    /// Creates an amount from integer cents.
+    ///
+    /// # Arguments
+    /// * `cents` - The amount in cents
+    ///
+    /// # Returns
+    /// * `Self` - The amount
+    pub const fn from_cents(cents: i64) -> Self {
+        Money(cents)
+    }
+
+    /// The amount in cents.
+    ///
+    /// # Returns
+    /// * `i64` - The amount in cents
+    pub const fn cents(&self) -> i64 {
+        self.0
+    }
+
+    /// Creates an amount from a floating point number of whole units, rounded to the nearest cent.
+    ///
+    /// # Arguments
+    /// * `amount` - The amount in whole units, e.g. `9.99`
+    ///
+    /// # Returns
+    /// * `Self` - The amount
+    pub fn from_f64(amount: f64) -> Self {
+        Money((amount * 100.0).round() as i64)
+    }
+
+    /// Takes a percentage of the amount, rounded half away from zero to the nearest cent.
+    ///
+    /// # Arguments
+    /// * `percent` - The percentage, written like an amount, e.g. `10.5` for 10.5%
+    ///
+    /// # Returns
+    /// * `Self` - The share of the amount
+    pub fn percent(&self, percent: Money) -> Self {
+        let scaled = i128::from(self.0) * i128::from(percent.0);
+        Money(((scaled + scaled.signum() * 5_000) / 10_000) as i64)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let cents = self.0.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, cents / 100, cents % 100)
+    }
+}
+
+impl FromStr for Money {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppError::InvalidInput(format!("Invalid amount: {}", s));
+        let trimmed = s.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, trimmed),
+        };
+        let (units, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if units.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+        // NOTE(dev): Digits only, `i64::parse` would take a second sign as in "--5" or "-+5"
+        if fraction.len() > 2
+            || !fraction.chars().all(|c| c.is_ascii_digit())
+            || !units.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let units: i64 = if units.is_empty() {
+            0
+        } else {
+            units.parse().map_err(|_| invalid())?
+        };
+        let fraction: i64 = format!("{:0<2}", fraction).parse().map_err(|_| invalid())?;
+        let cents = units
+            .checked_mul(100)
+            .and_then(|cents| cents.checked_add(fraction))
+            .ok_or_else(invalid)?;
+        Ok(Money(if negative { -cents } else { cents }))
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Amount {
+            Number(f64),
+            Text(String),
+        }
+
+        match Amount::deserialize(deserializer)? {
+            Amount::Number(amount) => Ok(Money::from_f64(amount)),
+            Amount::Text(amount) => amount
+                .parse()
+                .map_err(|_| de::Error::custom(format!("invalid amount: {}", amount))),
+        }
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl Mul<u32> for Money {
+    type Output = Money;
+
+    fn mul(self, count: u32) -> Money {
+        Money(self.0 * i64::from(count))
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_are_parsed_and_written_as_exact_decimals() {
+        assert_eq!("9.99".parse::<Money>().unwrap(), Money::from_cents(999));
+        assert_eq!("3.5".parse::<Money>().unwrap(), Money::from_cents(350));
+        assert_eq!("-.5".parse::<Money>().unwrap(), Money::from_cents(-50));
+        assert!("1.999".parse::<Money>().is_err());
+        assert!("abc".parse::<Money>().is_err());
+        assert!("--5".parse::<Money>().is_err());
+        assert!("-+5".parse::<Money>().is_err());
+        assert!("+5".parse::<Money>().is_err());
+        assert!("-".parse::<Money>().is_err());
+        assert_eq!(Money::from_cents(-105).to_string(), "-1.05");
+        assert_eq!(Money::from_cents(7).to_string(), "0.07");
+    }
+
+    #[test]
+    fn amounts_are_serialized_as_strings_and_read_from_numbers_or_strings() {
+        let amount: Money = serde_json::from_str("8.5").unwrap();
+        assert_eq!(amount, Money::from_cents(850));
+        let amount: Money = serde_json::from_str("\"2.64\"").unwrap();
+        assert_eq!(amount, Money::from_cents(264));
+        assert_eq!(serde_json::to_string(&amount).unwrap(), "\"2.64\"");
+    }
+
+    #[test]
+    fn sums_do_not_drift() {
+        let prices = ["0.1", "0.2", "2.64", "3.23", "1.18"].map(|price| price.parse().unwrap());
+        let total: Money = prices.into_iter().sum();
+        assert_eq!(total, Money::from_cents(735));
+        assert_eq!(
+            (Money::from_f64(0.1) + Money::from_f64(0.2)).to_string(),
+            "0.30"
+        );
+        assert_eq!(Money::from_cents(333) * 3, Money::from_cents(999));
+        assert_eq!(
+            Money::from_cents(1999).percent(Money::from_cents(1050)),
+            Money::from_cents(210)
+        );
+    }
+
+    #[test]
+    fn amounts_too_large_for_cents_are_refused() {
+        assert_eq!(
+            "92233720368547758.07".parse::<Money>().unwrap(),
+            Money::from_cents(i64::MAX)
+        );
+        for amount in [
+            "99999999999999999",
+            "92233720368547758.08",
+            "-99999999999999999",
+        ] {
+            assert!(
+                matches!(amount.parse::<Money>(), Err(AppError::InvalidInput(_))),
+                "{}",
+                amount
+            );
+        }
+    }
+}
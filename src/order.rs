@@ -1,11 +1,318 @@
-use redis::{Client, Commands, Connection};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use r2d2::{Pool, PooledConnection};
+use redis::{Client, Commands, RedisError};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
 use std::fmt;
-use tracing::{debug, info};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as TokioMutex, OwnedMutexGuard};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
 
-use crate::chat::ChatMessage;
+use crate::chat::{ChatMessage, ChatRole, OrderSummary};
+use crate::config::Config;
 use crate::error::{AppError, AppResult};
-use crate::menu::ItemStatus;
+use crate::menu::{ItemStatus, Menu};
+use crate::money::Money;
+
+/// Redis connection checked out of the `OrderStore` pool
+pub type RedisConnection = PooledConnection<Client>;
+
+/// Prefix of the Redis keys orders are stored under
+pub const ORDER_KEY_PREFIX: &str = "order:";
+
+/// Prefix of the Redis keys completed orders are archived under
+// NOTE(dev): Kept outside of `order:` so archived orders never show up when scanning the working set
+pub const ARCHIVE_KEY_PREFIX: &str = "archive:order:";
+
+/// Redis sorted set indexing archived orders by completion time
+pub const ARCHIVE_INDEX_KEY: &str = "archive:orders";
+
+/// Prefix of the Redis lists holding the audit trail of each order
+// NOTE(dev): Kept outside of `order:` for the same reason as the archive
+pub const AUDIT_KEY_PREFIX: &str = "audit:order:";
+
+/// Prefix of the Redis keys holding the OpenAI thread of each active order
+// NOTE(dev): Kept outside of `order:` so the thread can still be found once the order expired
+pub const THREAD_INDEX_KEY_PREFIX: &str = "thread:order:";
+
+/// Seconds a thread index outlives the order it belongs to, in case nobody was listening when
+/// the order expired
+const THREAD_INDEX_GRACE_SECONDS: usize = 24 * 60 * 60;
+
+/// Redis list collecting orders stuck on items that kept failing validation, for operators to
+/// find menu and model mismatches
+pub const DEAD_LETTER_KEY: &str = "dead_letter:orders";
+
+/// Reply added once an order has had invalid items for `MAX_INVALID_TURNS` turns in a row
+pub const HANDOFF_REPLY: &str =
+    "I'm having trouble getting that item right. Let me get a team member to help you.";
+
+/// Number of previous item lists kept on an order for undo
+pub const MAX_UNDO_SNAPSHOTS: usize = 5;
+
+/// Seconds an idempotency key keeps pointing at the order it started
+pub const IDEMPOTENCY_KEY_TTL_SECONDS: usize = 24 * 60 * 60;
+
+/// Limits applied to every order, read from the environment by `Config`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderLimits {
+    /// Largest number of items an order can hold, from `MAX_ITEMS_PER_ORDER`
+    pub max_items: usize,
+    /// Number of chat messages kept on an order and shown to the model, 0 for no limit, from
+    /// `MAX_THREAD_MESSAGES`
+    pub max_thread_messages: usize,
+    /// Seconds an active order is kept after its last change, 0 to keep it until it is
+    /// completed, from `ORDER_TTL_SECONDS`
+    pub ttl_seconds: usize,
+    /// Chat turns in a row an order may end with invalid items before it is handed to a human,
+    /// 0 to never hand it over, from `MAX_INVALID_TURNS`
+    pub max_invalid_turns: u32,
+    /// Prompt and completion tokens an order may use across all runs, 0 for no limit, from
+    /// `MAX_TOKENS_PER_ORDER`
+    pub max_tokens: u64,
+    /// Preparation seconds of a menu item that sets no `prepSeconds`, from
+    /// `DEFAULT_PREP_SECONDS`
+    pub default_prep_seconds: u32,
+    /// How the preparation times of an order's items add up to its ETA, from `ETA_MODE`
+    pub eta_mode: EtaMode,
+}
+
+/// How the preparation times of an order's items add up to its ETA
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum EtaMode {
+    /// Items are prepared in parallel, the order is ready when the slowest item is
+    #[default]
+    Max,
+    /// Items are prepared one after another
+    Sum,
+}
+
+impl std::str::FromStr for EtaMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "max" => Ok(EtaMode::Max),
+            "sum" => Ok(EtaMode::Sum),
+            other => Err(format!("Unknown ETA mode: {}", other)),
+        }
+    }
+}
+
+impl EtaMode {
+    /// Combines preparation times according to the mode.
+    ///
+    /// # Arguments
+    /// * `seconds` - Preparation times to combine
+    ///
+    /// # Returns
+    /// * `u32` - The slowest time with `Max`, the total with `Sum`
+    fn combine(self, seconds: impl Iterator<Item = u32>) -> u32 {
+        match self {
+            EtaMode::Max => seconds.max().unwrap_or(0),
+            EtaMode::Sum => seconds.fold(0, u32::saturating_add),
+        }
+    }
+}
+
+/// Lifecycle status of an order
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    /// Order is still being built through chat
+    #[default]
+    Active,
+    /// Order has been checked out and is final
+    Completed,
+    /// Order was abandoned and can no longer be changed
+    Cancelled,
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderStatus::Active => write!(f, "active"),
+            OrderStatus::Completed => write!(f, "completed"),
+            OrderStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// Record of a function call made on an order, kept permanently for dispute resolution
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Time at which the call was made
+    pub timestamp: DateTime<Utc>,
+    /// Name of the function called
+    pub function: String,
+    /// Arguments of the call, as a string if they were not valid JSON
+    pub arguments: serde_json::Value,
+    /// IDs of the items added, changed or removed by the call
+    #[serde(rename = "itemIds")]
+    pub item_ids: Vec<String>,
+    /// Whether the call succeeded
+    pub success: bool,
+}
+
+/// Order handed to a human after its items kept failing validation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterEntry {
+    /// Time at which the order was handed over
+    pub timestamp: DateTime<Utc>,
+    /// The ID of the order
+    #[serde(rename = "orderId")]
+    pub order_id: String,
+    /// The restaurant location of the order
+    pub location: Option<String>,
+    /// Chat turns in a row that ended with invalid items
+    #[serde(rename = "invalidTurns")]
+    pub invalid_turns: u32,
+    /// The invalid items with their status messages
+    pub items: Vec<String>,
+}
+
+/// Number of keys requested from Redis per SCAN call when reading every order
+const SCAN_COUNT: usize = 100;
+
+/// Cursor over every order in the working set and the archive, a SCAN page at a time
+///
+/// Orders still under legacy unprefixed keys are not visited. Unreadable orders are logged and
+/// skipped.
+#[derive(Debug)]
+pub struct OrderScan {
+    /// Key patterns still to scan, the current one first
+    patterns: Vec<String>,
+    /// SCAN cursor within the current pattern
+    cursor: u64,
+}
+
+impl Default for OrderScan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderScan {
+    /// Starts a scan over the working set and the archive.
+    ///
+    /// # Returns
+    /// * `Self` - A scan positioned before the first order
+    pub fn new() -> Self {
+        Self {
+            patterns: [ORDER_KEY_PREFIX, ARCHIVE_KEY_PREFIX]
+                .iter()
+                .map(|prefix| format!("{}*", prefix))
+                .collect(),
+            cursor: 0,
+        }
+    }
+
+    /// Reads the orders of the next non-empty SCAN page.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    ///
+    /// # Returns
+    /// * `AppResult<Option<Vec<Order>>>` - The orders of the page, or None once every key was visited
+    pub fn next_page(&mut self, conn: &mut RedisConnection) -> AppResult<Option<Vec<Order>>> {
+        while let Some(pattern) = self.patterns.first() {
+            debug!("Scanning {} from cursor {}", pattern, self.cursor);
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .cursor_arg(self.cursor)
+                .arg("MATCH")
+                .arg(pattern)
+                .arg("COUNT")
+                .arg(SCAN_COUNT)
+                .query(&mut **conn)?;
+            if next == 0 {
+                self.patterns.remove(0);
+            }
+            self.cursor = next;
+            if keys.is_empty() {
+                continue;
+            }
+            let orders_json: Vec<Option<String>> =
+                redis::cmd("MGET").arg(&keys).query(&mut **conn)?;
+            let orders = orders_json
+                .into_iter()
+                .flatten()
+                .filter_map(|json| match serde_json::from_str::<Order>(&json) {
+                    Ok(order) => Some(order),
+                    Err(e) => {
+                        warn!("Skipping unreadable order in scan: {}", e);
+                        None
+                    }
+                })
+                .collect();
+            return Ok(Some(orders));
+        }
+        Ok(None)
+    }
+}
+
+/// Aggregate counts over every stored order
+#[derive(Debug, Default)]
+pub struct OrderStats {
+    /// Orders still being built
+    pub active: usize,
+    /// Orders completed and archived
+    pub completed: usize,
+    /// Orders abandoned before completion
+    pub cancelled: usize,
+    /// Items across every order
+    pub items: usize,
+    /// Number of times each item name was ordered
+    pub item_counts: HashMap<String, usize>,
+}
+
+impl OrderStats {
+    /// Adds an order to the counts.
+    ///
+    /// # Arguments
+    /// * `order` - The order to count
+    fn record(&mut self, order: &Order) {
+        match order.status {
+            OrderStatus::Active => self.active += 1,
+            OrderStatus::Completed => self.completed += 1,
+            OrderStatus::Cancelled => self.cancelled += 1,
+        }
+        self.items += order.item_count();
+        for item in &order.order {
+            *self.item_counts.entry(item.item_name.clone()).or_default() += item.quantity as usize;
+        }
+    }
+
+    /// Average number of items per order.
+    ///
+    /// # Returns
+    /// * `f64` - The average, 0 if there are no orders
+    pub fn average_items(&self) -> f64 {
+        let orders = self.active + self.completed + self.cancelled;
+        if orders == 0 {
+            return 0.0;
+        }
+        self.items as f64 / orders as f64
+    }
+
+    /// Most ordered item names, ties broken alphabetically.
+    ///
+    /// # Arguments
+    /// * `limit` - Maximum number of items to return
+    ///
+    /// # Returns
+    /// * `Vec<(String, usize)>` - Item names and how often they were ordered
+    pub fn top_items(&self, limit: usize) -> Vec<(String, usize)> {
+        let mut items: Vec<(String, usize)> = self
+            .item_counts
+            .iter()
+            .map(|(name, count)| (name.clone(), *count))
+            .collect();
+        items.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        items.truncate(limit);
+        items
+    }
+}
 
 /// Represents a customer's order
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -17,10 +324,67 @@ pub struct Order {
     pub order: Vec<OrderItem>,
     /// Chat message history
     pub messages: Vec<ChatMessage>,
+    /// Restaurant location the order was started at
+    #[serde(default)]
+    pub location: Option<String>,
     // NOTE(dev): Renaming this field for consistency, not because it goes through the API
     /// ID of the associated chat thread
     #[serde(rename = "threadId")]
     pub thread_id: Option<String>,
+    /// Lifecycle status of the order
+    #[serde(default)]
+    pub status: OrderStatus,
+    /// Time at which the order was completed
+    #[serde(rename = "completedAt", default)]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Time at which the order was started, None for orders stored before it was recorded
+    #[serde(rename = "createdAt", default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Time at which the order was last saved, None for orders stored before it was recorded
+    #[serde(rename = "updatedAt", default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    // NOTE(dev): Renaming this field for consistency, not because it goes through the API
+    /// Previous item lists, most recent last, used to undo changes
+    #[serde(rename = "undoSnapshots", default)]
+    pub undo_snapshots: Vec<Vec<OrderItem>>,
+    /// Whether the order was read back and the customer's next yes completes it
+    #[serde(rename = "awaitingConfirmation", default)]
+    pub awaiting_confirmation: bool,
+    /// Number of older chat messages dropped from `messages`
+    #[serde(rename = "truncatedMessages", default)]
+    pub truncated_messages: usize,
+    /// Prompt tokens used by all runs of the order
+    #[serde(rename = "promptTokens", default)]
+    pub prompt_tokens: u64,
+    /// Completion tokens used by all runs of the order
+    #[serde(rename = "completionTokens", default)]
+    pub completion_tokens: u64,
+    /// Seconds needed to prepare the order, set when it is completed
+    #[serde(
+        rename = "prepSeconds",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub prep_seconds: Option<u32>,
+    /// Itemized order recorded by the assistant's `finalize` call, cleared when the items change
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub summary: Option<OrderSummary>,
+    /// Chat turns in a row that ended with an invalid item
+    #[serde(rename = "invalidTurns", default)]
+    pub invalid_turns: u32,
+    /// Whether the assistant's reply to the current message was cut off by a token limit
+    #[serde(skip)]
+    pub incomplete_reply: bool,
+    /// Items suggested with the last items added while handling the current message
+    #[serde(skip)]
+    pub suggestions: Vec<String>,
+    // NOTE(dev): Never serialized, the entries are appended to the audit list when the order is saved
+    /// Audit entries recorded since the order was loaded
+    #[serde(skip)]
+    pub pending_audit: Vec<AuditEntry>,
+    /// Entry appended to the dead-letter list when the order is saved
+    #[serde(skip)]
+    pub pending_dead_letter: Option<DeadLetterEntry>,
 }
 
 impl fmt::Display for Order {
@@ -32,6 +396,35 @@ impl fmt::Display for Order {
     }
 }
 
+/// Preparation seconds of an order item together with its combo components.
+///
+/// # Arguments
+/// * `item` - The order item
+/// * `menu` - The menu holding the preparation times
+/// * `default_prep_seconds` - Preparation seconds of a menu item that sets none
+/// * `mode` - How the item and its components add up
+///
+/// # Returns
+/// * `u32` - Seconds needed to prepare the item
+fn item_prep_seconds(
+    item: &OrderItem,
+    menu: &Menu,
+    default_prep_seconds: u32,
+    mode: EtaMode,
+) -> u32 {
+    let own = menu
+        .get_item(&item.item_name)
+        .and_then(|menu_item| menu_item.prep_seconds)
+        .unwrap_or(default_prep_seconds);
+    mode.combine(
+        std::iter::once(own).chain(
+            item.components
+                .iter()
+                .map(|component| item_prep_seconds(component, menu, default_prep_seconds, mode)),
+        ),
+    )
+}
+
 /// Represents a single item in an order
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OrderItem {
@@ -45,13 +438,76 @@ pub struct OrderItem {
     pub option_keys: Vec<String>,
     /// Values for the selected options
     #[serde(rename = "optionValues")]
-    pub option_values: Vec<Vec<String>>,
+    pub option_values: Vec<Vec<OptionValue>>,
     /// Total price including options
-    pub price: f64,
+    pub price: Money,
     // NOTE(dev): Renaming this field for consistency, not because it goes through the API
     /// Validation status of the item
     #[serde(rename = "itemStatus")]
     pub item_status: Option<ItemStatus>,
+    /// Items filling the slots of a combo, priced as part of the combo
+    #[serde(default)]
+    pub components: Vec<OrderItem>,
+    /// Special instructions that aren't menu options, e.g. "cut in half"
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Person the item is for in a group order, e.g. "Alice"
+    #[serde(rename = "cartLabel", skip_serializing_if = "Option::is_none", default)]
+    pub cart_label: Option<String>,
+    /// Number of identical items on the line, priced at `price` each
+    #[serde(default = "default_quantity")]
+    pub quantity: u32,
+}
+
+/// Lines stored before quantities existed hold a single item
+fn default_quantity() -> u32 {
+    1
+}
+
+impl OrderItem {
+    /// Price of every item on the line.
+    ///
+    /// # Returns
+    /// * `Money` - The unit price times the quantity
+    pub fn line_price(&self) -> Money {
+        self.price * self.quantity
+    }
+
+    /// Whether another line holds the same item, so the two can be merged into one.
+    ///
+    /// # Arguments
+    /// * `other` - The other line
+    ///
+    /// # Returns
+    /// * `bool` - True if the lines only differ in their IDs, quantities and statuses
+    pub fn is_identical(&self, other: &OrderItem) -> bool {
+        self.item_name == other.item_name
+            && self.option_keys == other.option_keys
+            && self.option_values == other.option_values
+            && self.price == other.price
+            && self.notes == other.notes
+            && self.cart_label == other.cart_label
+            && self.components.len() == other.components.len()
+            && self
+                .components
+                .iter()
+                .zip(&other.components)
+                .all(|(component, other)| {
+                    component.quantity == other.quantity && component.is_identical(other)
+                })
+    }
+
+    /// Copies the item, giving it and its components new IDs.
+    ///
+    /// # Returns
+    /// * `OrderItem` - The copy
+    pub fn with_new_id(&self) -> OrderItem {
+        OrderItem {
+            id: Uuid::new_v4().to_string(),
+            components: self.components.iter().map(OrderItem::with_new_id).collect(),
+            ..self.clone()
+        }
+    }
 }
 
 /// API response format for order items
@@ -67,9 +523,41 @@ pub struct OrderItemResponse {
     pub option_keys: Vec<String>,
     /// Values for the selected options
     #[serde(rename = "optionValues")]
-    pub option_values: Vec<Vec<String>>,
+    pub option_values: Vec<Vec<OptionValue>>,
     /// Total price including options
-    pub price: f64,
+    pub price: Money,
+    /// Validation status of the item, omitted if it has not been validated
+    #[serde(
+        rename = "itemStatus",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub item_status: Option<ItemStatusResponse>,
+    /// Items filling the slots of a combo
+    #[serde(default)]
+    pub components: Vec<OrderItemResponse>,
+    /// Number of identical items on the line, priced at `price` each
+    #[serde(default = "default_quantity")]
+    pub quantity: u32,
+    /// Special instructions for the kitchen, omitted if there are none
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub notes: Option<String>,
+    /// Person the item is for in a group order, omitted if it has no label
+    #[serde(rename = "cartLabel", skip_serializing_if = "Option::is_none", default)]
+    pub cart_label: Option<String>,
+}
+
+/// Items of an order sharing a cart label, with their total
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CartSection {
+    /// Label of the cart, None for items without a label
+    #[serde(rename = "cartLabel")]
+    pub cart_label: Option<String>,
+    /// IDs of the items in the cart
+    #[serde(rename = "itemIds")]
+    pub item_ids: Vec<String>,
+    /// Sum of the prices of the items in the cart
+    pub total: Money,
 }
 
 impl From<OrderItem> for OrderItemResponse {
@@ -80,7 +568,118 @@ impl From<OrderItem> for OrderItemResponse {
             option_keys: val.option_keys,
             option_values: val.option_values,
             price: val.price,
+            quantity: val.quantity,
+            item_status: val.item_status.map(Into::into),
+            components: val.components.into_iter().map(Into::into).collect(),
+            notes: val.notes,
+            cart_label: val.cart_label,
+        }
+    }
+}
+
+/// A chosen value of an option, with how many of it were asked for (e.g. double cheese)
+///
+/// Serialized as the plain choice name when the count is one, and as
+/// `{ "value": "cheese", "count": 2 }` otherwise. Both shapes are accepted when parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionValue {
+    /// Name of the chosen value
+    pub value: String,
+    /// Number of times the value was chosen
+    pub count: u32,
+}
+
+impl OptionValue {
+    /// Creates a value chosen once.
+    ///
+    /// # Arguments
+    /// * `value` - Name of the chosen value
+    ///
+    /// # Returns
+    /// * `Self` - The option value
+    pub fn new(value: impl Into<String>) -> Self {
+        OptionValue {
+            value: value.into(),
+            count: 1,
+        }
+    }
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.count == 1 {
+            write!(f, "{}", self.value)
+        } else {
+            write!(f, "{}x {}", self.count, self.value)
+        }
+    }
+}
+
+impl Serialize for OptionValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Counted<'a> {
+            value: &'a str,
+            count: u32,
         }
+
+        if self.count == 1 {
+            serializer.serialize_str(&self.value)
+        } else {
+            Counted {
+                value: &self.value,
+                count: self.count,
+            }
+            .serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for OptionValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Plain(String),
+            Counted { value: String, count: u32 },
+        }
+
+        Ok(match Value::deserialize(deserializer)? {
+            Value::Plain(value) => OptionValue::new(value),
+            Value::Counted { value, count } => OptionValue { value, count },
+        })
+    }
+}
+
+/// Validation state of an order item
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemState {
+    /// Item is missing required options
+    Incomplete,
+    /// Item meets all requirements
+    Complete,
+    /// Item has invalid options
+    Invalid,
+}
+
+/// API response format for an item's validation status
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ItemStatusResponse {
+    /// Validation state of the item
+    pub state: ItemState,
+    /// Explanation of the state, e.g. which option is missing
+    pub message: String,
+}
+
+impl From<ItemStatus> for ItemStatusResponse {
+    fn from(val: ItemStatus) -> Self {
+        let (state, message) = match val {
+            ItemStatus::Incomplete(message) => (ItemState::Incomplete, message),
+            ItemStatus::Complete(message) => (ItemState::Complete, message),
+            ItemStatus::Invalid(message) => (ItemState::Invalid, message),
+        };
+        ItemStatusResponse { state, message }
     }
 }
 
@@ -98,49 +697,681 @@ impl Order {
     ///
     /// # Arguments
     /// * `order_id` - The unique identifier for the order
+    /// * `location` - The restaurant location the order is placed at
     ///
     /// # Returns
     /// * `Self` - A new Order instance
-    pub fn new(order_id: String) -> Self {
+    pub fn new(order_id: String, location: String) -> Self {
         debug!("Creating new order with ID: {}", order_id);
+        let now = Utc::now();
         Self {
             order_id,
             order: Vec::new(),
             messages: Vec::new(),
+            location: Some(location),
             thread_id: None,
+            status: OrderStatus::Active,
+            completed_at: None,
+            created_at: Some(now),
+            updated_at: Some(now),
+            undo_snapshots: Vec::new(),
+            awaiting_confirmation: false,
+            truncated_messages: 0,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            prep_seconds: None,
+            summary: None,
+            invalid_turns: 0,
+            incomplete_reply: false,
+            suggestions: Vec::new(),
+            pending_audit: Vec::new(),
+            pending_dead_letter: None,
+        }
+    }
+
+    /// Records the items from before a change so the change can be undone, dropping the oldest
+    /// snapshot past `MAX_UNDO_SNAPSHOTS`. The order itself is left as it is.
+    ///
+    /// # Arguments
+    /// * `items` - The items as they were before the change
+    pub fn snapshot(&mut self, items: Vec<OrderItem>) {
+        self.undo_snapshots.push(items);
+        if self.undo_snapshots.len() > MAX_UNDO_SNAPSHOTS {
+            self.undo_snapshots.remove(0);
+        }
+        debug!(
+            "Order {} now has {} undo snapshots",
+            self.order_id,
+            self.undo_snapshots.len()
+        );
+    }
+
+    /// Restores the items from before the most recent change.
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if a snapshot was restored
+    pub fn undo(&mut self) -> AppResult<()> {
+        match self.undo_snapshots.pop() {
+            Some(items) => {
+                info!("Restoring order {} to {} items", self.order_id, items.len());
+                self.awaiting_confirmation = false;
+                self.summary = None;
+                self.order = items;
+                Ok(())
+            }
+            None => Err(AppError::InvalidInput(format!(
+                "Order {} has no changes to undo",
+                self.order_id
+            ))),
+        }
+    }
+
+    /// Drops the oldest chat messages so that at most `max` are kept, leaving the items as is.
+    ///
+    /// # Arguments
+    /// * `max` - Number of messages to keep, 0 to keep every message
+    pub fn truncate_messages(&mut self, max: usize) {
+        if max == 0 || self.messages.len() <= max {
+            return;
+        }
+        let dropped = self.messages.len() - max;
+        info!(
+            "Dropping {} old messages from order {}",
+            dropped, self.order_id
+        );
+        self.messages.drain(..dropped);
+        self.truncated_messages += dropped;
+    }
+
+    /// Copies the items of the order into a new active order at the same location.
+    ///
+    /// The items and their components get new IDs. Messages, the thread and undo history are
+    /// not copied, so the new order starts its own conversation.
+    ///
+    /// # Arguments
+    /// * `order_id` - ID of the new order
+    ///
+    /// # Returns
+    /// * `Order` - The new order
+    pub fn duplicate(&self, order_id: String) -> Order {
+        info!(
+            "Duplicating {} items of order {} into {}",
+            self.order.len(),
+            self.order_id,
+            order_id
+        );
+        let mut order = Order::new(order_id, self.location.clone().unwrap_or_default());
+        order.order = self.order.iter().map(OrderItem::with_new_id).collect();
+        order
+    }
+
+    /// Removes every item from the order, keeping the thread and messages.
+    pub fn clear(&mut self) {
+        info!(
+            "Clearing {} items from order {}",
+            self.order.len(),
+            self.order_id
+        );
+        self.awaiting_confirmation = false;
+        self.summary = None;
+        self.order.clear();
+    }
+
+    /// Completes the order once every item has passed menu validation.
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the order was marked as completed
+    pub fn complete(&mut self) -> AppResult<()> {
+        info!("Completing order {}", self.order_id);
+        self.ensure_active()?;
+        self.ensure_ready()?;
+        self.awaiting_confirmation = false;
+        self.set_status(OrderStatus::Completed);
+        debug!(
+            "Order {} completed at {:?}",
+            self.order_id, self.completed_at
+        );
+        Ok(())
+    }
+
+    /// Estimates how long the kitchen needs to prepare the order.
+    ///
+    /// Each item takes the `prepSeconds` of its menu item, or `default_prep_seconds` if the
+    /// menu sets none, combined with the items filling its combo slots. Items are combined
+    /// according to `mode`.
+    ///
+    /// # Arguments
+    /// * `menu` - The menu holding the preparation times
+    /// * `default_prep_seconds` - Preparation seconds of a menu item that sets none
+    /// * `mode` - How the items add up
+    ///
+    /// # Returns
+    /// * `u32` - Seconds until the order is ready
+    pub fn eta(&self, menu: &Menu, default_prep_seconds: u32, mode: EtaMode) -> u32 {
+        let eta = mode.combine(self.order.iter().flat_map(|item| {
+            std::iter::repeat_n(
+                item_prep_seconds(item, menu, default_prep_seconds, mode),
+                item.quantity as usize,
+            )
+        }));
+        debug!("Order {} is ready in {} seconds", self.order_id, eta);
+        eta
+    }
+
+    /// Checks that the order has items and that every item has passed menu validation.
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the order can be completed
+    pub fn ensure_ready(&self) -> AppResult<()> {
+        if self.order.is_empty() {
+            return Err(AppError::InvalidInput(format!(
+                "Order {} has no items",
+                self.order_id
+            )));
+        }
+
+        let incomplete: Vec<String> = self
+            .order
+            .iter()
+            .filter_map(|item| match &item.item_status {
+                Some(ItemStatus::Complete(_)) => None,
+                Some(ItemStatus::Incomplete(reason)) | Some(ItemStatus::Invalid(reason)) => {
+                    Some(format!("{} (ID: {}): {}", item.item_name, item.id, reason))
+                }
+                None => Some(format!(
+                    "{} (ID: {}): not validated",
+                    item.item_name, item.id
+                )),
+            })
+            .collect();
+        if !incomplete.is_empty() {
+            info!(
+                "Order {} has {} incomplete items",
+                self.order_id,
+                incomplete.len()
+            );
+            return Err(AppError::InvalidInput(format!(
+                "Order has incomplete items: {}",
+                incomplete.join("; ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Updates the lifecycle status of the order.
+    ///
+    /// # Arguments
+    /// * `status` - The new status of the order
+    pub fn set_status(&mut self, status: OrderStatus) {
+        debug!(
+            "Changing status of order {} from {} to {}",
+            self.order_id, self.status, status
+        );
+        if status == OrderStatus::Completed {
+            self.completed_at = Some(Utc::now());
+        }
+        self.status = status;
+    }
+
+    /// Sum of the prices of every item in the order.
+    ///
+    /// # Returns
+    /// * `Money` - The order total
+    pub fn total(&self) -> Money {
+        self.order.iter().map(OrderItem::line_price).sum()
+    }
+
+    /// Number of items in the order, counting each item of a line.
+    ///
+    /// # Returns
+    /// * `usize` - The sum of the quantities of every line
+    pub fn item_count(&self) -> usize {
+        self.order.iter().map(|item| item.quantity as usize).sum()
+    }
+
+    /// Groups the items by cart label, in the order each label first appears.
+    ///
+    /// # Returns
+    /// * `Vec<CartSection>` - One section per label, empty if no item has a label
+    pub fn carts(&self) -> Vec<CartSection> {
+        if self.order.iter().all(|item| item.cart_label.is_none()) {
+            return vec![];
+        }
+        let mut carts: Vec<CartSection> = Vec::new();
+        for item in &self.order {
+            match carts
+                .iter_mut()
+                .find(|cart| cart.cart_label == item.cart_label)
+            {
+                Some(cart) => {
+                    cart.item_ids.push(item.id.clone());
+                    cart.total += item.line_price();
+                }
+                None => carts.push(CartSection {
+                    cart_label: item.cart_label.clone(),
+                    item_ids: vec![item.id.clone()],
+                    total: item.line_price(),
+                }),
+            }
+        }
+        debug!("Order {} has {} carts", self.order_id, carts.len());
+        carts
+    }
+
+    /// Checks that the order can still be changed.
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the order is active, a conflict error otherwise
+    pub fn ensure_active(&self) -> AppResult<()> {
+        if self.status != OrderStatus::Active {
+            info!("Order {} is not active: {}", self.order_id, self.status);
+            return Err(AppError::OrderNotActive(format!(
+                "Order {} is {}",
+                self.order_id, self.status
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the order has room for more items.
+    ///
+    /// # Arguments
+    /// * `additional` - Number of items about to be added
+    /// * `max_items` - Largest number of items the order can hold
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the items fit, `OrderFull` otherwise
+    pub fn ensure_capacity(&self, additional: usize, max_items: usize) -> AppResult<()> {
+        if self.item_count() + additional > max_items {
+            info!(
+                "Order {} has {} items, cannot add {} more",
+                self.order_id,
+                self.item_count(),
+                additional
+            );
+            return Err(AppError::OrderFull(format!(
+                "Order {} already has {} of at most {} items",
+                self.order_id,
+                self.item_count(),
+                max_items
+            )));
+        }
+        Ok(())
+    }
+
+    /// Adds the tokens used by a run to the order's totals.
+    ///
+    /// # Arguments
+    /// * `prompt_tokens` - Prompt tokens used by the run
+    /// * `completion_tokens` - Completion tokens used by the run
+    pub fn record_tokens(&mut self, prompt_tokens: u32, completion_tokens: u32) {
+        self.prompt_tokens += u64::from(prompt_tokens);
+        self.completion_tokens += u64::from(completion_tokens);
+        debug!(
+            "Order {} has used {} tokens",
+            self.order_id,
+            self.total_tokens()
+        );
+    }
+
+    /// Counts the chat turns in a row that ended with an invalid item, handing the order to a
+    /// human once there were `MAX_INVALID_TURNS` of them.
+    ///
+    /// The handover adds `HANDOFF_REPLY` to the messages and appends the order to the
+    /// dead-letter list on the next save, once per run of failing turns.
+    ///
+    /// # Arguments
+    /// * `max_turns` - Turns in a row with invalid items before the handover, 0 for never
+    pub fn track_invalid_items(&mut self, max_turns: u32) {
+        let invalid: Vec<String> = self
+            .order
+            .iter()
+            .filter_map(|item| match &item.item_status {
+                Some(ItemStatus::Invalid(reason)) => {
+                    Some(format!("{} (ID: {}): {}", item.item_name, item.id, reason))
+                }
+                _ => None,
+            })
+            .collect();
+        if invalid.is_empty() {
+            self.invalid_turns = 0;
+            return;
+        }
+        self.invalid_turns += 1;
+        debug!(
+            "Order {} ended {} turns in a row with invalid items",
+            self.order_id, self.invalid_turns
+        );
+        if self.invalid_turns != max_turns {
+            return;
+        }
+
+        warn!(
+            "Handing order {} to a human after {} turns with invalid items: {}",
+            self.order_id,
+            self.invalid_turns,
+            invalid.join("; ")
+        );
+        self.messages.push(ChatMessage {
+            role: ChatRole::Assistant.to_string(),
+            content: HANDOFF_REPLY.to_string(),
+        });
+        self.pending_dead_letter = Some(DeadLetterEntry {
+            timestamp: Utc::now(),
+            order_id: self.order_id.clone(),
+            location: self.location.clone(),
+            invalid_turns: self.invalid_turns,
+            items: invalid,
+        });
+    }
+
+    /// Whether the order was handed to a human and still has invalid items.
+    ///
+    /// # Arguments
+    /// * `max_turns` - Turns in a row with invalid items before the handover, 0 for never
+    ///
+    /// # Returns
+    /// * `bool` - True once the order ended `max_turns` turns in a row with invalid items
+    pub fn needs_human(&self, max_turns: u32) -> bool {
+        max_turns > 0 && self.invalid_turns >= max_turns
+    }
+
+    /// Prompt and completion tokens used by all runs of the order.
+    ///
+    /// # Returns
+    /// * `u64` - The total number of tokens
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// Checks that the order has not used up its token budget.
+    ///
+    /// # Arguments
+    /// * `max_tokens` - Tokens the order may use across all runs, 0 for no limit
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if another run may be started for the order
+    pub fn ensure_token_budget(&self, max_tokens: u64) -> AppResult<()> {
+        if max_tokens > 0 && self.total_tokens() >= max_tokens {
+            info!(
+                "Order {} has used {} of {} tokens",
+                self.order_id,
+                self.total_tokens(),
+                max_tokens
+            );
+            return Err(AppError::TokenBudgetExceeded(format!(
+                "Order {} has used its budget of {} tokens, please complete it or start a new order",
+                self.order_id, max_tokens
+            )));
         }
+        Ok(())
+    }
+
+    /// Redis key an order is stored under.
+    ///
+    /// # Arguments
+    /// * `order_id` - The ID of the order
+    ///
+    /// # Returns
+    /// * `String` - The prefixed key
+    pub fn key(order_id: &str) -> String {
+        format!("{}{}", ORDER_KEY_PREFIX, order_id)
     }
 
-    /// Saves the order to Redis.
+    /// Saves the order to Redis, appending any pending audit entries to its audit trail.
     ///
     /// # Arguments
-    /// * `conn` - Redis connection
+    /// * `conn` - Pooled Redis connection
+    /// * `ttl_seconds` - Seconds the order is kept after this change, 0 to keep it for ever
     ///
     /// # Returns
     /// * `AppResult<()>` - Success if saved
-    pub async fn save(&self, conn: &mut Connection) -> AppResult<()> {
+    pub async fn save(&mut self, conn: &mut RedisConnection, ttl_seconds: usize) -> AppResult<()> {
         debug!(
-            "Saving order {} with {} items",
+            "Saving order {} with {} items and {} audit entries",
             self.order_id,
-            self.order.len()
+            self.order.len(),
+            self.pending_audit.len()
         );
+        self.updated_at = Some(Utc::now());
         let order_json = serde_json::to_string(&self)?;
-        conn.set::<_, _, ()>(&self.order_id, order_json)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if ttl_seconds > 0 {
+            pipe.set_ex(Self::key(&self.order_id), order_json, ttl_seconds)
+                .ignore();
+        } else {
+            pipe.set(Self::key(&self.order_id), order_json).ignore();
+        }
+        if let Some(thread_id) = &self.thread_id {
+            if ttl_seconds > 0 {
+                pipe.set_ex(
+                    Self::thread_index_key(&self.order_id),
+                    thread_id,
+                    ttl_seconds + THREAD_INDEX_GRACE_SECONDS,
+                )
+                .ignore();
+            } else {
+                pipe.set(Self::thread_index_key(&self.order_id), thread_id)
+                    .ignore();
+            }
+        }
+        for entry in &self.pending_audit {
+            pipe.rpush(
+                Self::audit_key(&self.order_id),
+                serde_json::to_string(entry)?,
+            )
+            .ignore();
+        }
+        if let Some(entry) = &self.pending_dead_letter {
+            pipe.rpush(DEAD_LETTER_KEY, serde_json::to_string(entry)?)
+                .ignore();
+        }
+        pipe.query::<()>(&mut **conn)?;
+        self.pending_audit.clear();
+        self.pending_dead_letter = None;
         debug!("Order {} saved successfully", self.order_id);
         Ok(())
     }
 
+    /// Redis key of the OpenAI thread of an active order.
+    ///
+    /// # Arguments
+    /// * `order_id` - The ID of the order
+    ///
+    /// # Returns
+    /// * `String` - The thread index key
+    pub fn thread_index_key(order_id: &str) -> String {
+        format!("{}{}", THREAD_INDEX_KEY_PREFIX, order_id)
+    }
+
+    /// Redis key of the audit trail of an order.
+    ///
+    /// # Arguments
+    /// * `order_id` - The ID of the order
+    ///
+    /// # Returns
+    /// * `String` - The audit key
+    pub fn audit_key(order_id: &str) -> String {
+        format!("{}{}", AUDIT_KEY_PREFIX, order_id)
+    }
+
+    /// Records a function call to be appended to the audit trail on the next save.
+    ///
+    /// # Arguments
+    /// * `function` - Name of the function called
+    /// * `arguments` - Raw arguments of the call
+    /// * `item_ids` - IDs of the items added, changed or removed
+    /// * `success` - Whether the call succeeded
+    pub fn record_audit(
+        &mut self,
+        function: &str,
+        arguments: &str,
+        item_ids: Vec<String>,
+        success: bool,
+    ) {
+        let arguments = serde_json::from_str(arguments)
+            .unwrap_or_else(|_| serde_json::Value::String(arguments.to_string()));
+        self.pending_audit.push(AuditEntry {
+            timestamp: Utc::now(),
+            function: function.to_string(),
+            arguments,
+            item_ids,
+            success,
+        });
+    }
+
+    /// Reads the audit trail of an order, oldest entry first.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    /// * `order_id` - The ID of the order
+    ///
+    /// # Returns
+    /// * `AppResult<Vec<AuditEntry>>` - The audit entries
+    pub fn audit_log(conn: &mut RedisConnection, order_id: &str) -> AppResult<Vec<AuditEntry>> {
+        debug!("Reading audit trail of order {}", order_id);
+        let entries: Vec<String> = conn.lrange(Self::audit_key(order_id), 0, -1)?;
+        let entries = entries
+            .iter()
+            .map(|entry| serde_json::from_str(entry))
+            .collect::<Result<Vec<AuditEntry>, _>>()?;
+        Ok(entries)
+    }
+
+    /// Redis key a completed order is archived under.
+    ///
+    /// # Arguments
+    /// * `order_id` - The ID of the order
+    ///
+    /// # Returns
+    /// * `String` - The archive key
+    pub fn archive_key(order_id: &str) -> String {
+        format!("{}{}", ARCHIVE_KEY_PREFIX, order_id)
+    }
+
+    /// Moves a completed order out of the working set into the archive, where it is kept
+    /// permanently and can still be retrieved with `get`.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if archived, `InvalidInput` if the order is not completed
+    pub fn archive(&mut self, conn: &mut RedisConnection) -> AppResult<()> {
+        if self.status != OrderStatus::Completed {
+            return Err(AppError::InvalidInput(format!(
+                "Order {} is {} and cannot be archived",
+                self.order_id, self.status
+            )));
+        }
+        info!("Archiving order {}", self.order_id);
+        self.updated_at = Some(Utc::now());
+        let order_json = serde_json::to_string(&self)?;
+        let completed_at = self.completed_at.unwrap_or_else(Utc::now).timestamp();
+        redis::pipe()
+            .atomic()
+            .set(Self::archive_key(&self.order_id), order_json)
+            .ignore()
+            .zadd(ARCHIVE_INDEX_KEY, &self.order_id, completed_at)
+            .ignore()
+            .del(&[
+                Self::key(&self.order_id),
+                self.order_id.clone(),
+                Self::thread_index_key(&self.order_id),
+            ])
+            .ignore()
+            .query::<()>(&mut **conn)?;
+        debug!("Order {} archived", self.order_id);
+        Ok(())
+    }
+
+    /// Lists archived orders, most recently completed first.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    /// * `offset` - Number of orders to skip
+    /// * `limit` - Maximum number of orders to return
+    ///
+    /// # Returns
+    /// * `AppResult<Vec<Self>>` - The archived orders
+    pub fn archived(
+        conn: &mut RedisConnection,
+        offset: usize,
+        limit: usize,
+    ) -> AppResult<Vec<Self>> {
+        debug!("Listing {} archived orders from {}", limit, offset);
+        if limit == 0 {
+            return Ok(vec![]);
+        }
+        let order_ids: Vec<String> = conn.zrevrange(
+            ARCHIVE_INDEX_KEY,
+            offset as isize,
+            (offset + limit - 1) as isize,
+        )?;
+        if order_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let keys: Vec<String> = order_ids.iter().map(|id| Self::archive_key(id)).collect();
+        let orders_json: Vec<Option<String>> = redis::cmd("MGET").arg(&keys).query(&mut **conn)?;
+        let orders = orders_json
+            .into_iter()
+            .flatten()
+            .map(|json| serde_json::from_str(&json))
+            .collect::<Result<Vec<Self>, _>>()?;
+        debug!("Found {} archived orders", orders.len());
+        Ok(orders)
+    }
+
+    /// Counts the orders in the working set and the archive.
+    ///
+    /// Keys are read a SCAN page at a time with `OrderScan` so large key sets are never loaded
+    /// at once. Orders still under legacy unprefixed keys are not counted.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    ///
+    /// # Returns
+    /// * `AppResult<OrderStats>` - The aggregate counts
+    pub fn stats(conn: &mut RedisConnection) -> AppResult<OrderStats> {
+        let mut stats = OrderStats::default();
+        let mut scan = OrderScan::new();
+        while let Some(orders) = scan.next_page(conn)? {
+            for order in &orders {
+                stats.record(order);
+            }
+        }
+        info!(
+            "Counted {} active, {} completed and {} cancelled orders",
+            stats.active, stats.completed, stats.cancelled
+        );
+        Ok(stats)
+    }
+
     /// Retrieves an order from Redis by ID.
     ///
     /// # Arguments
-    /// * `conn` - Redis connection
+    /// * `conn` - Pooled Redis connection
     /// * `order_id` - The ID of the order to retrieve
     ///
     /// # Returns
     /// * `AppResult<Self>` - The retrieved order or an error
-    pub fn get(conn: &mut Connection, order_id: &str) -> AppResult<Self> {
+    pub fn get(conn: &mut RedisConnection, order_id: &str) -> AppResult<Self> {
         debug!("Retrieving order: {}", order_id);
-        let order_json: Option<String> = conn.get(order_id)?;
+        let mut order_json: Option<String> = conn.get(Self::key(order_id))?;
+        // TODO(siyer): Remove the fallback to unprefixed keys once legacy orders have expired
+        if order_json.is_none() {
+            order_json = conn.get(order_id)?;
+            if order_json.is_some() {
+                info!("Order {} found under legacy unprefixed key", order_id);
+            }
+        }
+        if order_json.is_none() {
+            order_json = conn.get(Self::archive_key(order_id))?;
+            if order_json.is_some() {
+                debug!("Order {} found in the archive", order_id);
+            }
+        }
         match order_json {
             Some(json) => {
                 let order: Self = serde_json::from_str(&json)?;
@@ -157,28 +1388,553 @@ impl Order {
             }
         }
     }
+
+    /// Deletes an order from Redis, including any copy under the legacy unprefixed key.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    /// * `order_id` - The ID of the order to delete
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the order existed and was deleted
+    pub fn delete(conn: &mut RedisConnection, order_id: &str) -> AppResult<()> {
+        debug!("Deleting order: {}", order_id);
+        let deleted: usize = conn.del(&[Self::key(order_id), order_id.to_string()])?;
+        if deleted == 0 {
+            info!("Order not found: {}", order_id);
+            return Err(AppError::OrderNotFound(order_id.to_string()));
+        }
+        debug!("Order {} deleted", order_id);
+        Ok(())
+    }
+}
+
+/// Whether a Redis error means the connection was lost, so the command can be retried on a
+/// new connection.
+///
+/// # Arguments
+/// * `error` - The Redis error
+///
+/// # Returns
+/// * `bool` - True for dropped, refused or timed out connections
+fn is_connection_error(error: &RedisError) -> bool {
+    error.is_connection_dropped()
+        || error.is_connection_refusal()
+        || error.is_io_error()
+        || error.is_timeout()
 }
 
 /// Interface for order storage operations
 #[derive(Clone)]
 pub struct OrderStore {
-    client: Client,
+    pool: Pool<Client>,
+    max_retries: u32,
+    ttl_seconds: usize,
 }
 
 impl OrderStore {
-    /// Creates a new OrderStore instance.
+    /// Creates a new OrderStore instance backed by a connection pool.
     ///
     /// # Arguments
     /// * `client` - Redis client
-    pub fn new(client: Client) -> Self {
-        Self { client }
+    /// * `config` - The validated startup configuration holding the pool size, connect timeout,
+    ///   retries and order TTL
+    ///
+    /// # Returns
+    /// * `AppResult<Self>` - The store, or an error if Redis did not answer a PING, e.g. because
+    ///   it refused the credentials or database index, or if the pool could not be built
+    pub fn new(client: Client, config: &Config) -> AppResult<Self> {
+        let connect_timeout = config.redis_connect_timeout;
+        // NOTE(dev): Pinged on a single connection first, a pool that can't connect only reports
+        //            that it timed out, not that e.g. the password or database index was refused
+        let mut conn = client.get_connection_with_timeout(connect_timeout)?;
+        redis::cmd("PING").query::<String>(&mut conn)?;
+        debug!(
+            "Building Redis connection pool with size {} and connect timeout of {:?}",
+            config.redis_pool_size, connect_timeout
+        );
+        // NOTE(dev): Connections are pinged on checkout, so ones dropped by a Redis restart are
+        //            replaced with fresh connections instead of being handed out
+        let pool = Pool::builder()
+            .max_size(config.redis_pool_size)
+            .test_on_check_out(true)
+            .connection_timeout(connect_timeout)
+            .build(client)?;
+        Ok(Self {
+            pool,
+            max_retries: config.redis_max_retries,
+            ttl_seconds: config.order_limits.ttl_seconds,
+        })
+    }
+
+    /// Checks out a connection from the Redis pool.
+    ///
+    /// # Returns
+    /// * `AppResult<RedisConnection>` - A pooled Redis connection or an error
+    pub fn get_connection(&self) -> AppResult<RedisConnection> {
+        Ok(self.pool.get()?)
+    }
+
+    /// Retrieves an order, retrying on a new connection if the connection was lost.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection, replaced if it was lost
+    /// * `order_id` - The ID of the order to retrieve
+    ///
+    /// # Returns
+    /// * `AppResult<Order>` - The retrieved order or an error
+    pub fn get_order(&self, conn: &mut RedisConnection, order_id: &str) -> AppResult<Order> {
+        let mut attempt = 0;
+        loop {
+            match Order::get(conn, order_id) {
+                Err(e) if self.reconnect(conn, &e, attempt)? => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Saves an order, retrying on a new connection if the connection was lost.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection, replaced if it was lost
+    /// * `order` - The order to save
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if saved
+    pub async fn save_order(&self, conn: &mut RedisConnection, order: &mut Order) -> AppResult<()> {
+        let mut attempt = 0;
+        loop {
+            match order.save(conn, self.ttl_seconds).await {
+                Err(e) if self.reconnect(conn, &e, attempt)? => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Removes the thread index of an order and returns the thread it pointed at.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    /// * `order_id` - The ID of the order
+    ///
+    /// # Returns
+    /// * `AppResult<Option<String>>` - The thread ID, or None if the order had no thread
+    pub fn take_thread_index(
+        &self,
+        conn: &mut RedisConnection,
+        order_id: &str,
+    ) -> AppResult<Option<String>> {
+        let key = Order::thread_index_key(order_id);
+        let (thread_id,): (Option<String>,) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .del(&key)
+            .ignore()
+            .query(&mut **conn)?;
+        Ok(thread_id)
+    }
+
+    /// Replaces a connection that was lost with a new one from the pool.
+    ///
+    /// # Arguments
+    /// * `conn` - The connection the error happened on
+    /// * `error` - The error of the failed attempt
+    /// * `attempt` - Number of retries already made
+    ///
+    /// # Returns
+    /// * `AppResult<bool>` - True if the connection was replaced and the operation should be
+    ///   retried
+    fn reconnect(
+        &self,
+        conn: &mut RedisConnection,
+        error: &AppError,
+        attempt: u32,
+    ) -> AppResult<bool> {
+        let retryable = matches!(error, AppError::RedisError(e) if is_connection_error(e));
+        if !retryable || attempt >= self.max_retries {
+            return Ok(false);
+        }
+        warn!(
+            "Redis connection lost (attempt {}/{}), reconnecting: {:?}",
+            attempt + 1,
+            self.max_retries,
+            error
+        );
+        // NOTE(dev): The lost connection is discarded by the pool once it is dropped here
+        *conn = self.get_connection()?;
+        Ok(true)
+    }
+
+    /// Claims an idempotency key for a newly generated order ID.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    /// * `key` - The idempotency key sent by the client
+    /// * `order_id` - The order ID to associate with the key
+    ///
+    /// # Returns
+    /// * `AppResult<Option<String>>` - `None` if the key was claimed, otherwise the order ID
+    ///   previously stored under it
+    pub fn claim_idempotency_key(
+        &self,
+        conn: &mut RedisConnection,
+        key: &str,
+        order_id: &str,
+    ) -> AppResult<Option<String>> {
+        let redis_key = format!("idempotency:{}", key);
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(&redis_key)
+            .arg(order_id)
+            .arg("NX")
+            .arg("EX")
+            .arg(IDEMPOTENCY_KEY_TTL_SECONDS)
+            .query(&mut **conn)?;
+        if claimed.is_some() {
+            debug!("Claimed idempotency key {} for order {}", key, order_id);
+            return Ok(None);
+        }
+        let existing: Option<String> = conn.get(&redis_key)?;
+        debug!("Idempotency key {} already used by {:?}", key, existing);
+        Ok(existing)
+    }
+
+    /// Retrieves the response stored for a repeated request.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    /// * `key` - Redis key the response was stored under
+    ///
+    /// # Returns
+    /// * `AppResult<Option<String>>` - The serialized response, if it has not expired
+    pub fn get_cached_response(
+        &self,
+        conn: &mut RedisConnection,
+        key: &str,
+    ) -> AppResult<Option<String>> {
+        let response: Option<String> = conn.get(key)?;
+        debug!(
+            "Cached response under {} found: {}",
+            key,
+            response.is_some()
+        );
+        Ok(response)
+    }
+
+    /// Stores a response so repeats of its request can return it.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    /// * `key` - Redis key to store the response under
+    /// * `response` - The serialized response
+    /// * `ttl_seconds` - Seconds until the response expires
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if the response was stored
+    pub fn cache_response(
+        &self,
+        conn: &mut RedisConnection,
+        key: &str,
+        response: &str,
+        ttl_seconds: usize,
+    ) -> AppResult<()> {
+        debug!("Caching response under {} for {} seconds", key, ttl_seconds);
+        conn.set_ex::<_, _, ()>(key, response, ttl_seconds)?;
+        Ok(())
+    }
+
+    /// Retrieves the assistant shared by all replicas.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    ///
+    /// # Returns
+    /// * `AppResult<Option<(String, String)>>` - The assistant id and the hash of the
+    ///   configuration it was built from, if one is stored
+    pub fn get_assistant(&self, conn: &mut RedisConnection) -> AppResult<Option<(String, String)>> {
+        let (id, hash): (Option<String>, Option<String>) =
+            conn.hget("assistant", &["id", "hash"])?;
+        debug!("Stored assistant: {:?}", id);
+        Ok(id.zip(hash))
+    }
+
+    /// Stores the assistant shared by all replicas.
+    ///
+    /// # Arguments
+    /// * `conn` - Pooled Redis connection
+    /// * `assistant_id` - The OpenAI assistant id
+    /// * `config_hash` - Hash of the configuration the assistant was built from
+    ///
+    /// # Returns
+    /// * `AppResult<()>` - Success if stored
+    pub fn set_assistant(
+        &self,
+        conn: &mut RedisConnection,
+        assistant_id: &str,
+        config_hash: &str,
+    ) -> AppResult<()> {
+        debug!("Storing assistant {}", assistant_id);
+        conn.hset_multiple::<_, _, _, ()>(
+            "assistant",
+            &[("id", assistant_id), ("hash", config_hash)],
+        )?;
+        Ok(())
     }
 
-    /// Gets a connection from the Redis client.
+    /// Checks that Redis is reachable by sending a PING.
     ///
     /// # Returns
-    /// * `AppResult<Connection>` - A Redis connection or an error
-    pub fn get_connection(&self) -> AppResult<Connection> {
-        Ok(self.client.get_connection()?)
+    /// * `AppResult<()>` - Success if Redis responded
+    pub fn ping(&self) -> AppResult<()> {
+        let mut conn = self.get_connection()?;
+        redis::cmd("PING").query::<String>(&mut *conn)?;
+        Ok(())
+    }
+}
+
+/// Per-order locks so that independent orders are processed concurrently while changes to the
+/// same order are applied one at a time
+#[derive(Default)]
+pub struct OrderLocks {
+    locks: Mutex<HashMap<String, Arc<TokioMutex<()>>>>,
+}
+
+impl OrderLocks {
+    /// Acquires the lock for a single order, waiting for any in-flight change to finish.
+    ///
+    /// # Arguments
+    /// * `order_id` - The ID of the order to lock
+    ///
+    /// # Returns
+    /// * `AppResult<OwnedMutexGuard<()>>` - Guard that releases the order when dropped
+    pub async fn lock(&self, order_id: &str) -> AppResult<OwnedMutexGuard<()>> {
+        let lock = {
+            let mut locks = self.locks.lock()?;
+            // NOTE(dev): Drop locks that nobody holds or waits on so the map doesn't grow forever
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(order_id.to_string()).or_default().clone()
+        };
+        debug!("Waiting for lock on order {}", order_id);
+        Ok(lock.lock_owned().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{item, order, store, store_on, FakeRedis};
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn orders_are_locked_independently() {
+        let locks = OrderLocks::default();
+        let first = locks.lock("order-1").await.unwrap();
+
+        let second = timeout(Duration::from_secs(1), locks.lock("order-2")).await;
+        assert!(second.is_ok(), "another order must not wait on order-1");
+        assert!(
+            timeout(Duration::from_millis(100), locks.lock("order-1"))
+                .await
+                .is_err(),
+            "order-1 must wait for the change in flight"
+        );
+
+        drop(first);
+        assert!(timeout(Duration::from_secs(1), locks.lock("order-1"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn orders_are_stored_in_the_configured_database() {
+        let redis = FakeRedis::start();
+        let store = store_on(&redis, &[("REDIS_DB", "3")]).unwrap();
+        let mut conn = store.get_connection().unwrap();
+        store.save_order(&mut conn, &mut order()).await.unwrap();
+
+        assert!(redis.keys(3).contains(&"order:order-1".to_string()));
+        assert!(redis.keys(0).is_empty());
+        assert_eq!(
+            store.get_order(&mut conn, "order-1").unwrap().order_id,
+            "order-1"
+        );
+    }
+
+    #[test]
+    fn the_store_authenticates_with_the_configured_credentials() {
+        let redis = FakeRedis::start_with_auth(Some("orders"), "secret");
+        assert!(store_on(
+            &redis,
+            &[("REDIS_USERNAME", "orders"), ("REDIS_PASSWORD", "secret")]
+        )
+        .is_ok());
+        assert!(store_on(
+            &redis,
+            &[("REDIS_USERNAME", "orders"), ("REDIS_PASSWORD", "wrong")]
+        )
+        .is_err());
+        assert!(store_on(&redis, &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn orders_are_read_and_saved_again_after_a_redis_restart() {
+        let redis = FakeRedis::start();
+        let store = store_on(&redis, &[]).unwrap();
+        let mut conn = store.get_connection().unwrap();
+        let mut order = order();
+        store.save_order(&mut conn, &mut order).await.unwrap();
+
+        redis.drop_connections();
+        assert_eq!(
+            store.get_order(&mut conn, "order-1").unwrap().order_id,
+            "order-1"
+        );
+
+        redis.drop_connections();
+        order.location = Some("store-b".to_string());
+        store.save_order(&mut conn, &mut order).await.unwrap();
+        let mut fresh = store.get_connection().unwrap();
+        assert_eq!(
+            store.get_order(&mut fresh, "order-1").unwrap().location,
+            Some("store-b".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn orders_stuck_on_invalid_items_are_dead_lettered_once() {
+        let store = store();
+        let mut conn = store.get_connection().unwrap();
+        let mut order = order();
+        let mut coke = item("Coke", &[("meal option", "meal")]);
+        coke.item_status = Some(ItemStatus::Invalid("Option does not exist".to_string()));
+        order.order.push(coke);
+        let dead_letters = |conn: &mut RedisConnection| -> Vec<DeadLetterEntry> {
+            redis::cmd("LRANGE")
+                .arg(DEAD_LETTER_KEY)
+                .arg(0)
+                .arg(-1)
+                .query::<Vec<String>>(&mut **conn)
+                .unwrap()
+                .iter()
+                .map(|entry| serde_json::from_str(entry).unwrap())
+                .collect()
+        };
+
+        for _ in 0..2 {
+            order.track_invalid_items(3);
+            store.save_order(&mut conn, &mut order).await.unwrap();
+        }
+        assert!(!order.needs_human(3));
+        assert!(order.messages.is_empty());
+        assert!(dead_letters(&mut conn).is_empty());
+
+        order.track_invalid_items(3);
+        store.save_order(&mut conn, &mut order).await.unwrap();
+        assert!(order.needs_human(3));
+        assert_eq!(order.messages.len(), 1);
+        assert_eq!(order.messages[0].content, HANDOFF_REPLY);
+        let entries = dead_letters(&mut conn);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].order_id, "order-1");
+        assert_eq!(entries[0].invalid_turns, 3);
+        assert!(entries[0].items[0].starts_with("Coke"));
+
+        order.track_invalid_items(3);
+        store.save_order(&mut conn, &mut order).await.unwrap();
+        assert_eq!(order.messages.len(), 1);
+        assert_eq!(dead_letters(&mut conn).len(), 1);
+    }
+
+    #[test]
+    fn the_eta_combines_the_prep_times_of_the_items() {
+        let mut menu = crate::testing::menu();
+        for menu_item in menu.items.iter_mut() {
+            match menu_item.item_name.as_str() {
+                "Coke" => menu_item.prep_seconds = Some(30),
+                "Cheese Burger" => menu_item.prep_seconds = Some(240),
+                _ => {}
+            }
+        }
+        let mut order = order();
+        order.order = vec![item("Coke", &[]), item("Cheese Burger", &[])];
+
+        assert_eq!(order.eta(&menu, 300, EtaMode::Max), 240);
+        assert_eq!(order.eta(&menu, 300, EtaMode::Sum), 270);
+    }
+
+    #[tokio::test]
+    async fn saving_advances_updated_at() {
+        let store = store();
+        let mut conn = store.get_connection().unwrap();
+        let mut order = order();
+        let created = order.updated_at.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        store.save_order(&mut conn, &mut order).await.unwrap();
+        let first = order.updated_at.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        store.save_order(&mut conn, &mut order).await.unwrap();
+
+        assert!(first > created);
+        let stored = store.get_order(&mut conn, "order-1").unwrap();
+        assert!(stored.updated_at.unwrap() > first);
+        assert_eq!(stored.created_at, order.created_at);
+    }
+
+    #[test]
+    fn items_are_grouped_in_a_cart_per_label() {
+        let mut order = order();
+        assert!(order.carts().is_empty());
+        for (name, cents, label) in [("Coke", 350, "Ana"), ("Cheese Burger", 1450, "Ben")] {
+            let mut line = item(name, &[]);
+            line.price = Money::from_cents(cents);
+            line.quantity = 2;
+            line.cart_label = Some(label.to_string());
+            order.order.push(line);
+        }
+
+        let carts = order.carts();
+
+        assert_eq!(carts.len(), 2);
+        assert_eq!(carts[0].cart_label.as_deref(), Some("Ana"));
+        assert_eq!(carts[0].item_ids, vec![order.order[0].id.clone()]);
+        assert_eq!(carts[0].total, Money::from_cents(700));
+        assert_eq!(carts[1].cart_label.as_deref(), Some("Ben"));
+        assert_eq!(carts[1].item_ids, vec![order.order[1].id.clone()]);
+        assert_eq!(carts[1].total, Money::from_cents(2900));
+    }
+
+    #[test]
+    fn messages_are_truncated_only_past_the_maximum() {
+        let mut order = order();
+        order.order.push(item("Coke", &[]));
+        let say = |order: &mut Order, count: usize| {
+            for _ in 0..count {
+                let content = format!(
+                    "message {}",
+                    order.messages.len() + order.truncated_messages
+                );
+                order.messages.push(ChatMessage {
+                    role: ChatRole::User.to_string(),
+                    content,
+                });
+            }
+        };
+
+        say(&mut order, 3);
+        order.truncate_messages(3);
+        assert_eq!(order.messages.len(), 3);
+        assert_eq!(order.truncated_messages, 0);
+
+        say(&mut order, 1);
+        order.truncate_messages(3);
+        assert_eq!(order.messages.len(), 3);
+        assert_eq!(order.truncated_messages, 1);
+        assert_eq!(order.messages[0].content, "message 1");
+        assert_eq!(order.messages[2].content, "message 3");
+        assert_eq!(order.order.len(), 1);
+
+        order.truncate_messages(0);
+        assert_eq!(order.messages.len(), 3);
     }
 }
@@ -0,0 +1,75 @@
+use metrics::{describe_counter, describe_histogram, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::sync::OnceLock;
+use tracing::info;
+
+/// Counter of orders created through `/start`
+pub const ORDERS_STARTED: &str = "orders_started_total";
+/// Counter of chat messages processed through `/chat`
+pub const CHAT_MESSAGES: &str = "chat_messages_total";
+/// Counter of assistant function calls, labelled by `function`
+pub const FUNCTION_CALLS: &str = "function_calls_total";
+/// Counter of order item validations, labelled by resulting `status`
+pub const ITEM_VALIDATIONS: &str = "item_validations_total";
+/// Counter of failed OpenAI calls, labelled by `operation`
+pub const OPENAI_ERRORS: &str = "openai_errors_total";
+/// Counter of model-supplied item prices differing from the menu price, labelled by `function`
+pub const PRICE_MISMATCHES: &str = "price_mismatch_total";
+/// Histogram of the time spent handling a chat message
+pub const CHAT_LATENCY: &str = "chat_latency_seconds";
+/// Histogram of the time an OpenAI run took, labelled by whether it was streamed or polled
+pub const RUN_DURATION: &str = "openai_run_duration_seconds";
+
+/// Bucket boundaries for the chat latency histogram, in seconds
+const CHAT_LATENCY_BUCKETS: &[f64] = &[0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Installs the global Prometheus recorder.
+///
+/// The recorder can only be installed once per process, so later calls return the handle of
+/// the recorder installed by the first call.
+///
+/// # Returns
+/// * `PrometheusHandle` - Handle used to render the current metrics
+pub fn install_recorder() -> PrometheusHandle {
+    HANDLE
+        .get_or_init(|| {
+            info!("Installing Prometheus metrics recorder");
+            let handle = PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Full(CHAT_LATENCY.to_string()),
+                    CHAT_LATENCY_BUCKETS,
+                )
+                .expect("Invalid chat latency buckets")
+                .set_buckets_for_metric(
+                    Matcher::Full(RUN_DURATION.to_string()),
+                    CHAT_LATENCY_BUCKETS,
+                )
+                .expect("Invalid run duration buckets")
+                .install_recorder()
+                .expect("Failed to install metrics recorder");
+
+            describe_counter!(ORDERS_STARTED, "Orders started");
+            describe_counter!(CHAT_MESSAGES, "Chat messages processed");
+            describe_counter!(FUNCTION_CALLS, "Assistant function calls by function");
+            describe_counter!(ITEM_VALIDATIONS, "Order item validations by status");
+            describe_counter!(OPENAI_ERRORS, "Failed OpenAI calls by operation");
+            describe_counter!(
+                PRICE_MISMATCHES,
+                "Model-supplied item prices differing from the menu by function"
+            );
+            describe_histogram!(
+                CHAT_LATENCY,
+                Unit::Seconds,
+                "Time spent handling a chat message"
+            );
+            describe_histogram!(
+                RUN_DURATION,
+                Unit::Seconds,
+                "Time an OpenAI run took by mode"
+            );
+            handle
+        })
+        .clone()
+}
@@ -0,0 +1,740 @@
+//! Fixtures shared by the unit tests
+
+use async_openai::config::OpenAIConfig;
+use async_openai::types::FunctionCall;
+use async_openai::Client;
+use axum::body::Body;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tower::ServiceExt;
+
+use crate::api::{create_router, create_router_with_assistant};
+use crate::chat::{handle_function_call, FunctionResult};
+use crate::config::Config;
+use crate::error::AppResult;
+use crate::functions::{ComponentArgs, FunctionName};
+use crate::menu::{Menu, MenuItem};
+use crate::mock::MockAssistant;
+use crate::order::{EtaMode, OptionValue, Order, OrderItem, OrderLimits, OrderStore};
+
+/// The menu shipped in `static/menu.json`.
+pub fn menu() -> Menu {
+    let items: Vec<MenuItem> = serde_json::from_str(include_str!("../static/menu.json"))
+        .expect("static/menu.json must be a valid menu");
+    Menu {
+        items,
+        max_validation_problems: 0,
+    }
+}
+
+/// Limits with the defaults of `Config`.
+pub fn limits() -> OrderLimits {
+    OrderLimits {
+        max_items: 50,
+        max_thread_messages: 100,
+        ttl_seconds: 0,
+        max_invalid_turns: 3,
+        max_tokens: 0,
+        default_prep_seconds: 300,
+        eta_mode: EtaMode::Max,
+    }
+}
+
+/// A new active order at `store-a`.
+pub fn order() -> Order {
+    Order::new("order-1".to_string(), "store-a".to_string())
+}
+
+/// An order item with a single choice per option, not yet priced or validated.
+///
+/// # Arguments
+/// * `item_name` - Name of the menu item
+/// * `options` - Option keys with the choice made for each
+pub fn item(item_name: &str, options: &[(&str, &str)]) -> OrderItem {
+    OrderItem::from(&ComponentArgs {
+        item_name: item_name.to_string(),
+        option_keys: Some(options.iter().map(|(key, _)| key.to_string()).collect()),
+        option_values: Some(
+            options
+                .iter()
+                .map(|(_, value)| vec![OptionValue::new(*value)])
+                .collect(),
+        ),
+    })
+}
+
+/// Arguments of an `add_item` call for a menu item with a single choice per option.
+///
+/// # Arguments
+/// * `item_name` - Name of the menu item
+/// * `options` - Option keys with the choice made for each
+pub fn add_args(item_name: &str, options: &[(&str, &str)]) -> Value {
+    serde_json::json!({
+        "itemName": item_name,
+        "optionKeys": options.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+        "optionValues": options.iter().map(|(_, value)| vec![value]).collect::<Vec<_>>(),
+        "price": 0,
+        "components": [],
+        "notes": null,
+        "cartLabel": null,
+        "quantity": null,
+    })
+}
+
+/// Runs a function call the way the assistant would make it.
+///
+/// # Arguments
+/// * `menu` - The restaurant menu
+/// * `order` - The order to change
+/// * `name` - The function to call
+/// * `arguments` - Its JSON arguments
+///
+/// # Returns
+/// * `FunctionResult` - The parsed tool output
+pub async fn call(
+    menu: &Menu,
+    order: &mut Order,
+    name: FunctionName,
+    arguments: Value,
+) -> FunctionResult {
+    let function_call = FunctionCall {
+        name: name.to_string(),
+        arguments: arguments.to_string(),
+    };
+    let output = handle_function_call(&function_call, menu, &limits(), order)
+        .await
+        .expect("function call must produce a tool output");
+    serde_json::from_str(&output).expect("tool output must be a FunctionResult")
+}
+
+/// Every variable `Config::from_env` reads, cleared before each test
+const VARS: &[&str] = &[
+    "API_KEYS",
+    "HOST",
+    "PORT",
+    "METRICS_PORT",
+    "REDIS_URL",
+    "REDIS_DB",
+    "REDIS_USERNAME",
+    "REDIS_PASSWORD",
+    "REDIS_POOL_SIZE",
+    "REDIS_CONNECT_TIMEOUT_SECONDS",
+    "REDIS_MAX_RETRIES",
+    "ALLOWED_ORIGINS",
+    "MAX_INPUT_CHARS",
+    "MAX_BODY_BYTES",
+    "MAX_ITEMS_PER_ORDER",
+    "MAX_THREAD_MESSAGES",
+    "MAX_VALIDATION_PROBLEMS",
+    "ORDER_TTL_SECONDS",
+    "MAX_INVALID_TURNS",
+    "MAX_TOKENS_PER_ORDER",
+    "DEFAULT_PREP_SECONDS",
+    "ETA_MODE",
+    "CHAT_DEDUPE_SECONDS",
+    "STRUCTURED_ERRORS",
+    "CURRENCY",
+    "MENU_FILE",
+    "ALLOW_EMPTY_MENU",
+    "LOCATIONS_FILE",
+    "SYSTEM_PROMPT_FILE",
+    "OPENAI_MOCK",
+    "OPENAI_API_KEY",
+    "OPENAI_API_BASE",
+    "OPENAI_ORG_ID",
+    "OPENAI_TEMPERATURE",
+    "OPENAI_TOP_P",
+    "OPENAI_POLL_TIMEOUT_SECONDS",
+    "OPENAI_POLL_INTERVAL_MS",
+    "OPENAI_MAX_POLL_INTERVAL_MS",
+    "OPENAI_MAX_RETRIES",
+    "OPENAI_TOOL_CHOICE",
+    "OPENAI_STREAM_RUNS",
+    "OPENAI_MODELS",
+    "OPENAI_MODEL",
+    "STATELESS_THREADS",
+    "THREAD_CLEANUP",
+    "COMPLETION_WEBHOOK_URL",
+    "COMPLETION_WEBHOOK_SECRET",
+    "COMPLETION_WEBHOOK_MAX_RETRIES",
+];
+
+/// Serializes the tests, the environment is shared by every thread of the test binary
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Runs `Config::from_env` with only the given variables set.
+pub fn config(vars: &[(&str, &str)]) -> AppResult<Config> {
+    let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    for name in VARS {
+        std::env::remove_var(name);
+    }
+    for (name, value) in vars {
+        std::env::set_var(name, value);
+    }
+    let config = Config::from_env();
+    for (name, _) in vars {
+        std::env::remove_var(name);
+    }
+    config
+}
+
+/// API key accepted by the router of `app`, with the read-write scope and every location
+pub const API_KEY: &str = "test-key";
+
+/// Router backed by a fake Redis and the mock assistant.
+///
+/// # Arguments
+/// * `vars` - Variables set on top of `API_KEYS`, `OPENAI_MOCK` and `REDIS_URL`, which they
+///   may override
+pub async fn app(vars: &[(&str, &str)]) -> Router {
+    create_router(&app_config(vars)).await
+}
+
+/// Router backed by a fake Redis and a mock assistant the test can inspect.
+///
+/// # Arguments
+/// * `vars` - Variables set on top of the defaults of `app`, which they may override
+pub fn app_with_mock(vars: &[(&str, &str)]) -> (Router, MockAssistant) {
+    let config = app_config(vars);
+    let client = redis::Client::open(config.redis.clone()).expect("fake Redis URL must parse");
+    let store = OrderStore::new(client, &config).expect("fake Redis must answer");
+    let menu = Menu::new(&config.menu_settings).expect("test menu must load");
+    let mock = MockAssistant::new(config.order_limits);
+    let router = create_router_with_assistant(&config, store, menu, Box::new(mock.clone()));
+    (router, mock)
+}
+
+/// Configuration of `app` on a fresh fake Redis.
+fn app_config(vars: &[(&str, &str)]) -> Config {
+    let redis = FakeRedis::start();
+    let url = redis.url();
+    let mut all = vec![
+        ("API_KEYS", API_KEY),
+        ("OPENAI_MOCK", "true"),
+        ("REDIS_URL", url.as_str()),
+        ("STRUCTURED_ERRORS", "true"),
+    ];
+    all.retain(|(name, _)| !vars.iter().any(|(var, _)| var == name));
+    all.extend_from_slice(vars);
+    config(&all).expect("test configuration must be valid")
+}
+
+/// Order store on a fresh fake Redis, with the default configuration.
+pub fn store() -> OrderStore {
+    store_on(&FakeRedis::start(), &[]).expect("fake Redis must answer")
+}
+
+/// Order store on the given fake Redis, configured with `vars` on top of the defaults.
+pub fn store_on(redis: &FakeRedis, vars: &[(&str, &str)]) -> AppResult<OrderStore> {
+    let url = redis.url();
+    let mut all = vec![
+        ("API_KEYS", API_KEY),
+        ("OPENAI_MOCK", "true"),
+        ("REDIS_URL", url.as_str()),
+    ];
+    all.extend_from_slice(vars);
+    let config = config(&all).expect("test configuration must be valid");
+    let client = redis::Client::open(config.redis.clone()).expect("fake Redis URL must parse");
+    OrderStore::new(client, &config)
+}
+
+/// Builds a request authenticated with `key`, with a JSON body if one is given.
+///
+/// # Arguments
+/// * `method` - HTTP method
+/// * `uri` - Path and query
+/// * `key` - API key sent in `X-API-Key`
+/// * `body` - JSON body, if any
+pub fn request(method: Method, uri: &str, key: &str, body: Option<Value>) -> Request<Body> {
+    let builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("x-api-key", format!("Bearer {}", key));
+    match body {
+        Some(body) => builder
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => builder.body(Body::empty()),
+    }
+    .expect("test request must be valid")
+}
+
+/// Sends a request through the router.
+///
+/// # Returns
+/// * `(StatusCode, HeaderMap, Value)` - Status, headers and the body parsed as JSON, or as a
+///   JSON string if it is not JSON
+pub async fn send(app: &Router, request: Request<Body>) -> (StatusCode, HeaderMap, Value) {
+    let response = app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("router must respond");
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .expect("response body must be readable");
+    let body = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+    (status, headers, body)
+}
+
+/// Starts an order at `location` with `key`.
+///
+/// # Returns
+/// * `(StatusCode, Option<String>)` - Status and the ID of the new order, if it was started
+pub async fn start(app: &Router, key: &str, location: &str) -> (StatusCode, Option<String>) {
+    let body = serde_json::json!({ "location": location });
+    let (status, _, body) = send(app, request(Method::POST, "/start", key, Some(body))).await;
+    (status, body["orderId"].as_str().map(str::to_string))
+}
+
+/// Sends a chat message to an order at `store-a` with `API_KEY`.
+///
+/// # Returns
+/// * `(StatusCode, Value)` - Status and the JSON body of the response
+pub async fn chat(app: &Router, order_id: &str, input: &str) -> (StatusCode, Value) {
+    let body = serde_json::json!({ "orderId": order_id, "input": input, "location": "store-a" });
+    let (status, _, body) = send(app, request(Method::POST, "/chat", API_KEY, Some(body))).await;
+    (status, body)
+}
+
+/// Serves `routes` on a free local port.
+///
+/// The server runs on the test's runtime, so it must be called from within one.
+///
+/// # Returns
+/// * `String` - The base URL of the server, e.g. `http://127.0.0.1:1234`
+pub fn serve(routes: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("test server must bind");
+    let base_url = format!(
+        "http://{}",
+        listener
+            .local_addr()
+            .expect("test server must have an address")
+    );
+    let server = axum::Server::from_tcp(listener)
+        .expect("test server must listen")
+        .serve(routes.into_make_service());
+    tokio::spawn(server);
+    base_url
+}
+
+/// OpenAI client pointed at a local server answering with `routes`.
+pub fn openai(routes: Router) -> Client<OpenAIConfig> {
+    Client::with_config(
+        OpenAIConfig::new()
+            .with_api_key("test")
+            .with_api_base(serve(routes)),
+    )
+}
+
+/// A run on `thread-1` as OpenAI reports it.
+///
+/// # Arguments
+/// * `status` - The run status, e.g. `in_progress`
+pub fn run(status: &str) -> Value {
+    serde_json::json!({
+        "id": "run-1",
+        "object": "thread.run",
+        "created_at": 0,
+        "thread_id": "thread-1",
+        "assistant_id": "assistant-1",
+        "status": status,
+        "model": "gpt-4o",
+        "instructions": "",
+        "tools": [],
+        "parallel_tool_calls": true,
+    })
+}
+
+/// Value stored under a key of the fake Redis
+enum RedisValue {
+    String(Vec<u8>),
+    List(Vec<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    SortedSet(Vec<(f64, Vec<u8>)>),
+}
+
+/// Reply to a command of the fake Redis
+enum RedisReply {
+    Status(&'static str),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<RedisReply>),
+    Error(String),
+}
+
+impl RedisReply {
+    /// Encodes the reply in RESP.
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            RedisReply::Status(status) => {
+                out.extend_from_slice(format!("+{}\r\n", status).as_bytes())
+            }
+            RedisReply::Integer(value) => {
+                out.extend_from_slice(format!(":{}\r\n", value).as_bytes())
+            }
+            RedisReply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            RedisReply::Bulk(Some(value)) => {
+                out.extend_from_slice(format!("${}\r\n", value.len()).as_bytes());
+                out.extend_from_slice(value);
+                out.extend_from_slice(b"\r\n");
+            }
+            RedisReply::Array(replies) => {
+                out.extend_from_slice(format!("*{}\r\n", replies.len()).as_bytes());
+                for reply in replies {
+                    reply.write(out);
+                }
+            }
+            RedisReply::Error(message) => {
+                out.extend_from_slice(format!("-{}\r\n", message).as_bytes())
+            }
+        }
+    }
+}
+
+/// Keyspaces of the fake Redis, by database index
+type RedisDatabases = Arc<Mutex<HashMap<i64, HashMap<Vec<u8>, RedisValue>>>>;
+
+/// In-memory stand-in for Redis, speaking just enough RESP for the order store.
+///
+/// Each database selected with `SELECT` has its own keyspace, TTLs are accepted and ignored,
+/// and `SCAN` returns every matching key in a single page.
+pub struct FakeRedis {
+    addr: SocketAddr,
+    databases: RedisDatabases,
+    connections: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl FakeRedis {
+    /// Starts the server on a free local port, serving each connection on its own thread.
+    pub fn start() -> FakeRedis {
+        Self::serve(None)
+    }
+
+    /// Starts the server refusing every command until a connection sends `AUTH` with the given
+    /// credentials.
+    ///
+    /// # Arguments
+    /// * `username` - The ACL user, or None for the `default` user
+    /// * `password` - The user's password
+    pub fn start_with_auth(username: Option<&str>, password: &str) -> FakeRedis {
+        Self::serve(Some((
+            username.unwrap_or("default").to_string(),
+            password.to_string(),
+        )))
+    }
+
+    fn serve(credentials: Option<(String, String)>) -> FakeRedis {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("fake Redis must bind");
+        let addr = listener
+            .local_addr()
+            .expect("fake Redis must have an address");
+        let databases = RedisDatabases::default();
+        let connections = Arc::new(Mutex::new(vec![]));
+        let (shared, open) = (databases.clone(), connections.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(stream) = stream.try_clone() {
+                    open.lock().unwrap().push(stream);
+                }
+                let databases = shared.clone();
+                let credentials = credentials.clone();
+                thread::spawn(move || serve_redis(stream, databases, credentials));
+            }
+        });
+        FakeRedis {
+            addr,
+            databases,
+            connections,
+        }
+    }
+
+    /// Closes every open connection, as a Redis restart would, keeping the stored data.
+    pub fn drop_connections(&self) {
+        for stream in self.connections.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// URL to connect to the server with.
+    pub fn url(&self) -> String {
+        format!("redis://{}/", self.addr)
+    }
+
+    /// Keys stored in a database, sorted.
+    ///
+    /// # Arguments
+    /// * `db` - Index of the database
+    pub fn keys(&self, db: i64) -> Vec<String> {
+        let databases = self.databases.lock().unwrap();
+        let mut keys: Vec<String> = databases
+            .get(&db)
+            .map(|keys| {
+                keys.keys()
+                    .map(|key| String::from_utf8_lossy(key).to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        keys.sort();
+        keys
+    }
+}
+
+/// Answers the commands of one connection until it is closed.
+fn serve_redis(
+    stream: TcpStream,
+    databases: RedisDatabases,
+    credentials: Option<(String, String)>,
+) {
+    let mut reader = BufReader::new(stream.try_clone().expect("stream must clone"));
+    let mut writer = stream;
+    let mut queued: Option<Vec<Vec<Vec<u8>>>> = None;
+    let mut selected = 0;
+    let mut authenticated = credentials.is_none();
+    while let Some(args) = read_command(&mut reader) {
+        let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+        let reply = match (name.as_str(), &mut queued) {
+            ("AUTH", _) => {
+                let text = |arg: &Vec<u8>| String::from_utf8_lossy(arg).to_string();
+                let sent = match &args[1..] {
+                    [password] => ("default".to_string(), text(password)),
+                    [username, password] => (text(username), text(password)),
+                    _ => (String::new(), String::new()),
+                };
+                if credentials
+                    .as_ref()
+                    .is_none_or(|credentials| *credentials == sent)
+                {
+                    authenticated = true;
+                    RedisReply::Status("OK")
+                } else {
+                    RedisReply::Error(
+                        "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+                    )
+                }
+            }
+            _ if !authenticated => RedisReply::Error("NOAUTH Authentication required.".to_string()),
+            ("SELECT", None) => match String::from_utf8_lossy(&args[1]).parse() {
+                Ok(db) => {
+                    selected = db;
+                    RedisReply::Status("OK")
+                }
+                Err(_) => RedisReply::Error("ERR invalid DB index".to_string()),
+            },
+            ("MULTI", _) => {
+                queued = Some(vec![]);
+                RedisReply::Status("OK")
+            }
+            ("EXEC", queued @ Some(_)) => {
+                let commands = queued.take().unwrap_or_default();
+                let mut databases = databases.lock().unwrap();
+                let db = databases.entry(selected).or_default();
+                RedisReply::Array(
+                    commands
+                        .iter()
+                        .map(|args| execute_redis(db, args))
+                        .collect(),
+                )
+            }
+            ("DISCARD", queued) => {
+                *queued = None;
+                RedisReply::Status("OK")
+            }
+            (_, Some(commands)) => {
+                commands.push(args);
+                RedisReply::Status("QUEUED")
+            }
+            (_, None) => {
+                let mut databases = databases.lock().unwrap();
+                execute_redis(databases.entry(selected).or_default(), &args)
+            }
+        };
+        let mut out = vec![];
+        reply.write(&mut out);
+        if writer.write_all(&out).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads a command sent as a RESP array of bulk strings.
+fn read_command(reader: &mut impl BufRead) -> Option<Vec<Vec<u8>>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok().filter(|read| *read > 0)?;
+    let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).ok()?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Some(args)
+}
+
+/// Resolves a Redis range, where negative indexes count from the end, against a length.
+fn redis_range(start: &[u8], stop: &[u8], len: usize) -> std::ops::Range<usize> {
+    let index = |arg: &[u8]| {
+        let index: i64 = String::from_utf8_lossy(arg).parse().unwrap_or(0);
+        if index < 0 {
+            (len as i64 + index).max(0) as usize
+        } else {
+            index as usize
+        }
+    };
+    let start = index(start).min(len);
+    let stop = (index(stop) + 1).min(len);
+    start..stop.max(start)
+}
+
+/// Runs a single command against the database.
+fn execute_redis(db: &mut HashMap<Vec<u8>, RedisValue>, args: &[Vec<u8>]) -> RedisReply {
+    let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+    let arg = |index: usize| args.get(index).cloned().unwrap_or_default();
+    let text = |index: usize| String::from_utf8_lossy(&arg(index)).to_uppercase();
+    let string = |db: &HashMap<Vec<u8>, RedisValue>, key: &[u8]| match db.get(key) {
+        Some(RedisValue::String(value)) => Some(value.clone()),
+        _ => None,
+    };
+    match name.as_str() {
+        "PING" => RedisReply::Status("PONG"),
+        "CLIENT" | "WATCH" | "UNWATCH" => RedisReply::Status("OK"),
+        "CONFIG" if text(1) == "GET" => RedisReply::Array(vec![
+            RedisReply::Bulk(Some(arg(2))),
+            RedisReply::Bulk(Some(vec![])),
+        ]),
+        "CONFIG" => RedisReply::Status("OK"),
+        "GET" => RedisReply::Bulk(string(db, &arg(1))),
+        "MGET" => RedisReply::Array(
+            args[1..]
+                .iter()
+                .map(|key| RedisReply::Bulk(string(db, key)))
+                .collect(),
+        ),
+        "SET" => {
+            let options: Vec<String> = (3..args.len()).map(text).collect();
+            if options.iter().any(|option| option == "NX") && db.contains_key(&arg(1)) {
+                return RedisReply::Bulk(None);
+            }
+            db.insert(arg(1), RedisValue::String(arg(2)));
+            RedisReply::Status("OK")
+        }
+        "SETEX" => {
+            db.insert(arg(1), RedisValue::String(arg(3)));
+            RedisReply::Status("OK")
+        }
+        "EXPIRE" => RedisReply::Integer(i64::from(db.contains_key(&arg(1)))),
+        "DEL" => RedisReply::Integer(
+            args[1..]
+                .iter()
+                .filter(|key| db.remove(*key).is_some())
+                .count() as i64,
+        ),
+        "RPUSH" => {
+            let entry = db.entry(arg(1)).or_insert_with(|| RedisValue::List(vec![]));
+            let RedisValue::List(list) = entry else {
+                return RedisReply::Error("WRONGTYPE".to_string());
+            };
+            list.extend(args[2..].iter().cloned());
+            RedisReply::Integer(list.len() as i64)
+        }
+        "LRANGE" => match db.get(&arg(1)) {
+            Some(RedisValue::List(list)) => RedisReply::Array(
+                list[redis_range(&arg(2), &arg(3), list.len())]
+                    .iter()
+                    .map(|value| RedisReply::Bulk(Some(value.clone())))
+                    .collect(),
+            ),
+            _ => RedisReply::Array(vec![]),
+        },
+        "HSET" | "HMSET" => {
+            let entry = db
+                .entry(arg(1))
+                .or_insert_with(|| RedisValue::Hash(HashMap::new()));
+            let RedisValue::Hash(hash) = entry else {
+                return RedisReply::Error("WRONGTYPE".to_string());
+            };
+            for pair in args[2..].chunks(2) {
+                hash.insert(pair[0].clone(), pair[1].clone());
+            }
+            RedisReply::Status("OK")
+        }
+        "HGET" | "HMGET" => {
+            let mut values: Vec<RedisReply> = args[2..]
+                .iter()
+                .map(|field| match db.get(&arg(1)) {
+                    Some(RedisValue::Hash(hash)) => RedisReply::Bulk(hash.get(field).cloned()),
+                    _ => RedisReply::Bulk(None),
+                })
+                .collect();
+            if name == "HGET" {
+                values.pop().unwrap_or(RedisReply::Bulk(None))
+            } else {
+                RedisReply::Array(values)
+            }
+        }
+        "ZADD" => {
+            let entry = db
+                .entry(arg(1))
+                .or_insert_with(|| RedisValue::SortedSet(vec![]));
+            let RedisValue::SortedSet(set) = entry else {
+                return RedisReply::Error("WRONGTYPE".to_string());
+            };
+            let mut added = 0;
+            for pair in args[2..].chunks(2) {
+                let score: f64 = String::from_utf8_lossy(&pair[0]).parse().unwrap_or(0.0);
+                set.retain(|(_, member)| *member != pair[1]);
+                set.push((score, pair[1].clone()));
+                added += 1;
+            }
+            set.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+            RedisReply::Integer(added)
+        }
+        "ZREVRANGE" => match db.get(&arg(1)) {
+            Some(RedisValue::SortedSet(set)) => {
+                let members: Vec<&Vec<u8>> = set.iter().rev().map(|(_, member)| member).collect();
+                RedisReply::Array(
+                    members[redis_range(&arg(2), &arg(3), members.len())]
+                        .iter()
+                        .map(|member| RedisReply::Bulk(Some((*member).clone())))
+                        .collect(),
+                )
+            }
+            _ => RedisReply::Array(vec![]),
+        },
+        "SCAN" => {
+            let position = args
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case(b"MATCH"));
+            let pattern = position
+                .map(|index| arg(index + 1))
+                .unwrap_or(b"*".to_vec());
+            let keys = db
+                .keys()
+                .filter(|key| match pattern.strip_suffix(b"*") {
+                    Some(prefix) => key.starts_with(prefix),
+                    None => **key == pattern,
+                })
+                .map(|key| RedisReply::Bulk(Some(key.clone())))
+                .collect();
+            RedisReply::Array(vec![
+                RedisReply::Bulk(Some(b"0".to_vec())),
+                RedisReply::Array(keys),
+            ])
+        }
+        _ => RedisReply::Error(format!("ERR unknown command '{}'", name)),
+    }
+}
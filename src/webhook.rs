@@ -0,0 +1,235 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// Header carrying the HMAC-SHA256 signature of the webhook body
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Most retries `COMPLETION_WEBHOOK_MAX_RETRIES` may ask for
+pub const MAX_RETRIES: u32 = 10;
+
+/// Upper bound on the delay between two delivery attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Posts completed orders to an external system such as a POS or kitchen display
+#[derive(Clone)]
+pub struct CompletionWebhook {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    max_retries: u32,
+}
+
+impl CompletionWebhook {
+    /// Creates the webhook.
+    ///
+    /// # Arguments
+    /// * `url` - Where completed orders are POSTed
+    /// * `secret` - Key signing the requests, unsigned if None
+    /// * `max_retries` - Retries for failed deliveries, validated by `Config` to be at most
+    ///   `MAX_RETRIES`
+    ///
+    /// # Returns
+    /// * `Self` - The webhook
+    pub fn new(url: String, secret: Option<String>, max_retries: u32) -> Self {
+        if secret.is_none() {
+            warn!("COMPLETION_WEBHOOK_SECRET is not set, webhook requests will not be signed");
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build webhook client");
+        info!("Posting completed orders to {}", url);
+        Self {
+            client,
+            url,
+            secret,
+            max_retries,
+        }
+    }
+
+    /// Signs a body with the shared secret.
+    ///
+    /// # Arguments
+    /// * `body` - The request body
+    ///
+    /// # Returns
+    /// * `Option<String>` - The signature as `sha256=<hex>`, or `None` without a secret
+    pub fn sign(&self, body: &[u8]) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(body);
+        Some(format!(
+            "sha256={}",
+            hex::encode(mac.finalize().into_bytes())
+        ))
+    }
+
+    /// Posts a completed order in the background, retrying failed deliveries. Failures are
+    /// logged and never reach the caller.
+    ///
+    /// # Arguments
+    /// * `order_id` - The ID of the completed order, used for logging
+    /// * `payload` - The completed order
+    pub fn notify<T: Serialize>(&self, order_id: &str, payload: &T) {
+        let body = match serde_json::to_vec(payload) {
+            Ok(body) => body,
+            Err(e) => {
+                error!(
+                    "Failed to serialize webhook for order {}: {:?}",
+                    order_id, e
+                );
+                return;
+            }
+        };
+        let webhook = self.clone();
+        let order_id = order_id.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = webhook.deliver(&order_id, body).await {
+                error!("Failed to deliver webhook for order {}: {}", order_id, e);
+            }
+        });
+    }
+
+    /// Posts a body to the webhook, retrying with exponential backoff.
+    ///
+    /// # Arguments
+    /// * `order_id` - The ID of the completed order, used for logging
+    /// * `body` - The JSON body to post
+    ///
+    /// # Returns
+    /// * `Result<(), String>` - Success once the webhook responds with a success status, the
+    ///   last failure otherwise
+    async fn deliver(&self, order_id: &str, body: Vec<u8>) -> Result<(), String> {
+        let signature = self.sign(&body);
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Delivered webhook for order {}", order_id);
+                    return Ok(());
+                }
+                Ok(response) => format!("webhook responded with {}", response.status()),
+                Err(e) => e.to_string(),
+            };
+            if attempt >= self.max_retries {
+                return Err(error);
+            }
+            let delay = retry_delay(attempt);
+            warn!(
+                "Webhook for order {} failed (attempt {}/{}), retrying in {:?}: {}",
+                order_id,
+                attempt + 1,
+                self.max_retries + 1,
+                delay,
+                error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Computes the delay before retrying a delivery, doubling from half a second up to
+/// `MAX_RETRY_DELAY`.
+///
+/// # Arguments
+/// * `attempt` - Number of attempts already made, starting at 0
+///
+/// # Returns
+/// * `Duration` - How long to wait before the next attempt
+fn retry_delay(attempt: u32) -> Duration {
+    let delay_ms = 500u64
+        .saturating_mul(2u64.saturating_pow(attempt))
+        .min(MAX_RETRY_DELAY.as_millis() as u64);
+    Duration::from_millis(delay_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::serve;
+    use axum::body::Bytes;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    /// Receiver of a webhook that fails its first `failures` deliveries.
+    ///
+    /// # Returns
+    /// * `(String, mpsc::UnboundedReceiver<(HeaderMap, Bytes)>)` - The webhook URL and every
+    ///   request it received
+    fn receiver(failures: u32) -> (String, mpsc::UnboundedReceiver<(HeaderMap, Bytes)>) {
+        let (sender, received) = mpsc::unbounded_channel();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let routes = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                sender.send((headers, body)).unwrap();
+                if attempts.fetch_add(1, Ordering::SeqCst) < failures {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                } else {
+                    StatusCode::OK
+                }
+            }),
+        );
+        (format!("{}/hook", serve(routes)), received)
+    }
+
+    /// Checks a signature the way a receiver holding the shared secret would.
+    fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+        let signature = hex::decode(signature.trim_start_matches("sha256=")).unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_signed_and_retried_until_accepted() {
+        let (url, mut received) = receiver(1);
+        let webhook = CompletionWebhook::new(url, Some("shared".to_string()), 2);
+
+        let result = webhook
+            .deliver("order-1", br#"{"orderId":"order-1"}"#.to_vec())
+            .await;
+
+        assert!(result.is_ok());
+        for _ in 0..2 {
+            let (headers, body) = received.recv().await.unwrap();
+            assert_eq!(body.as_ref(), br#"{"orderId":"order-1"}"#);
+            assert_eq!(headers["content-type"], "application/json");
+            let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+            assert!(verify("shared", &body, signature));
+            assert!(!verify("other", &body, signature));
+        }
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn deliveries_give_up_after_the_configured_retries() {
+        let (url, mut received) = receiver(u32::MAX);
+        let webhook = CompletionWebhook::new(url, None, 0);
+
+        let result = webhook.deliver("order-1", b"{}".to_vec()).await;
+
+        assert!(result.unwrap_err().contains("500"));
+        let (headers, _) = received.recv().await.unwrap();
+        assert!(headers.get(SIGNATURE_HEADER).is_none());
+        assert!(received.try_recv().is_err());
+    }
+}